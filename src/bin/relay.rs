@@ -0,0 +1,17 @@
+use std::{env, net::TcpListener, process};
+use tetris::game::relay::relay;
+
+// relay [<address>], 0.0.0.0:7878 by default, see game::relay
+fn main() {
+    let addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "0.0.0.0:7878".to_string());
+    let result = TcpListener::bind(&addr).and_then(|listener| {
+        println!("relaying on {}", addr);
+        relay(listener)
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,701 @@
+use ggez::GameError;
+use std::{
+    collections::HashMap,
+    fmt,
+    hint::black_box,
+    io,
+    num::{ParseFloatError, ParseIntError},
+    path::Path,
+    time::Instant,
+};
+use tap::TapResultOps;
+use tetris::{
+    game::{
+        achievements::{load_achievements, AchievementsError},
+        bot::{BotError, BotRegistry, TetrisBot},
+        bot_visual::BotVisGame,
+        bracket::Bracket,
+        bracket_visual::BracketVisGame,
+        browser_visual::GenomeBrowser,
+        checkpoints::scan_checkpoints,
+        compare::Comparison,
+        compare_visual::CompareVisGame,
+        daily::today,
+        effects::Effects,
+        estimator::{train_estimator, Estimator},
+        evaluation::evaluate_network,
+        experiments::{run_experiment, Experiment, ExperimentError},
+        features::InputEncoder,
+        heatmap::Heatmap,
+        imitation::{
+            export_dataset, imitate, imitate_dataset, load_dataset, record_teacher,
+            DatasetReadError,
+        },
+        keymap::{load_keymap, KeyMapLoadError, Profile},
+        lineage::{report_lineage, LineageReadError},
+        missions::{load_missions, MissionsLoadError},
+        nn_trainer::{print_baseline, ConfigReadError, ControlMode, TrainerConfig},
+        nn_visual::NNVisGame,
+        population::{train_headless, train_islands, NNReadOrCreationError, Population},
+        rating::{load_ratings, save_ratings, RatingsError},
+        replay::ReplayReadError,
+        reward::{RewardExpr, RewardParseError},
+        rise::StackRise,
+        rl_trainer::{Prioritized, RLConfig, RLTrainer, RLTrainerError, ReplayBufferReadError},
+        scores::{
+            export_scores, import_scores, load_leaderboard, load_scores, save_leaderboard,
+            ExportedScores, ScoresError,
+        },
+        tbp::{serve, TbpError},
+        theme::{load_theme, set_theme, ThemeLoadError},
+        tournament::tournament,
+        trainer_visual::NNTrainer,
+        versus::{BotLimits, Handicap},
+        versus_visual::VersusVisGame,
+        visual::{Mirror, QueueReveal, VisGame},
+        HoldMode, RotationSystem,
+    },
+    neural_network::{self, Backend, NNCreationError, NNReadError, NN},
+};
+
+#[derive(From, Debug)]
+pub enum CliError {
+    Usage(String),
+    IoError(io::Error),
+    ParseIntError(ParseIntError),
+    ParseFloatError(ParseFloatError),
+    ConfigError(ConfigReadError),
+    NNError(NNReadOrCreationError),
+    NNReadError(NNReadError),
+    LineageReadError(LineageReadError),
+    ReplayReadError(ReplayReadError),
+    ReplayBufferReadError(ReplayBufferReadError),
+    RLTrainerError(RLTrainerError),
+    DatasetReadError(DatasetReadError),
+    RewardParseError(RewardParseError),
+    NNCreationError(NNCreationError),
+    GameError(GameError),
+    TbpError(TbpError),
+    BotError(BotError),
+    ThemeLoadError(ThemeLoadError),
+    KeyMapLoadError(KeyMapLoadError),
+    ScoresError(ScoresError),
+    RatingsError(RatingsError),
+    ExperimentError(ExperimentError),
+    AchievementsError(AchievementsError),
+    MissionsLoadError(MissionsLoadError),
+}
+
+pub type CliResult<T> = Result<T, CliError>;
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Usage(e) => write!(f, "{}", e),
+            Self::IoError(e) => write!(f, "{}", e),
+            Self::ParseIntError(e) => write!(f, "invalid number: {}", e),
+            Self::ParseFloatError(e) => write!(f, "invalid number: {}", e),
+            Self::ConfigError(e) => write!(f, "{}", e),
+            Self::NNError(e) => write!(f, "{}", e),
+            Self::NNReadError(e) => write!(f, "{}", e),
+            Self::LineageReadError(e) => write!(f, "{}", e),
+            Self::ReplayReadError(e) => write!(f, "{}", e),
+            Self::ReplayBufferReadError(e) => write!(f, "{}", e),
+            Self::RLTrainerError(e) => write!(f, "{}", e),
+            Self::DatasetReadError(e) => write!(f, "{}", e),
+            Self::RewardParseError(e) => write!(f, "{}", e),
+            Self::NNCreationError(e) => write!(f, "{}", e),
+            Self::GameError(e) => write!(f, "{}", e),
+            Self::TbpError(e) => write!(f, "{}", e),
+            Self::BotError(e) => write!(f, "{}", e),
+            Self::ThemeLoadError(e) => write!(f, "{}", e),
+            Self::KeyMapLoadError(e) => write!(f, "{}", e),
+            Self::ScoresError(e) => write!(f, "{}", e),
+            Self::RatingsError(e) => write!(f, "{}", e),
+            Self::ExperimentError(e) => write!(f, "{}", e),
+            Self::AchievementsError(e) => write!(f, "{}", e),
+            Self::MissionsLoadError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// "--name value" pairs, a flag without a value (like --vertical) is "true"
+fn parse_flags(args: &[String]) -> CliResult<HashMap<&str, &str>> {
+    let mut flags = HashMap::new();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| CliError::Usage(format!("unexpected argument \"{}\"", arg)))?;
+        let value = match args.peek() {
+            Some(value) if !value.starts_with("--") => args.next().unwrap().as_str(),
+            _ => "true",
+        };
+        flags.insert(name, value);
+    }
+    Ok(flags)
+}
+
+fn required<'a>(flags: &HashMap<&str, &'a str>, name: &str) -> CliResult<&'a str> {
+    flags
+        .get(name)
+        .copied()
+        .ok_or_else(|| CliError::Usage(format!("missing --{}", name)))
+}
+
+fn encoder(flags: &HashMap<&str, &str>, default: InputEncoder) -> CliResult<InputEncoder> {
+    match flags.get("encoder") {
+        None => Ok(default),
+        Some(&"cells") => Ok(InputEncoder::Cells),
+        Some(&"features") => Ok(InputEncoder::Features),
+        Some(&"split-cells") => Ok(InputEncoder::SplitCells),
+        Some(&"piece-planes") => Ok(InputEncoder::PiecePlanes),
+        Some(&"heights") => Ok(InputEncoder::Heights),
+        Some(&"surface") => Ok(InputEncoder::Surface),
+        Some(e) => Err(CliError::Usage(format!("unknown encoder \"{}\"", e))),
+    }
+}
+
+// --backend cpu|simd|blas for every network the command runs, blas needs the blas feature
+fn set_backend(flags: &HashMap<&str, &str>) -> CliResult<()> {
+    let name = match flags.get("backend") {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    match Backend::from_name(name) {
+        Some(backend) if backend.is_available() => {
+            neural_network::set_backend(backend);
+            Ok(())
+        }
+        Some(_) => Err(CliError::Usage(format!(
+            "backend \"{}\" needs a build with --features {}",
+            name, name
+        ))),
+        None => Err(CliError::Usage(format!("unknown backend \"{}\"", name))),
+    }
+}
+
+// the config file with --encoder and --mode applied on top
+fn config(flags: &HashMap<&str, &str>) -> CliResult<TrainerConfig> {
+    set_backend(flags)?;
+    let mut config = match flags.get("config") {
+        Some(path) => TrainerConfig::from_file(path)?,
+        None => TrainerConfig::default(),
+    };
+    config.encoder = encoder(flags, config.encoder)?;
+    match flags.get("mode") {
+        None => (),
+        Some(&"keys") => config.mode = ControlMode::Keys,
+        Some(&"placement") => config.mode = ControlMode::Placement,
+        Some(m) => return Err(CliError::Usage(format!("unknown mode \"{}\"", m))),
+    }
+    Ok(config)
+}
+
+// heuristic, nn:<file> or tbp:<command>, networks use --encoder
+fn bot(flags: &HashMap<&str, &str>, spec: &str) -> CliResult<Box<dyn TetrisBot>> {
+    set_backend(flags)?;
+    let registry = BotRegistry::with_defaults(encoder(flags, InputEncoder::Features)?);
+    Ok(registry.create(spec)?)
+}
+
+// seed, theme, keys, hold, rotation and window options shared by every windowed command
+fn vis_game(flags: &HashMap<&str, &str>) -> CliResult<VisGame> {
+    if let Some(path) = flags.get("theme") {
+        set_theme(load_theme(path)?);
+    }
+    let mut vis = VisGame::new();
+    if flags.contains_key("debug-kicks") {
+        vis = vis.with_kick_debug();
+    }
+    if flags.contains_key("reload") {
+        let theme = flags.get("theme").unwrap_or(&"theme.toml");
+        vis = vis.with_reloading(theme.into());
+    }
+    if let Some(seed) = flags.get("seed") {
+        vis = vis.seeded(seed.parse()?);
+    }
+    if flags.contains_key("vertical") {
+        vis = vis.vertical();
+    }
+    if flags.contains_key("stats") {
+        vis = vis.with_stats();
+    }
+    if flags.contains_key("bag") {
+        vis = vis.with_bag();
+    }
+    if flags.contains_key("no-animations") {
+        vis = vis.without_animations();
+    }
+    if let Some(names) = flags.get("effects") {
+        let effects = Effects::from_names(names)
+            .ok_or_else(|| CliError::Usage(format!("unknown effects \"{}\"", names)))?;
+        vis = vis.with_effects(effects);
+    }
+    if let Some(path) = flags.get("estimator") {
+        vis = vis.with_estimator(Estimator::load(path)?);
+    }
+    if let Some(keys) = flags.get("keys") {
+        let keymap = match Profile::from_name(keys) {
+            Some(profile) => profile.into(),
+            None => load_keymap(keys)?,
+        };
+        vis = vis.with_keymap(keymap);
+    }
+    if let Some(name) = flags.get("hold") {
+        let mode = HoldMode::from_name(name)
+            .ok_or_else(|| CliError::Usage(format!("unknown hold mode \"{}\"", name)))?;
+        vis = vis.with_hold_mode(mode);
+    }
+    if let Some(name) = flags.get("rotation") {
+        let system = RotationSystem::from_name(name)
+            .ok_or_else(|| CliError::Usage(format!("unknown rotation system \"{}\"", name)))?;
+        vis = vis.with_rotation_system(system);
+    }
+    Ok(vis)
+}
+
+// tetris play [--seed <n>] [--vertical] [--stats] [--bag] [--keys <profile>] [--hold <mode>] [--theme <toml>]
+//             [--record <dir>] [--hint <bot>] [--entry-delay <frames>] [--clear-delay <frames>]
+//             [--lock-delay <frames>] [--rotation <system>] [--reload] [--debug-kicks]
+//             [--best <toml>] [--achievements <toml>] [--daily] [--missions [<toml>]]
+//             [--frame-step] [--estimator <file>] [--heatmap <svg>] [--no-animations]
+//             [--effects bloom,crt,grayscale] [--rise [<seconds>]]
+//             [--mirror controls|board|both] [--memory [<pieces>] [--reveal <frames>]]
+// the delays are only for people, bots expect the next piece right after a lock, --daily
+// plays today's challenge (the same pieces for everyone) instead of a random or --seed game,
+// --rise is survival, a garbage row rises after that many seconds (10 by default) and then
+// sooner and sooner, --mirror flips left and right for training and --memory hides the
+// queue, with a number it shows for a second (or --reveal frames) every that many pieces
+pub fn play(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let mut vis = vis_game(&flags)?;
+    if flags.contains_key("daily") {
+        if flags.contains_key("seed") {
+            return Err(CliError::Usage("--daily has its own seed".to_string()));
+        }
+        let date = today();
+        println!("Daily challenge for {}", date);
+        vis = vis.daily(date);
+    }
+    let entry = flags.get("entry-delay").map_or(Ok(0), |n| n.parse())?;
+    let line_clear = flags.get("clear-delay").map_or(Ok(0), |n| n.parse())?;
+    vis = vis.with_delays(entry, line_clear);
+    if let Some(frames) = flags.get("lock-delay") {
+        vis = vis.with_lock_delay(frames.parse()?);
+    }
+    if let Some(spec) = flags.get("hint") {
+        vis = vis.with_hint(bot(&flags, spec)?);
+    }
+    if let Some(dir) = flags.get("record") {
+        vis = vis.record_to(dir.as_ref());
+    }
+    if let Some(path) = flags.get("heatmap") {
+        vis = vis.with_heatmap(path.into());
+    }
+    if flags.contains_key("frame-step") {
+        vis = vis.with_frame_step();
+    }
+    if let Some(name) = flags.get("mirror") {
+        let mirror = Mirror::from_name(name)
+            .ok_or_else(|| CliError::Usage(format!("unknown mirror \"{}\"", name)))?;
+        vis = vis.with_mirror(mirror);
+    }
+    if let Some(&every) = flags.get("memory") {
+        let every = if every == "true" { 0 } else { every.parse()? };
+        let frames = flags.get("reveal").map_or(Ok(60), |n| n.parse())?;
+        vis = vis.with_queue_reveal(QueueReveal { every, frames });
+    }
+    if let Some(&seconds) = flags.get("rise") {
+        let seconds = if seconds == "true" { "10" } else { seconds };
+        vis = vis.with_stack_rise(StackRise::every_seconds(seconds.parse()?));
+    }
+    let path = flags.get("best").unwrap_or(&"best.toml");
+    vis = vis.with_best_scores(path.into(), load_scores(path)?);
+    let path = flags.get("achievements").unwrap_or(&"achievements.toml");
+    vis = vis.with_achievements(path.into(), load_achievements(path)?);
+    if let Some(&path) = flags.get("missions") {
+        let path = if path == "true" {
+            "missions.toml"
+        } else {
+            path
+        };
+        vis = vis.with_missions(load_missions(path)?);
+    }
+    vis.run()?;
+    Ok(())
+}
+
+// tetris watch [--nn <file> [--config <toml>] [--encoder <e>] [--mode <m>] [--corrections <dir>]] [--seed <n>] [--vertical]
+//             [--stats] [--theme <toml>] [--estimator <file>]
+// tetris watch --bot <bot> [--encoder <e>] [--seed <n>] [--vertical]
+// --nn shows a network as it was trained (keys mode included), without either the
+// heuristic bot plays, F2 takes over from the network and --corrections keeps the pieces
+// locked meanwhile
+pub fn watch(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let vis = vis_game(&flags)?;
+    match (flags.get("nn"), flags.get("bot")) {
+        (Some(path), _) => {
+            let mut nn_vis = NNVisGame::with_nn(vis, NN::from_file(path)?, config(&flags)?);
+            if let Some(dir) = flags.get("corrections") {
+                nn_vis = nn_vis.with_corrections(Path::new(dir));
+            }
+            nn_vis.run()?
+        }
+        (None, Some(spec)) => BotVisGame::with_bot(vis, bot(&flags, spec)?).run()?,
+        (None, None) => BotVisGame::watching(vis).run()?,
+    }
+    Ok(())
+}
+
+// --<prefix>garbage <multiplier> and --<prefix>levels <n>, no handicap without either
+fn handicap(flags: &HashMap<&str, &str>, prefix: &str) -> CliResult<Handicap> {
+    let mut handicap = Handicap::default();
+    if let Some(garbage) = flags.get(format!("{}garbage", prefix).as_str()) {
+        handicap.garbage = garbage.parse()?;
+    }
+    if let Some(levels) = flags.get(format!("{}levels", prefix).as_str()) {
+        handicap.levels = levels.parse()?;
+    }
+    Ok(handicap)
+}
+
+// --difficulty <preset> with --bot-pps <n>, --bot-reaction <frames> and --bot-misdrop <chance>
+// on top, no limits without any of them
+fn bot_limits(flags: &HashMap<&str, &str>) -> CliResult<BotLimits> {
+    let mut limits = match flags.get("difficulty") {
+        None => BotLimits::default(),
+        Some(name) => BotLimits::preset(name)
+            .ok_or_else(|| CliError::Usage(format!("unknown difficulty \"{}\"", name)))?,
+    };
+    if let Some(pps) = flags.get("bot-pps") {
+        limits.pps = Some(pps.parse()?);
+    }
+    if let Some(reaction) = flags.get("bot-reaction") {
+        limits.reaction = reaction.parse()?;
+    }
+    if let Some(misdrop) = flags.get("bot-misdrop") {
+        limits.misdrop = misdrop.parse()?;
+    }
+    limits.check().map_err(CliError::Usage)?;
+    Ok(limits)
+}
+
+// tetris versus [--bot <bot>] [--encoder <e>] [--seed <n>] [--keys <profile>] [--hold <mode>]
+//               [--rotation <system>] [--vertical] [--garbage <x>] [--levels <n>]
+//               [--bot-garbage <x>] [--bot-levels <n>]
+//               [--difficulty beginner|intermediate|expert] [--bot-pps <n>]
+//               [--bot-reaction <frames>] [--bot-misdrop <chance>]
+// play against a bot (the heuristic one by default) with the same pieces, clearing lines
+// sends garbage, the handicaps multiply the garbage a side receives (1.5 for half as much
+// again) and start it that many levels higher, the difficulty slows the bot down to a human
+// pace and makes it misdrop now and then
+pub fn versus(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let vis = vis_game(&flags)?;
+    let bot = bot(&flags, flags.get("bot").unwrap_or(&"heuristic"))?;
+    let (player, bot_handicap) = (handicap(&flags, "")?, handicap(&flags, "bot-")?);
+    VersusVisGame::new(vis, bot)
+        .with_handicaps(player, bot_handicap)
+        .with_limits(bot_limits(&flags)?)
+        .run()?;
+    Ok(())
+}
+
+// tetris bracket --players <name>,<name>,... [--seed <n>] [--keys <profile>] [--hold <mode>]
+//                [--rotation <system>] [--vertical]
+// a knockout tournament at one keyboard, in every match both players play the same pieces
+// one after the other and the one with more points goes on
+pub fn bracket(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let players = required(&flags, "players")?
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .collect::<Vec<_>>();
+    if players.len() < 2 {
+        return Err(CliError::Usage(
+            "a bracket needs at least two players".to_string(),
+        ));
+    }
+    let seed = flags
+        .get("seed")
+        .map_or(Ok(rand::random()), |n| n.parse())?;
+    let vis = vis_game(&flags)?;
+    BracketVisGame::new(vis, Bracket::new(players), seed).run()?;
+    Ok(())
+}
+
+// tetris tbp [--bot <bot>] [--encoder <e>]
+// play as a Tetris Bot Protocol bot on stdin/stdout, the heuristic bot by default
+pub fn tbp(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let mut bot = bot(&flags, flags.get("bot").unwrap_or(&"heuristic"))?;
+    serve("tetris", bot.as_mut())?;
+    Ok(())
+}
+
+// tetris scores [--import <json> --key <key>] [--leaderboard <json>]
+// tetris scores --export <json> --name <player> --key <key> [--best <toml>]
+// --export writes the bests of play signed with the key for others to import, --import
+// merges someone's signed with the same key into the leaderboard (leaderboard.json by
+// default), which is then printed
+pub fn scores(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    if let Some(path) = flags.get("export") {
+        let best = load_scores(flags.get("best").unwrap_or(&"best.toml"))?;
+        let (key, name) = (required(&flags, "key")?, required(&flags, "name")?);
+        export_scores(path, &ExportedScores::new(key, name, &best))?;
+        return Ok(());
+    }
+    let path = flags.get("leaderboard").unwrap_or(&"leaderboard.json");
+    let mut leaderboard = load_leaderboard(path)?;
+    if let Some(import) = flags.get("import") {
+        leaderboard.merge(&import_scores(import, required(&flags, "key")?)?);
+        save_leaderboard(path, &leaderboard)?;
+    }
+    for mode in leaderboard.modes() {
+        println!("{}", mode);
+        for (i, (player, points)) in leaderboard.ranking(mode).into_iter().enumerate() {
+            println!("{:>4}. {:<20} {}", i + 1, player, points);
+        }
+    }
+    Ok(())
+}
+
+// tetris train --dir <dir> [--generations <n>] [--config <toml>] [--encoder <e>] [--mode <m>]
+// windowed unless a number of generations is given, the generation is kept in
+// <dir>/generation.txt, with islands in the config island i is trained in <dir>/island_<i>
+pub fn train(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let config = config(&flags)?;
+    let out = Path::new(required(&flags, "dir")?);
+    let generations = match flags.get("generations") {
+        Some(n) => n.parse()?,
+        None => {
+            NNTrainer::new(&out.join("generation.txt"), config)?.run()?;
+            return Ok(());
+        }
+    };
+    config.check().map_err(CliError::Usage)?;
+    print_baseline(config.mode);
+    match config.islands {
+        None => {
+            let mut population = Population::load_or_create(&out.join("generation.txt"), config)?;
+            train_headless(&mut population, generations, out)?;
+        }
+        Some(island_config) => {
+            let mut islands = (0..island_config.islands)
+                .map(|i| {
+                    let path = out.join(format!("island_{}", i)).join("generation.txt");
+                    Population::load_or_create(&path, config.clone())
+                        .tap_ok(|island| island.set_label(format!("island {}: ", i)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            train_islands(&mut islands, generations, island_config, out)?;
+        }
+    }
+    Ok(())
+}
+
+// tetris experiments --file <toml> --dir <dir>
+// trains every combination of the grid in the file in <dir>/run_<i>, see experiments.rs
+pub fn experiments(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let experiment = Experiment::from_file(required(&flags, "file")?)?;
+    run_experiment(&experiment, Path::new(required(&flags, "dir")?))?;
+    Ok(())
+}
+
+// tetris bench [--passes <n>] [--config <toml>] [--encoder <e>] [--mode <m>]
+// forward passes a second through a random network of the config's shape with every backend
+pub fn bench(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let config = config(&flags)?;
+    let passes = flags.get("passes").map_or(Ok(100_000), |n| n.parse())?;
+    let nn = config.new_network()?;
+    // a batch like the placements of a piece
+    let inputs = (0..34)
+        .map(|i| {
+            (0..nn.input_size())
+                .map(|j| ((i * j) as f64).sin())
+                .collect()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    for &backend in Backend::ALL.iter().filter(|b| b.is_available()) {
+        let start = Instant::now();
+        for _ in 0..passes / inputs.len() {
+            black_box(nn.apply_batch_with(backend, &inputs));
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        println!("{:?}: {:.0} passes/s", backend, passes as f64 / seconds);
+    }
+    Ok(())
+}
+
+// tetris eval --nn <file> [--config <toml>] [--encoder <e>] [--mode <m>] [--games <n>] [--seed <n>]
+// the config has to match the one the network was trained with
+pub fn eval(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let config = config(&flags)?;
+    let games = flags.get("games").map_or(Ok(100), |n| n.parse())?;
+    if games == 0 {
+        return Err(CliError::Usage("--games has to be at least 1".to_string()));
+    }
+    let nn = NN::from_file(required(&flags, "nn")?)?;
+    let seed = flags.get("seed").map_or(Ok(0), |n| n.parse())?;
+    evaluate_network(&nn, &config, games, seed);
+    Ok(())
+}
+
+// tetris lineage --dir <training dir> [--id <genome>]
+pub fn lineage(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let dir = Path::new(required(&flags, "dir")?);
+    let id = flags.get("id").map(|id| id.parse()).transpose()?;
+    report_lineage(dir, id)?;
+    Ok(())
+}
+
+// tetris browse [--dir <dir>] [--config <toml>] [--encoder <e>] [--mode <m>]
+// lists what training saved below dir (runs by default) to watch, train further or pit
+// against each other, the config is for those without a config.toml next to them
+pub fn browse(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let checkpoints = scan_checkpoints(Path::new(flags.get("dir").unwrap_or(&"runs")))?;
+    GenomeBrowser::new(checkpoints, config(&flags)?).run()?;
+    Ok(())
+}
+
+// tetris rl --out <file> [--encoder <e>] [--prioritized] [--replay <file>] [--reward <expr>]
+// the replay buffer is loaded from and saved to --replay so that it carries over between runs
+pub fn rl(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let encoder = encoder(&flags, InputEncoder::Features)?;
+    let out = required(&flags, "out")?;
+    let config = RLConfig {
+        prioritized: flags.contains_key("prioritized").then(Prioritized::default),
+        reward: flags
+            .get("reward")
+            .map(|reward| RewardExpr::parse(reward))
+            .transpose()?,
+        ..RLConfig::default()
+    };
+    let mut trainer = RLTrainer::new(encoder, config)?;
+    if let Some(path) = flags.get("replay") {
+        trainer = trainer.with_replay_buffer(path.into())?;
+    }
+    trainer.run(out.as_ref())?;
+    Ok(())
+}
+
+// tetris estimator --out <file> [--games <n>] [--pieces <n>] [--epochs <n>]
+// the result is shown as a gauge by play and watch --estimator <file>
+pub fn estimator(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let out = required(&flags, "out")?;
+    let games = flags.get("games").map_or(Ok(100), |n| n.parse())?;
+    let pieces = flags.get("pieces").map_or(Ok(500), |n| n.parse())?;
+    let epochs = flags.get("epochs").map_or(Ok(20), |n| n.parse())?;
+    train_estimator(games, pieces, epochs).to_file(out)?;
+    Ok(())
+}
+
+// tetris imitate --replays <dir> --out <file> [--epochs <n>] [--encoder <e>]
+// tetris imitate --dataset <file> --out <file> [--epochs <n>] [--encoder <e>]
+// the result can be used as the generation file of a placement mode trainer, a dataset has
+// to be read with the encoder it was exported with
+pub fn imitate_replays(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let encoder = encoder(&flags, InputEncoder::Features)?;
+    let epochs = flags.get("epochs").map_or(Ok(50), |n| n.parse())?;
+    let nn = match flags.get("dataset") {
+        Some(path) => imitate_dataset(load_dataset(path, encoder)?, encoder, epochs),
+        None => imitate(Path::new(required(&flags, "replays")?), encoder, epochs)?,
+    };
+    nn.to_file(required(&flags, "out")?)?;
+    Ok(())
+}
+
+// tetris heatmap --replays <dir> --out <svg>
+// where the pieces of every replay in dir were locked, per piece type
+pub fn heatmap(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let heatmap = Heatmap::of_replays(Path::new(required(&flags, "replays")?))?;
+    heatmap.save(required(&flags, "out")?)?;
+    Ok(())
+}
+
+// tetris dataset --replays <dir> --out <file> [--validation <share>] [--encoder <e>]
+// the placements of the replays shuffled into a file for imitate --dataset, with a share
+// of the pieces (0.1 by default) held out for validation
+pub fn dataset(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let encoder = encoder(&flags, InputEncoder::Features)?;
+    let validation = flags.get("validation").map_or(Ok(0.1), |x| x.parse())?;
+    let (train, validation) = export_dataset(
+        Path::new(required(&flags, "replays")?),
+        encoder,
+        validation,
+        Path::new(required(&flags, "out")?),
+    )?;
+    println!("{} train and {} validation pairs", train, validation);
+    Ok(())
+}
+
+// tetris teach --bot <bot> --out <dir> [--games <n>] [--pieces <n>] [--encoder <e>]
+// saves the bot's games for imitate --replays <dir>
+pub fn teach(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let mut teacher = bot(&flags, required(&flags, "bot")?)?;
+    let games = flags.get("games").map_or(Ok(10), |n| n.parse())?;
+    let pieces = flags.get("pieces").map_or(Ok(500), |n| n.parse())?;
+    record_teacher(
+        teacher.as_mut(),
+        Path::new(required(&flags, "out")?),
+        games,
+        pieces,
+    )?;
+    Ok(())
+}
+
+// tetris compare --left <bot> --right <bot> [--seed <n>] [--pieces <n>] [--encoder <e>]
+// both bots play the same pieces side by side, pointing out where they place them differently
+pub fn compare(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let (left_spec, right_spec) = (required(&flags, "left")?, required(&flags, "right")?);
+    let bots = [
+        (left_spec.to_string(), bot(&flags, left_spec)?),
+        (right_spec.to_string(), bot(&flags, right_spec)?),
+    ];
+    let seed = flags
+        .get("seed")
+        .map_or(Ok(rand::random()), |n| n.parse())?;
+    let pieces = flags.get("pieces").map_or(Ok(500), |n| n.parse())?;
+    CompareVisGame::new(Comparison::new(seed, bots, pieces)).run()?;
+    Ok(())
+}
+
+// tetris tournament --left <bot> --right <bot> [--games <n>] [--pieces <n>] [--encoder <e>]
+//     [--ratings <json>]
+// every game is rated, the bots are known by their specs in the ratings file (ratings.json
+// by default), which is then printed
+pub fn play_tournament(args: &[String]) -> CliResult<()> {
+    let flags = parse_flags(args)?;
+    let (left_spec, right_spec) = (required(&flags, "left")?, required(&flags, "right")?);
+    let mut left = bot(&flags, left_spec)?;
+    let mut right = bot(&flags, right_spec)?;
+    let games = flags.get("games").map_or(Ok(20), |n| n.parse())?;
+    let pieces = flags.get("pieces").map_or(Ok(1000), |n| n.parse())?;
+    let path = flags.get("ratings").unwrap_or(&"ratings.json");
+    let mut ratings = load_ratings(path)?;
+    for outcome in tournament(left.as_mut(), right.as_mut(), games, pieces).outcomes {
+        ratings.record(left_spec, right_spec, outcome);
+    }
+    save_ratings(path, &ratings)?;
+    for (i, (player, rating)) in ratings.ranking().into_iter().enumerate() {
+        let (elo, games) = (rating.elo, rating.games);
+        println!("{:>4}. {:<20} {:.0} ({} games)", i + 1, player, elo, games);
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use crate::game::{Game, GameEvent};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fmt, fs, io, path::Path};
+
+// milestones that stay unlocked across games, kept in a file (achievements.toml for play):
+//
+//     unlocked = ["first_tetris", "hundred_lines"]
+//     total_lines = 132
+//
+// they are checked against the game's events after every frame, so the game has to be
+// subscribed to them (see Game::subscribe_events)
+
+// the sprint is 40 lines in the first minute of a game, at 60 frames a second
+const SPRINT_LINES: usize = 40;
+const SPRINT_FRAMES: usize = 60 * 60;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Achievement {
+    FirstTetris,  // 4 lines with one piece
+    TSpinDouble,  // 2 lines with a T rotated into place
+    HundredLines, // over all games
+    Sprint,       // 40 lines in the first minute of a game
+    Level15,      // reach it
+}
+
+impl Achievement {
+    pub const ALL: [Self; 5] = [
+        Self::FirstTetris,
+        Self::TSpinDouble,
+        Self::HundredLines,
+        Self::Sprint,
+        Self::Level15,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::FirstTetris => "First Tetris",
+            Self::TSpinDouble => "T-Spin Double",
+            Self::HundredLines => "100 Lines",
+            Self::Sprint => "Sub-Minute Sprint",
+            Self::Level15 => "Survived Level 15",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Achievements {
+    #[serde(default)]
+    unlocked: BTreeSet<Achievement>,
+    #[serde(default)]
+    total_lines: usize, // over every game played with this file
+}
+
+impl Achievements {
+    pub fn is_unlocked(&self, achievement: Achievement) -> bool {
+        self.unlocked.contains(&achievement)
+    }
+
+    // with the events of the game since the last call, returns the ones unlocked by them
+    pub fn update(&mut self, game: &Game, events: &[GameEvent]) -> Vec<Achievement> {
+        let mut reached = vec![];
+        for event in events {
+            match *event {
+                GameEvent::LinesCleared(n) => {
+                    self.total_lines += n;
+                    if n == 4 {
+                        reached.push(Achievement::FirstTetris);
+                    }
+                }
+                GameEvent::TSpin(2) => reached.push(Achievement::TSpinDouble),
+                _ => (),
+            }
+        }
+        if self.total_lines >= 100 {
+            reached.push(Achievement::HundredLines);
+        }
+        if game.cleared >= SPRINT_LINES && game.tick <= SPRINT_FRAMES {
+            reached.push(Achievement::Sprint);
+        }
+        if game.level >= 15 {
+            reached.push(Achievement::Level15);
+        }
+        reached.retain(|&achievement| self.unlocked.insert(achievement));
+        reached
+    }
+}
+
+#[derive(From, Debug)]
+pub enum AchievementsError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+    TomlWriteError(toml::ser::Error),
+}
+
+pub type AchievementsResult<T> = Result<T, AchievementsError>;
+
+impl fmt::Display for AchievementsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to access achievements: {}", e),
+            Self::TomlError(e) => write!(f, "invalid achievements: {}", e),
+            Self::TomlWriteError(e) => write!(f, "failed to write achievements: {}", e),
+        }
+    }
+}
+
+// no file yet is nothing unlocked yet
+pub fn load_achievements<P: AsRef<Path>>(path: P) -> AchievementsResult<Achievements> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(toml::from_str(&text)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Achievements::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// overwrites!
+pub fn save_achievements<P: AsRef<Path>>(
+    path: P,
+    achievements: &Achievements,
+) -> AchievementsResult<()> {
+    fs::write(path, toml::to_string(achievements)?)?;
+    Ok(())
+}
+
+#[test]
+fn test_achievements_unlock_once() {
+    let mut game = Game::with_seed(0);
+    let mut achievements = Achievements::default();
+    let events = [GameEvent::LinesCleared(4), GameEvent::TSpin(1)];
+    assert_eq!(
+        achievements.update(&game, &events),
+        vec![Achievement::FirstTetris]
+    );
+    assert!(achievements.update(&game, &events).is_empty());
+    game.level = 15;
+    let events = vec![GameEvent::LinesCleared(2); 48];
+    assert_eq!(
+        achievements.update(&game, &events),
+        vec![Achievement::HundredLines, Achievement::Level15]
+    );
+    let text = toml::to_string(&achievements).unwrap();
+    assert_eq!(toml::from_str::<Achievements>(&text).unwrap(), achievements);
+}
@@ -0,0 +1,62 @@
+// things that move over a few frames instead of jumping, each one is started at a tick and
+// looked up by what it animates, the game's tick is the clock so that pausing or freezing
+// the game stops them too
+#[derive(Clone, Debug)]
+pub struct Timeline<K> {
+    running: Vec<(K, usize, usize)>, // (what, start tick, frames)
+}
+
+impl<K> Default for Timeline<K> {
+    fn default() -> Self {
+        Self { running: vec![] }
+    }
+}
+
+impl<K: Copy + Eq> Timeline<K> {
+    // starts over if key is already running
+    pub fn start(&mut self, key: K, tick: usize, frames: usize) {
+        self.running.retain(|&(k, _, _)| k != key);
+        if frames > 0 {
+            self.running.push((key, tick, frames));
+        }
+    }
+
+    // from 0 when it started to 1 when it's done, eased out, None once it's done
+    pub fn progress(&self, key: K, tick: usize) -> Option<f32> {
+        let &(_, start, frames) = self.running.iter().find(|&&(k, _, _)| k == key)?;
+        let elapsed = tick.checked_sub(start)?;
+        if elapsed >= frames {
+            return None;
+        }
+        Some(ease_out(elapsed as f32 / frames as f32))
+    }
+
+    // forgets everything that is done by tick, or was started after it (a game swapped in
+    // from outside can be at an earlier tick)
+    pub fn prune(&mut self, tick: usize) {
+        self.running
+            .retain(|&(_, start, frames)| start <= tick && tick < start + frames);
+    }
+}
+
+// fast at first and slowing down to a stop
+pub fn ease_out(t: f32) -> f32 {
+    1. - (1. - t.clamp(0., 1.)).powi(3)
+}
+
+#[test]
+fn test_timeline_runs_for_its_frames() {
+    let mut timeline = Timeline::default();
+    timeline.start('a', 10, 4);
+    assert_eq!(timeline.progress('a', 9), None);
+    assert_eq!(timeline.progress('a', 10), Some(0.));
+    let halfway = timeline.progress('a', 12).unwrap();
+    assert!(0.5 < halfway && halfway < 1.);
+    assert_eq!(timeline.progress('a', 14), None);
+    assert_eq!(timeline.progress('b', 12), None);
+    // restarting replaces the running one
+    timeline.start('a', 13, 4);
+    assert_eq!(timeline.progress('a', 14).map(|p| p > 0.), Some(true));
+    timeline.prune(17);
+    assert!(timeline.running.is_empty());
+}
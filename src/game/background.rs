@@ -0,0 +1,48 @@
+use crate::game::render::{Color, Rect, Renderer};
+use rand::prelude::*;
+
+// stars drifting down behind the board in a few layers, the ones further back are smaller,
+// darker and slower, which makes them look further away, always the same sky
+pub struct Starfield {
+    stars: Vec<(f32, f32, usize)>, // (x, y) as fractions of the area and the layer
+}
+
+// (side in pixels, brightness, areas drifted per 1000 ticks), back to front
+const LAYERS: [(f32, f32, f32); 3] = [(1., 0.35, 0.05), (2., 0.6, 0.12), (3., 1., 0.25)];
+const STARS: usize = 150;
+const SEED: u64 = 7;
+
+impl Default for Starfield {
+    fn default() -> Self {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let stars = (0..STARS)
+            .map(|i| {
+                // more of them in the back
+                let layer = match i % 6 {
+                    0..=2 => 0,
+                    3 | 4 => 1,
+                    _ => 2,
+                };
+                (rng.gen(), rng.gen(), layer)
+            })
+            .collect();
+        Self { stars }
+    }
+}
+
+impl Starfield {
+    // as of tick, darkened by dim like the rest of the background
+    pub fn add(&self, area: Rect, tick: usize, dim: f32, r: &mut dyn Renderer) {
+        for &(x, y, layer) in &self.stars {
+            let (side, brightness, speed) = LAYERS[layer];
+            let y = (y + speed * tick as f32 / 1000.).fract();
+            let rect = Rect {
+                x: area.x + x * area.w,
+                y: area.y + y * area.h,
+                w: side,
+                h: side,
+            };
+            r.rect(rect, Color::WHITE.scaled(brightness * (1. - dim)));
+        }
+    }
+}
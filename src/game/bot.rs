@@ -0,0 +1,301 @@
+use crate::{
+    game::{
+        estimator::Estimator,
+        evaluation::Noise,
+        features::{BoardMetrics, InputEncoder},
+        heuristic::{BeamBot, HeuristicBot, LookaheadBot},
+        nn_trainer::choose_placement,
+        opening::BookBot,
+        placement::{placements, placements_with_hold, Placement},
+        tbp::{split_command, TbpBot, TbpError},
+        versus::Incoming,
+        Game, PieceId,
+    },
+    neural_network::{NNReadError, NN},
+};
+use std::{collections::HashMap, fmt, path::Path};
+
+// what a bot gets to see of the game it's playing
+pub struct BoardView<'a> {
+    pub(super) game: &'a Game,
+    incoming: Option<&'a Incoming>, // the garbage meter, only in versus
+}
+
+impl<'a> BoardView<'a> {
+    pub fn new(game: &'a Game) -> Self {
+        Self {
+            game,
+            incoming: None,
+        }
+    }
+
+    pub fn with_incoming(mut self, incoming: &'a Incoming) -> Self {
+        self.incoming = Some(incoming);
+        self
+    }
+
+    // garbage on its way to the bot, None outside versus
+    pub fn incoming(&self) -> Option<&'a Incoming> {
+        self.incoming
+    }
+
+    #[allow(dead_code)]
+    pub fn current(&self) -> Option<PieceId> {
+        self.game.falling.as_ref().map(|falling| falling.id)
+    }
+
+    #[allow(dead_code)]
+    pub fn hold(&self) -> Option<PieceId> {
+        self.game.hold
+    }
+
+    // placements that start with a hold are only allowed while this is true
+    #[allow(dead_code)]
+    pub fn can_hold(&self) -> bool {
+        self.game.can_hold()
+    }
+
+    #[allow(dead_code)]
+    pub fn queue(&self) -> impl Iterator<Item = PieceId> + 'a {
+        self.game.piece_queue.iter()
+    }
+
+    // deeper than the queue, for lookahead
+    #[allow(dead_code)]
+    pub fn peek(&self, n: usize) -> Vec<PieceId> {
+        self.game.piece_queue.peek(n)
+    }
+
+    #[allow(dead_code)]
+    pub fn remaining_bag(&self) -> Vec<PieceId> {
+        self.game.piece_queue.remaining_bag()
+    }
+
+    // column heights and holes
+    #[allow(dead_code)]
+    pub fn metrics(&self) -> &'a BoardMetrics {
+        self.game.metrics()
+    }
+
+    // x from the left, y from the top
+    #[allow(dead_code)]
+    pub fn is_empty(&self, x: usize, y: usize) -> bool {
+        self.game.board[y][x].is_empty()
+    }
+
+    // every placement of the current piece
+    #[allow(dead_code)]
+    pub fn placements(&self) -> Vec<Placement> {
+        placements(self.game)
+    }
+
+    // and of the held piece, with a hold first
+    #[allow(dead_code)]
+    pub fn placements_with_hold(&self) -> Vec<Placement> {
+        placements_with_hold(self.game)
+    }
+}
+
+pub enum BotMove {
+    Place(Placement), // its inputs may start with a hold
+    Pass,             // nothing to do or the bot has given up
+}
+
+impl BotMove {
+    pub fn placement(self) -> Option<Placement> {
+        match self {
+            Self::Place(placement) => Some(placement),
+            Self::Pass => None,
+        }
+    }
+}
+
+impl From<Option<Placement>> for BotMove {
+    fn from(placement: Option<Placement>) -> Self {
+        placement.map_or(Self::Pass, Self::Place)
+    }
+}
+
+// anything that can play a piece, used by every mode that lets a bot play
+pub trait TetrisBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove;
+}
+
+// in versus the heuristic bots weigh garbage in as well, see HeuristicBot::evaluate_versus
+impl TetrisBot for HeuristicBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        match view.incoming() {
+            Some(incoming) => self.choose_versus(view.game, incoming),
+            None => HeuristicBot::choose(self, view.game),
+        }
+        .into()
+    }
+}
+
+impl TetrisBot for LookaheadBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        match view.incoming() {
+            Some(incoming) => self.choose_versus(view.game, incoming),
+            None => LookaheadBot::choose(self, view.game),
+        }
+        .into()
+    }
+}
+
+impl TetrisBot for BeamBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        match view.incoming() {
+            Some(incoming) => self.choose_versus(view.game, incoming),
+            None => BeamBot::choose(self, view.game),
+        }
+        .into()
+    }
+}
+
+// plain functions and closures, mostly for tests
+impl<F: FnMut(&Game) -> Option<Placement>> TetrisBot for F {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        self(view.game).into()
+    }
+}
+
+// a network trained in placement mode
+pub struct NNBot {
+    nn: NN,
+    encoder: InputEncoder,
+}
+
+impl NNBot {
+    #[allow(dead_code)]
+    pub fn new(nn: NN, encoder: InputEncoder) -> Self {
+        Self { nn, encoder }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, encoder: InputEncoder) -> Result<Self, NNReadError> {
+        Ok(Self::new(NN::from_file(path)?, encoder))
+    }
+}
+
+impl TetrisBot for NNBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        choose_placement(&self.nn, self.encoder, &Noise::default(), view.game).into()
+    }
+}
+
+#[derive(From, Debug)]
+pub enum BotError {
+    NNReadError(NNReadError),
+    TbpError(TbpError),
+    UnknownBot(String),
+}
+
+pub type BotResult<T> = Result<T, BotError>;
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NNReadError(e) => write!(f, "{}", e),
+            Self::TbpError(e) => write!(f, "{}", e),
+            Self::UnknownBot(name) => write!(f, "unknown bot \"{}\"", name),
+        }
+    }
+}
+
+type BotMaker = Box<dyn Fn(&str) -> BotResult<Box<dyn TetrisBot>>>;
+
+// creates bots from specs like "heuristic" or "nn:data/best_nn.txt", everything after the
+// first ':' is passed to the maker registered under the name before it
+pub struct BotRegistry {
+    makers: HashMap<String, BotMaker>,
+}
+
+impl BotRegistry {
+    pub fn new() -> Self {
+        Self {
+            makers: HashMap::new(),
+        }
+    }
+
+    // heuristic, lookahead[:<pieces>], beam[:<pieces>], book[:<pieces>] (an opener, then the
+    // beam search), nn:<file> (with the given encoder), estimator:<file> and tbp:<command>
+    pub fn with_defaults(encoder: InputEncoder) -> Self {
+        let mut registry = Self::new();
+        registry.register("heuristic", |_| Ok(Box::new(HeuristicBot::new())));
+        registry.register("lookahead", |depth| {
+            let depth = match depth {
+                "" => LookaheadBot::DEPTH,
+                depth => depth
+                    .parse()
+                    .map_err(|_| BotError::UnknownBot(format!("lookahead:{}", depth)))?,
+            };
+            Ok(Box::new(LookaheadBot::new(depth)))
+        });
+        registry.register("beam", |depth| {
+            let depth = match depth {
+                "" => BeamBot::DEPTH,
+                depth => depth
+                    .parse()
+                    .map_err(|_| BotError::UnknownBot(format!("beam:{}", depth)))?,
+            };
+            Ok(Box::new(BeamBot::new(depth)))
+        });
+        registry.register("book", |depth| {
+            let depth = match depth {
+                "" => BeamBot::DEPTH,
+                depth => depth
+                    .parse()
+                    .map_err(|_| BotError::UnknownBot(format!("book:{}", depth)))?,
+            };
+            Ok(Box::new(BookBot::new(Box::new(BeamBot::new(depth)))))
+        });
+        registry.register("nn", move |path| Ok(Box::new(NNBot::load(path, encoder)?)));
+        registry.register("estimator", |path| Ok(Box::new(Estimator::load(path)?)));
+        registry.register("tbp", |command| {
+            Ok(Box::new(TbpBot::spawn(&split_command(command)?)?))
+        });
+        registry
+    }
+
+    pub fn register(
+        &mut self,
+        name: &str,
+        maker: impl Fn(&str) -> BotResult<Box<dyn TetrisBot>> + 'static,
+    ) {
+        self.makers.insert(name.to_string(), Box::new(maker));
+    }
+
+    pub fn create(&self, spec: &str) -> BotResult<Box<dyn TetrisBot>> {
+        let mut split = spec.splitn(2, ':');
+        let name = split.next().unwrap_or_default();
+        match self.makers.get(name) {
+            Some(maker) => maker(split.next().unwrap_or_default()),
+            None => Err(BotError::UnknownBot(name.to_string())),
+        }
+    }
+}
+
+impl Default for BotRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_registry_creates_registered_bots() {
+    let mut registry = BotRegistry::with_defaults(InputEncoder::Features);
+    registry.register("first", |_| {
+        Ok(Box::new(|game: &Game| placements(game).into_iter().next()))
+    });
+    let game = Game::new();
+    for spec in &[
+        "heuristic",
+        "lookahead:2",
+        "beam:2",
+        "book:2",
+        "first:ignored",
+    ] {
+        let mut bot = registry.create(spec).unwrap();
+        assert!(bot.choose(&BoardView::new(&game)).placement().is_some());
+    }
+    assert!(registry.create("missing").is_err());
+}
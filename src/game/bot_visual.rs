@@ -0,0 +1,81 @@
+use crate::{
+    game::{
+        bot::{BoardView, TetrisBot},
+        heuristic::HeuristicBot,
+        render::Command,
+        visual::VisGame,
+        Controller,
+    },
+    run_game_in,
+};
+use ggez::{
+    event::{EventHandler, KeyMods},
+    input::keyboard::KeyCode,
+    Context, GameResult,
+};
+
+// watch a bot play, the heuristic one by default
+pub struct BotVisGame {
+    vis: VisGame,
+    bot: Box<dyn TetrisBot>,
+}
+
+impl BotVisGame {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::watching(VisGame::new())
+    }
+
+    // the heuristic bot plays the given game
+    pub fn watching(vis: VisGame) -> Self {
+        Self::with_bot(vis, Box::new(HeuristicBot::new()))
+    }
+
+    pub fn with_bot(vis: VisGame, bot: Box<dyn TetrisBot>) -> Self {
+        Self { vis, bot }
+    }
+
+    #[allow(dead_code)]
+    pub fn run(&mut self) -> GameResult<()> {
+        let window_mode = self.vis.window_mode();
+        run_game_in(self, window_mode)
+    }
+}
+
+impl Default for BotVisGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventHandler for BotVisGame {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        // a new placement is chosen once the inputs of the previous one have been applied
+        if !self.vis.paused && !self.vis.has_queued_actions() {
+            let view = BoardView::new(&self.vis.game);
+            if let Some(placement) = self.bot.choose(&view).placement() {
+                for action in placement.inputs {
+                    self.vis.queue_action(action);
+                }
+            }
+        }
+        self.vis.update(ctx)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.vis.draw(ctx)
+    }
+
+    // only pause and orientation are left to the user
+    fn key_down_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods, repeat: bool) {
+        if !matches!(self.vis.command(code), Some(Command::Play(_))) {
+            self.vis.key_down_event(ctx, code, mods, repeat)
+        }
+    }
+
+    fn key_up_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods) {
+        if !matches!(self.vis.command(code), Some(Command::Play(_))) {
+            self.vis.key_up_event(ctx, code, mods)
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::cmp::Reverse;
+
+// a single elimination tournament between people taking turns at the same keyboard, see
+// bracket_visual, in a match both players play a game with the same pieces one after the
+// other and the one with more points goes on (the first one on a tie)
+//
+// when the number of players isn't a power of two the first ones get a bye, they go on to
+// the second round without playing
+
+#[derive(Clone, Debug)]
+struct Match {
+    players: (usize, Option<usize>), // None for a bye
+    points: Option<(usize, usize)>,  // once played
+}
+
+impl Match {
+    fn winner(&self) -> Option<usize> {
+        match (self.players, self.points) {
+            ((first, None), _) => Some(first),
+            ((first, Some(second)), Some((a, b))) => Some(if a >= b { first } else { second }),
+            (_, None) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bracket {
+    players: Vec<String>,
+    rounds: Vec<Vec<Match>>, // the first round first, the last one may not be decided yet
+}
+
+impl Bracket {
+    // needs at least two players
+    pub fn new(players: Vec<String>) -> Self {
+        assert!(players.len() >= 2, "a bracket needs at least two players");
+        let byes = players.len().next_power_of_two() - players.len();
+        let mut first_round = (0..byes)
+            .map(|i| Match {
+                players: (i, None),
+                points: None,
+            })
+            .collect::<Vec<_>>();
+        let mut rest = byes..players.len();
+        while let (Some(a), Some(b)) = (rest.next(), rest.next()) {
+            first_round.push(Match {
+                players: (a, Some(b)),
+                points: None,
+            });
+        }
+        Self {
+            players,
+            rounds: vec![first_round],
+        }
+    }
+
+    fn current(&self) -> Option<&Match> {
+        let round = self.rounds.last()?;
+        round.iter().find(|m| m.winner().is_none())
+    }
+
+    // the players of the next match to play, None once there's a champion
+    pub fn next_match(&self) -> Option<(&str, &str)> {
+        let m = self.current()?;
+        let second = m.players.1.expect("byes are decided from the start");
+        Some((&self.players[m.players.0], &self.players[second]))
+    }
+
+    // 1 for the first round, for announcing the next match
+    pub fn round(&self) -> usize {
+        self.rounds.len()
+    }
+
+    // the points of both players of the next match
+    pub fn record(&mut self, points: (usize, usize)) {
+        let round = self.rounds.last_mut().expect("there is always a round");
+        let m = round
+            .iter_mut()
+            .find(|m| m.winner().is_none())
+            .expect("no match left to play");
+        m.points = Some(points);
+        if round.iter().all(|m| m.winner().is_some()) && round.len() > 1 {
+            let winners = round.iter().filter_map(Match::winner).collect::<Vec<_>>();
+            let next = winners
+                .chunks(2)
+                .map(|pair| Match {
+                    players: (pair[0], Some(pair[1])),
+                    points: None,
+                })
+                .collect();
+            self.rounds.push(next);
+        }
+    }
+
+    fn champion_index(&self) -> Option<usize> {
+        match self.rounds.last()?.as_slice() {
+            [last] => last.winner(),
+            _ => None,
+        }
+    }
+
+    pub fn champion(&self) -> Option<&str> {
+        let winner = self.champion_index()?;
+        Some(&self.players[winner])
+    }
+
+    // (player, matches won, points over all games) best first, by how far they got (a bye
+    // counts) and then by points
+    pub fn standings(&self) -> Vec<(&str, usize, usize)> {
+        let mut stats = vec![(0, 0, 0); self.players.len()]; // (rounds reached, won, points)
+        for m in self.rounds.iter().flatten() {
+            let (first, second) = m.players;
+            stats[first].0 += 1;
+            if let Some(second) = second {
+                stats[second].0 += 1;
+            }
+            if let (Some(second), Some((a, b))) = (second, m.points) {
+                stats[first].2 += a;
+                stats[second].2 += b;
+                stats[m.winner().unwrap()].1 += 1;
+            }
+        }
+        if let Some(winner) = self.champion_index() {
+            stats[winner].0 += 1;
+        }
+        let mut standings = self.players.iter().zip(stats).collect::<Vec<_>>();
+        standings.sort_by_key(|&(_, (reached, _, points))| Reverse((reached, points)));
+        standings
+            .into_iter()
+            .map(|(name, (_, won, points))| (name.as_str(), won, points))
+            .collect()
+    }
+}
+
+#[test]
+fn test_bracket_with_a_bye() {
+    let players = ["ann", "bob", "cy"].iter().map(|s| s.to_string()).collect();
+    let mut bracket = Bracket::new(players);
+    assert_eq!(bracket.next_match(), Some(("bob", "cy")));
+    bracket.record((100, 300));
+    assert_eq!(
+        (bracket.round(), bracket.next_match()),
+        (2, Some(("ann", "cy")))
+    );
+    bracket.record((500, 500));
+    assert_eq!(bracket.next_match(), None);
+    assert_eq!(bracket.champion(), Some("ann"));
+    assert_eq!(
+        bracket.standings(),
+        vec![("ann", 1, 500), ("cy", 1, 800), ("bob", 0, 100)]
+    );
+}
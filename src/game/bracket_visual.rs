@@ -0,0 +1,125 @@
+use crate::{
+    game::{bracket::Bracket, visual::VisGame},
+    run_game_in,
+};
+use ggez::{
+    event::{Button, EventHandler, GamepadId, KeyMods},
+    input::keyboard::KeyCode,
+    Context, GameResult,
+};
+use std::mem;
+
+// plays a bracket in one window, every game starts paused so that the next player can sit
+// down first (and unpause), who is up next is printed in the terminal
+pub struct BracketVisGame {
+    vis: VisGame,
+    bracket: Bracket,
+    seed: u64,            // of the first match, the others count up from it
+    matches: usize,       // played so far
+    first: Option<usize>, // the points of the first player once they are done
+}
+
+impl BracketVisGame {
+    // the game's settings (hold, rotation, keys, ...) are kept for every game
+    pub fn new(vis: VisGame, bracket: Bracket, seed: u64) -> Self {
+        let mut bracket_vis = Self {
+            vis,
+            bracket,
+            seed,
+            matches: 0,
+            first: None,
+        };
+        bracket_vis.next_game();
+        bracket_vis
+    }
+
+    #[allow(dead_code)]
+    pub fn run(&mut self) -> GameResult<()> {
+        let window_mode = self.vis.window_mode();
+        run_game_in(self, window_mode)
+    }
+
+    // both players of a match get the same pieces, returns false once there's a champion
+    fn next_game(&mut self) -> bool {
+        let (first, second) = match self.bracket.next_match() {
+            Some(players) => players,
+            None => return false,
+        };
+        let player = match self.first {
+            None => {
+                println!("Round {}: {} vs {}", self.bracket.round(), first, second);
+                first
+            }
+            Some(_) => second,
+        };
+        println!("{}'s turn, unpause to start", player);
+        let seed = self.seed.wrapping_add(self.matches as u64);
+        let vis = mem::take(&mut self.vis);
+        self.vis = vis.seeded(seed);
+        self.vis.paused = true;
+        true
+    }
+
+    // returns false once there's a champion
+    fn game_over(&mut self) -> bool {
+        let points = self.vis.game.points;
+        match self.first.take() {
+            None => self.first = Some(points),
+            Some(first) => {
+                self.bracket.record((first, points));
+                self.matches += 1;
+            }
+        }
+        self.next_game()
+    }
+
+    fn print_standings(&self) {
+        println!("Standings:");
+        for (i, (player, won, points)) in self.bracket.standings().iter().enumerate() {
+            println!("{}. {} ({} won, {} points)", i + 1, player, won, points);
+        }
+    }
+}
+
+impl EventHandler for BracketVisGame {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        // before the game quits by itself
+        if self.vis.game.is_over() {
+            println!("{} points", self.vis.game.points);
+            if !self.game_over() {
+                self.print_standings();
+                ggez::event::quit(ctx);
+            }
+            return Ok(());
+        }
+        self.vis.update(ctx)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.vis.draw(ctx)
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods, repeat: bool) {
+        self.vis.key_down_event(ctx, code, mods, repeat)
+    }
+
+    fn key_up_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods) {
+        self.vis.key_up_event(ctx, code, mods)
+    }
+
+    fn focus_event(&mut self, ctx: &mut Context, gained: bool) {
+        self.vis.focus_event(ctx, gained)
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.vis.resize_event(ctx, width, height)
+    }
+
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, button: Button, id: GamepadId) {
+        self.vis.gamepad_button_down_event(ctx, button, id)
+    }
+
+    fn gamepad_button_up_event(&mut self, ctx: &mut Context, button: Button, id: GamepadId) {
+        self.vis.gamepad_button_up_event(ctx, button, id)
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{
+    game::{
+        bot::NNBot,
+        checkpoints::Checkpoint,
+        ggez_frontend::GgezRenderer,
+        nn_trainer::{ControlMode, TrainerConfig},
+        nn_visual::NNVisGame,
+        render::{Color, Rect, Renderer},
+        tournament::tournament,
+        trainer_visual::NNTrainer,
+        visual::VisGame,
+    },
+    run_game, HORIZONTAL_WINDOW_DIMS,
+};
+use ggez::{
+    event::{EventHandler, KeyMods},
+    graphics::{clear, present, BLACK},
+    input::keyboard::KeyCode,
+    Context, GameResult,
+};
+use std::{thread, time::Duration};
+
+// the checkpoints found by scan_checkpoints in a list, the one selected with the arrow keys
+// can be watched (enter), trained further (T, generations only, saved back into the same
+// file with ctrl while paused) or pitted against another (V on both, placement mode only)
+//
+// watching and training take over the window, when a watched game ends it's back to the
+// list, closing the window closes everything
+pub struct GenomeBrowser {
+    checkpoints: Vec<Checkpoint>,
+    configs: Vec<TrainerConfig>, // of every checkpoint
+    selected: usize,
+    opponent: Option<usize>, // picked with V, the next V plays it
+    status: String,          // the result of the last match, or why something didn't work
+    scene: Option<Box<dyn EventHandler>>,
+}
+
+const MATCHES: usize = 10;
+const MATCH_PIECES: usize = 500;
+const MARGIN: f32 = 20.;
+const ROW: f32 = 22.;
+const LIST_WAIT: Duration = Duration::from_millis(30);
+const SELECTED_COLOR: Color = Color::rgb(50, 50, 50);
+const OPPONENT_COLOR: Color = Color::rgb(22, 101, 167);
+const UNFIT_COLOR: Color = Color::rgb(110, 110, 110);
+
+impl GenomeBrowser {
+    // config is for the checkpoints without a config.toml of their own
+    pub fn new(checkpoints: Vec<Checkpoint>, config: TrainerConfig) -> Self {
+        let configs = checkpoints.iter().map(|c| c.config(&config)).collect();
+        Self {
+            checkpoints,
+            configs,
+            selected: 0,
+            opponent: None,
+            status: "enter: watch, V: versus, T: train, escape: quit".to_string(),
+            scene: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn run(&mut self) -> GameResult<()> {
+        run_game(self)
+    }
+
+    // the checkpoint and the config it was trained with, if it fits
+    fn chosen(&self, i: usize) -> Option<(&Checkpoint, TrainerConfig)> {
+        let (checkpoint, config) = (self.checkpoints.get(i)?, &self.configs[i]);
+        if checkpoint.fits(config) {
+            Some((checkpoint, config.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn watch(&mut self) {
+        match self.chosen(self.selected) {
+            Some((checkpoint, config)) => {
+                let nn = checkpoint.nn.clone();
+                let nn_vis = NNVisGame::with_nn(VisGame::new(), nn, config);
+                self.scene = Some(Box::new(nn_vis));
+            }
+            None => self.status = "doesn't fit the config".to_string(),
+        }
+    }
+
+    fn train(&mut self) {
+        let (checkpoint, config) = match self.chosen(self.selected) {
+            Some(chosen) if chosen.0.is_generation() => chosen,
+            Some(_) => {
+                self.status = "only a generation can be trained further".to_string();
+                return;
+            }
+            None => {
+                self.status = "doesn't fit the config".to_string();
+                return;
+            }
+        };
+        match NNTrainer::new(&checkpoint.path, config) {
+            Ok(trainer) => self.scene = Some(Box::new(trainer)),
+            Err(e) => self.status = format!("failed to load generation: {}", e),
+        }
+    }
+
+    // the first V picks the opponent, the second plays the matches, which blocks the window
+    // until they're done
+    fn versus(&mut self) {
+        let opponent = match self.opponent.take() {
+            Some(opponent) if opponent != self.selected => opponent,
+            _ => {
+                self.opponent = Some(self.selected);
+                self.status = "V on another one to play against it".to_string();
+                return;
+            }
+        };
+        let bot = |i| match self.chosen(i) {
+            Some((checkpoint, config)) if config.mode == ControlMode::Placement => {
+                Some(NNBot::new(checkpoint.nn.clone(), config.encoder))
+            }
+            _ => None,
+        };
+        let (mut left, mut right) = match (bot(opponent), bot(self.selected)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => {
+                self.status = "versus needs placement mode networks that fit".to_string();
+                return;
+            }
+        };
+        let (left_name, right_name) = (
+            &self.checkpoints[opponent].name,
+            &self.checkpoints[self.selected].name,
+        );
+        println!("{} vs {}", left_name, right_name);
+        let result = tournament(&mut left, &mut right, MATCHES, MATCH_PIECES);
+        self.status = format!(
+            "{} won {}, {} won {}, {} draws",
+            left_name, result.left_wins, right_name, result.right_wins, result.draws
+        );
+    }
+
+    fn add_list(&self, r: &mut dyn Renderer) {
+        let (width, height) = HORIZONTAL_WINDOW_DIMS;
+        let columns = [MARGIN, 560., 760., 860.];
+        let header = ["file", "architecture", "genomes", "fitness"];
+        for (&x, text) in columns.iter().zip(header.iter()) {
+            r.text((x, MARGIN), text, Color::WHITE);
+        }
+        // scrolled so that the selected row is visible
+        let visible = ((height - 3. * MARGIN - 2. * ROW) / ROW) as usize;
+        let first = self.selected.saturating_sub(visible.saturating_sub(1));
+        let rows = self
+            .checkpoints
+            .iter()
+            .zip(&self.configs)
+            .enumerate()
+            .skip(first)
+            .take(visible);
+        for (row, (i, (checkpoint, config))) in rows.enumerate() {
+            let y = MARGIN + (row + 1) as f32 * ROW;
+            if i == self.selected || Some(i) == self.opponent {
+                let rect = Rect {
+                    x: MARGIN / 2.,
+                    y: y - 2.,
+                    w: width - MARGIN,
+                    h: ROW,
+                };
+                let color = if i == self.selected {
+                    SELECTED_COLOR
+                } else {
+                    OPPONENT_COLOR
+                };
+                r.rect(rect, color);
+            }
+            let color = if checkpoint.fits(config) {
+                Color::WHITE
+            } else {
+                UNFIT_COLOR
+            };
+            let fitness = checkpoint
+                .fitness
+                .map_or("-".to_string(), |f| format!("{:.1}", f));
+            let genomes = checkpoint.genomes.to_string();
+            let texts = [
+                &checkpoint.name,
+                &checkpoint.architecture(),
+                &genomes,
+                &fitness,
+            ];
+            for (&x, text) in columns.iter().zip(texts.iter()) {
+                r.text((x, y), text, color);
+            }
+        }
+        if self.checkpoints.is_empty() {
+            r.text((MARGIN, MARGIN + ROW), "no checkpoints", UNFIT_COLOR);
+        }
+        r.text((MARGIN, height - MARGIN - ROW), &self.status, Color::WHITE);
+    }
+}
+
+impl EventHandler for GenomeBrowser {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        match &mut self.scene {
+            Some(scene) => {
+                scene.update(ctx)?;
+                // the game ended, back to the list instead of quitting
+                if !ctx.continuing {
+                    ctx.continuing = true;
+                    self.scene = None;
+                }
+            }
+            None => thread::sleep(LIST_WAIT),
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if let Some(scene) = &mut self.scene {
+            return scene.draw(ctx);
+        }
+        clear(ctx, BLACK);
+        let mut renderer = GgezRenderer::new();
+        self.add_list(&mut renderer);
+        renderer.finish(ctx)?;
+        present(ctx)
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods, repeat: bool) {
+        if let Some(scene) = &mut self.scene {
+            return scene.key_down_event(ctx, code, mods, repeat);
+        }
+        match code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down if self.selected + 1 < self.checkpoints.len() => self.selected += 1,
+            KeyCode::Return => self.watch(),
+            KeyCode::T => self.train(),
+            KeyCode::V => self.versus(),
+            KeyCode::Escape => ggez::event::quit(ctx),
+            _ => (),
+        }
+    }
+
+    fn key_up_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods) {
+        if let Some(scene) = &mut self.scene {
+            scene.key_up_event(ctx, code, mods)
+        }
+    }
+
+    fn focus_event(&mut self, ctx: &mut Context, gained: bool) {
+        if let Some(scene) = &mut self.scene {
+            scene.focus_event(ctx, gained)
+        }
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if let Some(scene) = &mut self.scene {
+            scene.resize_event(ctx, width, height)
+        }
+    }
+
+    fn quit_event(&mut self, ctx: &mut Context) -> bool {
+        match &mut self.scene {
+            Some(scene) => scene.quit_event(ctx),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    game::nn_trainer::TrainerConfig,
+    neural_network::{NNReadResult, NN},
+};
+use itertools::Itertools;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// a network or generation saved by training (see Population::checkpoint), tetris browse
+// lists every one in a directory
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub name: String,              // the path below the directory that was scanned
+    pub nn: NN,                    // the best genome, the first one of a generation
+    pub genomes: usize,            // 1 for best_nn.txt
+    pub generation: Option<usize>, // of a numbered copy
+    pub fitness: Option<f64>,      // the best one metrics.csv has for it
+}
+
+impl Checkpoint {
+    fn load(path: &Path, root: &Path, fitness: &[(usize, f64)]) -> NNReadResult<Self> {
+        let text = fs::read_to_string(path)?;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        // generation_<n>.txt is saved after the genomes of generation n - 1 were ranked
+        let generation = file_name
+            .strip_prefix("generation_")
+            .and_then(|rest| rest.strip_suffix(".txt"))
+            .and_then(|n| n.parse::<usize>().ok());
+        let fitness = match generation {
+            Some(n) => fitness
+                .iter()
+                .find(|&&(step, _)| step + 1 == n)
+                .map(|&(_, f)| f),
+            None => fitness.last().map(|&(_, f)| f),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            name: path
+                .strip_prefix(root)
+                .unwrap_or(path)
+                .display()
+                .to_string(),
+            nn: NN::from_string(text.split("--\n").next().unwrap_or_default())?,
+            genomes: text.split("--\n").count(),
+            generation,
+            fitness,
+        })
+    }
+
+    // a generation that training can continue from, not just its best genome
+    pub fn is_generation(&self) -> bool {
+        self.path
+            .file_name()
+            .is_some_and(|name| name != "best_nn.txt")
+    }
+
+    // the config.toml next to it (experiments write one for every run) or default
+    pub fn config(&self, default: &TrainerConfig) -> TrainerConfig {
+        self.path
+            .parent()
+            .map(|dir| dir.join("config.toml"))
+            .filter(|path| path.exists())
+            .and_then(|path| TrainerConfig::from_file(path).ok())
+            .unwrap_or_else(|| default.clone())
+    }
+
+    // whether it was trained with the encoder and mode of config
+    pub fn fits(&self, config: &TrainerConfig) -> bool {
+        self.nn.input_size() == config.encoder.input_size()
+            && self.nn.output_size() == config.mode.output_size()
+    }
+
+    // e.g. "200-20-10-1"
+    pub fn architecture(&self) -> String {
+        self.nn.shape().iter().join("-")
+    }
+}
+
+// (generation, best fitness) of every line of <dir>/metrics.csv, none without one
+fn recorded_fitness(dir: &Path) -> Vec<(usize, f64)> {
+    let csv = match fs::read_to_string(dir.join("metrics.csv")) {
+        Ok(csv) => csv,
+        Err(_) => return vec![],
+    };
+    let mut lines = csv.lines();
+    let column = match lines
+        .next()
+        .and_then(|header| header.split(',').position(|name| name == "fitness/best"))
+    {
+        Some(column) => column,
+        None => return vec![],
+    };
+    lines
+        .filter_map(|line| {
+            let fields = line.split(',').collect::<Vec<_>>();
+            let step = fields.first()?.parse().ok()?;
+            Some((step, fields.get(column)?.parse().ok()?))
+        })
+        .collect()
+}
+
+// every best_nn.txt and generation file below dir sorted by path, the parents kept in
+// genomes/ are left out, files that can't be read are reported and skipped
+pub fn scan_checkpoints(dir: &Path) -> io::Result<Vec<Checkpoint>> {
+    let mut checkpoints = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let fitness = recorded_fitness(&current);
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.is_dir() {
+                if name != "genomes" {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            let saved =
+                name == "best_nn.txt" || (name.starts_with("generation") && name.ends_with(".txt"));
+            if !saved {
+                continue;
+            }
+            match Checkpoint::load(&path, dir, &fitness) {
+                Ok(checkpoint) => checkpoints.push(checkpoint),
+                Err(e) => eprintln!("Warning: failed to read \"{}\": {:?}", path.display(), e),
+            }
+        }
+    }
+    checkpoints.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(checkpoints)
+}
+
+#[test]
+fn test_scan_checkpoints() {
+    use crate::neural_network::ActivationType;
+    let dir = crate::game::temp_dir::TempDir::new("checkpoints");
+    let run = dir.join("run_0");
+    fs::create_dir_all(run.join("genomes")).unwrap();
+    let nn = || {
+        NN::make(4)
+            .add_layer(3, ActivationType::Relu)
+            .add_layer(1, ActivationType::Sigmoid)
+            .build()
+            .unwrap()
+    };
+    nn().to_file(run.join("best_nn.txt")).unwrap();
+    nn().to_file(run.join("genomes").join("0.txt")).unwrap();
+    let generation = [nn().to_string(), nn().to_string()].join("--\n");
+    fs::write(run.join("generation.txt"), &generation).unwrap();
+    fs::write(run.join("generation_2.txt"), &generation).unwrap();
+    let csv = "step,fitness/best,fitness/mean\n0,10,5\n1,20,8\n2,30,9\n";
+    fs::write(run.join("metrics.csv"), csv).unwrap();
+    fs::write(run.join("notes.txt"), "not a network").unwrap();
+
+    let checkpoints = scan_checkpoints(&dir).unwrap();
+    let names = checkpoints
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect::<Vec<_>>();
+    let expected = ["best_nn.txt", "generation.txt", "generation_2.txt"]
+        .iter()
+        .map(|name| Path::new("run_0").join(name).display().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, expected);
+    assert_eq!(checkpoints[0].architecture(), "4-3-1");
+    assert_eq!(checkpoints[0].genomes, 1);
+    assert!(!checkpoints[0].is_generation());
+    assert_eq!(checkpoints[1].genomes, 2);
+    // the latest generation has the last fitness, a numbered one that of the generation
+    // it was bred from
+    assert_eq!(checkpoints[1].fitness, Some(30.));
+    assert_eq!(checkpoints[2].generation, Some(2));
+    assert_eq!(checkpoints[2].fitness, Some(20.));
+}
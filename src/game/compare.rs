@@ -0,0 +1,142 @@
+use crate::game::{
+    bot::{BoardView, TetrisBot},
+    replay::LockRecord,
+    Controller, Game, PieceId,
+};
+
+// two bots playing the same pieces side by side (see compare_visual), a side that locked
+// more pieces than the other waits for it so that both place the same piece at the same time
+// and the pieces where their placements differ can be pointed out
+pub struct Comparison {
+    pub(super) games: [Game; 2],
+    pub names: [String; 2],
+    bots: [Box<dyn TetrisBot>; 2],
+    gave_up: [bool; 2], // the bot had nothing to place
+    max_pieces: usize,
+}
+
+// the piece and the cells it was locked on
+type Placed = (PieceId, Vec<(isize, isize)>);
+
+impl Comparison {
+    pub fn new(seed: u64, bots: [(String, Box<dyn TetrisBot>); 2], max_pieces: usize) -> Self {
+        let [(left_name, left), (right_name, right)] = bots;
+        let new_game = || {
+            let mut game = Game::with_seed(seed);
+            game.start_recording();
+            game
+        };
+        Self {
+            games: [new_game(), new_game()],
+            names: [left_name, right_name],
+            bots: [left, right],
+            gave_up: [false; 2],
+            max_pieces,
+        }
+    }
+
+    pub(super) fn is_finished(&self, side: usize) -> bool {
+        let game = &self.games[side];
+        game.is_over() || self.gave_up[side] || game.pieces >= self.max_pieces
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.is_finished(0) && self.is_finished(1)
+    }
+
+    // one frame of both games
+    pub fn step(&mut self) {
+        for side in 0..2 {
+            let other = 1 - side;
+            let ahead = self.games[side].pieces > self.games[other].pieces;
+            if self.is_finished(side) || (ahead && !self.is_finished(other)) {
+                continue;
+            }
+            let game = &mut self.games[side];
+            if !game.has_queued_actions() {
+                match self.bots[side].choose(&BoardView::new(game)).placement() {
+                    Some(placement) => placement
+                        .inputs
+                        .into_iter()
+                        .for_each(|a| game.queue_action(a)),
+                    None => {
+                        self.gave_up[side] = true;
+                        continue;
+                    }
+                }
+            }
+            game.iterate();
+        }
+    }
+
+    fn placed(game: &Game, record: &LockRecord) -> Placed {
+        let mask = &game.mask_map[&record.id][record.mask_idx];
+        let mut cells = mask.cells_at(record.pos).collect::<Vec<_>>();
+        cells.sort_unstable();
+        (record.id, cells)
+    }
+
+    // the nth piece each side locked, if both have
+    pub(super) fn placements(&self, n: usize) -> Option<[Placed; 2]> {
+        let placed = |game: &Game| {
+            let record = game.recording()?.get(n)?;
+            Some(Self::placed(game, record))
+        };
+        Some([placed(&self.games[0])?, placed(&self.games[1])?])
+    }
+
+    // the pieces (counted from 0) the two placed differently, as far as both got
+    pub fn divergences(&self) -> Vec<usize> {
+        let both = self.games[0].pieces.min(self.games[1].pieces);
+        (0..both)
+            .filter(|&n| match self.placements(n) {
+                Some([left, right]) => left != right,
+                None => false,
+            })
+            .collect()
+    }
+
+    // e.g. "3 placements differ, the first one at piece 12"
+    pub fn divergence_summary(&self) -> String {
+        let divergences = self.divergences();
+        match divergences.first() {
+            Some(first) => format!(
+                "{} placements differ, the first one at piece {}",
+                divergences.len(),
+                first
+            ),
+            None => "every placement so far is the same".to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_comparison_finds_divergences() {
+    use crate::game::{heuristic::HeuristicBot, placement::placements};
+    let heuristic = || Box::new(HeuristicBot::new()) as Box<dyn TetrisBot>;
+    let mut same = Comparison::new(
+        0,
+        [("a".into(), heuristic()), ("b".into(), heuristic())],
+        20,
+    );
+    while !same.is_over() {
+        same.step();
+        // neither side gets more than a piece ahead
+        let pieces = same.games.iter().map(|g| g.pieces).collect::<Vec<_>>();
+        assert!(pieces[0].max(pieces[1]) - pieces[0].min(pieces[1]) <= 1);
+    }
+    assert_eq!(same.games[0].pieces, 20);
+    assert!(same.divergences().is_empty());
+
+    // always the last placement instead of the best one
+    let last = |game: &Game| placements(game).pop();
+    let mut different = Comparison::new(
+        0,
+        [("a".into(), heuristic()), ("b".into(), Box::new(last))],
+        20,
+    );
+    while !different.is_over() {
+        different.step();
+    }
+    assert!(!different.divergences().is_empty());
+}
@@ -0,0 +1,135 @@
+use crate::{
+    game::{
+        compare::Comparison,
+        ggez_frontend::GgezRenderer,
+        render::{Color, MultiBoard, Rect, Renderer},
+    },
+    run_game, HORIZONTAL_WINDOW_DIMS,
+};
+use ggez::{
+    event::{EventHandler, KeyMods},
+    graphics::{clear, present, BLACK},
+    input::keyboard::KeyCode,
+    Context, GameResult,
+};
+use std::{thread, time::Duration};
+
+// a Comparison with both boards side by side, when the two placed the latest piece
+// differently their placements are outlined, each on its own board in yellow and the
+// other's in grey, escape pauses
+pub struct CompareVisGame {
+    comparison: Comparison,
+    paused: bool,
+    reported: bool, // the result was printed
+}
+
+const MARGIN: f32 = 20.;
+const TEXT_HEIGHT: f32 = 24.;
+const FRAME: Duration = Duration::from_millis(16);
+const OWN_COLOR: Color = Color::rgb(235, 190, 40);
+const OTHER_COLOR: Color = Color::rgb(150, 150, 150);
+
+impl CompareVisGame {
+    pub fn new(comparison: Comparison) -> Self {
+        Self {
+            comparison,
+            paused: false,
+            reported: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn run(&mut self) -> GameResult<()> {
+        run_game(self)
+    }
+
+    fn report(&self) {
+        for (name, game) in self.comparison.names.iter().zip(&self.comparison.games) {
+            println!("{}: {}", name, game.summary());
+        }
+        println!("{}", self.comparison.divergence_summary());
+    }
+
+    fn add_boards(&self, r: &mut dyn Renderer) {
+        let (width, height) = HORIZONTAL_WINDOW_DIMS;
+        let area = Rect {
+            x: MARGIN,
+            y: MARGIN,
+            w: width - 2. * MARGIN,
+            h: height - 2. * MARGIN - TEXT_HEIGHT,
+        };
+        // the name and the score below each board
+        let grid = MultiBoard::with_columns(2, 2, area, 2. * TEXT_HEIGHT);
+        let cell_side = grid.cell_side();
+        // the latest piece both have locked
+        let latest = self.comparison.games.iter().map(|g| g.pieces).min();
+        let placements = latest
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|n| self.comparison.placements(n))
+            .filter(|[left, right]| left != right);
+        for side in 0..2 {
+            let game = &self.comparison.games[side];
+            let board = grid.add(side, game, 1., r);
+            let (left, top, bottom) = (board.x, board.y, board.y + board.h);
+            let name = &self.comparison.names[side];
+            r.text((left, bottom + 5.), name, Color::WHITE);
+            if let Some(placements) = &placements {
+                let (other, own) = (&placements[1 - side].1, &placements[side].1);
+                for (cells, color) in [(other, OTHER_COLOR), (own, OWN_COLOR)].iter() {
+                    for &(x, y) in cells.iter() {
+                        let (x, y) = (left + x as f32 * cell_side, top + y as f32 * cell_side);
+                        let (x2, y2) = (x + cell_side, y + cell_side);
+                        let outline = [(x, y), (x2, y), (x2, y2), (x, y2), (x, y)];
+                        r.line(&outline, 2., *color);
+                    }
+                }
+            }
+            let mut status = format!(
+                "{} points, {} lines, {} pieces",
+                game.points, game.cleared, game.pieces
+            );
+            if self.comparison.is_finished(side) {
+                status += ", done";
+            }
+            r.text((left, bottom + 5. + TEXT_HEIGHT), &status, Color::WHITE);
+        }
+        let mut summary = self.comparison.divergence_summary();
+        if self.paused {
+            summary += ", paused";
+        }
+        r.text(
+            (MARGIN, height - MARGIN - TEXT_HEIGHT),
+            &summary,
+            Color::WHITE,
+        );
+    }
+}
+
+impl EventHandler for CompareVisGame {
+    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+        thread::sleep(FRAME);
+        if self.comparison.is_over() {
+            if !self.reported {
+                self.report();
+                self.reported = true;
+            }
+        } else if !self.paused {
+            self.comparison.step();
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        clear(ctx, BLACK);
+        let mut renderer = GgezRenderer::new();
+        self.add_boards(&mut renderer);
+        renderer.finish(ctx)?;
+        present(ctx)
+    }
+
+    fn key_down_event(&mut self, _ctx: &mut Context, code: KeyCode, _mods: KeyMods, _: bool) {
+        if code == KeyCode::Escape {
+            self.paused = !self.paused;
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::game::hash::fnv1a;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the daily challenge, everyone who plays on the same (UTC) day gets the same pieces since
+// the seed only depends on the date, the best result of every day is kept with the other
+// best scores under its own mode ("daily 2024-05-01, classic")
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// today in UTC as yyyy-mm-dd
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    date_from_days(secs / SECONDS_PER_DAY)
+}
+
+// yyyy-mm-dd of a day counted from 1970-01-01, after Howard Hinnant's civil_from_days
+fn date_from_days(days: u64) -> String {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// the same on every machine (unlike DefaultHasher)
+pub fn daily_seed(date: &str) -> u64 {
+    fnv1a(date.as_bytes())
+}
+
+#[test]
+fn test_daily_dates_and_seeds() {
+    assert_eq!(date_from_days(0), "1970-01-01");
+    assert_eq!(date_from_days(11_016), "2000-02-29");
+    assert_eq!(date_from_days(19_844), "2024-05-01");
+    assert_eq!(daily_seed("2024-05-01"), daily_seed("2024-05-01"));
+    assert_ne!(daily_seed("2024-05-01"), daily_seed("2024-05-02"));
+}
@@ -0,0 +1,191 @@
+use ggez::{
+    graphics::{self, set_canvas, use_shader, Canvas, DrawParam, Shader},
+    Context, GameResult,
+};
+
+// a pass over the finished frame: the window is drawn to a canvas first and the canvas is
+// then drawn through a shader, which glows after line clears, draws scanlines like an old
+// screen and turns grey while paused, each one can be turned on by itself (--effects)
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Effects {
+    pub bloom: bool,     // bright colors bleed into their surroundings for a moment
+    pub scanlines: bool, // every third row darker and the corners too, for the retro look
+    pub grayscale: bool, // while paused and fading back during the countdown
+}
+
+impl Effects {
+    // comma separated, "none" for none
+    pub fn from_names(names: &str) -> Option<Self> {
+        let mut effects = Self::default();
+        for name in names.split(',').map(str::trim) {
+            match name {
+                "bloom" => effects.bloom = true,
+                "crt" => effects.scanlines = true,
+                "grayscale" => effects.grayscale = true,
+                "none" => (),
+                _ => return None,
+            }
+        }
+        Some(effects)
+    }
+
+    pub fn any(self) -> bool {
+        self.bloom || self.scanlines || self.grayscale
+    }
+}
+
+gfx_defines! {
+    constant EffectConsts {
+        bloom: f32 = "u_Bloom",         // from 0 to 1
+        scanlines: f32 = "u_Scanlines", // 0 or 1
+        grayscale: f32 = "u_Grayscale", // from 0 to 1
+        texel_x: f32 = "u_TexelX",      // the size of a pixel of the canvas in uv
+        texel_y: f32 = "u_TexelY",
+    }
+}
+
+const VERTEX_SHADER: &[u8] = b"#version 150 core
+
+in vec2 a_Pos;
+in vec2 a_Uv;
+in vec4 a_VertColor;
+
+in vec4 a_Src;
+in vec4 a_TCol1;
+in vec4 a_TCol2;
+in vec4 a_TCol3;
+in vec4 a_TCol4;
+in vec4 a_Color;
+
+layout (std140) uniform Globals {
+    mat4 u_MVP;
+};
+
+out vec2 v_Uv;
+out vec4 v_Color;
+
+void main() {
+    v_Uv = a_Uv * a_Src.zw + a_Src.xy;
+    v_Color = a_Color * a_VertColor;
+    mat4 instance_transform = mat4(a_TCol1, a_TCol2, a_TCol3, a_TCol4);
+    gl_Position = u_MVP * instance_transform * vec4(a_Pos, 0.0, 1.0);
+}
+";
+
+const PIXEL_SHADER: &[u8] = b"#version 150 core
+
+uniform sampler2D t_Texture;
+in vec2 v_Uv;
+in vec4 v_Color;
+out vec4 Target0;
+
+layout (std140) uniform Globals {
+    mat4 u_MVP;
+};
+
+layout (std140) uniform Effects {
+    float u_Bloom;
+    float u_Scanlines;
+    float u_Grayscale;
+    float u_TexelX;
+    float u_TexelY;
+};
+
+void main() {
+    vec4 color = texture(t_Texture, v_Uv) * v_Color;
+    if (u_Bloom > 0.0) {
+        // what is brighter than a threshold, blurred over a few pixels
+        vec3 glow = vec3(0.0);
+        for (int dx = -3; dx <= 3; dx++) {
+            for (int dy = -3; dy <= 3; dy++) {
+                vec2 offset = vec2(float(dx) * u_TexelX, float(dy) * u_TexelY) * 2.0;
+                vec3 near = texture(t_Texture, v_Uv + offset).rgb;
+                glow += max(near - vec3(0.5), vec3(0.0));
+            }
+        }
+        color.rgb += glow / 49.0 * 3.0 * u_Bloom;
+    }
+    if (u_Scanlines > 0.0) {
+        if (mod(gl_FragCoord.y, 3.0) < 1.0) {
+            color.rgb *= 0.7;
+        }
+        vec2 centered = v_Uv * 2.0 - 1.0;
+        color.rgb *= 1.0 - 0.25 * dot(centered, centered) * 0.5;
+    }
+    float grey = dot(color.rgb, vec3(0.299, 0.587, 0.114));
+    color.rgb = mix(color.rgb, vec3(grey), u_Grayscale);
+    Target0 = color;
+}
+";
+
+pub(super) struct PostProcess {
+    effects: Effects,
+    shader: Shader<EffectConsts>,
+    canvas: Canvas,
+}
+
+impl PostProcess {
+    pub(super) fn new(ctx: &mut Context, effects: Effects) -> GameResult<Self> {
+        let consts = EffectConsts {
+            bloom: 0.,
+            scanlines: 0.,
+            grayscale: 0.,
+            texel_x: 0.,
+            texel_y: 0.,
+        };
+        let shader = Shader::from_u8(ctx, VERTEX_SHADER, PIXEL_SHADER, consts, "Effects", None)?;
+        let canvas = Canvas::with_window_size(ctx)?;
+        Ok(Self {
+            effects,
+            shader,
+            canvas,
+        })
+    }
+
+    // everything drawn until finish goes to the canvas, which follows the window's size
+    pub(super) fn begin(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let (width, height) = graphics::drawable_size(ctx);
+        let image = self.canvas.image();
+        if (image.width(), image.height()) != (width as u16, height as u16) {
+            self.canvas = Canvas::with_window_size(ctx)?;
+        }
+        set_canvas(ctx, Some(&self.canvas));
+        Ok(())
+    }
+
+    // draws the canvas to the window through the shader, stretched over dims (the screen
+    // coordinates), bloom and grayscale are how strong they are this frame, from 0 to 1,
+    // and left out if not enabled
+    pub(super) fn finish(
+        &mut self,
+        ctx: &mut Context,
+        dims: (f32, f32),
+        bloom: f32,
+        grayscale: f32,
+    ) -> GameResult<()> {
+        set_canvas(ctx, None);
+        let image = self.canvas.image();
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let enabled = |on: bool, strength: f32| if on { strength } else { 0. };
+        let consts = EffectConsts {
+            bloom: enabled(self.effects.bloom, bloom),
+            scanlines: enabled(self.effects.scanlines, 1.),
+            grayscale: enabled(self.effects.grayscale, grayscale),
+            texel_x: 1. / width,
+            texel_y: 1. / height,
+        };
+        let _lock = use_shader(ctx, &self.shader);
+        self.shader.send(ctx, consts)?;
+        let scale = [dims.0 / width, dims.1 / height];
+        graphics::draw(ctx, &self.canvas, DrawParam::default().scale(scale))
+    }
+}
+
+#[test]
+fn test_effects_from_names() {
+    let effects = Effects::from_names("bloom, crt").unwrap();
+    assert!(effects.bloom && effects.scanlines && !effects.grayscale);
+    assert!(!Effects::from_names("none").unwrap().any());
+    assert_eq!(Effects::from_names("blur"), None);
+}
@@ -0,0 +1,153 @@
+use crate::{
+    game::{
+        bot::{BoardView, BotMove, TetrisBot},
+        features::InputEncoder,
+        heuristic::HeuristicBot,
+        placement::{best_placement_batch, placements},
+        Board, Game, PieceId,
+    },
+    neural_network::{ActivationType, NNReadError, NN},
+};
+use rand::prelude::*;
+use std::path::Path;
+
+// a small network on the side that looks at a board and guesses the chance that the game is
+// still going HORIZON pieces later, shown as a gauge by play and watch --estimator <file> and
+// usable as a bot (estimator:<file>) that goes wherever the chance is highest
+//
+// it is trained on games of the heuristic bot that every so often places a piece at random,
+// more often in some games than in others, so that there are boards from which the game is
+// lost soon as well as ones from which it never is
+pub const HORIZON: usize = 50;
+const ENCODER: InputEncoder = InputEncoder::Features;
+const MAX_CHAOS: f64 = 0.5; // the largest share of random pieces in a training game
+
+pub struct Estimator {
+    nn: NN,
+}
+
+impl Estimator {
+    pub fn new(nn: NN) -> Self {
+        Self { nn }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, NNReadError> {
+        Ok(Self::new(NN::from_file(path)?))
+    }
+
+    // between 0 and 1, the current and next piece are those that are to be placed on board
+    fn of_board(&self, board: &Board, current: Option<PieceId>, next: Option<PieceId>) -> f64 {
+        self.nn.apply(&ENCODER.encode_board(board, current, next))[0]
+    }
+
+    // the chance of surviving another HORIZON pieces from the game as it is
+    pub fn estimate(&self, game: &Game) -> f64 {
+        let current = game.falling.as_ref().map(|f| f.id);
+        self.of_board(&game.board, current, game.piece_queue.iter().next())
+    }
+}
+
+impl TetrisBot for Estimator {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        let mut queue = view.queue();
+        // after the placement, the next piece becomes the current one
+        let (current, next) = (queue.next(), queue.next());
+        best_placement_batch(view.game, |boards| {
+            boards
+                .iter()
+                .map(|board| self.of_board(board, current, next))
+                .collect()
+        })
+        .into()
+    }
+}
+
+// (encoded board, 1 if the game lasted another HORIZON pieces) for every piece of a game that
+// randomly places a chaos share of its pieces
+fn play_labelled(seed: u64, chaos: f64, max_pieces: usize) -> Vec<(Box<[f64]>, f64)> {
+    let bot = HeuristicBot::new();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = Game::with_seed(seed);
+    let mut boards = vec![];
+    while !game.is_over() && boards.len() < max_pieces {
+        let current = game.falling.as_ref().map(|f| f.id);
+        let next = game.piece_queue.iter().next();
+        boards.push(ENCODER.encode_board(&game.board, current, next));
+        let placement = if rng.gen_bool(chaos) {
+            placements(&game).choose(&mut rng).cloned()
+        } else {
+            bot.choose(&game)
+        };
+        match placement {
+            Some(placement) => placement.inputs.into_iter().for_each(|a| game.apply(a)),
+            None => break,
+        }
+    }
+    // a game that wasn't lost counts as going on forever
+    let lost_at = if game.is_over() {
+        boards.len()
+    } else {
+        usize::MAX
+    };
+    boards
+        .into_iter()
+        .enumerate()
+        .map(|(i, board)| {
+            let label = if lost_at - i > HORIZON { 1. } else { 0. };
+            (board, label)
+        })
+        .collect()
+}
+
+// plays games with seeds 0..games of at most max_pieces each and fits a new network to them
+pub fn train_estimator(games: usize, max_pieces: usize, epochs: usize) -> NN {
+    let mut dataset = (0..games as u64)
+        .flat_map(|seed| {
+            let chaos = MAX_CHAOS * seed as f64 / games.max(2).saturating_sub(1) as f64;
+            play_labelled(seed, chaos, max_pieces)
+        })
+        .collect::<Vec<_>>();
+    let lost = dataset.iter().filter(|(_, label)| *label == 0.).count();
+    println!(
+        "{} boards, {} of them lost within {} pieces",
+        dataset.len(),
+        lost,
+        HORIZON
+    );
+    let mut nn = NN::make(ENCODER.input_size())
+        .init_range(-0.1, 0.1)
+        .add_layer(16, ActivationType::Relu)
+        .add_layer(1, ActivationType::Sigmoid)
+        .build()
+        .expect("network has layers");
+    let mut rng = thread_rng();
+    for epoch in 0..epochs {
+        dataset.shuffle(&mut rng);
+        let loss = dataset
+            .iter()
+            .map(|(input, label)| nn.train(input, &[*label], 0.01))
+            .sum::<f64>()
+            / dataset.len().max(1) as f64;
+        println!("epoch {}: loss {:.5}", epoch, loss);
+    }
+    nn
+}
+
+#[test]
+fn test_estimator_labels_and_estimates() {
+    // a game of random pieces is lost, and the boards right before that are labelled so
+    let labelled = play_labelled(0, 1., 1000);
+    assert!(labelled.len() < 1000);
+    assert!(labelled.iter().rev().take(HORIZON).all(|(_, l)| *l == 0.));
+    // the heuristic alone survives every board of a short game
+    assert!(play_labelled(0, 0., 30).iter().all(|(_, l)| *l == 1.));
+
+    let mut estimator = Estimator::new(train_estimator(2, 100, 1));
+    let game = Game::new();
+    let estimate = estimator.estimate(&game);
+    assert!((0. ..=1.).contains(&estimate));
+    assert!(estimator
+        .choose(&BoardView::new(&game))
+        .placement()
+        .is_some());
+}
@@ -0,0 +1,279 @@
+use crate::{
+    game::{
+        nn_trainer::{step, TrainerConfig},
+        Board, Game, GameEvent, Pixel, GAME_WIDTH,
+    },
+    neural_network::NN,
+};
+use rand::prelude::*;
+use serde::Deserialize;
+
+// cutoffs that end an evaluation game early, None means no limit
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct EvalLimits {
+    pub max_frames: Option<usize>,
+    // genomes that never clear a line are stopped before they top out
+    pub max_pieces_without_clear: Option<usize>,
+    // in rows from the ground, to stop genomes that just stack straight up
+    pub max_stack_height: Option<usize>,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            max_frames: Some(60 * 60 * 5),
+            max_pieces_without_clear: Some(50),
+            max_stack_height: None,
+        }
+    }
+}
+
+impl EvalLimits {
+    #[allow(dead_code)]
+    pub fn none() -> Self {
+        Self {
+            max_frames: None,
+            max_pieces_without_clear: None,
+            max_stack_height: None,
+        }
+    }
+
+    pub fn exceeded(&self, game: &Game) -> bool {
+        self.max_frames.is_some_and(|l| game.tick > l)
+            || self
+                .max_pieces_without_clear
+                .is_some_and(|l| game.pieces - game.pieces_at_last_clear > l)
+            || self
+                .max_stack_height
+                .is_some_and(|l| game.metrics().max_height() > l)
+    }
+}
+
+// fitness as a weighted sum of the final score and what happened during the game,
+// e.g. a negative hole_created weight punishes sloppy stacking even in games that
+// end with a good score
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FitnessWeights {
+    pub points: f64,
+    pub line: f64,
+    pub hole_created: f64,
+    pub piece: f64, // for surviving long enough to lock it
+    pub game_over: f64,
+}
+
+impl Default for FitnessWeights {
+    // just the score
+    fn default() -> Self {
+        Self {
+            points: 1.,
+            line: 0.,
+            hole_created: 0.,
+            piece: 0.,
+            game_over: 0.,
+        }
+    }
+}
+
+impl FitnessWeights {
+    pub fn fitness(&self, points: usize, events: &[GameEvent]) -> f64 {
+        let events = events
+            .iter()
+            .map(|event| match *event {
+                GameEvent::PieceLocked(_) => self.piece,
+                GameEvent::LinesCleared(n) => self.line * n as f64,
+                GameEvent::HolesCreated(n) => self.hole_created * n as f64,
+                GameEvent::GameOver => self.game_over,
+                // already rewarded through the points
+                GameEvent::LevelUp(_) | GameEvent::TSpin(_) => 0.,
+            })
+            .sum::<f64>();
+        self.points * points as f64 + events
+    }
+}
+
+// perturbations of games played by networks, to measure and select for policies that
+// don't depend on a clean board, everything is off by default
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Noise {
+    // chance of each cell being flipped every time the network looks at the board
+    pub cell_flip_chance: f64,
+    // chance of a row of garbage (with a random hole) being added after each locked piece
+    pub garbage_chance: f64,
+}
+
+impl Noise {
+    // what the network gets to see instead of the board, rng is the game's noise_rng so
+    // that the same seed always gives the same noise
+    pub(super) fn perturb(&self, board: &Board, rng: &mut StdRng) -> Board {
+        let mut board = *board;
+        if self.cell_flip_chance > 0. {
+            for px in board.iter_mut().flat_map(|row| row.iter_mut()) {
+                if rng.gen::<f64>() < self.cell_flip_chance {
+                    *px = if px.is_empty() {
+                        Pixel::Garbage
+                    } else {
+                        Pixel::Empty
+                    };
+                }
+            }
+        }
+        board
+    }
+
+    // call once per locked piece
+    pub(super) fn inject_garbage(&self, game: &mut Game) {
+        let rng = game.noise_rng.get_mut();
+        if self.garbage_chance > 0. && rng.gen::<f64>() < self.garbage_chance {
+            let hole = rng.gen_range(0, GAME_WIDTH);
+            game.add_garbage(1, hole);
+        }
+    }
+}
+
+// value below which the given fraction of the (sorted) values lies
+fn percentile(sorted: &[usize], fraction: f64) -> usize {
+    sorted[((sorted.len() - 1) as f64 * fraction).round() as usize]
+}
+
+fn describe(label: &str, mut values: Vec<usize>) {
+    if values.is_empty() {
+        println!("{:>7}: no games", label);
+        return;
+    }
+    values.sort_unstable();
+    let mean = values.iter().sum::<usize>() as f64 / values.len() as f64;
+    println!(
+        "{:>7}: mean {:.1}, p10 {}, median {}, p90 {}, max {}",
+        label,
+        mean,
+        percentile(&values, 0.1),
+        percentile(&values, 0.5),
+        percentile(&values, 0.9),
+        values[values.len() - 1]
+    );
+}
+
+// plays games with seeds seed, seed + 1, ... using the encoder, control mode and limits
+// of the config and prints score, line and survival statistics, every network gets the
+// same piece sequences for the same seed so checkpoints can be compared fairly
+pub fn evaluate_network(nn: &NN, config: &TrainerConfig, games: usize, seed: u64) {
+    let results = play_evaluation(nn, config, games, seed);
+    describe("points", results.iter().map(|r| r.0).collect());
+    describe("lines", results.iter().map(|r| r.1).collect());
+    describe("pieces", results.iter().map(|r| r.2).collect());
+    let survived = results.iter().filter(|r| !r.3).count();
+    println!(
+        "survived {} of {} games ({:.1}%)",
+        survived,
+        games,
+        100. * survived as f64 / games as f64
+    );
+}
+
+// points, lines, pieces and whether it's over of every game
+fn play_evaluation(
+    nn: &NN,
+    config: &TrainerConfig,
+    games: usize,
+    seed: u64,
+) -> Vec<(usize, usize, usize, bool)> {
+    let mut results = vec![];
+    for i in 0..games as u64 {
+        let mut game = Game::with_seed(seed + i);
+        while !game.is_over() && !config.limits.exceeded(&game) {
+            step(nn, config, &mut game);
+        }
+        results.push((game.points, game.cleared, game.pieces, game.is_over()));
+    }
+    results
+}
+
+#[test]
+fn test_fitness_from_events() {
+    use crate::game::{heuristic::HeuristicBot, placement::play_headless};
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    game.subscribe_events();
+    play_headless(&mut game, |game| bot.choose(game), 100);
+    let events = game.take_events();
+
+    let weights = FitnessWeights {
+        points: 0.,
+        line: 1.,
+        hole_created: 0.,
+        piece: 0.,
+        game_over: 0.,
+    };
+    assert_eq!(weights.fitness(game.points, &events), game.cleared as f64);
+    let pieces = FitnessWeights {
+        line: 0.,
+        piece: 1.,
+        ..weights
+    };
+    assert_eq!(pieces.fitness(game.points, &events), 100.);
+    assert!(game.take_events().is_empty());
+}
+
+#[test]
+fn test_eval_limits_each_trigger() {
+    use crate::game::Action;
+    let new_game = || {
+        let mut game = Game::with_seed(1);
+        game.set_gravity(0.);
+        game
+    };
+    // dropped where they spawn, every piece lands higher than the last and none clears
+    let drop = |game: &mut Game, n| (0..n).for_each(|_| game.apply(Action::HardDrop));
+
+    let frames = EvalLimits {
+        max_frames: Some(5),
+        ..EvalLimits::none()
+    };
+    let mut game = new_game();
+    (0..5).for_each(|_| game.iterate());
+    assert!(!frames.exceeded(&game));
+    game.iterate();
+    assert!(frames.exceeded(&game));
+
+    let pieces = EvalLimits {
+        max_pieces_without_clear: Some(3),
+        ..EvalLimits::none()
+    };
+    let mut game = new_game();
+    drop(&mut game, 3);
+    assert!(!pieces.exceeded(&game));
+    drop(&mut game, 1);
+    assert!(pieces.exceeded(&game));
+
+    let height = EvalLimits {
+        max_stack_height: Some(4),
+        ..EvalLimits::none()
+    };
+    let mut game = new_game();
+    assert!(!height.exceeded(&game));
+    drop(&mut game, 5);
+    assert!(height.exceeded(&game));
+    assert!(!EvalLimits::none().exceeded(&game));
+}
+
+#[test]
+fn test_noisy_evaluation_repeats() {
+    let config = TrainerConfig {
+        noise: Noise {
+            cell_flip_chance: 0.1,
+            garbage_chance: 0.3,
+        },
+        limits: EvalLimits {
+            max_frames: Some(3000),
+            ..EvalLimits::none()
+        },
+        ..TrainerConfig::default()
+    };
+    let nn = config.new_network().unwrap();
+    let results = play_evaluation(&nn, &config, 3, 7);
+    assert_eq!(play_evaluation(&nn, &config, 3, 7), results);
+    assert!(results.iter().all(|&(_, _, pieces, _)| pieces > 0));
+}
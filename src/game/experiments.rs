@@ -0,0 +1,266 @@
+use crate::game::{
+    nn_trainer::TrainerConfig,
+    population::{train_headless, NNReadOrCreationError, Population},
+};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{fmt, fs, io, path::Path, thread};
+use toml::{value::Table, Value};
+
+// a grid of training runs described by one file, e.g.
+//
+//     generations = 100
+//     jobs = 2 # runs trained at once
+//
+//     [base] # anything a trainer config can have, shared by every run
+//     generation_size = 20
+//
+//     [grid] # every combination of these values is a run
+//     mutation_rate = [0.05, 0.1]
+//     hidden_layers = [[20, 10], [40]]
+//     fitness = [{ line = 100.0 }, { hole_created = -50.0 }]
+//
+// a table in the grid only replaces the fields it has, run i is trained in <dir>/run_<i>
+// with the config it got in config.toml (so it can be continued with tetris train
+// --config), the results of all runs are printed at the end and kept in <dir>/summary.csv
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Experiment {
+    pub generations: usize,
+    pub jobs: usize,
+    pub base: Table,
+    pub grid: Table,
+}
+
+impl Default for Experiment {
+    fn default() -> Self {
+        Self {
+            generations: 50,
+            jobs: 1,
+            base: Table::new(),
+            grid: Table::new(),
+        }
+    }
+}
+
+#[derive(From, Debug)]
+pub enum ExperimentError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+    TomlWriteError(toml::ser::Error),
+    NNError(NNReadOrCreationError),
+    #[from(ignore)]
+    Other(String),
+}
+
+pub type ExperimentResult<T> = Result<T, ExperimentError>;
+
+impl fmt::Display for ExperimentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to access experiment: {}", e),
+            Self::TomlError(e) => write!(f, "invalid experiment: {}", e),
+            Self::TomlWriteError(e) => write!(f, "failed to write run config: {}", e),
+            Self::NNError(e) => write!(f, "failed to create generation: {}", e),
+            Self::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Experiment {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> ExperimentResult<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // the values of every run in the grid, in the order of the keys with the last key
+    // changing fastest
+    fn combinations(&self) -> ExperimentResult<Vec<Vec<(&str, &Value)>>> {
+        let mut runs = vec![vec![]];
+        for (key, values) in &self.grid {
+            let values = values.as_array().ok_or_else(|| {
+                ExperimentError::Other(format!("grid.{} has to be a list of values", key))
+            })?;
+            runs = runs
+                .into_iter()
+                .cartesian_product(values)
+                .map(|(mut run, value): (Vec<_>, _)| {
+                    run.push((key.as_str(), value));
+                    run
+                })
+                .collect();
+        }
+        Ok(runs)
+    }
+
+    // the base with the values of a run on top, checked by reading it as a config
+    fn runs(&self) -> ExperimentResult<Vec<Run>> {
+        self.combinations()?
+            .into_iter()
+            .map(|values| {
+                let mut config = self.base.clone();
+                for &(key, value) in &values {
+                    merge(&mut config, key, value.clone());
+                }
+                let config = Value::Table(config);
+                let trainer = config.clone().try_into::<TrainerConfig>()?;
+                if trainer.islands.is_some() {
+                    return Err(ExperimentError::Other(
+                        "experiments don't train islands".to_string(),
+                    ));
+                }
+                trainer.check().map_err(ExperimentError::Other)?;
+                Ok(Run {
+                    name: values
+                        .iter()
+                        .map(|(key, value)| format!("{} = {}", key, value))
+                        .join(", "),
+                    config,
+                    trainer,
+                })
+            })
+            .collect()
+    }
+}
+
+// tables are merged field by field, anything else is replaced
+fn merge(table: &mut Table, key: &str, value: Value) {
+    match (table.get_mut(key), value) {
+        (Some(Value::Table(old)), Value::Table(new)) => {
+            for (key, value) in new {
+                merge(old, &key, value);
+            }
+        }
+        (_, value) => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+struct Run {
+    name: String,  // the grid values it was given
+    config: Value, // as written to config.toml
+    trainer: TrainerConfig,
+}
+
+// best and mean fitness of the last generation
+type Summary = (f64, f64);
+
+fn train_run(
+    run: &Run,
+    label: String,
+    dir: &Path,
+    generations: usize,
+) -> ExperimentResult<Summary> {
+    fs::create_dir_all(dir)?;
+    let header = format!("# {}\n", run.name);
+    fs::write(
+        dir.join("config.toml"),
+        header + &toml::to_string(&run.config)?,
+    )?;
+    let mut population =
+        Population::load_or_create(&dir.join("generation.txt"), run.trainer.clone())?;
+    population.set_label(label);
+    train_headless(&mut population, generations, dir)?;
+    Ok(population.history.last().copied().unwrap_or((0., 0.)))
+}
+
+// trains every run of the grid in <dir>/run_<i>, jobs at a time
+pub fn run_experiment(experiment: &Experiment, dir: &Path) -> ExperimentResult<()> {
+    let runs = experiment.runs()?;
+    println!(
+        "{} runs of {} generations",
+        runs.len(),
+        experiment.generations
+    );
+    let mut summaries = vec![];
+    for (chunk, runs_chunk) in runs.chunks(experiment.jobs.max(1)).enumerate() {
+        let first = chunk * experiment.jobs.max(1);
+        let results = thread::scope(|scope| {
+            let handles = runs_chunk
+                .iter()
+                .enumerate()
+                .map(|(i, run)| {
+                    let i = first + i;
+                    let run_dir = dir.join(format!("run_{}", i));
+                    let label = format!("run {}: ", i);
+                    let generations = experiment.generations;
+                    scope.spawn(move || train_run(run, label, &run_dir, generations))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("training run panicked"))
+                .collect::<Vec<_>>()
+        });
+        for result in results {
+            summaries.push(result?);
+        }
+    }
+    let mut csv = "run,grid,best,mean\n".to_string();
+    for (i, (run, (best, mean))) in runs.iter().zip(&summaries).enumerate() {
+        csv += &format!(
+            "{},\"{}\",{},{}\n",
+            i,
+            run.name.replace('"', "'"),
+            best,
+            mean
+        );
+    }
+    fs::write(dir.join("summary.csv"), csv)?;
+    let mut ranking = runs.iter().zip(summaries).enumerate().collect::<Vec<_>>();
+    ranking.sort_by(|(_, (_, (a, _))), (_, (_, (b, _)))| b.total_cmp(a));
+    println!("best and mean fitness of the last generation:");
+    for (rank, (i, (run, (best, mean)))) in ranking.into_iter().enumerate() {
+        println!(
+            "{:>4}. run {:<3} {:>10.1} {:>10.1}  {}",
+            rank + 1,
+            i,
+            best,
+            mean,
+            run.name
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_experiment_grid() {
+    let experiment: Experiment = toml::from_str(
+        "
+        [base]
+        generation_size = 4
+        fitness = { points = 0.5 }
+
+        [grid]
+        mutation_rate = [0.05, 0.1]
+        hidden_layers = [[20, 10], [40]]
+        fitness = [{ line = 2.0 }]
+        ",
+    )
+    .unwrap();
+    let runs = experiment.runs().unwrap();
+    assert_eq!(runs.len(), 4);
+    // the last key changes fastest
+    assert_eq!(runs[1].trainer.mutation_rate, 0.1);
+    assert_eq!(runs[2].trainer.hidden_layers, vec![40]);
+    for run in &runs {
+        assert_eq!(run.trainer.generation_size, 4);
+        // a table only replaces the fields it has
+        assert_eq!(run.trainer.fitness.points, 0.5);
+        assert_eq!(run.trainer.fitness.line, 2.);
+    }
+    let bad: Experiment = toml::from_str("[grid]\nmutation_rate = 0.1").unwrap();
+    assert!(bad.runs().is_err());
+    // checked before anything is trained, not by the run that gets there
+    let keys_versus: Experiment = toml::from_str(
+        "
+        [base]
+        versus = {}
+
+        [grid]
+        mode = [\"Placement\", \"Keys\"]
+        ",
+    )
+    .unwrap();
+    assert!(keys_versus.runs().is_err());
+}
@@ -0,0 +1,387 @@
+use crate::game::{Board, Game, PieceId, Pixel, GAME_HEIGHT, GAME_WIDTH};
+use serde::Deserialize;
+use std::cmp::{max, min};
+
+// what the network gets to see of the game, every encoding can be written into a
+// reused buffer (the *_into functions) so that nothing is allocated per frame or placement
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+pub enum InputEncoder {
+    // every cell of the board (falling piece included) as 0 or 1
+    Cells,
+    // aggregate board features plus one-hot current and next piece
+    Features,
+    // like Cells but with the falling piece in a second plane of its own
+    SplitCells,
+    // one plane of 0 or 1 per piece the cells were locked as, plus one for garbage,
+    // falling piece included (boards loaded from replays only know occupancy and have
+    // every cell in the plane of the recorded piece)
+    PiecePlanes,
+    // height of each column scaled to [0, 1] plus one-hot current and next piece
+    Heights,
+    // the SURFACE_ROWS rows from the top of the stack down (the bottom ones while the stack is
+    // lower than that) as 0 or 1, then heights and pieces like Heights, so that the network
+    // sees where pieces go instead of rows that are empty or full anyway
+    Surface,
+}
+
+const PLANES: usize = 8; // 7 pieces and garbage
+pub const SURFACE_ROWS: usize = 6;
+
+impl InputEncoder {
+    pub fn input_size(self) -> usize {
+        let cells = GAME_WIDTH * GAME_HEIGHT;
+        let pieces = PieceId::ALL.len();
+        match self {
+            Self::Cells => cells,
+            // heights, holes, bumpiness, well depth, current piece, next piece
+            Self::Features => GAME_WIDTH + 3 + 2 * pieces,
+            Self::SplitCells => 2 * cells,
+            Self::PiecePlanes => PLANES * cells,
+            Self::Heights => GAME_WIDTH + 2 * pieces,
+            Self::Surface => (SURFACE_ROWS + 1) * GAME_WIDTH + 2 * pieces,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn encode(self, game: &Game) -> Box<[f64]> {
+        self.encode_seen(game, &game.board)
+    }
+
+    // overwrites out with the encoding, out keeps its capacity between calls
+    #[allow(dead_code)]
+    pub fn encode_into(self, game: &Game, out: &mut Vec<f64>) {
+        self.encode_seen_into(game, &game.board, out)
+    }
+
+    // encode the game as if its board was the given one (e.g. with noise added)
+    pub(super) fn encode_seen(self, game: &Game, board: &Board) -> Box<[f64]> {
+        let mut out = Vec::with_capacity(self.input_size());
+        self.encode_seen_into(game, board, &mut out);
+        out.into_boxed_slice()
+    }
+
+    pub(super) fn encode_seen_into(self, game: &Game, board: &Board, out: &mut Vec<f64>) {
+        let falling = game.falling.as_ref();
+        let current = falling.map(|f| f.id);
+        let next = game.piece_queue.iter().next();
+        let falling_cells = || falling.into_iter().flat_map(|f| f.mask.cells_at(f.pos));
+        out.clear();
+        match self {
+            Self::Cells => {
+                push_cells(board, out);
+                mark(out, 0, falling_cells());
+            }
+            Self::SplitCells => {
+                push_cells(board, out);
+                out.resize(2 * GAME_WIDTH * GAME_HEIGHT, 0.);
+                mark(out, GAME_WIDTH * GAME_HEIGHT, falling_cells());
+            }
+            Self::PiecePlanes => {
+                push_planes(board, out);
+                if let Some(id) = current {
+                    mark(out, plane(Pixel::Full(id)), falling_cells());
+                }
+            }
+            Self::Features | Self::Heights | Self::Surface => {
+                self.encode_board_into(board, current, next, out)
+            }
+        }
+    }
+
+    // encode a board without a falling piece (e.g. the result of a placement)
+    pub(super) fn encode_board(
+        self,
+        board: &Board,
+        current: Option<PieceId>,
+        next: Option<PieceId>,
+    ) -> Box<[f64]> {
+        let mut out = Vec::with_capacity(self.input_size());
+        self.encode_board_into(board, current, next, &mut out);
+        out.into_boxed_slice()
+    }
+
+    pub(super) fn encode_board_into(
+        self,
+        board: &Board,
+        current: Option<PieceId>,
+        next: Option<PieceId>,
+        out: &mut Vec<f64>,
+    ) {
+        out.clear();
+        match self {
+            Self::Cells => push_cells(board, out),
+            Self::SplitCells => {
+                push_cells(board, out);
+                out.resize(2 * GAME_WIDTH * GAME_HEIGHT, 0.);
+            }
+            Self::PiecePlanes => push_planes(board, out),
+            Self::Features => push_features(board, current, next, out),
+            Self::Heights => {
+                let scale = GAME_HEIGHT as f64;
+                out.extend(column_heights(board).iter().map(|&h| h as f64 / scale));
+                out.extend(one_hot(current).chain(one_hot(next)));
+            }
+            Self::Surface => {
+                let heights = column_heights(board);
+                let top = max(heights.iter().copied().max().unwrap_or(0), SURFACE_ROWS);
+                push_cells(&board[GAME_HEIGHT - top..][..SURFACE_ROWS], out);
+                let scale = GAME_HEIGHT as f64;
+                out.extend(heights.iter().map(|&h| h as f64 / scale));
+                out.extend(one_hot(current).chain(one_hot(next)));
+            }
+        }
+    }
+}
+
+// rows of cells, 1 for full and 0 for empty
+fn push_cells(board: &[[Pixel; GAME_WIDTH]], out: &mut Vec<f64>) {
+    let cells = board.iter().flat_map(|row| row.iter());
+    out.extend(cells.map(|px| if px.is_empty() { 0. } else { 1. }));
+}
+
+// plane index of a full pixel
+fn plane(px: Pixel) -> usize {
+    match px {
+        Pixel::Full(id) => id as usize,
+        Pixel::Garbage => PieceId::ALL.len(),
+        Pixel::Empty => unreachable!("empty cells aren't in any plane"),
+    }
+}
+
+fn push_planes(board: &Board, out: &mut Vec<f64>) {
+    let cells = GAME_WIDTH * GAME_HEIGHT;
+    out.resize(PLANES * cells, 0.);
+    for (i, px) in board.iter().flat_map(|row| row.iter()).enumerate() {
+        if !px.is_empty() {
+            out[plane(*px) * cells + i] = 1.;
+        }
+    }
+}
+
+// set the given cells to 1 in the plane starting at offset
+fn mark(out: &mut [f64], offset: usize, cells: impl Iterator<Item = (isize, isize)>) {
+    for (x, y) in cells {
+        out[offset + y as usize * GAME_WIDTH + x as usize] = 1.;
+    }
+}
+
+// height of each column measured from the ground, 0 for empty columns
+pub(super) fn column_heights(board: &Board) -> [usize; GAME_WIDTH] {
+    let mut heights = [0; GAME_WIDTH];
+    for (x, height) in heights.iter_mut().enumerate() {
+        *height = (0..GAME_HEIGHT)
+            .find(|&y| !board[y][x].is_empty())
+            .map_or(0, |y| GAME_HEIGHT - y);
+    }
+    heights
+}
+
+// empty cells with at least one full cell above them in the same column
+pub(super) fn holes(board: &Board, heights: &[usize; GAME_WIDTH]) -> usize {
+    (0..GAME_WIDTH)
+        .map(|x| {
+            (GAME_HEIGHT - heights[x]..GAME_HEIGHT)
+                .filter(|&y| board[y][x].is_empty())
+                .count()
+        })
+        .sum()
+}
+
+// column heights and holes per column, kept up to date by Game as pieces lock, lines clear
+// and garbage rises instead of scanning the board every time they're needed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BoardMetrics {
+    heights: [usize; GAME_WIDTH],
+    holes: [usize; GAME_WIDTH],
+}
+
+impl BoardMetrics {
+    // with a scan of the whole board
+    pub(super) fn of(board: &Board) -> Self {
+        let heights = column_heights(board);
+        let mut holes = [0; GAME_WIDTH];
+        for (x, holes) in holes.iter_mut().enumerate() {
+            *holes = (GAME_HEIGHT - heights[x]..GAME_HEIGHT)
+                .filter(|&y| board[y][x].is_empty())
+                .count();
+        }
+        Self { heights, holes }
+    }
+
+    pub fn heights(&self) -> &[usize; GAME_WIDTH] {
+        &self.heights
+    }
+
+    pub fn max_height(&self) -> usize {
+        self.heights.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn holes(&self) -> usize {
+        self.holes.iter().sum()
+    }
+
+    #[allow(dead_code)]
+    pub fn column_holes(&self, x: usize) -> usize {
+        self.holes[x]
+    }
+
+    // cells filled by a piece before any lines are cleared, (x, y) with y from the top
+    pub(super) fn lock(&mut self, cells: impl IntoIterator<Item = (isize, isize)>) {
+        let mut cells = cells
+            .into_iter()
+            .map(|(x, y)| (x as usize, GAME_HEIGHT - y as usize))
+            .collect::<Vec<_>>();
+        // lowest first so that the cells of a column stack on each other
+        cells.sort_unstable_by_key(|&(_, height)| height);
+        for (x, height) in cells {
+            if height <= self.heights[x] {
+                self.holes[x] -= 1;
+            } else {
+                self.holes[x] += height - self.heights[x] - 1;
+                self.heights[x] = height;
+            }
+        }
+    }
+
+    // after rows rows were cleared from the board, which is the one after the clear, full rows
+    // go through every column so each one is that much lower, columns whose cells above the
+    // holes were all cleared go down to the next full cell
+    pub(super) fn clear(&mut self, board: &Board, rows: usize) {
+        if rows == 0 {
+            return;
+        }
+        for x in 0..GAME_WIDTH {
+            self.heights[x] -= rows;
+            while self.heights[x] > 0 && board[GAME_HEIGHT - self.heights[x]][x].is_empty() {
+                self.heights[x] -= 1;
+                self.holes[x] -= 1;
+            }
+        }
+    }
+
+    // after rows of garbage with a hole at hole_x were pushed up from the bottom
+    pub(super) fn add_garbage(&mut self, rows: usize, hole_x: usize) {
+        for x in 0..GAME_WIDTH {
+            if x != hole_x {
+                self.heights[x] += rows;
+            } else if self.heights[x] > 0 {
+                self.heights[x] += rows;
+                self.holes[x] += rows;
+            }
+        }
+    }
+}
+
+// sum of height differences between adjacent columns
+pub(super) fn bumpiness(heights: &[usize; GAME_WIDTH]) -> usize {
+    heights
+        .windows(2)
+        .map(|w| max(w[0], w[1]) - min(w[0], w[1]))
+        .sum()
+}
+
+// depth of the deepest column relative to its lower neighbour (walls count as full height)
+pub(super) fn max_well_depth(heights: &[usize; GAME_WIDTH]) -> usize {
+    (0..GAME_WIDTH)
+        .map(|x| {
+            let left = if x == 0 { GAME_HEIGHT } else { heights[x - 1] };
+            let right = if x == GAME_WIDTH - 1 {
+                GAME_HEIGHT
+            } else {
+                heights[x + 1]
+            };
+            min(left, right).saturating_sub(heights[x])
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn one_hot(id: Option<PieceId>) -> impl Iterator<Item = f64> {
+    PieceId::ALL
+        .iter()
+        .map(move |&p| if Some(p) == id { 1. } else { 0. })
+}
+
+// all values are scaled to roughly [0, 1]
+fn push_features(
+    board: &Board,
+    current: Option<PieceId>,
+    next: Option<PieceId>,
+    out: &mut Vec<f64>,
+) {
+    let heights = column_heights(board);
+    let scale = GAME_HEIGHT as f64;
+    out.extend(heights.iter().map(|&h| h as f64 / scale));
+    out.extend(&[
+        holes(board, &heights) as f64 / scale,
+        bumpiness(&heights) as f64 / scale,
+        max_well_depth(&heights) as f64 / scale,
+    ]);
+    out.extend(one_hot(current).chain(one_hot(next)));
+}
+
+#[test]
+fn test_encodings_have_their_input_size() {
+    use InputEncoder::*;
+    let mut game = Game::with_seed(1);
+    game.hard_drop();
+    let mut buffer = vec![];
+    for &encoder in &[Cells, Features, SplitCells, PiecePlanes, Heights, Surface] {
+        let encoded = encoder.encode(&game);
+        assert_eq!(encoded.len(), encoder.input_size(), "{:?}", encoder);
+        encoder.encode_into(&game, &mut buffer);
+        assert_eq!(&buffer[..], &encoded[..], "{:?}", encoder);
+        // the locked piece and the falling one
+        if ![Features, Heights, Surface].contains(&encoder) {
+            assert_eq!(encoded.iter().sum::<f64>(), 8., "{:?}", encoder);
+        }
+    }
+}
+
+#[test]
+fn test_surface_follows_the_top_of_the_stack() {
+    let mut game = Game::with_seed(0);
+    let surface = |game: &Game| InputEncoder::Surface.encode(game)[..GAME_WIDTH].to_vec();
+    // an empty board shows its bottom rows
+    assert!(surface(&game).iter().all(|&cell| cell == 0.));
+    let mut rows = "..........\n".repeat(SURFACE_ROWS - 1);
+    rows.push_str("#########.\n".repeat(12).as_str());
+    game.load_board_from_ascii(&rows).unwrap();
+    // the first row is the one the stack ends in, whatever its height
+    let first = surface(&game);
+    assert_eq!(first.iter().sum::<f64>(), 9.);
+    let mut rows = "#.........\n".to_string();
+    rows.push_str("#########.\n".repeat(12).as_str());
+    game.load_board_from_ascii(&rows).unwrap();
+    assert_eq!(surface(&game).iter().sum::<f64>(), 1.);
+}
+
+#[test]
+fn test_board_metrics_follow_the_board() {
+    use crate::game::heuristic::HeuristicBot;
+    use crate::game::placement::play_headless;
+    let bot = HeuristicBot::new();
+    let mut game = Game::with_seed(5);
+    // the first pieces are dropped anywhere to leave holes for the rest to clear around
+    for i in 0..300 {
+        if i < 6 {
+            game.hard_drop();
+        } else {
+            play_headless(&mut game, |game| bot.choose(game), 1);
+        }
+        if i % 25 == 0 {
+            game.add_garbage(2, i % GAME_WIDTH);
+        }
+        if game.is_over() {
+            break;
+        }
+        assert_eq!(
+            game.metrics(),
+            &BoardMetrics::of(&game.board),
+            "piece {}",
+            i
+        );
+    }
+    assert!(game.cleared > 0);
+}
@@ -0,0 +1,200 @@
+use crate::game::{
+    render::{Color, Command, Rect, Renderer},
+    Action,
+};
+use ggez::{
+    audio::{SoundData, Source},
+    event::Button,
+    filesystem,
+    graphics::{
+        self, draw, draw_queued_text, queue_text, DrawMode, DrawParam, FilterMode, Image, Mesh,
+        MeshBuilder, Text,
+    },
+    mint::Point2,
+    Context, GameError, GameResult,
+};
+use std::{collections::HashMap, f32::consts::PI, fs, path::Path};
+
+fn to_ggez(color: Color) -> graphics::Color {
+    graphics::Color::from_rgb(color.r, color.g, color.b)
+}
+
+// collects everything into a single mesh that is drawn by finish, text is drawn on top
+pub(super) struct GgezRenderer {
+    builder: MeshBuilder,
+    empty: bool,
+    texts: Vec<((f32, f32), String, Color)>,
+    error: Option<GameError>, // the first one, reported by finish
+}
+
+impl GgezRenderer {
+    pub(super) fn new() -> Self {
+        Self {
+            builder: MeshBuilder::new(),
+            empty: true,
+            texts: vec![],
+            error: None,
+        }
+    }
+
+    pub(super) fn finish(self, ctx: &mut Context) -> GameResult<()> {
+        self.build(ctx, None)?.draw(ctx)
+    }
+
+    // for drawing the same thing over several frames, the Text objects of the layer this
+    // one replaces are reused for the same strings instead of being laid out again
+    pub(super) fn build(self, ctx: &mut Context, previous: Option<Layer>) -> GameResult<Layer> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mesh = if self.empty {
+            None
+        } else {
+            Some(self.builder.build(ctx)?)
+        };
+        let mut cached = previous.map_or_else(HashMap::new, |layer| {
+            layer
+                .texts
+                .into_iter()
+                .map(|(_, text, _)| (text.contents(), text))
+                .collect()
+        });
+        let texts = self
+            .texts
+            .into_iter()
+            .map(|(pos, string, color)| {
+                let text = cached.remove(&string).unwrap_or_else(|| Text::new(string));
+                (pos, text, color)
+            })
+            .collect();
+        Ok(Layer { mesh, texts })
+    }
+}
+
+// what a GgezRenderer drew, ready to be drawn again
+pub(super) struct Layer {
+    mesh: Option<Mesh>,
+    texts: Vec<((f32, f32), Text, Color)>,
+}
+
+impl Layer {
+    pub(super) fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        if let Some(mesh) = self.mesh.as_ref() {
+            draw(ctx, mesh, DrawParam::default())?;
+        }
+        for &((x, y), ref text, color) in &self.texts {
+            queue_text(ctx, text, Point2 { x, y }, Some(to_ggez(color)));
+        }
+        draw_queued_text(ctx, DrawParam::default(), None, FilterMode::Linear)
+    }
+}
+
+impl Renderer for GgezRenderer {
+    fn rect(&mut self, rect: Rect, color: Color) {
+        let rect = graphics::Rect::new(rect.x, rect.y, rect.w, rect.h);
+        self.builder
+            .rectangle(DrawMode::fill(), rect, to_ggez(color));
+        self.empty = false;
+    }
+
+    fn line(&mut self, points: &[(f32, f32)], width: f32, color: Color) {
+        let points = points
+            .iter()
+            .map(|&(x, y)| Point2 { x, y })
+            .collect::<Vec<_>>();
+        if let Err(e) = self.builder.line(&points, width, to_ggez(color)) {
+            self.error.get_or_insert(e);
+        }
+        self.empty = false;
+    }
+
+    fn text(&mut self, pos: (f32, f32), text: &str, color: Color) {
+        self.texts.push((pos, text.to_string(), color));
+    }
+}
+
+// from anywhere on disk, ggez only opens files in the directories mounted into it so the
+// one the image is in is mounted first
+pub(super) fn load_image(ctx: &mut Context, path: &Path) -> GameResult<Image> {
+    let path = fs::canonicalize(path)?;
+    if let (Some(dir), Some(name)) = (path.parent(), path.file_name()) {
+        filesystem::mount(ctx, dir, true);
+        return Image::new(ctx, Path::new("/").join(name));
+    }
+    Err(GameError::ResourceNotFound(
+        path.display().to_string(),
+        vec![],
+    ))
+}
+
+// stretched over (0, 0) to dims and darkened by brightness < 1
+pub(super) fn draw_stretched(
+    ctx: &mut Context,
+    image: &Image,
+    dims: (f32, f32),
+    brightness: f32,
+) -> GameResult<()> {
+    let scale = [
+        dims.0 / image.width() as f32,
+        dims.1 / image.height() as f32,
+    ];
+    let tint = to_ggez(Color::WHITE.scaled(brightness));
+    draw(ctx, image, DrawParam::default().scale(scale).color(tint))
+}
+
+// a short rising chime as a wav file, generated so that no sound files have to be shipped
+fn chime() -> Vec<u8> {
+    const RATE: u32 = 22050;
+    let notes = [(660., 0.08), (880., 0.16)]; // (Hz, seconds)
+    let samples = notes
+        .iter()
+        .flat_map(|&(freq, secs): &(f32, f32)| {
+            let len = (RATE as f32 * secs) as usize;
+            (0..len).map(move |i| {
+                let t = i as f32 / RATE as f32;
+                let fade = 1. - i as f32 / len as f32;
+                ((2. * PI * freq * t).sin() * fade * 0.3 * i16::MAX as f32) as i16
+            })
+        })
+        .collect::<Vec<_>>();
+    let data_len = 2 * samples.len() as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // format chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes()); // pcm
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&RATE.to_le_bytes());
+    wav.extend_from_slice(&(2 * RATE).to_le_bytes()); // bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // bytes per sample
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+pub(super) fn level_up_sound(ctx: &mut Context) -> GameResult<Source> {
+    Source::from_data(ctx, SoundData::from_bytes(&chime()))
+}
+
+// the default gamepad bindings, for any gamepad
+pub(super) fn pad_command(button: Button) -> Option<Command> {
+    use Button::*;
+    Some(match button {
+        DPadLeft => Command::Play(Action::MoveLeft),
+        DPadRight => Command::Play(Action::MoveRight),
+        DPadDown => Command::Play(Action::SoftDrop),
+        DPadUp => Command::Play(Action::HardDrop),
+        East => Command::Play(Action::RotateCW),
+        South => Command::Play(Action::RotateCCW),
+        North => Command::Play(Action::Rotate180),
+        LeftTrigger | RightTrigger => Command::Play(Action::Hold),
+        Start => Command::Pause,
+        Select => Command::SwitchLayout,
+        _ => return None,
+    })
+}
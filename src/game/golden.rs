@@ -0,0 +1,76 @@
+use crate::game::{
+    hash::fnv1a,
+    replay::load_input_replay,
+    simulator::{Score, Simulator},
+};
+use std::{env, fs, path::Path};
+
+// every tests/golden/<name>.replay is replayed headless and has to end exactly as described
+// in <name>.golden, run with UPDATE_GOLDENS=1 to rewrite them after an intended change
+// to the rules (and check the diff)
+
+const GOLDEN_DIR: &str = "tests/golden";
+
+// unlike DefaultHasher it's the same on every platform and compiler version
+fn board_hash(board: &str) -> u64 {
+    fnv1a(board.as_bytes())
+}
+
+fn golden(sim: &mut Simulator) -> String {
+    let Score {
+        points,
+        level,
+        cleared,
+        pieces,
+        frames,
+    } = sim.score();
+    let events = sim
+        .take_events()
+        .iter()
+        .map(|event| format!("{:?}\n", event))
+        .collect::<String>();
+    format!(
+        "board {:016x}\npoints {}\nlevel {}\ncleared {}\npieces {}\nframes {}\nover {}\nevents\n{}",
+        board_hash(&sim.board_string()),
+        points,
+        level,
+        cleared,
+        pieces,
+        frames,
+        sim.is_over(),
+        events
+    )
+}
+
+#[test]
+fn test_replays_match_goldens() {
+    let update = env::var_os("UPDATE_GOLDENS").is_some();
+    let mut replays = fs::read_dir(GOLDEN_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+        .collect::<Vec<_>>();
+    replays.sort();
+    assert!(!replays.is_empty(), "no replays in {}", GOLDEN_DIR);
+
+    let mut mismatched = vec![];
+    for path in &replays {
+        let replay = load_input_replay(path).unwrap();
+        let actual = golden(&mut Simulator::replay(&replay));
+        let golden_path = path.with_extension("golden");
+        if update {
+            fs::write(&golden_path, actual).unwrap();
+        } else if fs::read_to_string(&golden_path).ok().as_deref() != Some(actual.as_str()) {
+            mismatched.push(name(&golden_path));
+        }
+    }
+    assert!(
+        mismatched.is_empty(),
+        "gameplay changed for {:?}, rerun with UPDATE_GOLDENS=1 if that was intended",
+        mismatched
+    );
+}
+
+fn name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
@@ -0,0 +1,299 @@
+use crate::game::{
+    hash::{sha256, to_hex},
+    simulator::GameConfig,
+    versus::{attack, GARBAGE_DELAY},
+    GAME_HEIGHT, GAME_WIDTH,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+// the start of a networked game, before anything else both sides send a hello with their
+// protocol version, the rules they play by and the hash of a seed, then check the other's
+// and send start or refuse. Once both sent start they reveal their seeds and the game
+// starts with the xor of them. Neither side knows the other's seed before it has
+// committed to its own, so neither can pick the pieces alone. One JSON message per line:
+//
+//     {"type":"hello","version":2,"rules":{...},"commitment":"9a4f..."}
+//     {"type":"start"}
+//     {"type":"reveal","seed":42}
+
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// everything that has to be the same on both sides for rollback::RemoteGame to work,
+// including what isn't configurable yet so that builds which change it don't play
+// against each other
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Rules {
+    board: (usize, usize), // width, height
+    gravity: f32,
+    hold: String,
+    rotation: String,
+    // of the masks and kicks, masks.txt can be edited so the name isn't enough
+    pieces: String,
+    delays: (usize, usize), // entry, line clear
+    lock_delay: usize,
+    attack: Vec<usize>, // rows sent for clearing 0 to 4 lines
+    garbage_delay: usize,
+}
+
+impl From<&GameConfig> for Rules {
+    fn from(config: &GameConfig) -> Self {
+        Self {
+            board: (GAME_WIDTH, GAME_HEIGHT),
+            gravity: config.gravity,
+            hold: config.hold.name().to_string(),
+            rotation: config.rotation.name().to_string(),
+            pieces: config.rotation.piece_set().fingerprint(),
+            delays: (config.entry_delay, config.line_clear_delay),
+            lock_delay: config.lock_delay,
+            attack: (0..=4).map(attack).collect(),
+            garbage_delay: GARBAGE_DELAY,
+        }
+    }
+}
+
+impl Rules {
+    // what differs, by field name
+    fn differences(&self, other: &Self) -> Vec<&'static str> {
+        let mut differences = vec![];
+        let mut check = |same: bool, name| {
+            if !same {
+                differences.push(name)
+            }
+        };
+        check(self.board == other.board, "board");
+        check(self.gravity == other.gravity, "gravity");
+        check(self.hold == other.hold, "hold");
+        check(self.rotation == other.rotation, "rotation");
+        check(self.pieces == other.pieces, "pieces");
+        check(self.delays == other.delays, "delays");
+        check(self.lock_delay == other.lock_delay, "lock_delay");
+        check(self.attack == other.attack, "attack");
+        check(self.garbage_delay == other.garbage_delay, "garbage_delay");
+        differences
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Hello {
+        version: u32,
+        rules: Rules,
+        commitment: String,
+    },
+    Start,
+    Refuse {
+        reason: String,
+    },
+    Reveal {
+        seed: u64,
+    },
+}
+
+// what a side sends before its seed, the seed can't be changed later without the other
+// side noticing
+fn commitment(seed: u64) -> String {
+    to_hex(&sha256(&seed.to_be_bytes()))
+}
+
+#[derive(From, Debug)]
+pub enum HandshakeError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+    #[from(ignore)]
+    Mismatch(String), // refused by this side
+    #[from(ignore)]
+    Refused(String), // by the other side
+    #[from(ignore)]
+    Protocol(String),
+}
+
+pub type HandshakeResult<T> = Result<T, HandshakeError>;
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "connection failed: {}", e),
+            Self::JsonError(e) => write!(f, "invalid message: {}", e),
+            Self::Mismatch(reason) => write!(f, "refused to play: {}", reason),
+            Self::Refused(reason) => write!(f, "the other side refused to play: {}", reason),
+            Self::Protocol(message) => write!(f, "unexpected message: {}", message),
+        }
+    }
+}
+
+// the reason to refuse the other side's hello, if any
+fn check(rules: &Rules, version: u32, their_rules: &Rules) -> Option<String> {
+    if version != PROTOCOL_VERSION {
+        return Some(format!(
+            "protocol version {} instead of {}",
+            version, PROTOCOL_VERSION
+        ));
+    }
+    let differences = rules.differences(their_rules);
+    if !differences.is_empty() {
+        return Some(format!("different rules: {}", differences.join(", ")));
+    }
+    None
+}
+
+fn send<W: Write>(writer: &mut W, message: &Message) -> HandshakeResult<()> {
+    writeln!(writer, "{}", serde_json::to_string(message)?)?;
+    Ok(writer.flush()?)
+}
+
+fn receive<R: BufRead>(reader: &mut R) -> HandshakeResult<Message> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(HandshakeError::Protocol("connection closed".to_string()));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+// both sides call this with their own config and a random seed, returns the seed of the
+// game once both agreed to play and revealed seeds matching their commitments
+pub fn handshake<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    config: &GameConfig,
+    seed: u64,
+) -> HandshakeResult<u64> {
+    let rules = Rules::from(config);
+    send(
+        writer,
+        &Message::Hello {
+            version: PROTOCOL_VERSION,
+            rules: rules.clone(),
+            commitment: commitment(seed),
+        },
+    )?;
+    let (refusal, their_commitment) = match receive(reader)? {
+        Message::Hello {
+            version,
+            rules: their_rules,
+            commitment,
+        } => (check(&rules, version, &their_rules), commitment),
+        message => return Err(HandshakeError::Protocol(format!("{:?}", message))),
+    };
+    // sending back the same commitment and then the same seed would make the xor 0
+    if their_commitment == commitment(seed) {
+        return Err(HandshakeError::Protocol("the same commitment".to_string()));
+    }
+    match &refusal {
+        Some(reason) => send(
+            writer,
+            &Message::Refuse {
+                reason: reason.clone(),
+            },
+        )?,
+        None => send(writer, &Message::Start)?,
+    }
+    // the other side's answer first, it explains the most
+    match receive(reader)? {
+        Message::Start => (),
+        Message::Refuse { reason } => return Err(HandshakeError::Refused(reason)),
+        message => return Err(HandshakeError::Protocol(format!("{:?}", message))),
+    }
+    if let Some(reason) = refusal {
+        return Err(HandshakeError::Mismatch(reason));
+    }
+    send(writer, &Message::Reveal { seed })?;
+    match receive(reader)? {
+        Message::Reveal { seed: their_seed } if commitment(their_seed) == their_commitment => {
+            Ok(seed ^ their_seed)
+        }
+        message => Err(HandshakeError::Protocol(format!("{:?}", message))),
+    }
+}
+
+#[test]
+fn test_handshake_refuses_other_rules() {
+    use crate::game::RotationSystem;
+    use std::io::Cursor;
+
+    // what the other side sends with the given rules, revealing the given seed
+    let other = |rules: Rules, version, revealed| {
+        let mut messages = vec![];
+        let hello = Message::Hello {
+            version,
+            rules,
+            commitment: commitment(5),
+        };
+        send(&mut messages, &hello).unwrap();
+        send(&mut messages, &Message::Start).unwrap();
+        send(&mut messages, &Message::Reveal { seed: revealed }).unwrap();
+        Cursor::new(messages)
+    };
+    let config = GameConfig::default();
+    let rules = Rules::from(&config);
+    let mut sent = vec![];
+    let seed = handshake(
+        &mut other(rules.clone(), PROTOCOL_VERSION, 5),
+        &mut sent,
+        &config,
+        3,
+    );
+    assert_eq!(seed.unwrap(), 3 ^ 5);
+    let sent = String::from_utf8(sent).unwrap();
+    assert!(sent.contains(&commitment(3)) && !sent.contains(&commitment(5)));
+    assert!(sent.ends_with("{\"type\":\"start\"}\n{\"type\":\"reveal\",\"seed\":3}\n"));
+
+    // a seed other than the one committed to
+    let result = handshake(
+        &mut other(rules.clone(), PROTOCOL_VERSION, 6),
+        &mut vec![],
+        &config,
+        3,
+    );
+    assert!(matches!(result, Err(HandshakeError::Protocol(_))));
+    // the same seed sent back
+    let result = handshake(
+        &mut other(rules.clone(), PROTOCOL_VERSION, 5),
+        &mut vec![],
+        &config,
+        5,
+    );
+    assert!(matches!(result, Err(HandshakeError::Protocol(_))));
+
+    let srs = GameConfig {
+        rotation: RotationSystem::Srs,
+        ..config
+    };
+    let result = handshake(
+        &mut other(Rules::from(&srs), PROTOCOL_VERSION, 5),
+        &mut vec![],
+        &config,
+        3,
+    );
+    let differences = "different rules: rotation, pieces";
+    assert!(matches!(result, Err(HandshakeError::Mismatch(reason)) if reason == differences));
+    // an edited masks.txt
+    let edited = Rules {
+        pieces: crate::game::builtin_masks().fingerprint().replace('0', "1"),
+        ..rules.clone()
+    };
+    let result = handshake(
+        &mut other(edited, PROTOCOL_VERSION, 5),
+        &mut vec![],
+        &config,
+        3,
+    );
+    assert!(matches!(result, Err(HandshakeError::Mismatch(reason)) if reason.ends_with("pieces")));
+    let result = handshake(&mut other(rules, 0, 5), &mut vec![], &config, 3);
+    assert!(matches!(result, Err(HandshakeError::Mismatch(_))));
+}
+
+#[test]
+fn test_piece_fingerprints() {
+    use crate::game::{builtin_masks, parse_masks, RotationSystem, BUILTIN_MASKS};
+
+    let builtin = builtin_masks().fingerprint();
+    assert_eq!(builtin, builtin_masks().fingerprint());
+    assert_ne!(builtin, RotationSystem::Srs.piece_set().fingerprint());
+    let kicked = parse_masks(&format!("{}\nkicks 0>1 0,0 -1,2\n", BUILTIN_MASKS)).unwrap();
+    assert_ne!(builtin, kicked.fingerprint());
+}
@@ -0,0 +1,154 @@
+use crate::game::{
+    imitation::load_records,
+    render::{Color, Rect, Renderer, SvgRenderer},
+    replay::{LockRecord, ReplayReadResult},
+    Game, PieceId, GAME_HEIGHT, GAME_WIDTH,
+};
+use std::{fs, io, path::Path};
+
+// how often every cell was covered by a locked piece, per piece type, to see where one
+// stacks, e.g. that I pieces always go to the right edge or T pieces never leave the middle
+//
+// play --heatmap <svg> saves the one of the game when it ends, tetris heatmap --replays <dir>
+// that of every replay in dir
+pub struct Heatmap {
+    counts: Vec<[[usize; GAME_WIDTH]; GAME_HEIGHT]>, // in the order of PieceId::ALL
+}
+
+const CELL: f32 = 12.;
+const MARGIN: f32 = 10.;
+const LABEL: f32 = 24.; // room for the name above a board
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            counts: vec![[[0; GAME_WIDTH]; GAME_HEIGHT]; PieceId::ALL.len()],
+        }
+    }
+}
+
+impl Heatmap {
+    pub fn of_records(records: &[LockRecord]) -> Self {
+        let mut heatmap = Self::default();
+        // the masks of the default rotation system, like imitation
+        let game = Game::new();
+        for record in records {
+            let i = PieceId::ALL.iter().position(|&id| id == record.id).unwrap();
+            let mask = &game.mask_map[&record.id][record.mask_idx];
+            for (x, y) in mask.cells_at(record.pos) {
+                if (0..GAME_WIDTH as isize).contains(&x) && (0..GAME_HEIGHT as isize).contains(&y) {
+                    heatmap.counts[i][y as usize][x as usize] += 1;
+                }
+            }
+        }
+        heatmap
+    }
+
+    // every replay in dir, input replays (.replay) are played back
+    pub fn of_replays(dir: &Path) -> ReplayReadResult<Self> {
+        let mut records = vec![];
+        for entry in fs::read_dir(dir)? {
+            records.extend(load_records(&entry?.path())?);
+        }
+        Ok(Self::of_records(&records))
+    }
+
+    // all pieces together
+    fn total(&self) -> [[usize; GAME_WIDTH]; GAME_HEIGHT] {
+        let mut total = [[0; GAME_WIDTH]; GAME_HEIGHT];
+        for counts in &self.counts {
+            for (y, row) in counts.iter().enumerate() {
+                for (x, count) in row.iter().enumerate() {
+                    total[y][x] += count;
+                }
+            }
+        }
+        total
+    }
+
+    // one small board for all pieces and one for each type, in a row
+    fn add_to(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        let boards = std::iter::once(("all".to_string(), self.total(), Color::WHITE)).chain(
+            PieceId::ALL
+                .iter()
+                .zip(&self.counts)
+                .map(|(&id, &counts)| (id.to_char().to_string(), counts, id.color())),
+        );
+        for (i, (name, counts, color)) in boards.enumerate() {
+            let x = left + i as f32 * (GAME_WIDTH as f32 * CELL + MARGIN);
+            r.text((x, top), &name, Color::WHITE);
+            add_board(&counts, color, (x, top + LABEL), r);
+        }
+    }
+
+    pub fn to_svg(&self) -> String {
+        let boards = PieceId::ALL.len() + 1;
+        let width = MARGIN + boards as f32 * (GAME_WIDTH as f32 * CELL + MARGIN);
+        let height = 2. * MARGIN + LABEL + GAME_HEIGHT as f32 * CELL;
+        let mut svg = SvgRenderer::default();
+        svg.rect(
+            Rect {
+                x: 0.,
+                y: 0.,
+                w: width,
+                h: height,
+            },
+            Color::BLACK,
+        );
+        self.add_to((MARGIN, MARGIN), &mut svg);
+        svg.finish(width, height)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_svg())
+    }
+}
+
+// the cells in the color, darker the less they were covered and left out if never
+fn add_board(
+    counts: &[[usize; GAME_WIDTH]; GAME_HEIGHT],
+    color: Color,
+    (left, top): (f32, f32),
+    r: &mut dyn Renderer,
+) {
+    let bg_rect = Rect {
+        x: left,
+        y: top,
+        w: GAME_WIDTH as f32 * CELL,
+        h: GAME_HEIGHT as f32 * CELL,
+    };
+    r.rect(bg_rect, Color::rgb(28, 28, 28));
+    let most = counts.iter().flatten().copied().max().unwrap_or(0).max(1);
+    for (y, row) in counts.iter().enumerate() {
+        for (x, &count) in row.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let rect = Rect {
+                x: left + x as f32 * CELL,
+                y: top + y as f32 * CELL,
+                w: CELL,
+                h: CELL,
+            };
+            let share = count as f32 / most as f32;
+            r.rect(rect, color.scaled(0.15 + 0.85 * share));
+        }
+    }
+}
+
+#[test]
+fn test_heatmap_counts_locked_cells() {
+    use crate::game::{heuristic::HeuristicBot, placement::play_headless};
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    game.start_recording();
+    play_headless(&mut game, |game| bot.choose(game), 20);
+
+    let heatmap = Heatmap::of_records(game.recording().unwrap());
+    // every piece covers four cells
+    let covered = heatmap.total().iter().flatten().sum::<usize>();
+    assert_eq!(covered, 4 * 20);
+    let svg = heatmap.to_svg();
+    assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+    assert!(svg.contains(">all</text>"));
+}
@@ -0,0 +1,255 @@
+use crate::game::{
+    clear_lines,
+    placement::{best_beam, best_by, best_lookahead, occupied, play_headless, Placement},
+    versus::{attack, Incoming},
+    Board, Game, Pixel, GAME_HEIGHT, GAME_WIDTH,
+};
+
+// Dellacherie's features with the weights tuned by El-Tetris
+// (landing height, eroded cells, row transitions, column transitions, holes, wells)
+const DEFAULT_WEIGHTS: [f64; 6] = [
+    -4.500_158_825_082_766,
+    3.418_126_810_139_269_4,
+    -3.217_888_286_848_775_3,
+    -9.348_695_305_445_199,
+    -7.899_265_427_351_652,
+    -3.385_597_224_726_362_6,
+];
+
+fn is_full(board: &Board, x: isize, y: isize) -> bool {
+    // walls and floor count as full
+    x < 0
+        || x >= GAME_WIDTH as isize
+        || y >= GAME_HEIGHT as isize
+        || (y >= 0 && !board[y as usize][x as usize].is_empty())
+}
+
+// changes between full and empty along each row
+fn row_transitions(board: &Board) -> usize {
+    (0..GAME_HEIGHT as isize)
+        .map(|y| {
+            (-1..GAME_WIDTH as isize)
+                .filter(|&x| is_full(board, x, y) != is_full(board, x + 1, y))
+                .count()
+        })
+        .sum()
+}
+
+// changes between full and empty along each column
+fn column_transitions(board: &Board) -> usize {
+    (0..GAME_WIDTH as isize)
+        .map(|x| {
+            (0..GAME_HEIGHT as isize)
+                .filter(|&y| is_full(board, x, y) != is_full(board, x, y + 1))
+                .count()
+        })
+        .sum()
+}
+
+// every well of depth n counts 1 + 2 + ... + n
+fn cumulative_wells(board: &Board) -> usize {
+    let mut sum = 0;
+    for x in 0..GAME_WIDTH as isize {
+        let mut depth = 0;
+        for y in 0..GAME_HEIGHT as isize {
+            if !is_full(board, x, y) && is_full(board, x - 1, y) && is_full(board, x + 1, y) {
+                depth += 1;
+                sum += depth;
+            } else {
+                depth = 0;
+            }
+        }
+    }
+    sum
+}
+
+// added to the evaluation in versus: with garbage incoming or the stack above PRESSURE_HEIGHT
+// every cleared line cancels a row or buys time so any clear is good (downstacking), when safe
+// lines are worth the garbage they send and the ones that send none are burnt, so singles and
+// doubles are put off for tetrises
+const PRESSURE_HEIGHT: usize = 10;
+const CLEAR_UNDER_PRESSURE: f64 = 12.;
+const INCOMING_HEIGHT: f64 = -2.; // for every row that's incoming, on top of the stack
+const SENT_WHEN_SAFE: f64 = 8.;
+const BURNT_WHEN_SAFE: f64 = -6.;
+
+// a known-good hand-tuned policy, used as a baseline for evolved networks
+pub struct HeuristicBot {
+    weights: [f64; 6],
+}
+
+impl HeuristicBot {
+    pub fn new() -> Self {
+        Self {
+            weights: DEFAULT_WEIGHTS,
+        }
+    }
+
+    fn evaluate(&self, game: &Game, placement: &Placement) -> f64 {
+        let id = game.falling.as_ref().expect("no falling piece").id;
+        let cells = occupied(&game.mask_map[&id][placement.mask_idx], placement.pos);
+
+        let mut board = game.board;
+        for &(x, y) in cells.iter() {
+            board[y as usize][x as usize] = Pixel::Full(id);
+        }
+
+        // measured from the ground, middle of the piece
+        let landing_height = GAME_HEIGHT as f64
+            - cells.iter().map(|&(_, y)| y as f64).sum::<f64>() / cells.len() as f64;
+        let full_rows = (0..GAME_HEIGHT)
+            .filter(|&y| board[y].iter().all(|px| !px.is_empty()))
+            .collect::<Vec<_>>();
+        let piece_cells_cleared = cells
+            .iter()
+            .filter(|&&(_, y)| full_rows.contains(&(y as usize)))
+            .count();
+        let eroded = full_rows.len() * piece_cells_cleared;
+
+        clear_lines(&mut board);
+        let metrics = placement.after_metrics(game, &board, full_rows.len());
+        let features = [
+            landing_height,
+            eroded as f64,
+            row_transitions(&board) as f64,
+            column_transitions(&board) as f64,
+            metrics.holes() as f64,
+            cumulative_wells(&board) as f64,
+        ];
+        features
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(f, w)| f * w)
+            .sum()
+    }
+
+    // evaluate with the garbage meter of a versus game, see PRESSURE_HEIGHT
+    fn evaluate_versus(&self, game: &Game, placement: &Placement, incoming: &Incoming) -> f64 {
+        let (board, cleared) = placement.afterstate(game);
+        let height = placement.after_metrics(game, &board, cleared).max_height();
+        let rows = incoming.rows();
+        let pressure = if rows > 0 || height > PRESSURE_HEIGHT {
+            CLEAR_UNDER_PRESSURE * cleared as f64
+                + INCOMING_HEIGHT * rows.saturating_sub(cleared) as f64
+        } else if attack(cleared) > 0 {
+            SENT_WHEN_SAFE * attack(cleared) as f64
+        } else {
+            BURNT_WHEN_SAFE * cleared as f64
+        };
+        self.evaluate(game, placement) + pressure
+    }
+
+    pub fn choose(&self, game: &Game) -> Option<Placement> {
+        best_by(game, |p| self.evaluate(game, p))
+    }
+
+    pub fn choose_versus(&self, game: &Game, incoming: &Incoming) -> Option<Placement> {
+        best_by(game, |p| self.evaluate_versus(game, p, incoming))
+    }
+}
+
+// the heuristic bot's evaluation summed over the current piece and the next ones, with
+// hold, stronger but a lot slower
+pub struct LookaheadBot {
+    heuristic: HeuristicBot,
+    depth: usize, // pieces, the current one included
+    width: usize, // placements of each piece looked further into
+}
+
+impl LookaheadBot {
+    pub const DEPTH: usize = 2;
+    const WIDTH: usize = 6;
+
+    pub fn new(depth: usize) -> Self {
+        Self {
+            heuristic: HeuristicBot::new(),
+            depth,
+            width: Self::WIDTH,
+        }
+    }
+
+    pub fn choose(&self, game: &Game) -> Option<Placement> {
+        let mut score = |game: &Game, p: &Placement| self.heuristic.evaluate(game, p);
+        best_lookahead(game, self.depth, self.width, &mut score)
+    }
+
+    // the meter as it is now stands in for the whole line
+    pub fn choose_versus(&self, game: &Game, incoming: &Incoming) -> Option<Placement> {
+        let mut score =
+            |game: &Game, p: &Placement| self.heuristic.evaluate_versus(game, p, incoming);
+        best_lookahead(game, self.depth, self.width, &mut score)
+    }
+}
+
+// the heuristic bot's evaluation summed over the pieces of the best lines a beam search
+// finds, with hold, deeper than the lookahead bot for about the same time: the strongest
+// built-in bot, as a hard opponent and as a teacher for imitation
+pub struct BeamBot {
+    heuristic: HeuristicBot,
+    depth: usize, // pieces, the current one included
+    width: usize, // lines kept after each piece
+}
+
+impl BeamBot {
+    pub const DEPTH: usize = 4;
+    const WIDTH: usize = 8;
+
+    pub fn new(depth: usize) -> Self {
+        Self {
+            heuristic: HeuristicBot::new(),
+            depth,
+            width: Self::WIDTH,
+        }
+    }
+
+    pub fn choose(&self, game: &Game) -> Option<Placement> {
+        let mut score = |game: &Game, p: &Placement| self.heuristic.evaluate(game, p);
+        best_beam(game, self.depth, self.width, &mut score)
+    }
+
+    pub fn choose_versus(&self, game: &Game, incoming: &Incoming) -> Option<Placement> {
+        let mut score =
+            |game: &Game, p: &Placement| self.heuristic.evaluate_versus(game, p, incoming);
+        best_beam(game, self.depth, self.width, &mut score)
+    }
+}
+
+impl Default for HeuristicBot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// points scored by the heuristic bot in a fresh game, for comparison with evolved networks
+pub fn baseline_fitness(max_pieces: usize) -> usize {
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    play_headless(&mut game, |game| bot.choose(game), max_pieces);
+    game.points
+}
+
+#[test]
+fn test_heuristic_bot_survives() {
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    assert_eq!(play_headless(&mut game, |game| bot.choose(game), 200), 200);
+    assert!(!game.is_over());
+    assert!(game.cleared > 0);
+}
+
+#[test]
+fn test_versus_evaluation_downstacks_under_pressure() {
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    game.load_board_from_ascii("#########.").unwrap();
+    game.spawn_with_id(crate::game::PieceId::IBlock);
+    let cleared = |incoming: &Incoming| {
+        let p = bot.choose_versus(&game, incoming).unwrap();
+        p.afterstate(&game).1
+    };
+    // the well is kept for a tetris while it's safe and the single taken once garbage comes
+    let mut incoming = Incoming::default();
+    assert_eq!(cleared(&incoming), 0);
+    incoming.receive(4);
+    assert_eq!(cleared(&incoming), 1);
+}
@@ -0,0 +1,309 @@
+use crate::{
+    game::{
+        bot::{BoardView, TetrisBot},
+        features::InputEncoder,
+        placement::{occupied, placements, play_headless},
+        replay::{load_input_replay, load_replay, save_replay, LockRecord, ReplayReadResult},
+        simulator::Simulator,
+        Game,
+    },
+    neural_network::{ActivationType, NN},
+};
+use itertools::Itertools;
+use rand::prelude::*;
+use std::{collections::VecDeque, fmt, fs, io, num::ParseFloatError, path::Path};
+
+// an encoded afterstate and 1 if it is the placement the player chose, 0 otherwise
+type Pair = (Box<[f64]>, f64);
+
+// every reachable placement of a recorded piece as a pair, None if the choice can't be
+// reproduced
+fn training_pairs(record: &LockRecord, encoder: InputEncoder) -> Option<Vec<Pair>> {
+    let mut game = Game::new();
+    game.set_board(record.board);
+    game.piece_queue.queue = record.next.iter().copied().collect::<VecDeque<_>>();
+    game.spawn_with_id(record.id);
+    if game.is_over() {
+        return None;
+    }
+
+    let chosen = occupied(&game.mask_map[&record.id][record.mask_idx], record.pos);
+    let mut next = record.next.iter().copied();
+    // after the placement, the next piece becomes the current one
+    let (current, next) = (next.next(), next.next());
+    let mut found = false;
+    let pairs = placements(&game)
+        .into_iter()
+        .map(|p| {
+            let is_chosen = occupied(&game.mask_map[&record.id][p.mask_idx], p.pos) == chosen;
+            found |= is_chosen;
+            let (board, _) = p.afterstate(&game);
+            let label = if is_chosen { 1. } else { 0. };
+            (encoder.encode_board(&board, current, next), label)
+        })
+        .collect();
+    if found {
+        Some(pairs)
+    } else {
+        None
+    }
+}
+
+// the locked pieces of an input replay (.replay, as saved by play --record) are found by
+// playing it back, any other file is read as a list of locked pieces
+pub(super) fn load_records(path: &Path) -> ReplayReadResult<Vec<LockRecord>> {
+    if path.extension().is_some_and(|ext| ext == "replay") {
+        let sim = Simulator::replay(&load_input_replay(path)?);
+        Ok(sim.recording().unwrap_or_default().to_vec())
+    } else {
+        load_replay(path)
+    }
+}
+
+// the training pairs of every piece of every replay in dir, one list per piece
+fn pieces_in(dir: &Path, encoder: InputEncoder) -> ReplayReadResult<Vec<Vec<Pair>>> {
+    let mut pieces = vec![];
+    let mut skipped = 0;
+    for entry in fs::read_dir(dir)? {
+        for record in load_records(&entry?.path())? {
+            match training_pairs(&record, encoder) {
+                Some(pairs) => pieces.push(pairs),
+                None => skipped += 1,
+            }
+        }
+    }
+    if skipped > 0 {
+        eprintln!("Warning: skipped {} unreproducible placements", skipped);
+    }
+    Ok(pieces)
+}
+
+// training pairs from every replay in dir
+pub fn build_dataset(dir: &Path, encoder: InputEncoder) -> ReplayReadResult<Vec<Pair>> {
+    Ok(pieces_in(dir, encoder)?.into_iter().flatten().collect())
+}
+
+// pairs to fit to and pairs to check the fit on, never from the same piece
+#[derive(Default)]
+pub struct Dataset {
+    pub train: Vec<Pair>,
+    pub validation: Vec<Pair>,
+}
+
+#[derive(From, Debug)]
+pub enum DatasetReadError {
+    IoError(io::Error),
+    ParseFloatError(ParseFloatError),
+    #[from(ignore)]
+    Other(String),
+}
+
+pub type DatasetReadResult<T> = Result<T, DatasetReadError>;
+
+impl fmt::Display for DatasetReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read dataset: {}", e),
+            Self::ParseFloatError(e) => write!(f, "invalid dataset: {}", e),
+            Self::Other(e) => write!(f, "invalid dataset: {}", e),
+        }
+    }
+}
+
+// the replays in dir as a dataset file for imitate --dataset, one pair per line as
+// "<t|v> <label> <encoding>" (train or validation, the encoding comma separated), the
+// pieces are shuffled and a validation share of them is held out, return the number of
+// train and validation pairs
+pub fn export_dataset(
+    dir: &Path,
+    encoder: InputEncoder,
+    validation: f64,
+    out: &Path,
+) -> ReplayReadResult<(usize, usize)> {
+    let mut pieces = pieces_in(dir, encoder)?;
+    pieces.shuffle(&mut thread_rng());
+    let held_out = (pieces.len() as f64 * validation).round() as usize;
+    let (validation, train) = pieces.split_at(held_out.min(pieces.len()));
+    let lines = |split: char, pieces: &[Vec<Pair>]| {
+        pieces
+            .iter()
+            .flatten()
+            .map(|(input, label)| format!("{} {} {}\n", split, label, input.iter().join(",")))
+            .collect::<String>()
+    };
+    fs::write(out, lines('t', train) + &lines('v', validation))?;
+    let count = |pieces: &[Vec<Pair>]| pieces.iter().map(Vec::len).sum();
+    Ok((count(train), count(validation)))
+}
+
+// a file written by export_dataset, its encodings have to be of the given encoder
+pub fn load_dataset<P: AsRef<Path>>(path: P, encoder: InputEncoder) -> DatasetReadResult<Dataset> {
+    let mut dataset = Dataset::default();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let invalid = || DatasetReadError::Other(format!("invalid pair on line {}", i + 1));
+        let mut fields = line.split(' ');
+        let (split, label, input) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(split), Some(label), Some(input)) => (split, label.parse()?, input),
+            _ => return Err(invalid()),
+        };
+        let input = input
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Box<[f64]>, _>>()?;
+        if input.len() != encoder.input_size() {
+            return Err(DatasetReadError::Other(
+                "the dataset was exported with another encoder".to_string(),
+            ));
+        }
+        match split {
+            "t" => dataset.train.push((input, label)),
+            "v" => dataset.validation.push((input, label)),
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(dataset)
+}
+
+// fits a placement-scoring network (same shape as the trainer's placement mode) to
+// the choices made in the replays in dir, the result can seed a generation
+#[allow(dead_code)]
+pub fn imitate(dir: &Path, encoder: InputEncoder, epochs: usize) -> ReplayReadResult<NN> {
+    let dataset = Dataset {
+        train: build_dataset(dir, encoder)?,
+        validation: vec![],
+    };
+    Ok(imitate_dataset(dataset, encoder, epochs))
+}
+
+// like imitate, with the loss on the validation pairs printed after every epoch
+pub fn imitate_dataset(mut dataset: Dataset, encoder: InputEncoder, epochs: usize) -> NN {
+    let mut nn = NN::make(encoder.input_size())
+        .init_range(-0.1, 0.1)
+        .add_layer(20, ActivationType::Relu)
+        .add_layer(10, ActivationType::Relu)
+        .add_layer(1, ActivationType::Sigmoid)
+        .build()
+        .expect("network has layers");
+    let mut rng = thread_rng();
+    for epoch in 0..epochs {
+        dataset.train.shuffle(&mut rng);
+        let loss = dataset
+            .train
+            .iter()
+            .map(|(input, label)| nn.train(input, &[*label], 0.01))
+            .sum::<f64>()
+            / dataset.train.len() as f64;
+        if dataset.validation.is_empty() {
+            println!("epoch {}: loss {:.5}", epoch, loss);
+        } else {
+            // the same squared error train returns, without the step
+            let validation = dataset
+                .validation
+                .iter()
+                .map(|(input, label)| (nn.apply(input)[0] - label).powi(2) / 2.)
+                .sum::<f64>()
+                / dataset.validation.len() as f64;
+            println!(
+                "epoch {}: loss {:.5}, validation {:.5}",
+                epoch, loss, validation
+            );
+        }
+    }
+    nn
+}
+
+// lets a bot (beam for the best one) play games with seeds 0..games and saves the pieces it
+// locks in dir as teacher_<seed>.txt, for imitate to learn from like any other replays
+#[allow(dead_code)]
+pub fn record_teacher(
+    bot: &mut dyn TetrisBot,
+    dir: &Path,
+    games: usize,
+    max_pieces: usize,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for seed in 0..games as u64 {
+        let mut game = Game::with_seed(seed);
+        game.start_recording();
+        play_headless(
+            &mut game,
+            |game| bot.choose(&BoardView::new(game)).placement(),
+            max_pieces,
+        );
+        let path = dir.join(format!("teacher_{}.txt", seed));
+        save_replay(path, game.recording().unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_recorded_placements_are_reproducible() {
+    use crate::game::{heuristic::HeuristicBot, placement::play_headless, replay::save_replay};
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    game.start_recording();
+    play_headless(&mut game, |game| bot.choose(game), 30);
+
+    let path = "temporary_test_replay.txt";
+    save_replay(path, game.recording().unwrap()).unwrap();
+    let records = load_replay(path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(records.len(), 30);
+    for record in records.iter() {
+        let pairs = training_pairs(record, InputEncoder::Features).unwrap();
+        assert_eq!(pairs.iter().filter(|(_, label)| *label == 1.).count(), 1);
+    }
+}
+
+#[test]
+fn test_exported_datasets_split_by_piece() {
+    use crate::game::{heuristic::HeuristicBot, replay::save_replay};
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    game.start_recording();
+    play_headless(&mut game, |game| bot.choose(game), 20);
+
+    let dir = crate::game::temp_dir::TempDir::new("dataset");
+    let replays = dir.join("replays");
+    fs::create_dir_all(&replays).unwrap();
+    save_replay(replays.join("game.txt"), game.recording().unwrap()).unwrap();
+    let out = dir.join("dataset.txt");
+    let (train, validation) = export_dataset(&replays, InputEncoder::Features, 0.25, &out).unwrap();
+    let dataset = load_dataset(&out, InputEncoder::Features).unwrap();
+    let wrong_encoder = load_dataset(&out, InputEncoder::Heights);
+
+    assert_eq!(dataset.train.len(), train);
+    assert_eq!(dataset.validation.len(), validation);
+    // one chosen placement per piece, 5 of the 20 pieces held out
+    let chosen = |pairs: &[Pair]| pairs.iter().filter(|(_, label)| *label == 1.).count();
+    assert_eq!(chosen(&dataset.train), 15);
+    assert_eq!(chosen(&dataset.validation), 5);
+    assert!(wrong_encoder.is_err());
+}
+
+#[test]
+fn test_input_replays_give_the_same_records() {
+    use crate::game::{
+        heuristic::HeuristicBot, placement::play_headless, replay::save_input_replay,
+    };
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    game.start_recording();
+    game.start_input_log();
+    play_headless(&mut game, |game| bot.choose(game), 20);
+
+    let path = Path::new("temporary_test_replay.replay");
+    save_input_replay(path, &game.input_replay().unwrap()).unwrap();
+    let records = load_records(path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    let placed = |records: &[LockRecord]| {
+        records
+            .iter()
+            .map(|r| (r.id, r.mask_idx, r.pos))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(records.len(), 20);
+    assert_eq!(placed(&records), placed(game.recording().unwrap()));
+}
@@ -0,0 +1,134 @@
+use crate::game::{
+    intersects_with, zobrist::hash_board, Action, Game, HoldMode, PieceId, PieceQueue,
+    RotationSystem, GAME_HEIGHT, GAME_WIDTH,
+};
+use proptest::prelude::*;
+
+// property tests over random input sequences, every rule change has to keep these true
+
+// an action applied right away followed by a number of frames
+fn inputs() -> impl Strategy<Value = Vec<(Action, usize)>> {
+    prop::collection::vec((prop::sample::select(&Action::ALL[..]), 0..5usize), 0..300)
+}
+
+fn filled(game: &Game) -> usize {
+    game.board
+        .iter()
+        .flat_map(|row| row.iter())
+        .filter(|px| !px.is_empty())
+        .count()
+}
+
+// everything that may only grow, in order
+fn progress(game: &Game) -> [usize; 4] {
+    [game.points, game.level, game.cleared, game.pieces]
+}
+
+fn check_state(game: &Game, seed: u64) -> Result<(), TestCaseError> {
+    // there is a falling piece unless the game is over or waiting for the next one
+    prop_assert_eq!(game.falling.is_none(), game.is_over() || game.is_waiting());
+    prop_assert!(game.is_waiting() || game.buffered.is_empty());
+    if let Some(falling) = &game.falling {
+        for (x, y) in falling.mask.cells_at(falling.pos) {
+            prop_assert!(x >= 0 && x < GAME_WIDTH as isize, "x = {}", x);
+            prop_assert!(y >= 0 && y < GAME_HEIGHT as isize, "y = {}", y);
+            prop_assert!(
+                game.board[y as usize][x as usize].is_empty(),
+                "falling piece overlaps the board at ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+    prop_assert_eq!(game.board_hash(), hash_board(&game.board));
+    if let Some(falling) = &game.falling {
+        // the cached ghost against a fresh scan
+        let (x, mut y) = falling.pos;
+        while !intersects_with(&falling.mask, (x, y + 1), &game.board) {
+            y += 1
+        }
+        prop_assert_eq!(game.ghost_pos(), Some((x, y)));
+    }
+    for row in game.board.iter() {
+        prop_assert!(
+            row.iter().any(|px| px.is_empty()),
+            "full row left on the board"
+        );
+    }
+
+    // every dealt piece is locked, falling or held exactly once (the piece that couldn't
+    // be spawned when the game ended is lost)
+    if !game.is_over() {
+        let mut dealt = game
+            .recording()
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .chain(game.falling.as_ref().map(|falling| falling.id))
+            .chain(game.hold)
+            .collect::<Vec<_>>();
+        let mut queue = PieceQueue::new(Some(seed));
+        let mut expected = (0..dealt.len()).map(|_| queue.pop()).collect::<Vec<_>>();
+        let key = |id: &PieceId| id.to_char();
+        dealt.sort_by_key(key);
+        expected.sort_by_key(key);
+        for &id in PieceId::ALL {
+            let count = dealt.iter().filter(|&&d| d == id).count();
+            prop_assert_eq!(game.dealt(id), count, "{:?} dealt", id);
+        }
+        prop_assert_eq!(dealt, expected);
+        prop_assert!(game.piece_queue.iter().eq(queue.iter()));
+    }
+    Ok(())
+}
+
+// lines are only cleared when full: each cleared line removes exactly GAME_WIDTH cells
+fn check_transition(
+    (filled_before, progress_before): (usize, [usize; 4]),
+    after: &Game,
+) -> Result<(), TestCaseError> {
+    let progress_after = progress(after);
+    for (b, a) in progress_before.iter().zip(progress_after.iter()) {
+        prop_assert!(a >= b, "{:?} -> {:?}", progress_before, progress_after);
+    }
+    let [_, _, cleared_before, pieces_before] = progress_before;
+    let locked = after.pieces - pieces_before;
+    let cleared = after.cleared - cleared_before;
+    prop_assert_eq!(
+        filled(after) + cleared * GAME_WIDTH,
+        filled_before + locked * 4
+    );
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_invariants_hold_for_any_inputs(
+        seed in any::<u64>(),
+        hold_mode in prop::sample::select(&HoldMode::ALL[..]),
+        rotation in prop::sample::select(&RotationSystem::ALL[..]),
+        (entry_delay, line_clear_delay) in (0..3usize, 0..3usize),
+        inputs in inputs(),
+    ) {
+        let mut game = Game::with_seed(seed);
+        game.set_hold_mode(hold_mode);
+        game.set_rotation_system(rotation);
+        game.set_delays(entry_delay, line_clear_delay);
+        game.start_recording();
+        check_state(&game, seed)?;
+        for (action, frames) in inputs {
+            let snapshot = (filled(&game), progress(&game));
+            let held = (game.hold, game.can_hold());
+            game.apply(action);
+            if action == Action::Hold && !held.1 {
+                prop_assert_eq!(game.hold, held.0, "held with {:?} hold", hold_mode);
+            }
+            check_state(&game, seed)?;
+            for _ in 0..frames {
+                game.iterate();
+                check_state(&game, seed)?;
+            }
+            check_transition(snapshot, &game)?;
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::game::{render::Command, Action};
+use ggez::input::keyboard::KeyCode;
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+// which key does what, a profile is one of the built-in layouts and a file (see --keys in
+// the README) starts from a profile and rebinds single commands:
+//
+//     profile = "wasd"
+//     [keys]
+//     Hold = ["LShift", "C"]
+//     Pause = ["Escape"]
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    #[default]
+    Classic, // this game's own keys
+    Guideline, // the standard keys
+    Wasd,      // for the left hand, rotations on Q and E
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Self::Classic),
+            "guideline" => Some(Self::Guideline),
+            "wasd" => Some(Self::Wasd),
+            _ => None,
+        }
+    }
+
+    fn bindings(self) -> Vec<(KeyCode, Command)> {
+        use Action::*;
+        use KeyCode::*;
+        let play = |keys: &[KeyCode], action| {
+            keys.iter()
+                .map(|&code| (code, Command::Play(action)))
+                .collect::<Vec<_>>()
+        };
+        let mut bindings = match self {
+            Self::Classic => vec![
+                play(&[Left], MoveLeft),
+                play(&[Right], MoveRight),
+                play(&[Down], SoftDrop),
+                play(&[Up], RotateCW),
+                play(&[RShift], RotateCCW),
+                play(&[A], Rotate180),
+                play(&[Space], HardDrop),
+                play(&[J], Hold),
+            ],
+            Self::Guideline => vec![
+                play(&[Left], MoveLeft),
+                play(&[Right], MoveRight),
+                play(&[Down], SoftDrop),
+                play(&[Up, X], RotateCW),
+                play(&[Z, LControl], RotateCCW),
+                play(&[A], Rotate180),
+                play(&[Space], HardDrop),
+                play(&[C, LShift], Hold),
+            ],
+            Self::Wasd => vec![
+                play(&[A], MoveLeft),
+                play(&[D], MoveRight),
+                play(&[S], SoftDrop),
+                play(&[E], RotateCW),
+                play(&[Q], RotateCCW),
+                play(&[R], Rotate180),
+                play(&[W], HardDrop),
+                play(&[LShift], Hold),
+            ],
+        }
+        .concat();
+        bindings.push((Escape, Command::Pause));
+        bindings.push((Tab, Command::SwitchLayout));
+        bindings.push((F9, Command::Freeze));
+        bindings.push((F10, Command::Step));
+        bindings.push((F2, Command::TakeOver));
+        bindings
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyMap {
+    commands: HashMap<KeyCode, Command>,
+}
+
+impl KeyMap {
+    pub fn command(&self, code: KeyCode) -> Option<Command> {
+        self.commands.get(&code).copied()
+    }
+}
+
+impl From<Profile> for KeyMap {
+    fn from(profile: Profile) -> Self {
+        Self {
+            commands: profile.bindings().into_iter().collect(),
+        }
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Profile::default().into()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyMapFile {
+    #[serde(default)]
+    profile: Profile,
+    #[serde(default)]
+    keys: HashMap<String, Vec<String>>, // key names by command name
+}
+
+#[derive(From, Debug)]
+pub enum KeyMapLoadError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+    #[from(ignore)]
+    UnknownCommand(String),
+    #[from(ignore)]
+    UnknownKey(String),
+}
+
+pub type KeyMapLoadResult<T> = Result<T, KeyMapLoadError>;
+
+impl fmt::Display for KeyMapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read key bindings: {}", e),
+            Self::TomlError(e) => write!(f, "invalid key bindings: {}", e),
+            Self::UnknownCommand(name) => write!(f, "no command is called \"{}\"", name),
+            Self::UnknownKey(name) => write!(f, "no key is called \"{}\"", name),
+        }
+    }
+}
+
+fn command_from_name(name: &str) -> Option<Command> {
+    match name {
+        "Pause" => Some(Command::Pause),
+        "SwitchLayout" => Some(Command::SwitchLayout),
+        "Freeze" => Some(Command::Freeze),
+        "Step" => Some(Command::Step),
+        "TakeOver" => Some(Command::TakeOver),
+        _ => Action::from_name(name).map(Command::Play),
+    }
+}
+
+// by the variant name, only the keys anyone would bind a command to
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    macro_rules! keys {
+        ($( $code:ident ),* $(,)?) => {
+            match name {
+                $( stringify!($code) => Some(KeyCode::$code), )*
+                _ => None,
+            }
+        };
+    }
+    keys!(
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1,
+        Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Numpad0, Numpad1, Numpad2, Numpad3,
+        Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, Left, Right, Up, Down, Space, Return,
+        Tab, Escape, Back, LShift, RShift, LControl, RControl, LAlt, RAlt, Comma, Period, Slash,
+        Semicolon, Apostrophe, LBracket, RBracket, Backslash, Minus, Equals, F1, F2, F3, F4, F5,
+        F6, F7, F8, F9, F10, F11, F12,
+    )
+}
+
+fn parse_keymap(text: &str) -> KeyMapLoadResult<KeyMap> {
+    let file: KeyMapFile = toml::from_str(text)?;
+    let mut commands = KeyMap::from(file.profile).commands;
+    for (name, keys) in file.keys {
+        let command = command_from_name(&name).ok_or(KeyMapLoadError::UnknownCommand(name))?;
+        // the profile's keys for the command are replaced, not added to
+        commands.retain(|_, c| *c != command);
+        for key in keys {
+            let code = key_from_name(&key).ok_or(KeyMapLoadError::UnknownKey(key))?;
+            commands.insert(code, command);
+        }
+    }
+    Ok(KeyMap { commands })
+}
+
+pub fn load_keymap<P: AsRef<Path>>(path: P) -> KeyMapLoadResult<KeyMap> {
+    parse_keymap(&fs::read_to_string(path)?)
+}
+
+#[test]
+fn test_keymap_rebinds_single_commands() {
+    let keymap = parse_keymap("profile = \"wasd\"\n[keys]\nHold = [\"C\", \"Key1\"]").unwrap();
+    assert_eq!(
+        keymap.command(KeyCode::C),
+        Some(Command::Play(Action::Hold))
+    );
+    assert_eq!(keymap.command(KeyCode::LShift), None);
+    assert_eq!(keymap.command(KeyCode::F2), Some(Command::TakeOver));
+    assert_eq!(
+        keymap.command(KeyCode::A),
+        Some(Command::Play(Action::MoveLeft))
+    );
+    assert!(matches!(
+        parse_keymap("[keys]\nSpin = [\"A\"]"),
+        Err(KeyMapLoadError::UnknownCommand(_))
+    ));
+    assert!(matches!(
+        parse_keymap("[keys]\nHold = [\"Shift\"]"),
+        Err(KeyMapLoadError::UnknownKey(_))
+    ));
+}
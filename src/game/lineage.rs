@@ -0,0 +1,176 @@
+use crate::neural_network::{NNReadError, NN};
+use itertools::Itertools;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    num::{ParseFloatError, ParseIntError},
+    path::Path,
+};
+
+// where a genome came from, one line of <dir>/lineage.txt:
+// "<id> <parent or -> <generation> <mutation rate> <mutation strength>"
+#[derive(Clone, Debug)]
+pub struct LineageRecord {
+    pub id: usize,
+    pub parent: Option<usize>, // None for random and migrated genomes
+    pub generation: usize,     // in which the genome was created
+    pub mutation_rate: f64,
+    pub mutation_strength: f64,
+}
+
+#[derive(From, Debug)]
+pub enum LineageReadError {
+    IoError(io::Error),
+    ParseIntError(ParseIntError),
+    ParseFloatError(ParseFloatError),
+    NNReadError(NNReadError),
+    Other(String),
+}
+
+pub type LineageReadResult<T> = Result<T, LineageReadError>;
+
+impl fmt::Display for LineageReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read lineage: {}", e),
+            Self::ParseIntError(e) => write!(f, "invalid lineage: {}", e),
+            Self::ParseFloatError(e) => write!(f, "invalid lineage: {}", e),
+            Self::NNReadError(e) => write!(f, "{}", e),
+            Self::Other(e) => write!(f, "invalid lineage: {}", e),
+        }
+    }
+}
+
+impl LineageRecord {
+    fn to_line(&self) -> String {
+        let parent = self.parent.map_or("-".to_string(), |p| p.to_string());
+        format!(
+            "{} {} {} {} {}",
+            self.id, parent, self.generation, self.mutation_rate, self.mutation_strength
+        )
+    }
+
+    fn from_line(line: &str) -> LineageReadResult<Self> {
+        let invalid = || LineageReadError::Other(format!("invalid record: \"{}\"", line));
+        let mut split = line.split(' ');
+        let mut field = || split.next().ok_or_else(invalid);
+        Ok(Self {
+            id: field()?.parse()?,
+            parent: match field()? {
+                "-" => None,
+                p => Some(p.parse()?),
+            },
+            generation: field()?.parse()?,
+            mutation_rate: field()?.parse()?,
+            mutation_strength: field()?.parse()?,
+        })
+    }
+}
+
+pub(super) fn genome_path(dir: &Path, id: usize) -> std::path::PathBuf {
+    dir.join("genomes").join(format!("{}.txt", id))
+}
+
+// adds to <dir>/lineage.txt, creating it if necessary
+pub(super) fn append_records(dir: &Path, records: &[LineageRecord]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("lineage.txt"))?;
+    for record in records {
+        writeln!(file, "{}", record.to_line())?;
+    }
+    Ok(())
+}
+
+pub(super) fn load_records(dir: &Path) -> LineageReadResult<HashMap<usize, LineageRecord>> {
+    fs::read_to_string(dir.join("lineage.txt"))?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| LineageRecord::from_line(l).map(|r| (r.id, r)))
+        .collect()
+}
+
+// <dir>/ids.txt, ids of the saved generation in the same order
+pub(super) fn save_ids(dir: &Path, ids: &[usize]) -> io::Result<()> {
+    fs::write(dir.join("ids.txt"), ids.iter().join(" "))
+}
+
+pub(super) fn load_ids(dir: &Path) -> LineageReadResult<Vec<usize>> {
+    fs::read_to_string(dir.join("ids.txt"))?
+        .split_whitespace()
+        .map(|id| Ok(id.parse()?))
+        .collect()
+}
+
+// prints the ancestors of a genome (the best one of the saved generation by default)
+// with the weight-space distance to each of them, ancestors are only saved once they
+// have children so the genome itself has to be in the saved generation or have children
+pub fn report_lineage(dir: &Path, id: Option<usize>) -> LineageReadResult<()> {
+    let records = load_records(dir)?;
+    let ids = load_ids(dir)?;
+    let id = match id {
+        Some(id) => id,
+        None => *ids
+            .first()
+            .ok_or_else(|| LineageReadError::Other("no saved generation".to_string()))?,
+    };
+    let nn = match ids.iter().position(|&i| i == id) {
+        Some(idx) => {
+            let generation = fs::read_to_string(dir.join("generation.txt"))?;
+            let string = generation.split("--\n").nth(idx).ok_or_else(|| {
+                LineageReadError::Other("ids don't match the saved generation".to_string())
+            })?;
+            NN::from_string(string)?
+        }
+        None => NN::from_file(genome_path(dir, id))?,
+    };
+
+    let mut current = records
+        .get(&id)
+        .ok_or_else(|| LineageReadError::Other(format!("unknown genome {}", id)))?;
+    println!(
+        "genome {}, created in generation {}",
+        id, current.generation
+    );
+    while let Some(parent) = current.parent {
+        let distance = NN::from_file(genome_path(dir, parent))
+            .ok()
+            .and_then(|ancestor| nn.distance(&ancestor))
+            .map_or("unknown".to_string(), |d| format!("{:.3}", d));
+        println!(
+            "  <- {} (mutation rate {:.3}, strength {:.3}), distance {}",
+            parent, current.mutation_rate, current.mutation_strength, distance
+        );
+        current = records
+            .get(&parent)
+            .ok_or_else(|| LineageReadError::Other(format!("unknown genome {}", parent)))?;
+    }
+    let origin = if current.generation == 0 {
+        "random"
+    } else {
+        "migrated"
+    };
+    println!(
+        "  {} was created in generation {} ({})",
+        current.id, current.generation, origin
+    );
+    Ok(())
+}
+
+#[test]
+fn test_lineage_record_roundtrip() {
+    let record = LineageRecord {
+        id: 12,
+        parent: Some(3),
+        generation: 4,
+        mutation_rate: 0.1,
+        mutation_strength: 0.5,
+    };
+    let read = LineageRecord::from_line(&record.to_line()).unwrap();
+    assert_eq!(read.to_line(), record.to_line());
+    let random = LineageRecord::from_line("0 - 0 0 0").unwrap();
+    assert_eq!(random.parent, None);
+}
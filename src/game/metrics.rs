@@ -0,0 +1,175 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// where the numbers of every generation go, training writes them to <dir>/metrics.csv and,
+// with tensorboard = true in the config, to an event file in <dir> that
+// tensorboard --logdir <dir> shows as curves, other exporters can be added to a population
+pub trait MetricsExporter: Send {
+    // named values of one step, always with the same names in the same order
+    fn export(&mut self, step: usize, metrics: &[(&str, f64)]) -> io::Result<()>;
+}
+
+// one line per step after a header with the names, appends to an existing file
+pub struct CsvExporter {
+    file: File,
+    header: bool, // written already
+}
+
+impl CsvExporter {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let header = file.metadata()?.len() > 0;
+        Ok(Self { file, header })
+    }
+}
+
+impl MetricsExporter for CsvExporter {
+    fn export(&mut self, step: usize, metrics: &[(&str, f64)]) -> io::Result<()> {
+        if !self.header {
+            let names = metrics.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            writeln!(self.file, "step,{}", names.join(","))?;
+            self.header = true;
+        }
+        let values = metrics
+            .iter()
+            .map(|(_, value)| value.to_string())
+            .collect::<Vec<_>>();
+        writeln!(self.file, "{},{}", step, values.join(","))
+    }
+}
+
+// TensorBoard's event files are TFRecord files of Event protobufs, the handful of fields
+// scalars need are encoded by hand rather than pulling in protobuf
+pub struct TensorBoardExporter {
+    file: File,
+}
+
+impl TensorBoardExporter {
+    // a new event file in dir, named the way TensorBoard looks for them
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        let name = format!("events.out.tfevents.{}.tetris", wall_time() as u64);
+        let mut exporter = Self {
+            file: File::create(dir.join(name))?,
+        };
+        // the first event says which format the rest are in
+        let mut event = event_header(0);
+        field(&mut event, 3, 2, b"brain.Event:2");
+        exporter.write_record(&event)?;
+        Ok(exporter)
+    }
+
+    // length, its checksum, the data and its checksum
+    fn write_record(&mut self, data: &[u8]) -> io::Result<()> {
+        let length = (data.len() as u64).to_le_bytes();
+        self.file.write_all(&length)?;
+        self.file.write_all(&masked_crc(&length).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.file.write_all(&masked_crc(data).to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl MetricsExporter for TensorBoardExporter {
+    fn export(&mut self, step: usize, metrics: &[(&str, f64)]) -> io::Result<()> {
+        let mut summary = vec![];
+        for (name, value) in metrics {
+            // Summary.Value: tag = 1, simple_value = 2
+            let mut value_message = vec![];
+            field(&mut value_message, 1, 2, name.as_bytes());
+            field(&mut value_message, 2, 5, &(*value as f32).to_le_bytes());
+            field(&mut summary, 1, 2, &value_message);
+        }
+        let mut event = event_header(step);
+        field(&mut event, 5, 2, &summary);
+        self.write_record(&event)
+    }
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |t| t.as_secs_f64())
+}
+
+fn varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+// a protobuf field, wire type 2 is length-delimited, 5 is 32 bits and 1 is 64 bits
+fn field(out: &mut Vec<u8>, number: u64, wire_type: u64, data: &[u8]) {
+    varint(out, number << 3 | wire_type);
+    if wire_type == 2 {
+        varint(out, data.len() as u64);
+    }
+    out.extend_from_slice(data);
+}
+
+// Event: wall_time = 1, step = 2
+fn event_header(step: usize) -> Vec<u8> {
+    let mut event = vec![];
+    field(&mut event, 1, 1, &wall_time().to_le_bytes());
+    varint(&mut event, 2 << 3);
+    varint(&mut event, step as u64);
+    event
+}
+
+// crc32c (Castagnoli), bit by bit since records are small and few
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82f6_3b78 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    (crc.rotate_right(15)).wrapping_add(0xa282_ead8)
+}
+
+#[test]
+fn test_tensorboard_records() {
+    use std::convert::TryInto;
+    assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    let dir = crate::game::temp_dir::TempDir::new("metrics");
+    let mut exporter = TensorBoardExporter::create(&dir).unwrap();
+    exporter.export(3, &[("fitness/best", 1.5)]).unwrap();
+    let path = std::fs::read_dir(&dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let bytes = std::fs::read(&path).unwrap();
+    // two records whose checksums match
+    let mut records = vec![];
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        let length = u64::from_le_bytes(rest[..8].try_into().unwrap()) as usize;
+        let length_crc = u32::from_le_bytes(rest[8..12].try_into().unwrap());
+        assert_eq!(length_crc, masked_crc(&rest[..8]));
+        let data = &rest[12..12 + length];
+        let data_crc = u32::from_le_bytes(rest[12 + length..16 + length].try_into().unwrap());
+        assert_eq!(data_crc, masked_crc(data));
+        records.push(data.to_vec());
+        rest = &rest[16 + length..];
+    }
+    assert_eq!(records.len(), 2);
+    let scalar = &records[1];
+    // the step follows the wall time, then the summary with the tag and value
+    assert_eq!(&scalar[9..11], &[0x10, 3]);
+    let tag = b"fitness/best";
+    assert!(scalar.windows(tag.len()).any(|w| w == tag));
+    assert!(scalar.ends_with(&1.5f32.to_le_bytes()));
+}
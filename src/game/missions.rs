@@ -0,0 +1,223 @@
+use crate::game::{GameEvent, PieceId};
+use serde::Deserialize;
+use std::{fmt, fs, io, path::Path};
+
+// objectives to complete one after the other, checked against the game's events (so the
+// game has to be subscribed to them), a file (see missions.toml) lists them in order:
+//
+//     [[missions]]
+//     type = "clear"
+//     lines = 2
+//     piece = "T"
+//
+//     [[missions]]
+//     type = "t_spin"
+//     lines = 1
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Objective {
+    // at once, with that piece if there is one
+    Clear {
+        lines: usize,
+        piece: Option<PieceId>,
+    },
+    // 0 for a T-spin that clears nothing
+    TSpin {
+        lines: usize,
+    },
+    // in total, counted from when the objective comes up
+    Lines {
+        lines: usize,
+    },
+}
+
+impl Objective {
+    // short enough for the side panel
+    pub fn describe(self) -> String {
+        let name = |lines| match lines {
+            1 => "single",
+            2 => "double",
+            3 => "triple",
+            _ => "tetris",
+        };
+        match self {
+            Self::Clear { lines, piece } => {
+                let mut text = name(lines).to_string();
+                text[..1].make_ascii_uppercase();
+                if let Some(id) = piece {
+                    text += &format!(" with {}", id.to_char());
+                }
+                text
+            }
+            Self::TSpin { lines: 0 } => "T-spin".to_string(),
+            Self::TSpin { lines } => format!("T-spin {}", name(lines)),
+            Self::Lines { lines } => format!("Clear {} lines", lines),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Missions {
+    objectives: Vec<Objective>,
+    done: usize,                 // objectives completed, the next one is the current one
+    lines: usize,                // cleared since the current objective came up
+    last_piece: Option<PieceId>, // the last one locked, which cleared the lines that follow
+}
+
+impl Missions {
+    pub fn new(objectives: Vec<Objective>) -> Self {
+        Self {
+            objectives,
+            done: 0,
+            lines: 0,
+            last_piece: None,
+        }
+    }
+
+    // None once all are completed
+    pub fn current(&self) -> Option<Objective> {
+        self.objectives.get(self.done).copied()
+    }
+
+    // (completed, all)
+    pub fn progress(&self) -> (usize, usize) {
+        (self.done, self.objectives.len())
+    }
+
+    // with the events of the game since the last call, returns the objectives completed by
+    // them, an event only ever counts towards one objective
+    pub fn update(&mut self, events: &[GameEvent]) -> Vec<Objective> {
+        let mut completed = vec![];
+        for event in events {
+            let objective = match self.current() {
+                Some(objective) => objective,
+                None => break,
+            };
+            let done = match *event {
+                GameEvent::PieceLocked(id) => {
+                    self.last_piece = Some(id);
+                    false
+                }
+                GameEvent::LinesCleared(n) => {
+                    self.lines += n;
+                    match objective {
+                        Objective::Clear { lines, piece } => {
+                            n == lines && piece.is_none_or(|id| self.last_piece == Some(id))
+                        }
+                        Objective::Lines { lines } => self.lines >= lines,
+                        Objective::TSpin { .. } => false,
+                    }
+                }
+                GameEvent::TSpin(n) => objective == Objective::TSpin { lines: n },
+                _ => false,
+            };
+            if done {
+                completed.push(objective);
+                self.done += 1;
+                self.lines = 0;
+            }
+        }
+        completed
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MissionsFile {
+    missions: Vec<ObjectiveEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectiveEntry {
+    Clear { lines: usize, piece: Option<String> }, // by piece letter
+    TSpin { lines: usize },
+    Lines { lines: usize },
+}
+
+#[derive(From, Debug)]
+pub enum MissionsLoadError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+    #[from(ignore)]
+    UnknownPiece(String),
+    #[from(ignore)]
+    BadLines(usize), // more than a piece can clear at once
+}
+
+pub type MissionsLoadResult<T> = Result<T, MissionsLoadError>;
+
+impl fmt::Display for MissionsLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read missions: {}", e),
+            Self::TomlError(e) => write!(f, "invalid missions: {}", e),
+            Self::UnknownPiece(name) => write!(f, "no piece is called \"{}\"", name),
+            Self::BadLines(lines) => write!(f, "a piece can't clear {} lines at once", lines),
+        }
+    }
+}
+
+fn parse_missions(text: &str) -> MissionsLoadResult<Missions> {
+    let file: MissionsFile = toml::from_str(text)?;
+    let objectives = file
+        .missions
+        .into_iter()
+        .map(|entry| match entry {
+            ObjectiveEntry::Clear { lines, .. } | ObjectiveEntry::TSpin { lines } if lines > 4 => {
+                Err(MissionsLoadError::BadLines(lines))
+            }
+            ObjectiveEntry::Clear { lines: 0, .. } => Err(MissionsLoadError::BadLines(0)),
+            ObjectiveEntry::Clear { lines, piece } => {
+                let piece = piece
+                    .map(|name| {
+                        let mut chars = name.chars();
+                        match (chars.next().and_then(PieceId::from_char), chars.next()) {
+                            (Some(id), None) => Ok(id),
+                            _ => Err(MissionsLoadError::UnknownPiece(name)),
+                        }
+                    })
+                    .transpose()?;
+                Ok(Objective::Clear { lines, piece })
+            }
+            ObjectiveEntry::TSpin { lines } => Ok(Objective::TSpin { lines }),
+            ObjectiveEntry::Lines { lines } => Ok(Objective::Lines { lines }),
+        })
+        .collect::<MissionsLoadResult<_>>()?;
+    Ok(Missions::new(objectives))
+}
+
+pub fn load_missions<P: AsRef<Path>>(path: P) -> MissionsLoadResult<Missions> {
+    parse_missions(&fs::read_to_string(path)?)
+}
+
+#[test]
+fn test_missions_complete_in_order() {
+    let mut missions = parse_missions(&fs::read_to_string("missions.toml").unwrap()).unwrap();
+    assert_eq!(
+        missions.current(),
+        Some(Objective::Clear {
+            lines: 2,
+            piece: Some(PieceId::TBlock)
+        })
+    );
+    // the tetris doesn't count before its turn, the double has to be made with a T
+    let events = [
+        GameEvent::PieceLocked(PieceId::IBlock),
+        GameEvent::LinesCleared(4),
+        GameEvent::PieceLocked(PieceId::SBlock),
+        GameEvent::LinesCleared(2),
+    ];
+    assert!(missions.update(&events).is_empty());
+    let events = [
+        GameEvent::PieceLocked(PieceId::TBlock),
+        GameEvent::LinesCleared(2),
+        GameEvent::TSpin(2),
+    ];
+    assert_eq!(missions.update(&events).len(), 1);
+    assert_eq!(missions.progress().0, 1);
+    assert!(matches!(
+        parse_missions("[[missions]]\ntype = \"clear\"\nlines = 2\npiece = \"X\""),
+        Err(MissionsLoadError::UnknownPiece(_))
+    ));
+}
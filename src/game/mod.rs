@@ -1,55 +1,264 @@
-use ggez::graphics::Color;
+use features::{BoardMetrics, InputEncoder};
 use itertools::Itertools;
-use no_comment::IntoWithoutComments;
+use nn_trainer::KeyState;
 use rand::prelude::*;
+use render::Color;
+use replay::{InputReplay, LockRecord};
+use rise::StackRise;
+use serde::Deserialize;
 use std::{
-    cmp::{max, min},
+    cell::{Cell, RefCell},
+    cmp::min,
     collections::{HashMap, VecDeque},
-    fs::File,
-    io::{BufRead, BufReader},
+    fmt, fs, io, mem,
     path::Path,
+    sync::OnceLock,
 };
 use tap::TapOps;
 
-pub(crate) mod nn_trainer;
+pub mod achievements;
+pub mod animation;
+pub mod background;
+pub mod bot;
+#[cfg(feature = "window")]
+pub mod bot_visual;
+pub mod bracket;
+#[cfg(feature = "window")]
+pub mod bracket_visual;
+#[cfg(feature = "window")]
+pub mod browser_visual;
+pub mod checkpoints;
+pub mod compare;
+#[cfg(feature = "window")]
+pub mod compare_visual;
+pub mod daily;
+#[cfg(feature = "window")]
+pub mod effects;
+pub mod estimator;
+pub mod evaluation;
+pub mod experiments;
+pub mod features;
+#[cfg(feature = "window")]
+pub mod ggez_frontend;
+#[cfg(test)]
+mod golden;
+pub mod handshake;
+pub mod hash;
+pub mod heatmap;
+pub mod heuristic;
+pub mod imitation;
+#[cfg(test)]
+mod invariants;
+#[cfg(feature = "window")]
+pub mod keymap;
+pub mod lineage;
+pub mod metrics;
+pub mod missions;
+pub mod nn_trainer;
+#[cfg(feature = "window")]
 pub mod nn_visual;
+pub mod opening;
+pub mod placement;
+pub mod population;
+pub mod rating;
+pub mod relay;
+#[cfg(feature = "window")]
+pub mod reload;
+pub mod render;
+pub mod replay;
+pub mod reward;
+pub mod rise;
+pub mod rl_trainer;
+pub mod rollback;
+pub mod scores;
+pub mod self_play;
+pub mod simulator;
+pub mod tbp;
+#[cfg(test)]
+mod temp_dir;
+pub mod theme;
+pub mod tournament;
+#[cfg(feature = "window")]
+pub mod trainer_visual;
+pub mod versus;
+#[cfg(feature = "window")]
+pub mod versus_visual;
+#[cfg(feature = "window")]
 pub mod visual;
+#[cfg(feature = "web")]
+pub mod web;
+pub mod zobrist;
+
+// a piece in one rotation as the offsets of its cells from the top-left corner of its 4x4
+// grid, precomputed when the masks are loaded so that nothing has to scan the grid
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Mask {
+    cells: [(isize, isize); 4],
+    // bounding box of the cells, inclusive
+    min: (isize, isize),
+    max: (isize, isize),
+}
+
+impl Mask {
+    // None unless exactly four cells are set
+    fn from_grid(grid: &[[bool; 4]; 4]) -> Option<Self> {
+        let offsets = (0..4)
+            .cartesian_product(0..4)
+            .filter(|&(y, x)| grid[y][x])
+            .map(|(y, x)| (x as isize, y as isize))
+            .collect::<Vec<_>>();
+        let mut cells = [(0, 0); 4];
+        if offsets.len() != cells.len() {
+            return None;
+        }
+        cells.copy_from_slice(&offsets);
+        let (xs, ys) = (cells.iter().map(|c| c.0), cells.iter().map(|c| c.1));
+        Some(Self {
+            cells,
+            min: (xs.clone().min()?, ys.clone().min()?),
+            max: (xs.max()?, ys.max()?),
+        })
+    }
+
+    // absolute coordinates of the cells with the top-left corner at pos
+    fn cells_at(&self, (x, y): (isize, isize)) -> impl Iterator<Item = (isize, isize)> + '_ {
+        self.cells.iter().map(move |&(dx, dy)| (x + dx, y + dy))
+    }
+
+    fn contains(&self, offset: (isize, isize)) -> bool {
+        self.cells.contains(&offset)
+    }
+}
 
-type Mask = [[bool; 4]; 4];
 type Masks = [Mask; 4];
 type MaskMap = HashMap<PieceId, Masks>;
+// kick offsets of a single piece by (from, to) mask index
+type Kicks = HashMap<(usize, usize), Vec<(isize, isize)>>;
+type KickMap = HashMap<PieceId, Kicks>;
+
+// everything masks.txt defines
+#[derive(Clone, Debug)]
+pub struct PieceSet {
+    masks: MaskMap,
+    kicks: KickMap, // an entry for every rotation of every piece
+}
+
+impl PieceSet {
+    // sha-256 of every mask and kick in a fixed order, equal piece sets have equal
+    // fingerprints however they were loaded
+    pub fn fingerprint(&self) -> String {
+        let mut text = String::new();
+        for id in PieceId::ALL {
+            let cells = self.masks[id].iter().map(|mask| mask.cells).collect_vec();
+            let kicks = self.kicks[id]
+                .iter()
+                .sorted_by_key(|(&rotation, _)| rotation);
+            text += &format!("{:?} {:?} {:?}\n", id, cells, kicks.collect_vec());
+        }
+        hash::to_hex(&hash::sha256(text.as_bytes()))
+    }
+}
 
+#[derive(Clone)]
 struct FallingPiece {
     id: PieceId,
     pos: (isize, isize), // top-left corner
     mask_idx: usize,
     mask: Mask, // cached
 
-    lock_delay: u8,
+    lock_delay: usize, // frames left while resting on something
     lock_delay_resets: u8,
+    rotated_last: bool, // the last thing that moved the piece was a rotation, for t-spins
 }
 
 // check whether the given mask at the given position intersects with any elements of the board
 // such as pixels or borders
 fn intersects_with(mask: &Mask, (x, y): (isize, isize), board: &Board) -> bool {
-    for (rel_y, row) in mask.iter().enumerate() {
-        for (rel_x, &val) in row.iter().enumerate() {
-            if val {
-                let abs_x = rel_x as isize + x;
-                let abs_y = rel_y as isize + y;
-                if abs_x < 0
-                    || abs_x >= GAME_WIDTH as isize
-                    || abs_y < 0
-                    || abs_y >= GAME_HEIGHT as isize
-                {
-                    return true;
-                } else if let Pixel::Full(_) = board[abs_y as usize][abs_x as usize] {
-                    return true;
-                }
-            }
+    if x + mask.min.0 < 0
+        || x + mask.max.0 >= GAME_WIDTH as isize
+        || y + mask.min.1 < 0
+        || y + mask.max.1 >= GAME_HEIGHT as isize
+    {
+        return true;
+    }
+    mask.cells_at((x, y))
+        .any(|(x, y)| !board[y as usize][x as usize].is_empty())
+}
+
+// kick offsets tried in order when rotating, this is so that rotation
+// isn't blocked when touching the ground or next to a wall, unless masks.txt has other
+// ones for the piece
+const ROTATION_KICKS: [(isize, isize); 9] = #[rustfmt::skip] [
+    (0, 0),
+    (0, -1), (0, -2), // up
+    (0, 1), (0, 2), // down
+    (-1, 0), (-2, 0), // left
+    (1, 0), (2, 0), // right
+];
+
+// the new mask index and every position to try it at in order, with whether it fits there
+fn rotation_attempts<'a>(
+    masks: &'a Masks,
+    kicks: &'a Kicks,
+    mask_idx: usize,
+    pos: (isize, isize),
+    di: isize,
+    board: &'a Board,
+) -> (usize, impl Iterator<Item = ((isize, isize), bool)> + 'a) {
+    let new_idx = ((mask_idx as isize + di % 4 + 4) % 4) as usize;
+    let new_mask = &masks[new_idx];
+    let attempts = kicks
+        .get(&(mask_idx, new_idx))
+        .map_or(&ROTATION_KICKS[..], |kicks| &kicks[..])
+        .iter()
+        .map(move |(dx, dy)| (pos.0 + dx, pos.1 + dy))
+        .map(move |pos| (pos, !intersects_with(new_mask, pos, board)));
+    (new_idx, attempts)
+}
+
+// +1 is 90° clockwise, -1 is 90° counterclockwise
+// returns the new mask index and position if the rotation is possible
+fn try_rotate(
+    masks: &Masks,
+    kicks: &Kicks,
+    mask_idx: usize,
+    pos: (isize, isize),
+    di: isize,
+    board: &Board,
+) -> Option<(usize, (isize, isize))> {
+    let (new_idx, mut attempts) = rotation_attempts(masks, kicks, mask_idx, pos, di, board);
+    attempts
+        .find(|&(_, fits)| fits)
+        .map(|(pos, _)| (new_idx, pos))
+}
+
+// the last rotation of the falling piece, see Game::trace_kicks
+#[derive(Clone, Debug)]
+pub struct KickTrace {
+    pub tick: usize,
+    pub id: PieceId,
+    pub mask_idx: usize, // rotated to
+    // in the order they were tried, the last one is where the piece went if it fit
+    pub tried: Vec<(isize, isize)>,
+    pub succeeded: bool,
+}
+
+// remove full rows and shift everything above them down, returns the number of rows cleared
+fn clear_lines(board: &mut Board) -> usize {
+    let mut shift_up = 0; // shift towards ground (positive-y)
+    for y in (0..GAME_HEIGHT).rev() {
+        if board[y].iter().all(|px| !px.is_empty()) {
+            shift_up += 1;
+        } else if shift_up > 0 {
+            board[y + shift_up] = board[y];
+            board[y] = [Pixel::Empty; GAME_WIDTH];
         }
     }
-    false
+    // clear rows at the very top that were shifted down but not overwritten
+    for row in board.iter_mut().take(shift_up) {
+        *row = [Pixel::Empty; GAME_WIDTH];
+    }
+    shift_up
 }
 
 impl FallingPiece {
@@ -62,32 +271,62 @@ impl FallingPiece {
         )
     }
 
-    fn print_onto(&self, board: &mut Board) {
-        for rel_x in 0..4 {
-            for rel_y in 0..4 {
-                if self.mask[rel_y][rel_x] {
-                    let abs_x = (self.pos.0 + rel_x as isize) as usize;
-                    let abs_y = (self.pos.1 + rel_y as isize) as usize;
-                    // this check might be useless if collision checking is already implemented...
-                    match &mut board[abs_y][abs_x] {
-                        c @ Pixel::Empty => *c = Pixel::Full(self.id),
-                        Pixel::Full(_) => panic!(
-                            "intersected with board while printing onto it at abs (x, y) == ({}, {})",
-                            abs_x, abs_y,
-                        ),
-                    }
-                }
+    // returns the cells that were printed, collision checking should keep the piece inside
+    // the board and off full cells but if it ever isn't those cells are left out (and the
+    // board as it was) instead of panicking
+    fn print_onto(&self, board: &mut Board) -> Vec<(isize, isize)> {
+        let mut printed = Vec::with_capacity(4);
+        for (abs_x, abs_y) in self.mask.cells_at(self.pos) {
+            if abs_x < 0
+                || abs_x >= GAME_WIDTH as isize
+                || abs_y < 0
+                || abs_y >= GAME_HEIGHT as isize
+            {
+                continue;
+            }
+            if let c @ Pixel::Empty = &mut board[abs_y as usize][abs_x as usize] {
+                *c = Pixel::Full(self.id);
+                printed.push((abs_x, abs_y));
             }
         }
+        printed
     }
 
-    const LOCK_DELAY: u8 = 5;
+    // a T that was rotated into place with at least three of the four cells diagonal to
+    // its center blocked (by the walls too)
+    fn is_t_spin(&self, board: &Board) -> bool {
+        if self.id != PieceId::TBlock || !self.rotated_last {
+            return false;
+        }
+        // the center is the cell next to all three others
+        let neighbors = |(x, y): (isize, isize)| {
+            let sides = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+            sides
+                .iter()
+                .filter(|&&(dx, dy)| self.mask.contains((x + dx, y + dy)))
+                .count()
+        };
+        let (cx, cy) = match self.mask.cells.iter().find(|&&cell| neighbors(cell) == 3) {
+            Some(&(x, y)) => (self.pos.0 + x, self.pos.1 + y),
+            None => return false,
+        };
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+        let blocked = corners.iter().filter(|&&(dx, dy)| {
+            let (x, y) = (cx + dx, cy + dy);
+            x < 0
+                || y < 0
+                || x >= GAME_WIDTH as isize
+                || y >= GAME_HEIGHT as isize
+                || !board[y as usize][x as usize].is_empty()
+        });
+        blocked.count() >= 3
+    }
 
-    // will only reset lock delay if the piece is already counting down
+    // will only reset lock delay (to full) if the piece is already counting down
     // and there are resets left
-    fn checked_reset_lock_delay(&mut self) {
-        if self.lock_delay < Self::LOCK_DELAY && self.lock_delay_resets > 0 {
-            self.lock_delay = Self::LOCK_DELAY;
+    fn checked_reset_lock_delay(&mut self, full: usize) {
+        if self.lock_delay < full && self.lock_delay_resets > 0 {
+            self.lock_delay = full;
             self.lock_delay_resets -= 1;
         }
     }
@@ -115,99 +354,324 @@ impl PieceId {
         Self::ZBlock,
     ];
 
+    // as set by the current theme
     pub fn color(self) -> Color {
+        theme::current().color(self)
+    }
+
+    // single letter used in text formats
+    pub fn to_char(self) -> char {
         use PieceId::*;
         match self {
-            IBlock => Color::from_rgb(88, 176, 188),
-            JBlock => Color::from_rgb(22, 101, 167),
-            LBlock => Color::from_rgb(217, 133, 1),
-            OBlock => Color::from_rgb(235, 214, 1),
-            SBlock => Color::from_rgb(55, 154, 48),
-            TBlock => Color::from_rgb(137, 64, 135),
-            ZBlock => Color::from_rgb(205, 12, 17),
+            IBlock => 'I',
+            JBlock => 'J',
+            LBlock => 'L',
+            OBlock => 'O',
+            SBlock => 'S',
+            TBlock => 'T',
+            ZBlock => 'Z',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Self> {
+        Self::ALL.iter().copied().find(|id| id.to_char() == c)
+    }
+
+    // as written in masks.txt
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|id| format!("{:?}", id) == name)
+    }
+}
+
+#[derive(From, Debug)]
+pub enum MaskLoadError {
+    IoError(io::Error),
+    // line and column are 1-based and refer to the original text, comments included
+    #[from(ignore)]
+    Syntax {
+        line: usize,
+        column: usize,
+        expected: &'static str,
+        found: String,
+    },
+    #[from(ignore)]
+    DuplicatePiece(PieceId),
+    #[from(ignore)]
+    MissingPiece(PieceId),
+    // every piece has four cells in every rotation
+    #[from(ignore)]
+    CellCount {
+        id: PieceId,
+        rotation: usize,
+        cells: usize,
+    },
+}
+
+pub type MaskLoadResult<T> = Result<T, MaskLoadError>;
+
+impl fmt::Display for MaskLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read masks: {}", e),
+            Self::Syntax {
+                line,
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}, column {}: expected {}, found {}",
+                line, column, expected, found
+            ),
+            Self::DuplicatePiece(id) => write!(f, "masks for {:?} are defined twice", id),
+            Self::MissingPiece(id) => write!(f, "no masks for {:?}", id),
+            Self::CellCount {
+                id,
+                rotation,
+                cells,
+            } => write!(
+                f,
+                "mask {} of {:?} has {} cells instead of 4",
+                rotation, id, cells
+            ),
         }
     }
 }
 
-pub fn load_masks<P: AsRef<Path>>(path: P) -> MaskMap {
-    let path = path.as_ref();
-    let file = File::open(path).unwrap_or_else(|_| panic!("failed to open \"{}\"", path.display()));
+// rows and columns are 1-based, counted from the top and from the left of the text
+#[derive(Debug, Eq, PartialEq)]
+pub enum AsciiBoardError {
+    TooManyRows(usize),
+    RowWidth {
+        row: usize,
+        width: usize,
+    },
+    UnknownCell {
+        row: usize,
+        column: usize,
+        found: char,
+    },
+}
 
-    let text = BufReader::new(file)
+impl fmt::Display for AsciiBoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooManyRows(rows) => {
+                write!(f, "{} rows, the board has {}", rows, GAME_HEIGHT)
+            }
+            Self::RowWidth { row, width } => write!(
+                f,
+                "row {} is {} cells wide, the board is {}",
+                row, width, GAME_WIDTH
+            ),
+            Self::UnknownCell { row, column, found } => {
+                write!(
+                    f,
+                    "row {}, column {}: unknown cell '{}'",
+                    row, column, found
+                )
+            }
+        }
+    }
+}
+
+// read at startup and by reload::Reloader
+const MASKS_FILE: &str = "masks.txt";
+
+// the masks.txt that was around at compile time
+const BUILTIN_MASKS: &str = include_str!("../../masks.txt");
+
+pub fn load_masks<P: AsRef<Path>>(path: P) -> MaskLoadResult<PieceSet> {
+    parse_masks(&fs::read_to_string(path)?)
+}
+
+pub fn builtin_masks() -> PieceSet {
+    parse_masks(BUILTIN_MASKS).expect("built-in masks are invalid")
+}
+
+// masks.txt if it can be loaded, the built-in masks otherwise, only loaded (and
+// warned about) once
+fn default_masks() -> PieceSet {
+    static MASKS: OnceLock<PieceSet> = OnceLock::new();
+    MASKS
+        .get_or_init(|| {
+            load_masks(MASKS_FILE).unwrap_or_else(|e| {
+                eprintln!("Warning: {}, using built-in masks", e);
+                builtin_masks()
+            })
+        })
+        .clone()
+}
+
+// each piece is its name on a line followed by 4 masks of 4 lines of 4 values ('.' or
+// '0') separated by whitespace and optionally by "kicks <from>><to> <dx>,<dy> ..." lines
+// with the offsets to try for rotating from one mask to another (y is down), a
+// "kicks <dx>,<dy> ..." line before the first piece replaces ROTATION_KICKS for the
+// rotations without their own, blank lines and "//" comments are ignored
+fn parse_masks(text: &str) -> MaskLoadResult<PieceSet> {
+    let mut lines = text
         .lines()
-        .map(|l| l.expect("failed to read line"))
-        .join("\n");
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.split("//").next().unwrap()))
+        .filter(|(_, l)| !l.trim().is_empty())
+        .peekable();
+    let end_line = text.lines().count() + 1;
+    let syntax = |line, column, expected, found: &str| MaskLoadError::Syntax {
+        line,
+        column,
+        expected,
+        found: found.to_string(),
+    };
+    // tokens of a line with their 1-based columns
+    fn tokens(l: &str) -> Vec<(usize, &str)> {
+        l.split_whitespace()
+            .map(|t| {
+                let offset = t.as_ptr() as usize - l.as_ptr() as usize;
+                (l[..offset].chars().count() + 1, t)
+            })
+            .collect()
+    }
 
-    let text: String = text
-        .chars()
-        .without_comments()
-        .skip_while(|c| c.is_whitespace())
-        .collect();
+    // "1>2" and "-1,2"
+    fn pair<T: std::str::FromStr>(token: &str, separator: char) -> Option<(T, T)> {
+        let (a, b) = token.split_once(separator)?;
+        Some((a.parse().ok()?, b.parse().ok()?))
+    }
 
-    let iter = &mut text.chars();
+    // the rest of a kicks line
+    let offsets = |n, l: &str, tokens: &[(usize, &str)]| {
+        if tokens.is_empty() {
+            let end = l.trim_end().chars().count() + 1;
+            return Err(syntax(n, end, "an offset like -1,2", "end of line"));
+        }
+        tokens
+            .iter()
+            .map(|&(column, token)| {
+                pair(token, ',').ok_or_else(|| syntax(n, column, "an offset like -1,2", token))
+            })
+            .collect::<MaskLoadResult<Vec<_>>>()
+    };
+    let is_kicks = |(_, l): &(usize, &str)| l.trim_start().starts_with("kicks");
+
+    // for every rotation a piece has no kicks of its own for
+    let mut default_kicks = ROTATION_KICKS.to_vec();
+    if let Some((n, l)) = lines.next_if(is_kicks) {
+        default_kicks = offsets(n, l, &tokens(l)[1..])?;
+    }
 
     let mut map = MaskMap::new();
-    for _ in 0..7 {
-        // also gets and drops '\n'
-        let name: String = iter.take_while(|c| c.is_alphabetic()).collect();
-        use PieceId::*;
-        let name = match name.as_str() {
-            "IBlock" => IBlock,
-            "JBlock" => JBlock,
-            "LBlock" => LBlock,
-            "OBlock" => OBlock,
-            "SBlock" => SBlock,
-            "TBlock" => TBlock,
-            "ZBlock" => ZBlock,
-            n => panic!("unexpected piece name \"{}\"", n),
-        };
+    let mut kick_map = KickMap::new();
+    while let Some((n, l)) = lines.next() {
+        let name = l.trim();
+        let column = l.chars().count() - l.trim_start().chars().count() + 1;
+        let id = PieceId::from_name(name).ok_or_else(|| syntax(n, column, "a piece name", name))?;
+        if map.contains_key(&id) {
+            return Err(MaskLoadError::DuplicatePiece(id));
+        }
 
-        // 4 masks, 4 lines, 4 values
-        let mut masks = [[[false; 4]; 4]; 4];
-        for mask in masks.iter_mut() {
-            for line in mask.iter_mut() {
-                let l: String = iter.take_while(|&c| c != '\n').collect();
-                for (i, c) in l.split("  ").enumerate() {
-                    line[i] = match c {
+        let mut grids = [[[false; 4]; 4]; 4];
+        for grid in grids.iter_mut() {
+            for row in grid.iter_mut() {
+                let (n, l) = lines
+                    .next()
+                    .ok_or_else(|| syntax(end_line, 1, "a mask row", "end of file"))?;
+                let tokens = tokens(l);
+                for (i, cell) in row.iter_mut().enumerate() {
+                    let (column, token) = match tokens.get(i) {
+                        Some(&t) => t,
+                        None => {
+                            let column = l.trim_end().chars().count() + 1;
+                            return Err(syntax(n, column, "'.' or '0'", "end of line"));
+                        }
+                    };
+                    *cell = match token {
                         "." => false,
                         "0" => true,
-                        c => panic!("unexpected '{}'", c),
-                    }
+                        t => return Err(syntax(n, column, "'.' or '0'", t)),
+                    };
+                }
+                if let Some(&(column, token)) = tokens.get(4) {
+                    return Err(syntax(n, column, "end of line", token));
                 }
             }
-            // drop empty line
-            iter.take_while(|&c| c != '\n').for_each(|_| {});
         }
+        let mask = |rotation: usize| {
+            let grid = &grids[rotation];
+            Mask::from_grid(grid).ok_or_else(|| MaskLoadError::CellCount {
+                id,
+                rotation,
+                cells: grid.iter().flatten().filter(|&&c| c).count(),
+            })
+        };
+        map.insert(id, [mask(0)?, mask(1)?, mask(2)?, mask(3)?]);
 
-        map.insert(name, masks);
+        let mut kicks = Kicks::new();
+        while let Some((n, l)) = lines.next_if(is_kicks) {
+            let tokens = tokens(l);
+            let end = l.trim_end().chars().count() + 1;
+            let (column, token) = tokens.get(1).copied().unwrap_or((end, "end of line"));
+            let rotation = pair::<usize>(token, '>')
+                .filter(|&(from, to)| from < 4 && to < 4 && from != to)
+                .ok_or_else(|| syntax(n, column, "a rotation like 0>1", token))?;
+            kicks.insert(rotation, offsets(n, l, tokens.get(2..).unwrap_or(&[]))?);
+        }
+        for rotation in (0..4)
+            .cartesian_product(0..4)
+            .filter(|(from, to)| from != to)
+        {
+            kicks
+                .entry(rotation)
+                .or_insert_with(|| default_kicks.clone());
+        }
+        kick_map.insert(id, kicks);
     }
 
-    map
+    match PieceId::ALL.iter().find(|id| !map.contains_key(id)) {
+        Some(&id) => Err(MaskLoadError::MissingPiece(id)),
+        None => Ok(PieceSet {
+            masks: map,
+            kicks: kick_map,
+        }),
+    }
 }
 
 #[derive(Copy, Clone)]
 enum Pixel {
     Empty,
     Full(PieceId),
+    Garbage, // received from an opponent
 }
 
 impl Pixel {
     pub fn is_empty(self) -> bool {
         match self {
             Self::Empty => true,
-            Self::Full(_) => false,
+            Self::Full(_) | Self::Garbage => false,
+        }
+    }
+
+    pub fn color(self) -> Option<Color> {
+        match self {
+            Self::Empty => None,
+            Self::Full(id) => Some(id.color()),
+            Self::Garbage => Some(Color::rgb(110, 110, 110)),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct PieceQueue {
-    rng: ThreadRng,
+    seed: u64,
+    rng: StdRng,
     bag: Vec<PieceId>,
     queue: VecDeque<PieceId>,
 }
 
 impl PieceQueue {
-    fn pop_from_bag(rng: &mut ThreadRng, bag: &mut Vec<PieceId>) -> PieceId {
+    fn pop_from_bag(rng: &mut StdRng, bag: &mut Vec<PieceId>) -> PieceId {
         if bag.is_empty() {
             bag.extend_from_slice(PieceId::ALL)
         }
@@ -215,14 +679,21 @@ impl PieceQueue {
         bag.remove(idx)
     }
 
-    fn new() -> Self {
-        let mut rng = thread_rng();
+    // the same seed always deals the same pieces, a random one is picked without one
+    fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut bag = Vec::with_capacity(7);
         let mut queue = VecDeque::with_capacity(3);
         for _ in 0..3 {
             queue.push_back(Self::pop_from_bag(&mut rng, &mut bag))
         }
-        Self { rng, bag, queue }
+        Self {
+            seed,
+            rng,
+            bag,
+            queue,
+        }
     }
 
     fn pop(&mut self) -> PieceId {
@@ -235,74 +706,366 @@ impl PieceQueue {
     pub fn iter<'a>(&'a self) -> impl Iterator<Item = PieceId> + 'a {
         self.queue.iter().copied()
     }
+
+    // the next n pieces, past the ones shown as well, the same ones that will be dealt
+    pub fn peek(&self, n: usize) -> Vec<PieceId> {
+        let (mut rng, mut bag) = (self.rng.clone(), self.bag.clone());
+        let mut pieces = self.iter().take(n).collect::<Vec<_>>();
+        while pieces.len() < n {
+            pieces.push(Self::pop_from_bag(&mut rng, &mut bag));
+        }
+        pieces
+    }
+
+    // the pieces of the current bag that aren't in the queue yet, in PieceId::ALL order,
+    // empty when the next piece after the queue starts a new bag
+    pub fn remaining_bag(&self) -> Vec<PieceId> {
+        PieceId::ALL
+            .iter()
+            .copied()
+            .filter(|id| self.bag.contains(id))
+            .collect()
+    }
 }
 
+// gravity is counted in millionths of a row
+const ROW: u64 = 1_000_000;
+
 pub const GAME_WIDTH: usize = 10;
 pub const GAME_HEIGHT: usize = 20;
 
 // 20 rows of 10 pixels
 type Board = [[Pixel; GAME_WIDTH]; GAME_HEIGHT];
 
+// cloning is how rollback::RemoteGame takes snapshots
+#[derive(Clone)]
 pub struct Game {
     mask_map: MaskMap,
+    kick_map: KickMap,
+    rotation: RotationSystem,
     tick: usize, // frame tick tied to fps (== number of vis frames)
     points: usize,
     level: usize,
-    cleared: usize, // number of rows cleared so far
+    start_level: usize, // the level before any lines are cleared
+    cleared: usize,     // number of rows cleared so far
+    pieces: usize,      // number of pieces locked so far
+    pieces_at_last_clear: usize,
+    dealt: HashMap<PieceId, usize>, // pieces taken from the queue, by type
 
     board: Board,
+    board_hash: u64,       // zobrist hash of the board, kept up to date
+    metrics: BoardMetrics, // likewise
     piece_queue: PieceQueue,
     falling: Option<FallingPiece>,
+    ghost_y: Cell<Option<isize>>, // where the falling piece lands, None until asked for
     hold: Option<PieceId>,
     can_switch: bool, // to prevent double-switching hold
+    hold_mode: HoldMode,
+    // frames without a falling piece after a lock, and extra ones after clearing lines
+    entry_delay: usize,
+    line_clear_delay: usize,
+    spawn_in: Option<usize>, // frames left until the next piece spawns, while waiting
+    buffered: Vec<Action>,   // shifts and rotations applied while waiting, for the next piece
+    over: bool,              // set when a new piece can't be spawned
+    recording: Option<Vec<LockRecord>>,
+    inputs: Option<Vec<(usize, Action)>>, // applied actions with the tick they were applied on
+    actions: VecDeque<Action>,            // queued through Controller, one is applied per frame
+    gravity: f32,                         // multiplier for the fall speed of the current level
+    gravity_fixed: u64,                   // the same in millionths, what the game runs on
+    fall: u64,                            // millionths of a row towards the next one, < ROW
+    lock_frames: usize,                   // lock delay, the same at every level
+    events: Option<Vec<GameEvent>>,
+    kick_trace: Option<Option<KickTrace>>, // None unless tracing, see trace_kicks
+    key_state: KeyState,                   // of a network in keys mode, see nn_trainer::decide
+    noise_rng: RefCell<StdRng>,            // for evaluation::Noise, seeded like the pieces
+    rise: Option<StackRise>,               // survival mode, see set_stack_rise
+    risen: usize,                          // rows of garbage risen so far
+    rise_in: usize,                        // frames until the next one
 }
 
 impl Game {
     pub fn new() -> Self {
+        Self::with_piece_queue(PieceQueue::new(None))
+    }
+
+    #[allow(dead_code)]
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_piece_queue(PieceQueue::new(Some(seed)))
+    }
+
+    fn with_piece_queue(piece_queue: PieceQueue) -> Self {
         let board = [[Pixel::Empty; 10]; 20];
+        let PieceSet { masks, kicks } = default_masks();
+        // not the pieces' own stream, the noise would follow the pieces then
+        let noise_rng = StdRng::seed_from_u64(!piece_queue.seed);
         Self {
-            mask_map: load_masks("masks.txt"),
+            mask_map: masks,
+            kick_map: kicks,
+            rotation: RotationSystem::Classic,
             tick: 0,
             points: 0,
             level: 1,
+            start_level: 1,
             cleared: 0,
+            pieces: 0,
+            pieces_at_last_clear: 0,
+            dealt: HashMap::new(),
 
             board,
-            piece_queue: PieceQueue::new(),
+            board_hash: 0, // empty
+            metrics: BoardMetrics::of(&board),
+            piece_queue,
             falling: None,
+            ghost_y: Cell::new(None),
             hold: None,
             can_switch: true,
+            hold_mode: HoldMode::Standard,
+            entry_delay: 0,
+            line_clear_delay: 0,
+            spawn_in: None,
+            buffered: Vec::new(),
+            over: false,
+            recording: None,
+            inputs: None,
+            actions: VecDeque::new(),
+            gravity: 1.,
+            gravity_fixed: ROW,
+            fall: 0,
+            lock_frames: Self::LOCK_DELAY,
+            events: None,
+            kick_trace: None,
+            key_state: KeyState::default(),
+            noise_rng: RefCell::new(noise_rng),
+            rise: None,
+            risen: 0,
+            rise_in: 0,
         }
         .tap(Game::spawn)
     }
 
     // return concatenated rows of cells, includes falling piece
     pub fn get_cells(&self) -> Box<[f64]> {
-        // board
-        let mut cells = self
-            .board
-            .iter()
-            .flat_map(|row| row.iter().map(|px| if px.is_empty() { 0. } else { 1. }))
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
-        // falling piece
-        if let Some(falling) = &self.falling {
-            let mask = falling.mask;
-            for (rel_y, rel_x) in (0..4).cartesian_product(0..4) {
-                if mask[rel_y][rel_x] {
-                    let abs_y = rel_y as isize + falling.pos.1;
-                    let abs_x = rel_x as isize + falling.pos.0;
-                    cells[abs_y as usize * GAME_WIDTH + abs_x as usize] = 1.;
-                }
-            }
+        InputEncoder::Cells.encode(self)
+    }
+
+    // like get_cells but overwrites out, which keeps its capacity between calls
+    #[allow(dead_code)]
+    pub fn write_cells(&self, out: &mut Vec<f64>) {
+        InputEncoder::Cells.encode_into(self, out)
+    }
+
+    fn lose(&mut self) {
+        self.over = true;
+        self.emit(GameEvent::GameOver);
+    }
+
+    fn emit(&mut self, event: GameEvent) {
+        if let Some(events) = &mut self.events {
+            events.push(event);
         }
+    }
+
+    // collect events from now on, see take_events
+    pub fn subscribe_events(&mut self) {
+        self.events = Some(vec![]);
+    }
+
+    // events since the last call, empty if not subscribed
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        self.events.as_mut().map(mem::take).unwrap_or_default()
+    }
 
-        cells
+    // remember every rotation of the falling piece with the kicks it tried, for checking
+    // custom kick tables, see last_rotation
+    pub fn trace_kicks(&mut self) {
+        self.kick_trace = Some(None);
     }
 
-    fn lose(&self) {
-        panic!(
-            "Lost {{ points: {}, level: {}, cleared: {} }}",
+    // None before the first rotation or unless traced
+    pub fn last_rotation(&self) -> Option<&KickTrace> {
+        self.kick_trace.as_ref()?.as_ref()
+    }
+
+    // 0 stops pieces from falling on their own altogether, rounded to a millionth so that
+    // nothing after this depends on how a platform does floating point math
+    pub fn set_gravity(&mut self, gravity: f32) {
+        self.gravity = gravity;
+        self.gravity_fixed = (gravity as f64 * ROW as f64).round() as u64;
+    }
+
+    // skips the slower levels, the level still goes up every ten lines from there, call
+    // before the first input
+    pub fn set_start_level(&mut self, level: usize) {
+        self.start_level = level.max(1);
+        self.level = self.start_level;
+    }
+
+    // half a second
+    pub const LOCK_DELAY: usize = 30;
+
+    // frames a piece rests on something before it locks
+    pub fn lock_delay(&self) -> usize {
+        self.lock_frames
+    }
+
+    // call before the first input
+    pub fn set_lock_delay(&mut self, frames: usize) {
+        self.lock_frames = frames;
+    }
+
+    // (entry, line clear)
+    pub fn delays(&self) -> (usize, usize) {
+        (self.entry_delay, self.line_clear_delay)
+    }
+
+    // both 0 (the default) spawns the next piece on the frame the last one locks, call
+    // before the first input
+    pub fn set_delays(&mut self, entry: usize, line_clear: usize) {
+        self.entry_delay = entry;
+        self.line_clear_delay = line_clear;
+    }
+
+    pub fn stack_rise(&self) -> Option<StackRise> {
+        self.rise
+    }
+
+    // garbage rows rise from the bottom on a timer, their holes go by the seed, None (the
+    // default) for none, call before the first input
+    pub fn set_stack_rise(&mut self, rise: Option<StackRise>) {
+        self.rise = rise;
+        self.risen = 0;
+        self.rise_in = rise.map_or(0, |rise| rise.interval_after(0));
+    }
+
+    // between a lock and the next spawn
+    pub fn is_waiting(&self) -> bool {
+        self.spawn_in.is_some()
+    }
+
+    // remember every locked piece from now on
+    pub fn start_recording(&mut self) {
+        self.recording = Some(vec![]);
+    }
+
+    pub fn recording(&self) -> Option<&[LockRecord]> {
+        self.recording.as_deref()
+    }
+
+    // remember every applied action from now on, see replay::InputReplay
+    pub fn start_input_log(&mut self) {
+        self.inputs = Some(vec![]);
+    }
+
+    pub fn input_log(&self) -> Option<&[(usize, Action)]> {
+        self.inputs.as_deref()
+    }
+
+    // the game so far if the input log was started before the first input, see
+    // Simulator::replay
+    pub fn input_replay(&self) -> Option<InputReplay> {
+        Some(InputReplay {
+            seed: self.piece_queue.seed,
+            gravity: self.gravity,
+            hold: self.hold_mode,
+            delays: self.delays(),
+            lock_delay: self.lock_frames,
+            rotation: self.rotation,
+            rise: self.rise,
+            frames: self.tick,
+            inputs: self.input_log()?.to_vec(),
+        })
+    }
+
+    // equal for boards with the same full cells, see zobrist
+    pub fn board_hash(&self) -> u64 {
+        self.board_hash
+    }
+
+    // column heights and holes of the locked cells
+    pub fn metrics(&self) -> &BoardMetrics {
+        &self.metrics
+    }
+
+    // replaces the locked cells, the falling piece is left alone
+    fn set_board(&mut self, board: Board) {
+        self.board = board;
+        self.board_hash = zobrist::hash_board(&board);
+        self.metrics = BoardMetrics::of(&board);
+        self.ghost_y.set(None);
+    }
+
+    // the position the falling piece would be locked at by a hard drop, remembered until
+    // the piece moves sideways, rotates or the board changes (falling doesn't change it)
+    pub fn ghost_pos(&self) -> Option<(isize, isize)> {
+        let falling = self.falling.as_ref()?;
+        let (x, mut y) = falling.pos;
+        if let Some(ghost_y) = self.ghost_y.get() {
+            return Some((x, ghost_y));
+        }
+        while !intersects_with(&falling.mask, (x, y + 1), &self.board) {
+            y += 1
+        }
+        self.ghost_y.set(Some(y));
+        Some((x, y))
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.over
+    }
+
+    // how many pieces of the type came out of the queue so far, the current one included
+    pub fn dealt(&self, id: PieceId) -> usize {
+        self.dealt.get(&id).copied().unwrap_or(0)
+    }
+
+    // whether Action::Hold would do anything right now, never between pieces
+    pub fn can_hold(&self) -> bool {
+        self.falling.is_some()
+            && match self.hold_mode {
+                HoldMode::Disabled => false,
+                HoldMode::Standard => self.can_switch,
+                HoldMode::Unlimited => true,
+            }
+    }
+
+    pub fn hold_mode(&self) -> HoldMode {
+        self.hold_mode
+    }
+
+    // call before the first input
+    pub fn set_hold_mode(&mut self, mode: HoldMode) {
+        self.hold_mode = mode;
+    }
+
+    pub fn rotation_system(&self) -> RotationSystem {
+        self.rotation
+    }
+
+    // call before the first input
+    pub fn set_rotation_system(&mut self, system: RotationSystem) {
+        self.replace_pieces(system.piece_set());
+        self.rotation = system;
+    }
+
+    // the falling piece keeps its place and rotation if it still fits there, it starts
+    // over otherwise
+    fn replace_pieces(&mut self, PieceSet { masks, kicks }: PieceSet) {
+        self.mask_map = masks;
+        self.kick_map = kicks;
+        self.ghost_y.set(None);
+        if let Some(falling) = self.falling.as_mut() {
+            falling.mask = self.mask_map[&falling.id][falling.mask_idx];
+            if intersects_with(&falling.mask, falling.pos, &self.board) {
+                let id = falling.id;
+                self.spawn_with_id(id);
+            }
+        }
+    }
+
+    // printed when a game ends
+    pub fn summary(&self) -> String {
+        format!(
+            "{{ points: {}, level: {}, cleared: {} }}",
             self.points, self.level, self.cleared
         )
     }
@@ -312,6 +1075,7 @@ impl Game {
         let mask_idx = 0;
         let mask = self.mask_map[&id][mask_idx];
 
+        self.ghost_y.set(None);
         if intersects_with(&mask, pos, &self.board) {
             self.lose()
         } else {
@@ -320,41 +1084,96 @@ impl Game {
                 pos,
                 mask_idx,
                 mask,
-                lock_delay: FallingPiece::LOCK_DELAY,
+                lock_delay: self.lock_frames,
                 lock_delay_resets: 10,
+                rotated_last: false,
             })
         }
     }
 
     fn spawn(&mut self) {
         let id = self.piece_queue.pop();
+        *self.dealt.entry(id).or_insert(0) += 1;
         self.spawn_with_id(id)
     }
 
     // print falling piece onto the board and destroy it (will be spawned next iteration)
     fn destroy_falling_and_respawn(&mut self) {
-        self.falling.as_mut().unwrap().print_onto(&mut self.board);
+        let falling = match &self.falling {
+            Some(falling) => falling,
+            None => return,
+        };
+        if let Some(records) = &mut self.recording {
+            records.push(LockRecord {
+                board: self.board,
+                id: falling.id,
+                next: self.piece_queue.iter().collect(),
+                mask_idx: falling.mask_idx,
+                pos: falling.pos,
+            });
+        }
+        let holes_before = self.metrics.holes();
+        let t_spin = falling.is_t_spin(&self.board);
+        let printed = falling.print_onto(&mut self.board);
+        self.board_hash ^= zobrist::hash_cells(printed.iter().copied());
+        self.metrics.lock(printed);
+        self.pieces += 1;
+        self.emit(GameEvent::PieceLocked(falling.id));
+        if self.events.is_some() {
+            let (mut board, mut metrics) = (self.board, self.metrics);
+            let rows = clear_lines(&mut board);
+            metrics.clear(&board, rows);
+            let after = metrics.holes();
+            if after > holes_before {
+                self.emit(GameEvent::HolesCreated(after - holes_before));
+            }
+        }
         self.falling = None;
         self.can_switch = true;
+        // clear lines before spawning so the new piece sees the final board
+        let cleared = self.cleared;
+        self.compact_board();
+        if t_spin {
+            self.emit(GameEvent::TSpin(self.cleared - cleared));
+        }
+        let delay = match self.cleared > cleared {
+            true => self.entry_delay + self.line_clear_delay,
+            false => self.entry_delay,
+        };
+        match delay {
+            0 => self.spawn(),
+            frames => self.spawn_in = Some(frames),
+        }
+    }
+
+    // the buffered inputs are applied right away, so a shift held through the delay moves
+    // the new piece before it has fallen at all
+    fn spawn_buffered(&mut self) {
+        self.spawn_in = None;
         self.spawn();
+        for action in std::mem::take(&mut self.buffered) {
+            self.perform(action);
+        }
     }
 
     // might get called twice but that shouldn't matter
     // also does scoring
     fn compact_board(&mut self) {
-        let mut shift_up = 0; // shift towards ground (positive-y)
-        for y in (0..GAME_HEIGHT).rev() {
-            if self.board[y].iter().all(|px| !px.is_empty()) {
-                shift_up += 1;
-            } else if shift_up > 0 {
-                self.board[y + shift_up] = self.board[y];
-                self.board[y] = [Pixel::Empty; GAME_WIDTH];
-            }
+        let shift_up = clear_lines(&mut self.board);
+        if shift_up > 0 {
+            self.board_hash = zobrist::hash_board(&self.board);
+            self.metrics.clear(&self.board, shift_up);
+            self.ghost_y.set(None);
+            self.pieces_at_last_clear = self.pieces;
+            self.emit(GameEvent::LinesCleared(shift_up));
         }
-        // at this point shift_up == number of rows cleared
         self.cleared += shift_up;
         // level goes up every ten lines
-        self.level = (self.cleared / 10) + 1;
+        let level = self.start_level + self.cleared / 10;
+        if level > self.level {
+            self.emit(GameEvent::LevelUp(level));
+        }
+        self.level = level;
         self.points += self.level
             * match shift_up {
                 0 => 0,
@@ -362,138 +1181,726 @@ impl Game {
                 2 => 100,
                 3 => 300,
                 4 => 1200,
-                n => panic!("unexpected {} lines cleared", n),
+                // a piece spans at most four rows
+                n => unreachable!("{} lines cleared at once", n),
             }
     }
 
     pub fn iterate(&mut self) {
+        if self.over {
+            return;
+        }
         self.compact_board();
 
-        // rows to fall per frame, assumes 60 fps (levels 1-15+)
-        const ROWS_PER_FRAME: [f32; 15] = #[rustfmt::skip] [
-            0.01667,
-            0.021_017,
-            0.026_977,
-            0.035_256,
-            0.04693,
-            0.06361,
-            0.0879,
-            0.1236,
-            0.1775,
-            0.2598,
-            0.388,
-            0.59,
-            0.92,
-            1.46,
-            2.36,
+        match self.spawn_in {
+            Some(1) => self.spawn_buffered(),
+            Some(frames) => self.spawn_in = Some(frames - 1),
+            None => (),
+        }
+        if self.over {
+            return;
+        }
+
+        if let Some(action) = self.actions.pop_front() {
+            self.apply(action);
+            if self.over {
+                return;
+            }
+        }
+
+        // gravity in millionths of G (rows to fall per frame), assumes 60 fps (levels 1-15+)
+        const ROWS_PER_FRAME: [u64; 15] = #[rustfmt::skip] [
+            16_670,
+            21_017,
+            26_977,
+            35_256,
+            46_930,
+            63_610,
+            87_900,
+            123_600,
+            177_500,
+            259_800,
+            388_000,
+            590_000,
+            920_000,
+            1_460_000,
+            2_360_000,
         ];
 
-        let rows_per_frame = ROWS_PER_FRAME[min(self.level, 15) - 1];
-        let frames_per_row = max(1, (1. / rows_per_frame) as _);
+        // fractions of a row add up over frames and more than one row can fall in a single
+        // frame, 20G and up drops the piece all the way right away, all in integers so that
+        // replays come out the same everywhere
+        let speed = ROWS_PER_FRAME[min(self.level, 15) - 1];
+        let fall = speed
+            .saturating_mul(self.gravity_fixed)
+            .saturating_add(ROW / 2)
+            / ROW;
+        self.fall = self.fall.saturating_add(fall);
+        let rows = min(self.fall / ROW, GAME_HEIGHT as u64) as usize;
+        self.fall %= ROW;
 
-        // every 15 frames iterate falling piece
-        if self.tick % frames_per_row == 0 {
-            if let Some(ref mut falling) = self.falling {
+        if let Some(ref mut falling) = self.falling {
+            for _ in 0..rows {
                 if falling.is_touching_ground(&self.board) {
-                    if falling.lock_delay == 0 {
-                        self.destroy_falling_and_respawn();
-                    } else {
-                        falling.lock_delay -= 1;
-                    }
+                    break;
+                }
+                falling.pos.1 += 1;
+                falling.rotated_last = false;
+            }
+            // lock delay counts frames spent resting, however fast pieces fall
+            if falling.is_touching_ground(&self.board) {
+                if falling.lock_delay == 0 {
+                    self.destroy_falling_and_respawn();
                 } else {
-                    falling.pos.1 += 1;
+                    falling.lock_delay -= 1;
                 }
-            } else {
-                panic!("no falling piece")
             }
         }
+        if let Some(rise) = self.rise {
+            self.rise_in = self.rise_in.saturating_sub(1);
+            if self.rise_in == 0 {
+                let hole = StackRise::hole(self.piece_queue.seed, self.risen);
+                self.add_garbage(1, hole);
+                self.risen += 1;
+                self.rise_in = rise.interval_after(self.risen);
+            }
+        }
+        if self.falling.is_none() && !self.is_waiting() && !self.over {
+            // can't happen while the game is running, but respawning beats panicking
+            self.spawn();
+        }
 
         self.tick += 1;
     }
 }
 
-// control
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// what Action::Hold does
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HoldMode {
+    Disabled,
+    Standard, // once until the next piece is locked
+    Unlimited,
+}
+
+impl HoldMode {
+    pub const ALL: [Self; 3] = [Self::Disabled, Self::Standard, Self::Unlimited];
+
+    // lowercase, as written on the command line and in input replays
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::Standard => "standard",
+            Self::Unlimited => "unlimited",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+}
+
+// where the masks and kicks come from, the presets are files like masks.txt in rotations/
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RotationSystem {
+    #[default]
+    Classic, // masks.txt
+    Srs,      // the guideline's super rotation system
+    Ars,      // the arcade one from the TGM games
+    Nintendo, // no kicks at all
+}
+
+impl RotationSystem {
+    pub const ALL: [Self; 4] = [Self::Classic, Self::Srs, Self::Ars, Self::Nintendo];
+
+    // lowercase, as written on the command line and in input replays
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Classic => "classic",
+            Self::Srs => "srs",
+            Self::Ars => "ars",
+            Self::Nintendo => "nintendo",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|system| system.name() == name)
+    }
+
+    fn piece_set(self) -> PieceSet {
+        static PRESETS: OnceLock<[PieceSet; 3]> = OnceLock::new();
+        let presets = PRESETS.get_or_init(|| {
+            [
+                include_str!("../../rotations/srs.txt"),
+                include_str!("../../rotations/ars.txt"),
+                include_str!("../../rotations/nintendo.txt"),
+            ]
+            .map(|text| parse_masks(text).expect("built-in rotation system is invalid"))
+        });
+        match self {
+            Self::Classic => default_masks(),
+            Self::Srs => presets[0].clone(),
+            Self::Ars => presets[1].clone(),
+            Self::Nintendo => presets[2].clone(),
+        }
+    }
+}
+
+// things that happen during a game that fitness functions can react to, see
+// Game::subscribe_events
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GameEvent {
+    PieceLocked(PieceId),
+    LinesCleared(usize),
+    HolesCreated(usize), // by the piece that was just locked, after clearing lines
+    LevelUp(usize),      // the new level, after the lines that caused it
+    TSpin(usize),        // lines cleared by it (maybe 0), after LinesCleared
+    GameOver,
+}
+
+// a single input as understood by the game, independent of how it was produced
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Rotate180,
+    Hold,
+}
+
+impl Action {
+    pub const ALL: [Self; 8] = [
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::SoftDrop,
+        Self::HardDrop,
+        Self::RotateCW,
+        Self::RotateCCW,
+        Self::Rotate180,
+        Self::Hold,
+    ];
+
+    // the order inputs of the same frame are applied in: hold, then rotations, then
+    // shifts, then drops (so that a piece is rotated where it is before it moves)
+    pub fn priority(self) -> u8 {
+        use Action::*;
+        match self {
+            Hold => 0,
+            RotateCW => 1,
+            RotateCCW => 2,
+            Rotate180 => 3,
+            MoveLeft => 4,
+            MoveRight => 5,
+            SoftDrop => 6,
+            HardDrop => 7,
+        }
+    }
+
+    // what it does in a mirror, left and right swap and so do the directions of rotation
+    pub fn mirrored(self) -> Self {
+        use Action::*;
+        match self {
+            MoveLeft => MoveRight,
+            MoveRight => MoveLeft,
+            RotateCW => RotateCCW,
+            RotateCCW => RotateCW,
+            action => action,
+        }
+    }
+
+    // the variant name, as written in input replays
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|action| format!("{:?}", action) == name)
+    }
+}
+
+// programmatic input for bots and networks, bypasses keyboard simulation (and with it
+// key repeat) entirely, queued actions are applied one per frame
+pub trait Controller {
+    fn queue_action(&mut self, action: Action);
+    fn has_queued_actions(&self) -> bool;
+}
+
+impl Controller for Game {
+    fn queue_action(&mut self, action: Action) {
+        self.actions.push_back(action)
+    }
+
+    fn has_queued_actions(&self) -> bool {
+        !self.actions.is_empty()
+    }
+}
+
 impl Game {
+    // the queued actions that haven't been applied yet are dropped
+    #[allow(dead_code)]
+    pub(super) fn clear_queued_actions(&mut self) {
+        self.actions.clear()
+    }
+}
+
+// control, inputs are ignored while there is no falling piece (game over) so that
+// injected inputs never have to check the state of the game first, except for shifts and
+// rotations between pieces which are kept for the next one
+impl Game {
+    pub fn apply(&mut self, action: Action) {
+        use Action::*;
+        if let Some(inputs) = &mut self.inputs {
+            inputs.push((self.tick, action));
+        }
+        match action {
+            MoveLeft | MoveRight | RotateCW | RotateCCW | Rotate180 if self.is_waiting() => {
+                self.buffered.push(action)
+            }
+            _ => self.perform(action),
+        }
+    }
+
+    fn perform(&mut self, action: Action) {
+        use Action::*;
+        match action {
+            MoveLeft => self.move_falling_piece(-1, 0),
+            MoveRight => self.move_falling_piece(1, 0),
+            SoftDrop => self.move_falling_piece(0, 1),
+            HardDrop => self.hard_drop(),
+            RotateCW => self.rotate_falling_piece(1),
+            RotateCCW => self.rotate_falling_piece(-1),
+            Rotate180 => self.rotate_falling_piece(2),
+            Hold => self.switch_hold(),
+        }
+    }
+
     pub fn move_falling_piece(&mut self, dx: isize, dy: isize) {
         if let Some(ref mut falling) = self.falling {
             let mask = &self.mask_map[&falling.id][falling.mask_idx];
             let new_pos = (falling.pos.0 as isize + dx, falling.pos.1 as isize + dy);
             if !intersects_with(mask, new_pos, &self.board) {
                 falling.pos = new_pos;
-                falling.checked_reset_lock_delay();
+                falling.rotated_last = false;
+                falling.checked_reset_lock_delay(self.lock_frames);
+                if dx != 0 {
+                    self.ghost_y.set(None);
+                }
             }
-        } else {
-            panic!("tried to move with no falling piece")
         }
     }
 
     pub fn rotate_falling_piece(&mut self, di: isize) {
-        // +1 is 90° clockwise, -1 is 90° counterclockwise
+        // +1 is 90° clockwise, -1 is 90° counterclockwise, 2 is 180°
         if let Some(ref mut falling) = self.falling {
-            let new_idx = ((falling.mask_idx as isize + di % 4 + 4) % 4) as usize;
-            let new_mask = self.mask_map[&falling.id][new_idx];
-            // sometimes it's necessary to shift a bit when rotating, this is so
-            // that rotation isn't blocked when touching the ground or next to a wall
-            let mut success = false;
-            for (dx, dy) in #[rustfmt::skip] &[
-                    (0, 0),
-                    (0, -1), (0, -2), // up
-                    (0, 1), (0, 2), // down
-                    (-1, 0), (-2, 0), // left
-                    (1, 0), (2, 0), // right
-                ]
-            {
-                let pos = (falling.pos.0 + dx, falling.pos.1 + dy);
-                if !intersects_with(&new_mask, pos, &self.board) {
-                    falling.pos = pos;
-                    falling.mask_idx = new_idx;
-                    falling.mask = new_mask;
-                    success = true;
-                    break;
+            let masks = &self.mask_map[&falling.id];
+            let kicks = &self.kick_map[&falling.id];
+            if let Some(trace) = self.kick_trace.as_mut() {
+                let (mask_idx, attempts) =
+                    rotation_attempts(masks, kicks, falling.mask_idx, falling.pos, di, &self.board);
+                let (mut tried, mut succeeded) = (vec![], false);
+                for (pos, fits) in attempts {
+                    tried.push(pos);
+                    if fits {
+                        succeeded = true;
+                        break;
+                    }
                 }
+                *trace = Some(KickTrace {
+                    tick: self.tick,
+                    id: falling.id,
+                    mask_idx,
+                    tried,
+                    succeeded,
+                });
             }
-            if success {
-                falling.checked_reset_lock_delay();
+            // sometimes it's necessary to shift a bit when rotating
+            if let Some((new_idx, pos)) =
+                try_rotate(masks, kicks, falling.mask_idx, falling.pos, di, &self.board)
+            {
+                falling.pos = pos;
+                falling.mask_idx = new_idx;
+                falling.mask = masks[new_idx];
+                falling.rotated_last = true;
+                falling.checked_reset_lock_delay(self.lock_frames);
+                self.ghost_y.set(None);
             }
-        } else {
-            panic!("tried to rotate with no falling piece")
         }
     }
 
     // does scoring
     pub fn hard_drop(&mut self) {
         self.compact_board();
-        if self.falling.is_none() {
-            // self.spawn();
-            panic!("attempted to hard drop with no falling piece")
-        }
-        let falling = self.falling.as_mut().unwrap();
-        let mask = &falling.mask;
-        let pos = falling.pos;
-        let mut delta = 0;
-        while !intersects_with(mask, (pos.0, pos.1 + delta as isize + 1), &self.board) {
-            delta += 1
-        }
-        falling.pos = (pos.0, pos.1 + delta as isize);
+        let ghost = match self.ghost_pos() {
+            Some(ghost) => ghost,
+            None => return,
+        };
+        let falling = self.falling.as_mut().expect("there is a ghost");
+        let delta = (ghost.1 - falling.pos.1) as usize;
+        falling.pos = ghost;
+        falling.rotated_last &= delta == 0;
         self.destroy_falling_and_respawn();
         self.points += delta + 1;
     }
 
-    pub fn switch_hold(&mut self) {
-        if self.can_switch {
-            self.can_switch = false;
-            let old = self.hold.take();
-            self.hold = Some(
-                self.falling
-                    .take()
-                    .map(|fp| fp.id)
-                    .expect("tried to swap with no falling piece"),
-            );
-            if let Some(id) = old {
-                self.spawn_with_id(id)
-            } else {
-                self.spawn()
+    // push rows of garbage up from the bottom, each full except for a hole at hole_x,
+    // the falling piece is pushed up with the stack if necessary and the game is lost
+    // if anything would end up above the top
+    pub fn add_garbage(&mut self, rows: usize, hole_x: usize) {
+        let rows = min(rows, GAME_HEIGHT);
+        if rows == 0 {
+            return;
+        }
+        if self.board[..rows]
+            .iter()
+            .any(|row| row.iter().any(|px| !px.is_empty()))
+        {
+            self.falling = None;
+            self.lose();
+            return;
+        }
+        for y in 0..GAME_HEIGHT - rows {
+            self.board[y] = self.board[y + rows];
+        }
+        for row in self.board[GAME_HEIGHT - rows..].iter_mut() {
+            *row = [Pixel::Garbage; GAME_WIDTH];
+            row[hole_x] = Pixel::Empty;
+        }
+        self.board_hash = zobrist::hash_board(&self.board);
+        self.metrics.add_garbage(rows, hole_x);
+        self.ghost_y.set(None);
+        if let Some(falling) = &mut self.falling {
+            let board = &self.board;
+            let pushed = (0..=rows as isize)
+                .map(|dy| (falling.pos.0, falling.pos.1 - dy))
+                .find(|&pos| !intersects_with(&falling.mask, pos, board));
+            match pushed {
+                Some(pos) => falling.pos = pos,
+                None => {
+                    self.falling = None;
+                    self.lose();
+                }
+            }
+        }
+    }
+
+    // one line per row, '.' for empty cells, '#' for garbage and the piece letter otherwise,
+    // without the falling piece, for tests, bug reports and puzzles, see load_board_from_ascii
+    pub fn board_to_ascii(&self) -> String {
+        let cell = |px: &Pixel| match px {
+            Pixel::Empty => '.',
+            Pixel::Full(id) => id.to_char(),
+            Pixel::Garbage => '#',
+        };
+        self.board
+            .iter()
+            .map(|row| row.iter().map(cell).chain(Some('\n')).collect::<String>())
+            .collect()
+    }
+
+    // replaces the board with one written like board_to_ascii's, blank lines and the
+    // whitespace around rows are ignored and fewer rows than the board has fill it from the
+    // bottom, lowercase letters (the falling piece in Simulator::board_string) are left
+    // empty, a falling piece that doesn't fit anymore is dealt again at the top
+    pub fn load_board_from_ascii(&mut self, text: &str) -> Result<(), AsciiBoardError> {
+        let rows = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        if rows.len() > GAME_HEIGHT {
+            return Err(AsciiBoardError::TooManyRows(rows.len()));
+        }
+        let mut board = [[Pixel::Empty; GAME_WIDTH]; GAME_HEIGHT];
+        let top = GAME_HEIGHT - rows.len();
+        for (y, line) in rows.iter().enumerate() {
+            let width = line.chars().count();
+            if width != GAME_WIDTH {
+                return Err(AsciiBoardError::RowWidth { row: y + 1, width });
+            }
+            for (x, c) in line.chars().enumerate() {
+                board[top + y][x] = match c {
+                    '.' => Pixel::Empty,
+                    '#' => Pixel::Garbage,
+                    c if c.is_ascii_lowercase() => Pixel::Empty,
+                    c => {
+                        Pixel::Full(PieceId::from_char(c).ok_or(AsciiBoardError::UnknownCell {
+                            row: y + 1,
+                            column: x + 1,
+                            found: c,
+                        })?)
+                    }
+                };
+            }
+        }
+        self.set_board(board);
+        if let Some(falling) = &self.falling {
+            if intersects_with(&falling.mask, falling.pos, &self.board) {
+                let id = falling.id;
+                self.spawn_with_id(id);
             }
         }
+        Ok(())
+    }
+
+    pub fn switch_hold(&mut self) {
+        // there is nothing to hold between pieces, and the press isn't used up either
+        if !self.can_hold() {
+            return;
+        }
+        let falling = self
+            .falling
+            .take()
+            .expect("can_hold checks for a falling piece");
+        self.can_switch = false;
+        if let Some(id) = self.hold.replace(falling.id) {
+            self.spawn_with_id(id)
+        } else {
+            self.spawn()
+        }
+    }
+}
+
+#[test]
+fn test_inputs_after_game_over_are_ignored() {
+    let mut game = Game::new();
+    while !game.is_over() {
+        game.hard_drop();
+    }
+    let points = game.points;
+    for action in [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateCW,
+        Action::RotateCCW,
+        Action::Rotate180,
+        Action::Hold,
+    ] {
+        game.apply(action);
+        game.iterate();
+    }
+    assert_eq!(game.points, points);
+}
+
+#[test]
+fn test_locking_onto_full_cells_leaves_them() {
+    // as if garbage had come up under the falling piece without moving it
+    let mut game = Game::with_seed(1);
+    let mut board = [[Pixel::Empty; GAME_WIDTH]; GAME_HEIGHT];
+    for row in &mut board[..4] {
+        *row = [Pixel::Garbage; GAME_WIDTH];
+        row[0] = Pixel::Empty;
+    }
+    game.set_board(board);
+    game.destroy_falling_and_respawn();
+    assert_eq!(game.pieces, 1);
+    let garbage = game.board.iter().flatten();
+    let garbage = garbage.filter(|px| matches!(px, Pixel::Garbage));
+    assert_eq!(garbage.count(), 4 * 9);
+}
+
+#[test]
+fn test_shifts_during_entry_delay_move_the_next_piece() {
+    let mut game = Game::with_seed(1);
+    game.set_delays(3, 0);
+    game.hard_drop();
+    assert!(game.falling.is_none());
+    for action in [Action::MoveLeft, Action::SoftDrop, Action::MoveLeft] {
+        game.apply(action);
+        game.iterate();
+    }
+    let falling = game.falling.as_ref().expect("spawned after the delay");
+    assert_eq!(falling.pos, (GAME_WIDTH as isize / 2 - 4, 0));
+}
+
+#[test]
+fn test_gravity_accumulates_fractions_of_rows() {
+    let mut game = Game::with_seed(1);
+    // 1.5 rows per frame at level 1
+    game.set_gravity(1.5 / 0.01667);
+    game.iterate();
+    game.iterate();
+    assert_eq!(game.falling.as_ref().unwrap().pos.1, 3);
+    // 20G lands the piece on the first frame
+    let mut game = Game::with_seed(1);
+    game.set_gravity(20. / 0.01667);
+    game.iterate();
+    assert_eq!(game.ghost_pos(), Some(game.falling.as_ref().unwrap().pos));
+}
+
+#[test]
+fn test_lock_delay_is_the_same_for_any_gravity() {
+    let frames_to_lock = |gravity| {
+        let mut game = Game::with_seed(1);
+        game.set_gravity(gravity);
+        for _ in 0..GAME_HEIGHT {
+            game.move_falling_piece(0, 1);
+        }
+        (1..).find(|_| {
+            game.iterate();
+            game.pieces == 1
+        })
+    };
+    assert_eq!(frames_to_lock(0.), Some(Game::LOCK_DELAY + 1));
+    assert_eq!(frames_to_lock(1.), Some(Game::LOCK_DELAY + 1));
+    assert_eq!(frames_to_lock(20. / 0.01667), Some(Game::LOCK_DELAY + 1));
+}
+
+#[test]
+fn test_mask_syntax_errors() {
+    assert_eq!(builtin_masks().masks.len(), PieceId::ALL.len());
+    let position = |text: &str| match parse_masks(text) {
+        Err(MaskLoadError::Syntax { line, column, .. }) => (line, column),
+        _ => panic!("expected a syntax error"),
+    };
+    let typo = BUILTIN_MASKS.replacen(".  0  .  .", ".  0  x  .", 1);
+    let line = BUILTIN_MASKS
+        .lines()
+        .position(|l| l == ".  0  .  .")
+        .unwrap()
+        + 1;
+    assert_eq!(position(&typo), (line, 7));
+    assert_eq!(position("// comment\n  Foo"), (2, 3));
+    assert_eq!(position("IBlock\n.  .  ."), (2, 8));
+    let missing_cell = BUILTIN_MASKS.replacen("0  0  0  0", ".  0  0  0", 1);
+    assert!(matches!(
+        parse_masks(&missing_cell),
+        Err(MaskLoadError::CellCount {
+            id: PieceId::IBlock,
+            rotation: 0,
+            cells: 3
+        })
+    ));
+}
+
+#[test]
+fn test_kicks_in_masks_file() {
+    // the kicks belong to the last piece in the file
+    let with_kicks = |kicks: &str| parse_masks(&format!("{}\n{}\n", BUILTIN_MASKS, kicks));
+    let set = with_kicks("kicks 0>1 0,0 -1,2\nkicks 3>0 1,0").unwrap();
+    let kicks = &set.kicks[&PieceId::ZBlock];
+    assert_eq!(kicks[&(0, 1)], vec![(0, 0), (-1, 2)]);
+    assert_eq!(kicks[&(1, 2)], ROTATION_KICKS.to_vec());
+    assert_eq!(kicks.len(), 12);
+    let set = parse_masks(&format!("kicks 0,0\n{}", BUILTIN_MASKS)).unwrap();
+    assert_eq!(set.kicks[&PieceId::TBlock][&(3, 1)], vec![(0, 0)]);
+
+    let line = BUILTIN_MASKS.lines().count() + 2;
+    let position = |kicks: &str| match with_kicks(kicks) {
+        Err(MaskLoadError::Syntax { line, column, .. }) => (line, column),
+        _ => panic!("expected a syntax error"),
+    };
+    assert_eq!(position("kicks 0>4 0,0"), (line, 7));
+    assert_eq!(position("kicks 0>1 0,0 1"), (line, 15));
+    assert_eq!(position("kicks 0>1"), (line, 10));
+}
+
+#[test]
+fn test_action_priorities_are_a_total_order() {
+    let mut actions = Action::ALL;
+    actions.sort_by_key(|action| action.priority());
+    assert!(actions
+        .windows(2)
+        .all(|w| w[0].priority() < w[1].priority()));
+    let position = |action| actions.iter().position(|&a| a == action);
+    assert!(position(Action::RotateCW) < position(Action::MoveLeft));
+}
+
+#[test]
+fn test_rotation_system_replaces_kicks() {
+    let mut game = Game::with_seed(0);
+    for &system in &RotationSystem::ALL {
+        game.set_rotation_system(system);
+        assert_eq!(game.rotation_system(), system);
+        assert!(game.falling.is_some());
+    }
+    game.set_rotation_system(RotationSystem::Srs);
+    let kicks = &game.kick_map[&PieceId::IBlock][&(0, 1)];
+    assert_eq!(kicks, &vec![(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)]);
+    game.set_rotation_system(RotationSystem::Nintendo);
+    assert_eq!(game.kick_map[&PieceId::TBlock][&(2, 3)], vec![(0, 0)]);
+}
+
+#[test]
+fn test_kick_trace_records_tried_positions() {
+    let mut game = Game::with_seed(0);
+    game.rotate_falling_piece(1);
+    assert!(game.last_rotation().is_none());
+    game.trace_kicks();
+    // nothing fits once the board is full around the piece
+    let falling = game.falling.as_ref().unwrap();
+    let (id, pos) = (falling.id, falling.pos);
+    let mut board = [[Pixel::Garbage; GAME_WIDTH]; GAME_HEIGHT];
+    for (x, y) in falling.mask.cells_at(pos) {
+        board[y as usize][x as usize] = Pixel::Empty;
+    }
+    game.set_board(board);
+    game.rotate_falling_piece(1);
+    let trace = game.last_rotation().unwrap();
+    assert_eq!((trace.id, trace.succeeded), (id, false));
+    assert_eq!(trace.tried.len(), game.kick_map[&id][&(0, 1)].len());
+    assert_eq!(trace.tried[0], pos);
+}
+
+#[test]
+fn test_ascii_board_round_trip() {
+    let mut game = Game::with_seed(0);
+    let text = "
+        ..........
+        T.......##
+        TTIIII.O##
+    ";
+    game.load_board_from_ascii(text).unwrap();
+    assert!(matches!(
+        game.board[GAME_HEIGHT - 1][2],
+        Pixel::Full(PieceId::IBlock)
+    ));
+    let ascii = game.board_to_ascii();
+    assert_eq!(ascii.lines().count(), GAME_HEIGHT);
+    assert!(ascii.ends_with("T.......##\nTTIIII.O##\n"));
+    let mut other = Game::with_seed(1);
+    other.load_board_from_ascii(&ascii).unwrap();
+    assert_eq!(other.board_hash(), game.board_hash());
+    assert_eq!(
+        game.load_board_from_ascii("T.......X#"),
+        Err(AsciiBoardError::UnknownCell {
+            row: 1,
+            column: 9,
+            found: 'X'
+        })
+    );
+}
+
+#[test]
+fn test_peek_deals_the_same_pieces() {
+    let mut game = Game::with_seed(3);
+    let peeked = game.piece_queue.peek(20);
+    assert_eq!(peeked[..3], game.piece_queue.iter().collect::<Vec<_>>()[..]);
+    let dealt = (0..20).map(|_| game.piece_queue.pop()).collect::<Vec<_>>();
+    assert_eq!(peeked, dealt);
+    // every bag has each piece once
+    let remaining = game.piece_queue.remaining_bag();
+    let upcoming = game.piece_queue.peek(3 + remaining.len());
+    assert!(remaining.iter().all(|id| upcoming[3..].contains(id)));
+}
+
+#[test]
+fn test_mirrored_actions() {
+    for action in Action::ALL.iter().copied() {
+        assert_eq!(action.mirrored().mirrored(), action);
     }
+    assert_eq!(Action::MoveLeft.mirrored(), Action::MoveRight);
+    assert_eq!(Action::Rotate180.mirrored(), Action::Rotate180);
 }
@@ -1,134 +1,465 @@
 use crate::{
-    game::{nn_visual::KEY_ORDER, visual::VisGame, GAME_HEIGHT, GAME_WIDTH},
-    neural_network::{ActivationType, NNCreationError, NNReadError, NNReadResult, NN},
-    run_game,
+    game::{
+        evaluation::{EvalLimits, FitnessWeights, Noise},
+        features::InputEncoder,
+        heuristic::baseline_fitness,
+        placement::{best_placement_batch, Placement},
+        population::IslandConfig,
+        reward::RewardExpr,
+        self_play::VersusConfig,
+        Action, Controller, Game,
+    },
+    neural_network::{ActivationType, NNCreationError, NN},
 };
-use ggez::{
-    event::{EventHandler, KeyMods},
-    input::keyboard::KeyCode,
-    Context, GameResult,
-};
-use itertools::Itertools;
-use std::{
-    fs, io,
-    path::{Path, PathBuf},
-};
-use tap::TapOps;
+use serde::Deserialize;
+use std::{cell::RefCell, fmt, fs, io, mem, path::Path, thread};
+
+// meaning of each network output, without Rotate180 which came later (networks saved
+// before it still load)
+pub const ACTION_ORDER: [Action; 7] = [
+    Action::RotateCW,
+    Action::SoftDrop,
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::Hold,
+    Action::RotateCCW,
+    Action::HardDrop,
+];
 
-fn load_generation<P: AsRef<Path>>(path: &P) -> NNReadResult<Vec<NN>> {
-    fs::read_to_string(path)?
-        .split("--\n")
-        .map(NN::from_string)
-        .collect()
+// placement whose afterstate the network scores highest, the network sees the afterstate
+// through the noise, all of them in one batch
+pub(super) fn choose_placement(
+    nn: &NN,
+    encoder: InputEncoder,
+    noise: &Noise,
+    game: &Game,
+) -> Option<Placement> {
+    let mut queue = game.piece_queue.iter();
+    // after the placement, the next piece becomes the current one
+    let (current, next) = (queue.next(), queue.next());
+    best_placement_batch(game, |boards| {
+        // only borrowed once the afterstates are made, they are clones of the game
+        let mut rng = game.noise_rng.borrow_mut();
+        INPUTS.with(|inputs| {
+            let mut inputs = inputs.borrow_mut();
+            if inputs.len() < boards.len() {
+                inputs.resize_with(boards.len(), Vec::new);
+            }
+            for (board, input) in boards.iter().zip(inputs.iter_mut()) {
+                let seen = noise.perturb(board, &mut rng);
+                encoder.encode_board_into(&seen, current, next, input);
+            }
+            nn.apply_batch(&inputs[..boards.len()])
+                .into_iter()
+                .map(|output| output[0])
+                .collect()
+        })
+    })
 }
 
-fn save_generation<P: AsRef<Path>>(path: &P, generation: &[NN]) -> io::Result<()> {
-    fs::write(path, generation.iter().map(NN::to_string).join("--\n"))
+// when a key output counts as pressed, a released key is pressed once its output is above
+// press and a pressed one released once its output is at or below release
+#[derive(Copy, Clone, Debug)]
+struct Threshold {
+    press: f64,
+    release: f64,
 }
 
-pub struct NNTrainer {
-    vis: VisGame,
+// thresholds of their own for one key
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct KeyThreshold {
+    pub key: Action,
+    pub press: f64,
+    pub release: f64,
+}
 
-    dir: PathBuf,
-    generation: Vec<NN>,
-    training: usize, // index
+// how often the network may use one key, whatever its output
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct KeyLimit {
+    pub key: Action,
+    #[serde(default)]
+    pub cooldown: usize, // frames after a use before the next one
+    pub per_piece: Option<usize>,
 }
 
-#[derive(From, Debug)]
-pub enum NNReadOrCreationError {
-    Read(NNReadError),
-    Create(NNCreationError),
+// how the outputs of a keys mode network become key presses, e.g.
+//
+//     [keys]
+//     press = 0.6
+//     release = 0.4 # outputs hovering around one threshold don't make keys chatter
+//     min_hold = 3  # decisions a pressed key stays pressed at least
+//     per_key = [{ key = "HardDrop", press = 0.9, release = 0.5 }]
+//     limits = [{ key = "HardDrop", per_piece = 1 }, { key = "RotateCW", cooldown = 10 }]
+//
+// a pressed key's action is queued at every decision unless a limit holds it back, limits
+// keep early generations from winning by spamming a key, the default single threshold of 0.5
+// without a minimum or limits is how networks have always been played
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct KeyControl {
+    pub press: f64,
+    pub release: f64,
+    pub min_hold: usize,
+    pub per_key: Vec<KeyThreshold>,
+    pub limits: Vec<KeyLimit>,
 }
 
-pub type NNReadOrCreateResult<T> = Result<T, NNReadOrCreationError>;
+impl Default for KeyControl {
+    fn default() -> Self {
+        Self {
+            press: 0.5,
+            release: 0.5,
+            min_hold: 0,
+            per_key: vec![],
+            limits: vec![],
+        }
+    }
+}
+
+impl KeyControl {
+    fn threshold(&self, action: Action) -> Threshold {
+        let own = self.per_key.iter().find(|k| k.key == action);
+        Threshold {
+            press: own.map_or(self.press, |k| k.press),
+            release: own.map_or(self.release, |k| k.release),
+        }
+    }
+}
 
-impl NNTrainer {
+// the network's keys, in the order of ACTION_ORDER, kept in the game so that every game a
+// network plays starts with all keys released
+#[derive(Clone, Debug, Default)]
+pub(super) struct KeyState {
+    held: [Option<usize>; ACTION_ORDER.len()], // decisions pressed for, None while released
+    last_use: [Option<usize>; ACTION_ORDER.len()], // tick
+    uses: [usize; ACTION_ORDER.len()],         // with the current piece
+    piece: usize,                              // pieces locked when uses were counted
+}
+
+impl KeyState {
     #[allow(dead_code)]
-    pub fn new(dir: &Path) -> NNReadOrCreateResult<Self> {
-        let dir = PathBuf::from(".").tap(|pb| pb.push(dir));
-        let generation = match load_generation(&dir) {
-            Ok(gen) => gen,
-            Err(_) => {
-                let gen_size = 10;
-                eprintln!(
-                    "Warning: failed to load generation, creating a random one of size {}",
-                    gen_size
-                );
-                (0..gen_size)
-                    .map(|_| {
-                        NN::make(GAME_WIDTH * GAME_HEIGHT)
-                            .add_layer(20, ActivationType::Relu)
-                            .add_layer(10, ActivationType::Relu)
-                            .add_layer(7, ActivationType::Sigmoid)
-                            .build()
-                    })
-                    .collect::<Result<_, _>>()?
-            }
-        };
-        Ok(Self {
-            vis: VisGame::new(),
+    pub(super) fn is_held(&self, i: usize) -> bool {
+        self.held[i].is_some()
+    }
 
-            dir,
-            generation,
-            training: 0,
-        })
+    // whether key i is pressed after a decision with the given output
+    fn update(&mut self, i: usize, output: f64, threshold: Threshold, min_hold: usize) -> bool {
+        let held = &mut self.held[i];
+        *held = match *held {
+            None if output > threshold.press => Some(1),
+            Some(n) if n < min_hold || output > threshold.release => Some(n + 1),
+            _ => None,
+        };
+        held.is_some()
     }
 
-    #[allow(dead_code)]
-    pub fn run(&mut self) -> GameResult<()> {
-        run_game(self)
+    // counts a use of key i at tick unless the limit holds it back, return whether it
+    // may be used
+    fn use_key(&mut self, i: usize, limit: Option<&KeyLimit>, tick: usize, pieces: usize) -> bool {
+        if pieces != self.piece {
+            self.piece = pieces;
+            self.uses = Default::default();
+        }
+        if let Some(limit) = limit {
+            let cooling = self.last_use[i].is_some_and(|last| tick < last + limit.cooldown);
+            let used_up = limit.per_piece.is_some_and(|max| self.uses[i] >= max);
+            if cooling || used_up {
+                return false;
+            }
+        }
+        self.last_use[i] = Some(tick);
+        self.uses[i] += 1;
+        true
     }
 }
 
-impl EventHandler for NNTrainer {
-    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let input = self.vis.game.get_cells();
-        let nn_output = self.generation[self.training].apply(&input);
-        // if manual control is on, this depends on the user, otherwise, it depends on the nn
-        let manual_output = (0..7)
-            .map(|i| self.vis.keys[&KEY_ORDER[i]].state.is_pressed())
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
+thread_local! {
+    // reused by decide, which runs every frame
+    static INPUT: RefCell<Vec<f64>> = const { RefCell::new(Vec::new()) };
+    // reused by choose_placement, one buffer per afterstate, kept between pieces
+    static INPUTS: RefCell<Vec<Vec<f64>>> = const { RefCell::new(Vec::new()) };
+}
 
-        for ((&nn_out, &is_pressed), &code) in nn_output
-            .iter()
-            .zip(manual_output.iter())
-            .zip(KEY_ORDER.iter())
-        {
-            let should_be_pressed = nn_out > 0.5;
-            if is_pressed && !should_be_pressed {
-                self.vis.key_up_event(ctx, code, KeyMods::default())
-            } else if !is_pressed && should_be_pressed {
-                self.vis
-                    .key_down_event(ctx, code, KeyMods::default(), false)
+// queues the genome's next inputs once the previous ones have been applied
+pub(super) fn decide(nn: &NN, config: &TrainerConfig, game: &mut Game) {
+    if game.has_queued_actions() {
+        return;
+    }
+    match config.mode {
+        ControlMode::Keys => {
+            // every action whose key is pressed, see KeyControl
+            let seen = config.noise.perturb(&game.board, game.noise_rng.get_mut());
+            let output = INPUT.with(|input| {
+                let mut input = input.borrow_mut();
+                config.encoder.encode_seen_into(game, &seen, &mut input);
+                nn.apply_batch(std::slice::from_ref(&*input)).remove(0)
+            });
+            let mut keys = mem::take(&mut game.key_state);
+            for (i, (&out, &action)) in output.iter().zip(ACTION_ORDER.iter()).enumerate() {
+                let threshold = config.keys.threshold(action);
+                let limit = config.keys.limits.iter().find(|limit| limit.key == action);
+                if keys.update(i, out, threshold, config.keys.min_hold)
+                    && keys.use_key(i, limit, game.tick, game.pieces)
+                {
+                    game.queue_action(action);
+                }
+            }
+            game.key_state = keys;
+        }
+        ControlMode::Placement => {
+            if let Some(best) = choose_placement(nn, config.encoder, &config.noise, game) {
+                for action in best.inputs {
+                    game.queue_action(action);
+                }
             }
         }
+    }
+}
 
-        self.vis.update(ctx)
+// one frame of a game played by the network, with garbage noise after every lock
+pub(super) fn step(nn: &NN, config: &TrainerConfig, game: &mut Game) {
+    let pieces = game.pieces;
+    decide(nn, config, game);
+    game.iterate();
+    if game.pieces > pieces {
+        config.noise.inject_garbage(game);
     }
+}
+
+// how the network's output is turned into inputs
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+pub enum ControlMode {
+    // the network decides which keys are pressed every frame (7 outputs)
+    Keys,
+    // the network scores every reachable placement of the current piece (1 output),
+    // the best one is reached with an automatic input sequence
+    Placement,
+}
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        self.vis.draw(ctx)
+impl ControlMode {
+    pub(super) fn output_size(self) -> usize {
+        match self {
+            ControlMode::Keys => ACTION_ORDER.len(),
+            ControlMode::Placement => 1,
+        }
     }
+}
+
+// how mutation rate and strength change over the course of training
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum MutationSchedule {
+    Fixed,
+    // both are multiplied by decay every generation but never drop below min_factor times
+    // their configured value
+    Annealed { decay: f64, min_factor: f64 },
+    // both are multiplied by boost after patience generations without a new best fitness,
+    // and go back to normal as soon as there is one
+    Stagnation { patience: usize, boost: f64 },
+}
+
+impl MutationSchedule {
+    // multiplier for the configured rate and strength
+    pub(super) fn factor(self, generation_number: usize, stagnant_generations: usize) -> f64 {
+        match self {
+            MutationSchedule::Fixed => 1.,
+            MutationSchedule::Annealed { decay, min_factor } => {
+                decay.powi(generation_number as i32).max(min_factor)
+            }
+            MutationSchedule::Stagnation { patience, boost } => {
+                if stagnant_generations >= patience {
+                    boost
+                } else {
+                    1.
+                }
+            }
+        }
+    }
+}
+
+// early generations play with reduced gravity, which is raised as they get better
+// (the board size is fixed at compile time so it can't be part of the curriculum)
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Curriculum {
+    pub start_gravity: f32,
+    // gravity goes up by step (up to normal) whenever a generation's best fitness
+    // reaches promote_at
+    pub step: f32,
+    pub promote_at: f64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct TrainerConfig {
+    pub encoder: InputEncoder,
+    pub mode: ControlMode,
+    pub keys: KeyControl,          // keys mode only
+    pub generation_size: usize,    // only used when starting from scratch
+    pub hidden_layers: Vec<usize>, // relu layers of new genomes, like generation_size
+    // the better half of a generation survives, the rest is replaced by mutated copies
+    pub mutation_rate: f64,     // chance of each weight being changed
+    pub mutation_strength: f64, // maximum change of a weight
+    pub mutation_schedule: MutationSchedule,
+    pub curriculum: Option<Curriculum>,
+    pub limits: EvalLimits,
+    pub fitness: FitnessWeights,
+    // replaces the fitness weights when set, see reward.rs
+    pub reward: Option<RewardExpr>,
+    pub noise: Noise,
+    // evaluate the whole generation at once, shown as a grid of thumbnails
+    pub parallel: bool,
+    // headless training only
+    pub islands: Option<IslandConfig>,
+    // an event file for TensorBoard next to metrics.csv, headless training only
+    pub tensorboard: bool,
+    // genomes play versus matches against each other instead of solo games, placement mode
+    // and headless training only
+    pub versus: Option<VersusConfig>,
+}
+
+impl Default for TrainerConfig {
+    fn default() -> Self {
+        Self {
+            encoder: InputEncoder::Features,
+            mode: ControlMode::Placement,
+            keys: KeyControl::default(),
+            generation_size: 10,
+            hidden_layers: vec![20, 10],
+            mutation_rate: 0.1,
+            mutation_strength: 0.5,
+            mutation_schedule: MutationSchedule::Fixed,
+            curriculum: None,
+            limits: EvalLimits::default(),
+            fitness: FitnessWeights::default(),
+            reward: None,
+            noise: Noise::default(),
+            parallel: false,
+            islands: None,
+            tensorboard: false,
+            versus: None,
+        }
+    }
+}
+
+#[derive(From, Debug)]
+pub enum ConfigReadError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+}
 
-    fn key_down_event(
-        &mut self,
-        ctx: &mut Context,
-        keycode: KeyCode,
-        keymods: KeyMods,
-        repeat: bool,
-    ) {
-        if keycode == KeyCode::Escape {
-            self.vis.key_down_event(ctx, keycode, keymods, repeat);
-        } else if self.vis.paused && keycode == KeyCode::LControl {
-            save_generation(&self.dir, &self.generation).expect("failed to save generation");
-            println!("saved nn in \"{}\"", self.dir.display());
+impl fmt::Display for ConfigReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read config: {}", e),
+            Self::TomlError(e) => write!(f, "invalid config: {}", e),
         }
     }
+}
 
-    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
-        if keycode == KeyCode::Escape {
-            self.vis.key_up_event(ctx, keycode, keymods)
+impl TrainerConfig {
+    // a random network of the shape the config asks for
+    pub fn new_network(&self) -> Result<NN, NNCreationError> {
+        self.hidden_layers
+            .iter()
+            .fold(NN::make(self.encoder.input_size()), |nn, &size| {
+                nn.add_layer(size, ActivationType::Relu)
+            })
+            .add_layer(self.mode.output_size(), ActivationType::Sigmoid)
+            .build()
+    }
+
+    // what's wrong with the config for headless training
+    pub fn check(&self) -> Result<(), String> {
+        if self.versus.is_some() && self.mode != ControlMode::Placement {
+            return Err("versus training needs placement mode".to_string());
         }
+        Ok(())
+    }
+
+    // a toml file with any of the fields above, missing ones keep their default value,
+    // enums with fields have to be written as inline tables, e.g.
+    // mutation_schedule = { Annealed = { decay = 0.99, min_factor = 0.1 } }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigReadError> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+// the baseline is a whole game, it's played on a thread of its own so that the window
+// (or the first generation) doesn't wait for it and printed whenever it's done
+pub fn print_baseline(mode: ControlMode) {
+    if mode == ControlMode::Placement {
+        thread::spawn(|| {
+            println!(
+                "heuristic baseline: {} points in 500 pieces",
+                baseline_fitness(500)
+            )
+        });
     }
 }
+
+#[test]
+fn test_mutation_schedule() {
+    let annealed = MutationSchedule::Annealed {
+        decay: 0.5,
+        min_factor: 0.2,
+    };
+    assert_eq!(annealed.factor(0, 0), 1.);
+    assert_eq!(annealed.factor(2, 0), 0.25);
+    assert_eq!(annealed.factor(10, 0), 0.2);
+
+    let stagnation = MutationSchedule::Stagnation {
+        patience: 5,
+        boost: 3.,
+    };
+    assert_eq!(stagnation.factor(100, 4), 1.);
+    assert_eq!(stagnation.factor(100, 5), 3.);
+}
+
+#[test]
+fn test_key_hysteresis() {
+    let keys: KeyControl = toml::from_str(
+        "
+        press = 0.6
+        release = 0.4
+        min_hold = 2
+        per_key = [{ key = \"HardDrop\", press = 0.9, release = 0.5 }]
+        ",
+    )
+    .unwrap();
+    let threshold = keys.threshold(Action::MoveLeft);
+    let mut held = KeyState::default();
+    let pressed = [0.55, 0.65, 0.3, 0.45, 0.35, 0.55]
+        .iter()
+        .map(|&out| held.update(0, out, threshold, keys.min_hold))
+        .collect::<Vec<_>>();
+    // pressed above 0.6, held for two decisions whatever the output, then until it drops
+    // to 0.4 and not pressed again below 0.6
+    assert_eq!(pressed, vec![false, true, true, true, false, false]);
+    assert_eq!(keys.threshold(Action::HardDrop).press, 0.9);
+}
+
+#[test]
+fn test_key_limits() {
+    let drop = KeyLimit {
+        key: Action::HardDrop,
+        cooldown: 0,
+        per_piece: Some(1),
+    };
+    let rotate = KeyLimit {
+        key: Action::RotateCW,
+        cooldown: 10,
+        per_piece: None,
+    };
+    let mut keys = KeyState::default();
+    // one hard drop per piece
+    assert!(keys.use_key(6, Some(&drop), 0, 0));
+    assert!(!keys.use_key(6, Some(&drop), 1, 0));
+    assert!(keys.use_key(6, Some(&drop), 2, 1));
+    // a rotation every ten frames
+    assert!(keys.use_key(0, Some(&rotate), 0, 1));
+    assert!(!keys.use_key(0, Some(&rotate), 9, 1));
+    assert!(keys.use_key(0, Some(&rotate), 10, 1));
+    // no limit, no holding back
+    assert!((0..3).all(|tick| keys.use_key(2, None, tick, 1)));
+}
@@ -1,107 +1,287 @@
 use crate::{
-    game::{visual::VisGame, GAME_HEIGHT, GAME_WIDTH},
+    game::{
+        evaluation::Noise,
+        features::InputEncoder,
+        ggez_frontend::GgezRenderer,
+        nn_trainer::{choose_placement, decide, ControlMode, TrainerConfig, ACTION_ORDER},
+        render::{Color, Command, Rect, Renderer},
+        replay::{save_replay, LockRecord},
+        visual::VisGame,
+        Action, GAME_HEIGHT, GAME_WIDTH,
+    },
     neural_network::{ActivationType, NN},
-    run_game,
+    run_game_in,
 };
 use ggez::{
     event::{EventHandler, KeyMods},
     input::keyboard::KeyCode,
     Context, GameResult,
 };
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-// don't know how useful this actually is
+// a network playing a visible game, controlled the same way as during training, with what
+// it sees and the output of every layer in the side panel
+//
+// the take over key (F2) hands the game to the player until it's pressed again, the pieces
+// the player locks in the meantime can be kept as corrections to imitate
 pub struct NNVisGame {
     vis: VisGame,
     nn: NN,
+    config: TrainerConfig,
+    input: Box<[f64]>,
+    activations: Vec<Vec<f64>>,
+    piece: usize, // pieces locked when the activations were taken, in placement mode
+    manual: bool, // the player has taken over
+    corrections: Option<(PathBuf, Vec<LockRecord>)>,
+}
+
+const UNIT_COLOR: Color = Color::rgb(70, 150, 240);
+const PRESSED_COLOR: Color = Color::rgb(181, 45, 45);
+
+// short enough for the side panel
+fn label(action: Action) -> &'static str {
+    match action {
+        Action::MoveLeft => "left",
+        Action::MoveRight => "right",
+        Action::SoftDrop => "soft",
+        Action::HardDrop => "hard",
+        Action::RotateCW => "cw",
+        Action::RotateCCW => "ccw",
+        Action::Rotate180 => "180",
+        Action::Hold => "hold",
+    }
 }
 
 impl NNVisGame {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        Self {
-            vis: VisGame::new(),
+        Self::with_nn(
+            VisGame::new(),
             // all cells as input, 7 keys as output
-            // nn: NN::new(&[GAME_WIDTH * GAME_HEIGHT, 20, 10, 7]),
-            nn: NN::make(GAME_WIDTH * GAME_HEIGHT)
+            NN::make(GAME_WIDTH * GAME_HEIGHT)
                 .add_layer(20, ActivationType::Relu)
                 .add_layer(10, ActivationType::Relu)
                 .add_layer(7, ActivationType::Sigmoid)
                 .build()
                 .unwrap(),
+            TrainerConfig {
+                encoder: InputEncoder::Cells,
+                mode: ControlMode::Keys,
+                ..TrainerConfig::default()
+            },
+        )
+    }
+
+    // config has to match the one the network was trained with
+    pub fn with_nn(vis: VisGame, nn: NN, config: TrainerConfig) -> Self {
+        Self {
+            vis,
+            nn,
+            config,
+            input: Box::new([]),
+            activations: vec![],
+            piece: usize::MAX,
+            manual: false,
+            corrections: None,
+        }
+    }
+
+    // keep the pieces the player locks after taking over and save them as a replay in dir
+    // when the game ends, for imitate --replays <dir>, call after seeded
+    pub fn with_corrections(mut self, dir: &Path) -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = dir.join(format!("corrections_{}.txt", secs));
+        self.corrections = Some((path, vec![]));
+        self.vis.game.start_recording();
+        self
+    }
+
+    fn take_over(&mut self) {
+        self.manual = !self.manual;
+        // what the network queued isn't played on the player's behalf
+        self.vis.game.clear_queued_actions();
+        if self.manual {
+            println!("you have control");
+        } else {
+            println!("the network has control");
+        }
+    }
+
+    // the pieces locked since recorded pieces were, if the player locked them
+    fn add_corrections(&mut self, recorded: usize) {
+        if let (true, Some((_, corrections))) = (self.manual, &mut self.corrections) {
+            let recording = self.vis.game.recording().unwrap_or_default();
+            corrections.extend_from_slice(&recording[recorded.min(recording.len())..]);
+        }
+    }
+
+    fn save_corrections(&mut self) {
+        match self.corrections.take() {
+            Some((path, corrections)) if !corrections.is_empty() => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).expect("failed to create corrections directory");
+                }
+                save_replay(&path, &corrections).expect("failed to save corrections");
+                println!(
+                    "saved {} corrections in \"{}\"",
+                    corrections.len(),
+                    path.display()
+                );
+            }
+            _ => (),
+        }
+    }
+
+    // in keys mode what the network sees every frame, in placement mode the afterstate it
+    // scored highest for the current piece, both without the noise of training
+    fn update_activations(&mut self) {
+        let game = &self.vis.game;
+        match self.config.mode {
+            ControlMode::Keys => self.input = self.config.encoder.encode(game),
+            ControlMode::Placement => {
+                if game.pieces == self.piece || game.falling.is_none() {
+                    return;
+                }
+                self.piece = game.pieces;
+                let chosen =
+                    choose_placement(&self.nn, self.config.encoder, &Noise::default(), game);
+                let mut queue = game.piece_queue.iter();
+                let (current, next) = (queue.next(), queue.next());
+                self.input = match chosen {
+                    Some(placement) => {
+                        let (board, _) = placement.afterstate(game);
+                        self.config.encoder.encode_board(&board, current, next)
+                    }
+                    None => return,
+                };
+            }
+        }
+        self.activations = self.nn.activations(&self.input);
+    }
+
+    // a strip of units per layer, input first, brighter the more active, then the keys the
+    // network holds down or the score of the placement
+    fn add_activations(&self, panel: Rect, r: &mut dyn Renderer) {
+        let outputs = match self.config.mode {
+            ControlMode::Keys => ACTION_ORDER.len(),
+            ControlMode::Placement => 1,
+        };
+        let strips = 1 + self.activations.len();
+        let row = (panel.h / (strips + outputs) as f32).min(24.);
+        let mut y = panel.y;
+        let layers =
+            std::iter::once(&self.input[..]).chain(self.activations.iter().map(|a| &a[..]));
+        for layer in layers {
+            // relu is unbounded, every layer is scaled to its own largest value
+            let most = layer.iter().fold(1e-9, |most: f64, a| most.max(a.abs()));
+            let width = panel.w / layer.len().max(1) as f32;
+            for (i, a) in layer.iter().enumerate() {
+                let rect = Rect {
+                    x: panel.x + i as f32 * width,
+                    y,
+                    w: width.max(1.),
+                    h: row * 0.8,
+                };
+                r.rect(rect, UNIT_COLOR.scaled((a.abs() / most) as f32));
+            }
+            y += row;
+        }
+        let output = match self.activations.last() {
+            Some(output) => output,
+            None => return,
+        };
+        match self.config.mode {
+            ControlMode::Keys => {
+                for (i, (&a, &action)) in output.iter().zip(ACTION_ORDER.iter()).enumerate() {
+                    let bar = Rect {
+                        x: panel.x + panel.w / 3.,
+                        y,
+                        w: 2. * panel.w / 3. * a as f32,
+                        h: row * 0.8,
+                    };
+                    let color = if self.vis.game.key_state.is_held(i) {
+                        PRESSED_COLOR
+                    } else {
+                        UNIT_COLOR
+                    };
+                    r.rect(bar, color);
+                    r.text((panel.x, y), label(action), Color::WHITE);
+                    y += row;
+                }
+            }
+            ControlMode::Placement => {
+                let score = format!("score {:.3}", output[0]);
+                r.text((panel.x, y), &score, Color::WHITE);
+                y += row;
+            }
+        }
+        if self.manual {
+            r.text((panel.x, y), "you have control", PRESSED_COLOR);
         }
     }
 
     #[allow(dead_code)]
     pub fn run(&mut self) -> GameResult<()> {
-        run_game(self)
+        let window_mode = self.vis.window_mode();
+        run_game_in(self, window_mode)
     }
 }
 
-pub(crate) const KEY_ORDER: [KeyCode; 7] = [
-    KeyCode::Up,
-    KeyCode::Down,
-    KeyCode::Left,
-    KeyCode::Right,
-    KeyCode::J,
-    KeyCode::RShift,
-    KeyCode::Space,
-];
-
-fn print_out(label: &str, out: &[f64]) {
-    print!("{:>5}: [", label);
-    for n in out[..out.len() - 1].iter() {
-        print!("{:.2}, ", n);
-    }
-    println!("{:.2}]", out[out.len() - 1]);
+impl Default for NNVisGame {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl EventHandler for NNVisGame {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        println!("update");
-        let input = self.vis.game.get_cells();
-        print_out("in", &input);
-        let mut output = self
-            .nn
-            .apply(&input)
-            .iter()
-            .copied()
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
-        print_out("raw", &output);
-        // normalize with sigmoid
-        for out in output.iter_mut() {
-            *out = 1. / (1. + (-*out).exp())
+        if !self.manual {
+            decide(&self.nn, &self.config, &mut self.vis.game);
         }
-        print_out("norm", &output);
-        for (i, out) in output.iter_mut().enumerate() {
-            let code = KEY_ORDER[i];
-            let is_pressed = self.vis.keys[&code].state.is_pressed();
-            let should_be_pressed = *out > 0.5;
-            if is_pressed && !should_be_pressed {
-                self.vis.key_up_event(ctx, code, KeyMods::default())
-            } else if !is_pressed && should_be_pressed {
-                self.vis
-                    .key_down_event(ctx, code, KeyMods::default(), false)
-            }
+        self.update_activations();
+        let recorded = self.vis.game.recording().map_or(0, |r| r.len());
+        self.vis.update(ctx)?;
+        self.add_corrections(recorded);
+        if self.vis.game.is_over() {
+            self.save_corrections();
         }
-        self.vis.update(ctx)
+        Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        self.vis.draw(ctx)
+        if self.vis.is_minimized() {
+            return Ok(());
+        }
+        self.vis.draw_frame(ctx)?;
+        if !self.vis.paused {
+            let mut renderer = GgezRenderer::new();
+            self.add_activations(self.vis.side_panel(), &mut renderer);
+            renderer.finish(ctx)?;
+        }
+        self.vis.present_frame(ctx)
+    }
+
+    // the player's moves only count after taking over, pausing and the rest always work
+    fn key_down_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods, repeat: bool) {
+        match self.vis.command(code) {
+            Some(Command::TakeOver) => self.take_over(),
+            Some(Command::Play(_)) if !self.manual => (),
+            _ => self.vis.key_down_event(ctx, code, mods, repeat),
+        }
     }
 
-    fn key_down_event(
-        &mut self,
-        _ctx: &mut Context,
-        _keycode: KeyCode,
-        _keymods: KeyMods,
-        _repeat: bool,
-    ) {
-        panic!("no forwarding yet")
+    fn key_up_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods) {
+        self.vis.key_up_event(ctx, code, mods)
     }
 
-    fn key_up_event(&mut self, _ctx: &mut Context, _keycode: KeyCode, _keymods: KeyMods) {
-        panic!("no forwarding yet")
+    fn quit_event(&mut self, ctx: &mut Context) -> bool {
+        self.save_corrections();
+        self.vis.quit_event(ctx)
     }
 }
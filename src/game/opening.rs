@@ -0,0 +1,292 @@
+use crate::game::{
+    bot::{BoardView, BotMove, TetrisBot},
+    placement::{held, occupied, placements, with_hold, Placement},
+    Action, Game, PieceId, GAME_HEIGHT,
+};
+use std::collections::HashMap;
+
+// a standard setup for the first bag, written like Game::board_to_ascii with each piece's
+// letter where it goes, the piece left out of it stays in hold for the payoff
+pub struct Opener {
+    pub name: &'static str,
+    pub board: &'static str,
+}
+
+// TKI-3 sets up a T-spin double on top of a flat I, the perfect clear opener leaves a hole
+// that the held T and three pieces of the second bag clear the bottom four rows with, both
+// come mirrored too since which one can be built depends on the order of the bag
+pub const OPENERS: &[Opener] = &[
+    Opener {
+        name: "tki-3",
+        board: "
+            ZZ...S....
+            JZZ..SS...
+            JJJ...SLOO
+            IIII.LLLOO
+        ",
+    },
+    Opener {
+        name: "tki-3-mirrored",
+        board: "
+            ....Z...SS
+            ...ZZ..SSL
+            OOJZ...LLL
+            OOJJJ.IIII
+        ",
+    },
+    Opener {
+        name: "pco",
+        board: "
+            JJ.......Z
+            JOO.....ZZ
+            JOO...SSZL
+            IIII.SSLLL
+        ",
+    },
+    Opener {
+        name: "pco-mirrored",
+        board: "
+            S.......LL
+            SS.....OOL
+            JSZZ...OOL
+            JJJZZ.IIII
+        ",
+    },
+];
+
+// the cells of each piece of an opener, from the bottom of the board like the text
+fn targets(opener: &Opener) -> HashMap<PieceId, Vec<(isize, isize)>> {
+    let rows = opener
+        .board
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    let top = GAME_HEIGHT - rows.len();
+    let mut targets = HashMap::new();
+    for (y, line) in rows.iter().enumerate() {
+        for (x, c) in line.chars().enumerate() {
+            if let Some(id) = PieceId::from_char(c) {
+                targets
+                    .entry(id)
+                    .or_insert_with(Vec::new)
+                    .push((x as isize, (top + y) as isize));
+            }
+        }
+    }
+    targets
+}
+
+// the placements that build an opener in the given game, in the order they're made, each
+// with the board hash it's meant for so that a bot notices when the game goes off-book
+pub struct Line {
+    pub opener: &'static str,
+    pub moves: Vec<(u64, Placement)>,
+}
+
+// recognizes which opener the pieces of the first bag allow and how to build it
+pub struct OpeningBook {
+    openers: Vec<&'static Opener>,
+}
+
+impl OpeningBook {
+    pub fn new() -> Self {
+        Self {
+            openers: OPENERS.iter().collect(),
+        }
+    }
+
+    // the first opener in the book that can be built on the game's board with its pieces and
+    // one hold, None unless the board is empty
+    pub fn line(&self, game: &Game) -> Option<Line> {
+        if game.board_hash() != 0 {
+            return None;
+        }
+        self.openers.iter().find_map(|opener| {
+            let mut moves = vec![];
+            match build(game, &mut targets(opener), &mut moves) {
+                true => Some(Line {
+                    opener: opener.name,
+                    moves,
+                }),
+                false => None,
+            }
+        })
+    }
+}
+
+impl Default for OpeningBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// depth-first over placing the current piece or the one a hold brings in, a piece can only
+// go where the opener has it, moves is the line that finishes the opener when true
+fn build(
+    game: &Game,
+    targets: &mut HashMap<PieceId, Vec<(isize, isize)>>,
+    moves: &mut Vec<(u64, Placement)>,
+) -> bool {
+    if targets.is_empty() {
+        return true;
+    }
+    let held = held(game);
+    for (hold, game) in Some(game).into_iter().chain(held.as_ref()).enumerate() {
+        let id = match &game.falling {
+            Some(falling) => falling.id,
+            None => continue,
+        };
+        let cells = match targets.remove(&id) {
+            Some(cells) => cells,
+            None => continue,
+        };
+        let mut sorted = cells.clone();
+        sorted.sort_unstable();
+        let target = placements(game).into_iter().find(|p| {
+            let mut placed = occupied(&game.mask_map[&id][p.mask_idx], p.pos);
+            placed.sort_unstable();
+            placed == sorted
+        });
+        if let Some(p) = target {
+            let mut next = game.clone();
+            for &action in &p.inputs {
+                next.apply(action);
+            }
+            if !next.is_over() && next.cleared == 0 {
+                let p = if hold == 1 { with_hold(p) } else { p };
+                moves.push((game.board_hash(), p));
+                if build(&next, targets, moves) {
+                    return true;
+                }
+                moves.pop();
+            }
+        }
+        targets.insert(id, cells);
+    }
+    false
+}
+
+enum Book {
+    Undecided,
+    Playing(Line, usize), // placements made so far
+    Done,
+}
+
+// plays an opener from the book when one fits the first bag and hands over to another bot
+// once it's built or the game goes off-book (garbage in versus, for one)
+pub struct BookBot {
+    book: OpeningBook,
+    state: Book,
+    fallback: Box<dyn TetrisBot>,
+}
+
+impl BookBot {
+    pub fn new(fallback: Box<dyn TetrisBot>) -> Self {
+        Self {
+            book: OpeningBook::new(),
+            state: Book::Undecided,
+            fallback,
+        }
+    }
+
+    // the opener being built, None before the first piece and after leaving the book
+    #[allow(dead_code)]
+    pub fn opener(&self) -> Option<&'static str> {
+        match &self.state {
+            Book::Playing(line, _) => Some(line.opener),
+            _ => None,
+        }
+    }
+
+    fn book_move(&mut self, game: &Game) -> Option<Placement> {
+        if let Book::Undecided = self.state {
+            self.state = self
+                .book
+                .line(game)
+                .map_or(Book::Done, |line| Book::Playing(line, 0));
+        }
+        if let Book::Playing(line, made) = &mut self.state {
+            if let Some((hash, p)) = line.moves.get(*made) {
+                // a hold made in between would change the piece the placement is for
+                let hold_ok = p.inputs.first() != Some(&Action::Hold) || game.can_hold();
+                if *hash == game.board_hash() && hold_ok {
+                    *made += 1;
+                    return Some(p.clone());
+                }
+            }
+        }
+        self.state = Book::Done;
+        None
+    }
+}
+
+impl TetrisBot for BookBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        match self.book_move(view.game) {
+            Some(p) => BotMove::Place(p),
+            None => self.fallback.choose(view),
+        }
+    }
+}
+
+#[test]
+fn test_openers_are_whole_pieces() {
+    use crate::game::GAME_WIDTH;
+    for opener in OPENERS {
+        let targets = targets(opener);
+        assert_eq!(targets.len(), 6, "{}", opener.name);
+        for cells in targets.values() {
+            assert_eq!(cells.len(), 4, "{}", opener.name);
+        }
+        let width = |line: &str| line.trim().chars().count();
+        assert!(opener
+            .board
+            .lines()
+            .all(|line| [0, GAME_WIDTH].contains(&width(line))));
+    }
+}
+
+#[test]
+fn test_tki_leaves_a_t_spin_double() {
+    for opener in &OPENERS[..2] {
+        let mut game = Game::new();
+        game.load_board_from_ascii(opener.board).unwrap();
+        game.spawn_with_id(PieceId::TBlock);
+        let double = placements(&game)
+            .into_iter()
+            .any(|p| p.afterstate(&game).1 == 2);
+        assert!(double, "{}", opener.name);
+    }
+}
+
+#[test]
+fn test_book_builds_openers() {
+    use crate::game::Pixel;
+    let book = OpeningBook::new();
+    let mut built = 0;
+    for seed in 0..20 {
+        let mut game = Game::with_seed(seed);
+        let line = match book.line(&game) {
+            Some(line) => line,
+            None => continue,
+        };
+        let opener = OPENERS.iter().find(|o| o.name == line.opener).unwrap();
+        for (hash, p) in line.moves {
+            assert_eq!(hash, game.board_hash());
+            for action in p.inputs {
+                game.apply(action);
+            }
+        }
+        let expected = targets(opener);
+        for (id, cells) in expected {
+            for (x, y) in cells {
+                let px = game.board[y as usize][x as usize];
+                assert!(matches!(px, Pixel::Full(placed) if placed == id));
+            }
+        }
+        built += 1;
+    }
+    // hold only keeps one piece, so some bags come in an order none of them can be built in
+    assert!(built >= 5, "only {} of 20 bags built an opener", built);
+}
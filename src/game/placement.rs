@@ -0,0 +1,433 @@
+use crate::game::{
+    clear_lines, features::BoardMetrics, intersects_with, try_rotate, zobrist, Action, Board, Game,
+    Kicks, Mask, Masks, Pixel,
+};
+use std::collections::{HashSet, VecDeque};
+
+// a final resting place for the falling piece and the inputs that get it there
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub mask_idx: usize,
+    pub pos: (isize, isize), // top-left corner, like FallingPiece
+    pub inputs: Vec<Action>, // always ends with a hard drop
+}
+
+// lowest y the mask can fall to from the given position
+fn drop_y(mask: &Mask, (x, mut y): (isize, isize), board: &Board) -> isize {
+    while !intersects_with(mask, (x, y + 1), board) {
+        y += 1
+    }
+    y
+}
+
+// absolute coordinates of the full cells of the mask, used to tell apart
+// placements that look the same but use different masks (O, I, S, Z)
+pub(super) fn occupied(mask: &Mask, (x, y): (isize, isize)) -> Vec<(isize, isize)> {
+    mask.cells_at((x, y)).collect()
+}
+
+type PieceState = (usize, (isize, isize)); // mask index and position
+
+// state of the piece after a single input, None if the input has no effect
+fn step(
+    masks: &Masks,
+    kicks: &Kicks,
+    (mask_idx, pos): PieceState,
+    action: Action,
+    board: &Board,
+) -> Option<PieceState> {
+    use Action::*;
+    let shift = |dx, dy| {
+        let new_pos = (pos.0 + dx, pos.1 + dy);
+        if intersects_with(&masks[mask_idx], new_pos, board) {
+            None
+        } else {
+            Some((mask_idx, new_pos))
+        }
+    };
+    match action {
+        MoveLeft => shift(-1, 0),
+        MoveRight => shift(1, 0),
+        SoftDrop => shift(0, 1),
+        RotateCW => try_rotate(masks, kicks, mask_idx, pos, 1, board),
+        RotateCCW => try_rotate(masks, kicks, mask_idx, pos, -1, board),
+        Rotate180 => try_rotate(masks, kicks, mask_idx, pos, 2, board),
+        HardDrop | Hold => None,
+    }
+}
+
+// every distinct placement of the falling piece reachable with any combination of
+// shifts, rotations and soft drops (tucks and spins included), empty if there is no
+// falling piece. The breadth-first search means each placement comes with the shortest
+// input sequence that reaches it; gravity and lock delay are not taken into account
+pub fn placements(game: &Game) -> Vec<Placement> {
+    let falling = match &game.falling {
+        Some(falling) => falling,
+        None => return vec![],
+    };
+    let masks = &game.mask_map[&falling.id];
+    let kicks = &game.kick_map[&falling.id];
+    let board = &game.board;
+
+    use Action::*;
+    let start = (falling.mask_idx, falling.pos);
+    let mut visited = HashSet::new();
+    visited.insert(start);
+    let mut queue = VecDeque::new();
+    queue.push_back((start, vec![]));
+
+    let mut seen = HashSet::new();
+    let mut out = vec![];
+    while let Some(((mask_idx, pos), inputs)) = queue.pop_front() {
+        let mask = &masks[mask_idx];
+        let landing = (pos.0, drop_y(mask, pos, board));
+        // placements with the same cells look the same
+        if seen.insert(zobrist::hash_cells(mask.cells_at(landing))) {
+            let mut inputs = inputs.clone();
+            inputs.push(HardDrop);
+            out.push(Placement {
+                mask_idx,
+                pos: landing,
+                inputs,
+            });
+        }
+        for &action in &[MoveLeft, MoveRight, RotateCW, RotateCCW, SoftDrop] {
+            if let Some(next) = step(masks, kicks, (mask_idx, pos), action, board) {
+                if visited.insert(next) {
+                    let mut inputs = inputs.clone();
+                    inputs.push(action);
+                    queue.push_back((next, inputs));
+                }
+            }
+        }
+    }
+    out
+}
+
+impl Placement {
+    // board after locking the piece and clearing lines, and the number of lines cleared
+    pub(super) fn afterstate(&self, game: &Game) -> (Board, usize) {
+        let id = game
+            .falling
+            .as_ref()
+            .expect("placement without a falling piece")
+            .id;
+        let mut board = game.board;
+        for (x, y) in occupied(&game.mask_map[&id][self.mask_idx], self.pos) {
+            board[y as usize][x as usize] = Pixel::Full(id);
+        }
+        let cleared = clear_lines(&mut board);
+        (board, cleared)
+    }
+
+    // column heights and holes of the board afterstate returns (after, which cleared rows),
+    // from the game's without scanning the board
+    pub(super) fn after_metrics(&self, game: &Game, after: &Board, cleared: usize) -> BoardMetrics {
+        let mut metrics = *game.metrics();
+        if let Some(falling) = &game.falling {
+            metrics.lock(occupied(
+                &game.mask_map[&falling.id][self.mask_idx],
+                self.pos,
+            ));
+        }
+        metrics.clear(after, cleared);
+        metrics
+    }
+
+    // zobrist hash of the board afterstate returns, only builds it if lines are cleared
+    pub(super) fn afterstate_hash(&self, game: &Game) -> u64 {
+        let id = match &game.falling {
+            Some(falling) => falling.id,
+            None => return game.board_hash(),
+        };
+        let cells = occupied(&game.mask_map[&id][self.mask_idx], self.pos);
+        let fills_row = |y: isize| {
+            let empty = game.board[y as usize].iter().filter(|px| px.is_empty());
+            empty.count() == cells.iter().filter(|c| c.1 == y).count()
+        };
+        if cells.iter().any(|&(_, y)| fills_row(y)) {
+            zobrist::hash_board(&self.afterstate(game).0)
+        } else {
+            game.board_hash() ^ zobrist::hash_cells(cells)
+        }
+    }
+}
+
+// play until the game is lost, choose returns None or max_pieces have been placed,
+// pieces are placed instantly so gravity never comes into play
+pub fn play_headless(
+    game: &mut Game,
+    mut choose: impl FnMut(&Game) -> Option<Placement>,
+    max_pieces: usize,
+) -> usize {
+    let mut placed = 0;
+    while !game.is_over() && placed < max_pieces {
+        match choose(game) {
+            Some(placement) => {
+                for action in placement.inputs {
+                    game.apply(action);
+                }
+                placed += 1;
+            }
+            None => break,
+        }
+    }
+    placed
+}
+
+// the first of the placements with the highest score
+fn highest(scored: impl Iterator<Item = (f64, Placement)>) -> Option<Placement> {
+    scored
+        .fold(None, |best: Option<(f64, Placement)>, (s, p)| match best {
+            Some((best_s, _)) if best_s >= s => best,
+            _ => Some((s, p)),
+        })
+        .map(|(_, p)| p)
+}
+
+// placement with the highest score
+pub(super) fn best_by(game: &Game, mut score: impl FnMut(&Placement) -> f64) -> Option<Placement> {
+    highest(placements(game).into_iter().map(|p| (score(&p), p)))
+}
+
+// placement with the highest score when all the afterstates are scored at once, score
+// returns one for each board
+pub(super) fn best_placement_batch(
+    game: &Game,
+    score: impl FnOnce(&[Board]) -> Vec<f64>,
+) -> Option<Placement> {
+    let placements = placements(game);
+    let boards = placements
+        .iter()
+        .map(|p| p.afterstate(game).0)
+        .collect::<Vec<_>>();
+    highest(score(&boards).into_iter().zip(placements))
+}
+
+// the game after a hold, if hold can be used and brings in another piece
+pub(super) fn held(game: &Game) -> Option<Game> {
+    if !game.can_hold() {
+        return None;
+    }
+    let mut held = game.clone();
+    held.apply(Action::Hold);
+    let id = |game: &Game| game.falling.as_ref().map(|falling| falling.id);
+    Some(held).filter(|held| id(held).is_some() && id(held) != id(game))
+}
+
+pub(super) fn with_hold(mut placement: Placement) -> Placement {
+    placement.inputs.insert(0, Action::Hold);
+    placement
+}
+
+// placements of the current piece and, if hold can be used, those of the piece a hold
+// brings in (with a hold in front of their inputs)
+pub fn placements_with_hold(game: &Game) -> Vec<Placement> {
+    let mut out = placements(game);
+    if let Some(held) = held(game) {
+        out.extend(placements(&held).into_iter().map(with_hold));
+    }
+    out
+}
+
+// the game after the placement, pieces spawn right away unless the game has delays
+fn after(game: &Game, placement: &Placement) -> Game {
+    let mut game = game.clone();
+    for &action in &placement.inputs {
+        game.apply(action);
+    }
+    game
+}
+
+// the best placement of the current piece (hold included) looking depth pieces ahead,
+// score rates a placement in the game it is made in (after the hold, for the held piece)
+// and a line of placements is worth the sum of its scores, only the width best placements
+// of a piece are looked further into. Losing ends a line, it's worth what it scored until
+// then minus a lot
+pub fn best_lookahead(
+    game: &Game,
+    depth: usize,
+    width: usize,
+    score: &mut impl FnMut(&Game, &Placement) -> f64,
+) -> Option<Placement> {
+    lookahead(game, depth.max(1), width, score).map(|(_, p)| p)
+}
+
+fn lookahead(
+    game: &Game,
+    depth: usize,
+    width: usize,
+    score: &mut impl FnMut(&Game, &Placement) -> f64,
+) -> Option<(f64, Placement)> {
+    let held = held(game);
+    let mut scored = vec![];
+    for (hold, game) in Some(game).into_iter().chain(held.as_ref()).enumerate() {
+        for p in placements(game) {
+            scored.push((score(game, &p), hold == 1, p));
+        }
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let width = if depth == 1 { 1 } else { width };
+    scored
+        .into_iter()
+        .take(width)
+        .map(|(s, hold, p)| {
+            let rest = match depth {
+                1 => 0.,
+                _ => {
+                    let next = after(if hold { held.as_ref().unwrap() } else { game }, &p);
+                    match next.is_over() {
+                        true => LOSS,
+                        false => {
+                            lookahead(&next, depth - 1, width, score).map_or(0., |(rest, _)| rest)
+                        }
+                    }
+                }
+            };
+            (s + rest, if hold { with_hold(p) } else { p })
+        })
+        .fold(None, |best: Option<(f64, Placement)>, (s, p)| match best {
+            Some((best_s, _)) if best_s >= s => best,
+            _ => Some((s, p)),
+        })
+}
+
+// the best placement of the current piece (hold included) by a beam search depth pieces
+// deep, score and the worth of a line are the same as for best_lookahead but instead of
+// the best few placements of every piece only the width best lines over all of them are
+// kept after each piece. Lines that reach the same board with the same piece held are
+// merged (their queues are the same too), lost lines stay in the beam without going on
+pub fn best_beam(
+    game: &Game,
+    depth: usize,
+    width: usize,
+    score: &mut impl FnMut(&Game, &Placement) -> f64,
+) -> Option<Placement> {
+    // (worth, game at the end of the line, first placement of the line)
+    let mut beam: Vec<(f64, Game, Option<Placement>)> = vec![(0., game.clone(), None)];
+    for _ in 0..depth.max(1) {
+        let helds = beam
+            .iter()
+            .map(|(_, game, _)| held(game))
+            .collect::<Vec<_>>();
+        let mut next = vec![];
+        let mut scored = vec![];
+        for (i, (worth, game, first)) in beam.iter().enumerate() {
+            let before = scored.len();
+            if !game.is_over() {
+                for (hold, game) in Some(game).into_iter().chain(&helds[i]).enumerate() {
+                    for p in placements(game) {
+                        scored.push((worth + score(game, &p), i, hold == 1, p));
+                    }
+                }
+            }
+            if scored.len() == before {
+                next.push((*worth, game.clone(), first.clone()));
+            }
+        }
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut seen = HashSet::new();
+        let mut live = 0;
+        for (worth, i, hold, p) in scored {
+            if live == width {
+                break;
+            }
+            let game = if hold {
+                helds[i].as_ref().unwrap()
+            } else {
+                &beam[i].1
+            };
+            if !seen.insert((p.afterstate_hash(game), game.hold)) {
+                continue;
+            }
+            let after = after(game, &p);
+            let first = beam[i].2.clone().unwrap_or_else(|| match hold {
+                true => with_hold(p),
+                false => p,
+            });
+            if after.is_over() {
+                next.push((worth + LOSS, after, Some(first)));
+            } else {
+                next.push((worth, after, Some(first)));
+                live += 1;
+            }
+        }
+        next.sort_by(|a, b| b.0.total_cmp(&a.0));
+        next.truncate(width.max(1));
+        beam = next;
+    }
+    beam.into_iter().next().and_then(|(_, _, first)| first)
+}
+
+// what topping out is worth in a lookahead, worse than any board
+const LOSS: f64 = -1e9;
+
+#[test]
+fn test_placements_on_empty_board() {
+    use crate::game::PieceId;
+    let mut game = Game::new();
+    game.spawn_with_id(PieceId::OBlock);
+    // one for every column the 2-wide piece fits in
+    assert_eq!(placements(&game).len(), 9);
+    game.spawn_with_id(PieceId::IBlock);
+    // 7 horizontal and 10 vertical
+    assert_eq!(placements(&game).len(), 17);
+    game.spawn_with_id(PieceId::TBlock);
+    assert_eq!(placements(&game).len(), 8 + 9 + 8 + 9);
+}
+
+#[test]
+fn test_placements_include_tucks() {
+    use crate::game::{PieceId, GAME_HEIGHT};
+    let mut game = Game::new();
+    // a roof over the bottom two rows on the left side
+    for x in 0..6 {
+        game.board[GAME_HEIGHT - 3][x] = Pixel::Full(PieceId::IBlock);
+    }
+    game.spawn_with_id(PieceId::OBlock);
+    let bottom = GAME_HEIGHT as isize - 1;
+    let tuck = placements(&game)
+        .into_iter()
+        .find(|p| {
+            let cells = occupied(&game.mask_map[&PieceId::OBlock][p.mask_idx], p.pos);
+            cells.contains(&(0, bottom)) && cells.contains(&(1, bottom))
+        })
+        .expect("tuck under the roof not found");
+    assert!(tuck.inputs.contains(&Action::SoftDrop));
+}
+
+#[test]
+fn test_searches_use_hold() {
+    use crate::game::{PieceId, GAME_HEIGHT, GAME_WIDTH};
+    let mut game = Game::new();
+    game.spawn_with_id(PieceId::SBlock);
+    game.hold = Some(PieceId::IBlock);
+    // a well four deep on the right that only the I fills without holes
+    let mut board = game.board;
+    for row in board[GAME_HEIGHT - 4..].iter_mut() {
+        for px in row[..GAME_WIDTH - 1].iter_mut() {
+            *px = Pixel::Garbage;
+        }
+    }
+    game.set_board(board);
+    let cleared = |game: &Game, p: &Placement| p.afterstate(game).1 as f64;
+    let best = best_lookahead(&game, 2, 4, &mut |game, p| cleared(game, p)).unwrap();
+    assert_eq!(best.inputs.first(), Some(&Action::Hold));
+    assert!(placements_with_hold(&game).len() > placements(&game).len());
+    let best = best_beam(&game, 2, 4, &mut |game, p| cleared(game, p)).unwrap();
+    assert_eq!(best.inputs.first(), Some(&Action::Hold));
+}
+
+#[test]
+fn test_afterstate_hash_matches_afterstate() {
+    use crate::game::heuristic::HeuristicBot;
+    let mut game = Game::with_seed(3);
+    let bot = HeuristicBot::new();
+    for _ in 0..40 {
+        for p in placements(&game) {
+            let (board, _) = p.afterstate(&game);
+            assert_eq!(p.afterstate_hash(&game), zobrist::hash_board(&board));
+        }
+        play_headless(&mut game, |game| bot.choose(game), 1);
+    }
+}
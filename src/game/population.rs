@@ -0,0 +1,589 @@
+use crate::{
+    game::{
+        lineage::{append_records, genome_path, load_ids, load_records, save_ids, LineageRecord},
+        metrics::{CsvExporter, MetricsExporter, TensorBoardExporter},
+        nn_trainer::{decide, step, TrainerConfig},
+        rating::{load_ratings, save_ratings, Ratings, RatingsError},
+        reward::RewardVars,
+        self_play::versus_fitness,
+        Game,
+    },
+    neural_network::{NNCreationError, NNReadError, NNReadResult, NN},
+};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{collections::HashSet, fmt, fs, io, path::Path, thread};
+use tap::TapOps;
+
+const CHECKPOINT_INTERVAL: usize = 50; // generations
+
+fn load_generation<P: AsRef<Path>>(path: P) -> NNReadResult<Vec<NN>> {
+    fs::read_to_string(path)?
+        .split("--\n")
+        .map(NN::from_string)
+        .collect()
+}
+
+fn save_generation<P: AsRef<Path>>(path: P, generation: &[NN]) -> io::Result<()> {
+    fs::write(path, generation.iter().map(NN::to_string).join("--\n"))
+}
+
+// <dir>/progress.txt, "<generation number> <best fitness> <stagnant generations> <gravity>"
+// of the saved generation, so that a resumed run carries on where it stopped
+fn save_progress(dir: &Path, population: &Population) -> io::Result<()> {
+    let progress = format!(
+        "{} {} {} {}",
+        population.generation_number,
+        population.best_fitness,
+        population.stagnant_generations,
+        population.gravity
+    );
+    fs::write(dir.join("progress.txt"), progress)
+}
+
+// None if there is no (valid) progress file, e.g. for runs saved before there was one
+fn load_progress(dir: &Path) -> Option<(usize, f64, usize, f32)> {
+    let progress = fs::read_to_string(dir.join("progress.txt")).ok()?;
+    let mut fields = progress.split_whitespace();
+    let progress = (
+        fields.next()?.parse().ok()?,
+        fields.next()?.parse().ok()?,
+        fields.next()?.parse().ok()?,
+        fields.next()?.parse().ok()?,
+    );
+    match fields.next() {
+        None => Some(progress),
+        Some(_) => None,
+    }
+}
+
+#[derive(From, Debug)]
+pub enum NNReadOrCreationError {
+    Read(NNReadError),
+    Create(NNCreationError),
+    Ratings(RatingsError),
+    #[from(ignore)]
+    Mismatch(String), // the saved generation doesn't fit the config
+}
+
+pub type NNReadOrCreateResult<T> = Result<T, NNReadOrCreationError>;
+
+impl fmt::Display for NNReadOrCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "{}", e),
+            Self::Create(e) => write!(f, "{}", e),
+            Self::Ratings(e) => write!(f, "{}", e),
+            Self::Mismatch(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// the genetic algorithm on its own, evaluating the genomes is up to the trainer
+pub struct Population {
+    pub(super) config: TrainerConfig,
+    pub(super) generation: Vec<NN>,
+    pub(super) generation_number: usize,
+    best_fitness: f64,           // over all generations
+    stagnant_generations: usize, // since best_fitness last improved
+    gravity: f32,                // given to every new game
+    label: String,
+    pub(super) history: Vec<(f64, f64)>, // best and mean fitness of every generation
+
+    // lineage, written out by checkpoint
+    ids: Vec<usize>, // in the same order as generation
+    next_id: usize,
+    new_records: Vec<LineageRecord>,
+    new_parents: Vec<(usize, NN)>, // ancestors are saved once they have children
+    saved_parents: HashSet<usize>,
+
+    ratings: Ratings, // from versus matches, written out by checkpoint too
+
+    // metrics of the generations since the last checkpoint, which exports them
+    new_metrics: Vec<(usize, Vec<(&'static str, f64)>)>,
+    exporters: Vec<Box<dyn MetricsExporter>>,
+    opened_exporters: bool, // metrics.csv and the event file, by the first checkpoint
+}
+
+impl Population {
+    // continues from the generation saved in path, fails rather than replacing one that
+    // doesn't fit the config (it would be overwritten by the next save)
+    pub fn load_or_create(path: &Path, config: TrainerConfig) -> NNReadOrCreateResult<Self> {
+        let shape = (config.encoder.input_size(), config.mode.output_size());
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut ids = None;
+        let mut progress = None;
+        let generation = match load_generation(path) {
+            Ok(gen) => {
+                if let Some(nn) = gen
+                    .iter()
+                    .find(|nn| (nn.input_size(), nn.output_size()) != shape)
+                {
+                    return Err(NNReadOrCreationError::Mismatch(format!(
+                        "the generation in {} has {} inputs and {} outputs, the config's \
+                         encoder and mode need {} and {}",
+                        path.display(),
+                        nn.input_size(),
+                        nn.output_size(),
+                        shape.0,
+                        shape.1
+                    )));
+                }
+                ids = load_ids(dir).ok().filter(|ids| ids.len() == gen.len());
+                progress = load_progress(dir);
+                if progress.is_none() {
+                    eprintln!("Warning: no progress saved, counting generations from 0");
+                }
+                gen
+            }
+            Err(NNReadError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
+                let gen_size = config.generation_size;
+                eprintln!(
+                    "Warning: no saved generation, creating a random one of size {}",
+                    gen_size
+                );
+                (0..gen_size)
+                    .map(|_| config.new_network())
+                    .collect::<Result<_, _>>()?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let next_id = load_records(dir)
+            .ok()
+            .and_then(|records| records.keys().max().map(|&id| id + 1))
+            .unwrap_or(0);
+        let start_gravity = config.curriculum.map_or(1., |c| c.start_gravity);
+        let (generation_number, best_fitness, stagnant_generations, gravity) =
+            progress.unwrap_or((0, f64::NEG_INFINITY, 0, start_gravity));
+        let mut population = Self {
+            gravity,
+            config,
+            generation,
+            generation_number,
+            best_fitness,
+            stagnant_generations,
+            label: String::new(),
+            history: vec![],
+
+            ids: vec![],
+            next_id,
+            new_records: vec![],
+            new_parents: vec![],
+            saved_parents: HashSet::new(),
+            ratings: load_ratings(dir.join("ratings.json"))?,
+
+            new_metrics: vec![],
+            exporters: vec![],
+            opened_exporters: false,
+        };
+        population.ids = match ids {
+            Some(ids) => ids,
+            None => (0..population.len())
+                .map(|_| population.register(None, 0., 0.))
+                .collect(),
+        };
+        Ok(population)
+    }
+
+    // new id for a genome created in the current generation
+    fn register(
+        &mut self,
+        parent: Option<usize>,
+        mutation_rate: f64,
+        mutation_strength: f64,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.new_records.push(LineageRecord {
+            id,
+            parent,
+            generation: self.generation_number,
+            mutation_rate,
+            mutation_strength,
+        });
+        id
+    }
+
+    // gets the metrics of every generation at each checkpoint besides metrics.csv
+    #[allow(dead_code)]
+    pub fn add_exporter(&mut self, exporter: Box<dyn MetricsExporter>) {
+        self.exporters.push(exporter);
+    }
+
+    // prefixed to everything printed about this population
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+    }
+
+    // overwrites!
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_generation(path, &self.generation)
+    }
+
+    pub fn len(&self) -> usize {
+        self.generation.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.generation.is_empty()
+    }
+
+    // subscribed to events for fitness shaping
+    pub(super) fn new_game(&self) -> Game {
+        Game::new().tap(|game| {
+            game.set_gravity(self.gravity);
+            game.subscribe_events();
+        })
+    }
+
+    // the game is over or one of the limits has been hit
+    pub(super) fn is_finished(&self, game: &Game) -> bool {
+        game.is_over() || self.config.limits.exceeded(game)
+    }
+
+    // queues the next inputs of the given genome
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub(super) fn decide(&self, genome: usize, game: &mut Game) {
+        decide(&self.generation[genome], &self.config, game)
+    }
+
+    // one frame of the given genome's game
+    pub(super) fn step(&self, genome: usize, game: &mut Game) {
+        step(&self.generation[genome], &self.config, game)
+    }
+
+    // one frame of every unfinished game, game i played by genome i, the games are split
+    // between as many threads as there are cores, returns whether any game was unfinished
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub(super) fn step_all(&self, games: &mut [Game]) -> bool {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = games.len().div_ceil(threads).max(1);
+        let (generation, config) = (&self.generation, &self.config);
+        thread::scope(|scope| {
+            let handles = games
+                .chunks_mut(chunk_size)
+                .enumerate()
+                .map(|(chunk, games)| {
+                    scope.spawn(move || {
+                        let mut running = false;
+                        for (i, game) in games.iter_mut().enumerate() {
+                            if !game.is_over() && !config.limits.exceeded(game) {
+                                running = true;
+                                step(&generation[chunk * chunk_size + i], config, game);
+                            }
+                        }
+                        running
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("evaluation panicked"))
+                .collect::<Vec<_>>()
+                .contains(&true)
+        })
+    }
+
+    // takes the events collected so far
+    pub(super) fn fitness(&self, game: &mut Game) -> f64 {
+        let events = game.take_events();
+        match &self.config.reward {
+            Some(reward) => reward.eval(&RewardVars::of_game(game, &events)),
+            None => self.config.fitness.fitness(game.points, &events),
+        }
+    }
+
+    // plays a whole game with the given genome as fast as possible
+    pub fn play(&self, genome: usize) -> Game {
+        let mut game = self.new_game();
+        while !self.is_finished(&game) {
+            self.step(genome, &mut game);
+        }
+        game
+    }
+
+    // the worst genomes (the newest children at the end) are replaced, migrants start a
+    // new lineage on this island
+    fn receive_migrants(&mut self, migrants: Vec<NN>) {
+        let k = migrants.len().min(self.len());
+        let len = self.len();
+        for (i, nn) in (len - k..len).zip(migrants) {
+            self.generation[i] = nn;
+            self.ids[i] = self.register(None, 0., 0.);
+        }
+    }
+
+    // plays every genome headless (against each other with a versus config) and moves on to
+    // the next generation
+    pub fn run_generation(&mut self) {
+        if let Some(versus) = self.config.versus {
+            let label = format!("{}generation {}, ", self.label, self.generation_number);
+            let seed = self.generation_number as u64;
+            let fitness = versus_fitness(
+                &self.generation,
+                &self.ids,
+                &mut self.ratings,
+                &self.config,
+                versus,
+                seed,
+                &label,
+            );
+            self.next_generation(fitness);
+            return;
+        }
+        let fitness = (0..self.len())
+            .map(|genome| {
+                let mut game = self.play(genome);
+                println!(
+                    "{}generation {}, genome {}: {}",
+                    self.label,
+                    self.generation_number,
+                    genome,
+                    game.summary()
+                );
+                self.fitness(&mut game)
+            })
+            .collect();
+        self.next_generation(fitness);
+    }
+
+    // saves the generation in dir/generation.txt (which is also where training resumes
+    // from, see save_progress) and its best genome in dir/best_nn.txt, every
+    // CHECKPOINT_INTERVAL generations
+    // a numbered copy of the generation is kept as well
+    // together with the lineage of every genome (see lineage.rs), the metrics of every
+    // generation in dir/metrics.csv (see metrics.rs) and, when versus matches are played, the
+    // ratings of the genomes in dir/ratings.json
+    pub fn checkpoint(&mut self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir.join("genomes"))?;
+        if !self.opened_exporters {
+            self.exporters
+                .push(Box::new(CsvExporter::open(dir.join("metrics.csv"))?));
+            if self.config.tensorboard {
+                self.exporters
+                    .push(Box::new(TensorBoardExporter::create(dir)?));
+            }
+            self.opened_exporters = true;
+        }
+        for (step, metrics) in self.new_metrics.drain(..) {
+            for exporter in &mut self.exporters {
+                exporter.export(step, &metrics)?;
+            }
+        }
+        self.save(&dir.join("generation.txt"))?;
+        save_ids(dir, &self.ids)?;
+        save_progress(dir, self)?;
+        append_records(dir, &self.new_records)?;
+        self.new_records.clear();
+        for (id, nn) in self.new_parents.drain(..) {
+            nn.to_file(genome_path(dir, id))?;
+        }
+        self.generation[0].to_file(dir.join("best_nn.txt"))?;
+        if self.config.versus.is_some() {
+            save_ratings(dir.join("ratings.json"), &self.ratings)?;
+        }
+        if self.generation_number.is_multiple_of(CHECKPOINT_INTERVAL) {
+            let name = format!("generation_{}.txt", self.generation_number);
+            self.save(&dir.join(name))?;
+        }
+        Ok(())
+    }
+
+    // fitness is in the same order as the generation, the better half survives and the
+    // rest is replaced by mutated copies, the best genome ends up first
+    pub fn next_generation(&mut self, fitness: Vec<f64>) {
+        let size = self.generation.len();
+        let mut ranked = self
+            .generation
+            .drain(..)
+            .zip(self.ids.drain(..))
+            .zip(fitness)
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| b.partial_cmp(a).expect("fitness is NaN"));
+        let mean = ranked.iter().map(|(_, f)| f).sum::<f64>() / size as f64;
+        println!(
+            "{}generation {}: best {:.1}, mean {:.1}",
+            self.label, self.generation_number, ranked[0].1, mean
+        );
+        self.history.push((ranked[0].1, mean));
+
+        if ranked[0].1 > self.best_fitness {
+            self.best_fitness = ranked[0].1;
+            self.stagnant_generations = 0;
+        } else {
+            self.stagnant_generations += 1;
+        }
+
+        if let Some(curriculum) = self.config.curriculum {
+            if ranked[0].1 >= curriculum.promote_at && self.gravity < 1. {
+                self.gravity = (self.gravity + curriculum.step).min(1.);
+                println!(
+                    "{}curriculum: gravity raised to {:.2}",
+                    self.label, self.gravity
+                );
+            }
+        }
+
+        let survivors = ranked
+            .into_iter()
+            .take(size - size / 2)
+            .map(|(genome, _)| genome)
+            .collect::<Vec<_>>();
+        let factor = self
+            .config
+            .mutation_schedule
+            .factor(self.generation_number, self.stagnant_generations);
+        let (rate, strength) = (
+            (self.config.mutation_rate * factor).min(1.),
+            self.config.mutation_strength * factor,
+        );
+        if factor != 1. {
+            println!(
+                "{}mutation rate {:.3}, strength {:.3}",
+                self.label, rate, strength
+            );
+        }
+        self.new_metrics.push((
+            self.generation_number,
+            vec![
+                ("fitness/best", self.history.last().unwrap().0),
+                ("fitness/mean", mean),
+                ("mutation/rate", rate),
+                ("mutation/strength", strength),
+                ("gravity", self.gravity as f64),
+            ],
+        ));
+        let children = survivors
+            .iter()
+            .cycle()
+            .take(size - survivors.len())
+            .map(|(nn, id)| (nn.mutated(rate, strength), *id))
+            .collect::<Vec<_>>();
+        self.generation_number += 1;
+        for (nn, id) in survivors.iter() {
+            if children.iter().any(|(_, parent)| parent == id) && self.saved_parents.insert(*id) {
+                self.new_parents.push((*id, nn.clone()));
+            }
+        }
+        let (generation, ids) = survivors.into_iter().unzip();
+        self.generation = generation;
+        self.ids = ids;
+        for (nn, parent) in children {
+            let id = self.register(Some(parent), rate, strength);
+            self.generation.push(nn);
+            self.ids.push(id);
+        }
+    }
+}
+
+// trains without a window for the given number of generations, checkpointing in out
+// after each one
+pub fn train_headless(
+    population: &mut Population,
+    generations: usize,
+    out: &Path,
+) -> io::Result<()> {
+    for _ in 0..generations {
+        population.run_generation();
+        population.checkpoint(out)?;
+    }
+    Ok(())
+}
+
+// independent populations that evolve in parallel threads, every migration_interval
+// generations the best genomes of each island replace the worst ones of the next
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct IslandConfig {
+    pub islands: usize,
+    pub migration_interval: usize,
+    pub migrants: usize,
+}
+
+// the islands form a ring so good genomes spread slowly and diversity is kept
+fn migrate(islands: &mut [Population], migrants: usize) {
+    let n = islands.len();
+    if n < 2 {
+        return;
+    }
+    let emigrants = islands
+        .iter()
+        .map(|island| island.generation[..migrants.min(island.len())].to_vec())
+        .collect::<Vec<_>>();
+    for (i, emigrants) in emigrants.into_iter().enumerate() {
+        islands[(i + 1) % n].receive_migrants(emigrants);
+    }
+    println!("migrated {} genomes between {} islands", migrants, n);
+}
+
+// like train_headless, island i is checkpointed in out/island_<i>
+pub fn train_islands(
+    islands: &mut [Population],
+    generations: usize,
+    config: IslandConfig,
+    out: &Path,
+) -> io::Result<()> {
+    let mut done = 0;
+    while done < generations {
+        let epoch = config.migration_interval.max(1).min(generations - done);
+        thread::scope(|scope| {
+            for island in islands.iter_mut() {
+                scope.spawn(move || {
+                    for _ in 0..epoch {
+                        island.run_generation();
+                    }
+                });
+            }
+        });
+        done += epoch;
+        migrate(islands, config.migrants);
+        for (i, island) in islands.iter_mut().enumerate() {
+            island.checkpoint(&out.join(format!("island_{}", i)))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_resumed_training_continues_numbering() {
+    let dir = crate::game::temp_dir::TempDir::new("resume");
+    let config = TrainerConfig {
+        generation_size: 4,
+        ..TrainerConfig::default()
+    };
+    let path = dir.join("generation.txt");
+    let mut population = Population::load_or_create(&path, config.clone()).unwrap();
+    train_headless(&mut population, 2, &dir).unwrap();
+
+    let mut resumed = Population::load_or_create(&path, config).unwrap();
+    assert_eq!(resumed.generation_number, 2);
+    assert_eq!(resumed.best_fitness, population.best_fitness);
+    assert_eq!(
+        resumed.stagnant_generations,
+        population.stagnant_generations
+    );
+    train_headless(&mut resumed, 1, &dir).unwrap();
+    assert_eq!(resumed.generation_number, 3);
+    // a config with another encoder doesn't throw the saved generation away
+    let saved = fs::read_to_string(&path).unwrap();
+    let cells = TrainerConfig {
+        encoder: crate::game::features::InputEncoder::Cells,
+        ..TrainerConfig::default()
+    };
+    let result = Population::load_or_create(&path, cells);
+    assert!(matches!(result, Err(NNReadOrCreationError::Mismatch(_))));
+    assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+    // the children of the resumed generation are recorded as its own
+    let records = load_records(&dir).unwrap();
+    let newest = records.values().filter(|record| record.generation == 3);
+    assert_eq!(newest.count(), 2);
+    // damaged ratings aren't silently replaced by empty ones
+    fs::write(dir.join("ratings.json"), "{").unwrap();
+    assert!(Population::load_or_create(&path, TrainerConfig::default()).is_err());
+    let metrics = fs::read_to_string(dir.join("metrics.csv")).unwrap();
+    let steps = metrics
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(steps, vec!["0", "1", "2"]);
+}
@@ -0,0 +1,152 @@
+use crate::game::versus::Outcome;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+// Elo ratings of bots and genomes from the head-to-head matches they played, kept in a JSON
+// file between runs (ratings.json for the tournament command, <dir>/ratings.json for a
+// training run with versus fitness) so that a network trained today can be compared with
+// last week's by its rating instead of a feeling
+//
+// a bot is known by the spec it was created from ("heuristic", "nn:best_nn.txt"), so
+// retraining a network into the same file makes it a different player with the same name,
+// rename the file to keep them apart
+
+pub const INITIAL_RATING: f64 = 1500.;
+const K: f64 = 32.; // the most a single game can move a rating
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Rating {
+    pub elo: f64,
+    pub games: usize,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            elo: INITIAL_RATING,
+            games: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Ratings {
+    players: BTreeMap<String, Rating>,
+}
+
+// the points a player rated a is expected to score against one rated b, 1 for a win and
+// 0.5 for a draw
+fn expected(a: f64, b: f64) -> f64 {
+    1. / (1. + 10f64.powf((b - a) / 400.))
+}
+
+impl Ratings {
+    // INITIAL_RATING for someone who hasn't played yet
+    pub fn rating(&self, player: &str) -> Rating {
+        self.players.get(player).copied().unwrap_or_default()
+    }
+
+    // one game between left and right, a game against itself (the same spec on both
+    // sides) says nothing about a player and isn't counted
+    pub fn record(&mut self, left: &str, right: &str, outcome: Outcome) {
+        if left == right {
+            return;
+        }
+        let score = match outcome {
+            Outcome::Left => 1.,
+            Outcome::Right => 0.,
+            Outcome::Draw => 0.5,
+        };
+        let (a, b) = (self.rating(left), self.rating(right));
+        let change = K * (score - expected(a.elo, b.elo));
+        self.players.insert(
+            left.to_string(),
+            Rating {
+                elo: a.elo + change,
+                games: a.games + 1,
+            },
+        );
+        self.players.insert(
+            right.to_string(),
+            Rating {
+                elo: b.elo - change,
+                games: b.games + 1,
+            },
+        );
+    }
+
+    // best first
+    pub fn ranking(&self) -> Vec<(&str, Rating)> {
+        let mut ranking = self
+            .players
+            .iter()
+            .map(|(player, &rating)| (player.as_str(), rating))
+            .collect::<Vec<_>>();
+        ranking.sort_by(|(_, a), (_, b)| b.elo.total_cmp(&a.elo));
+        ranking
+    }
+}
+
+#[derive(From, Debug)]
+pub enum RatingsError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+}
+
+pub type RatingsResult<T> = Result<T, RatingsError>;
+
+impl fmt::Display for RatingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to access ratings: {}", e),
+            Self::JsonError(e) => write!(f, "invalid ratings: {}", e),
+        }
+    }
+}
+
+// for saving ratings along with everything else a training run saves
+impl From<RatingsError> for io::Error {
+    fn from(e: RatingsError) -> Self {
+        match e {
+            RatingsError::IoError(e) => e,
+            RatingsError::JsonError(e) => e.into(),
+        }
+    }
+}
+
+// no file yet is nobody rated yet
+pub fn load_ratings<P: AsRef<Path>>(path: P) -> RatingsResult<Ratings> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Ratings::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// overwrites!
+pub fn save_ratings<P: AsRef<Path>>(path: P, ratings: &Ratings) -> RatingsResult<()> {
+    fs::write(path, serde_json::to_string_pretty(ratings)?)?;
+    Ok(())
+}
+
+#[test]
+fn test_ratings() {
+    let mut ratings = Ratings::default();
+    ratings.record("a", "b", Outcome::Left);
+    let (a, b) = (ratings.rating("a"), ratings.rating("b"));
+    // equals exchange half of K, and points are only moved around
+    assert!((a.elo - INITIAL_RATING - K / 2.).abs() < 1e-9);
+    assert!((a.elo + b.elo - 2. * INITIAL_RATING).abs() < 1e-9);
+    assert_eq!((a.games, b.games), (1, 1));
+    // beating a weaker player is worth less than beating an equal
+    ratings.record("a", "c", Outcome::Left);
+    assert!(ratings.rating("a").elo - a.elo < K / 2.);
+    // a draw against a weaker player costs points
+    let before = ratings.rating("a").elo;
+    ratings.record("b", "a", Outcome::Draw);
+    assert!(ratings.rating("a").elo < before);
+    assert_eq!(ratings.ranking()[0].0, "a");
+    assert_eq!(ratings.rating("nobody"), Rating::default());
+    ratings.record("b", "b", Outcome::Left);
+    assert_eq!(ratings.rating("b").games, 2);
+}
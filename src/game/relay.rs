@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+// a server for online versus games that can be watched, players and spectators connect to
+// it instead of to each other. Every client starts with a join line, players get their
+// number and then paired once the other player is there, from then on every line a player
+// sends goes to the other player unchanged (the handshake, inputs, checksums) and to every
+// spectator wrapped in a relayed message:
+//
+//     > {"type":"join","role":"player"}
+//     < {"type":"joined","player":0}
+//     < {"type":"paired"}
+//
+//     > {"type":"join","role":"spectator"}
+//     < {"type":"watching"}
+//     < {"type":"relayed","player":1,"message":{"type":"start"}}
+//
+// see src/bin/relay.rs for the binary
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Player,
+    Spectator,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Join { role: Role },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayMessage {
+    Joined {
+        player: usize,
+    },
+    Full, // two players are connected already
+    Watching,
+    Paired,
+    Left {
+        player: usize,
+    },
+    // for spectators, the message is the player's line as it was sent (a string if it
+    // wasn't JSON)
+    Relayed {
+        player: usize,
+        message: serde_json::Value,
+    },
+}
+
+#[derive(From, Debug)]
+pub enum RelayError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+}
+
+pub type RelayResult<T> = Result<T, RelayError>;
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "connection failed: {}", e),
+            Self::JsonError(e) => write!(f, "invalid message: {}", e),
+        }
+    }
+}
+
+// lines waiting for a client before it counts as too slow and is disconnected
+const OUTBOX_LINES: usize = 1024;
+// a write that takes longer than this means the client is gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// for the join line, so that connecting and saying nothing doesn't hold a thread forever
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// lines for one client, written to it by a thread of its own so that a client that stops
+// reading only holds itself up and never anyone holding the clients' lock
+struct Outbox {
+    lines: SyncSender<String>,
+    stream: TcpStream, // to disconnect the client
+}
+
+impl Outbox {
+    fn new(stream: &TcpStream) -> io::Result<Self> {
+        let mut writer = stream.try_clone()?;
+        writer.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let (lines, queued) = mpsc::sync_channel::<String>(OUTBOX_LINES);
+        thread::spawn(move || {
+            for line in queued {
+                if writeln!(writer, "{}", line).is_err() {
+                    break;
+                }
+            }
+            // ends a player's thread too
+            writer.shutdown(Shutdown::Both).ok();
+        });
+        Ok(Self {
+            lines,
+            stream: stream.try_clone()?,
+        })
+    }
+
+    // false if the client is gone or too far behind, it's disconnected then
+    fn send_line(&self, line: String) -> bool {
+        let sent = self.lines.try_send(line).is_ok();
+        if !sent {
+            self.stream.shutdown(Shutdown::Both).ok();
+        }
+        sent
+    }
+
+    fn send(&self, message: &RelayMessage) -> bool {
+        self.send_line(serde_json::to_string(message).expect("messages are valid JSON"))
+    }
+}
+
+#[derive(Default)]
+struct Clients {
+    players: [Option<Outbox>; 2],
+    spectators: Vec<Outbox>,
+}
+
+impl Clients {
+    // to every spectator, the ones that can't be sent to anymore are dropped
+    fn broadcast(&mut self, message: &RelayMessage) {
+        let line = serde_json::to_string(message).expect("messages are valid JSON");
+        self.spectators
+            .retain(|spectator| spectator.send_line(line.clone()));
+    }
+
+    // to the other player as it is, to the spectators wrapped
+    fn forward(&mut self, player: usize, line: &str) {
+        if let Some(other) = &self.players[1 - player] {
+            // a player that's gone (or disconnected for being too slow) notices on its own
+            // thread
+            other.send_line(line.to_string());
+        }
+        let message = serde_json::from_str(line).unwrap_or_else(|_| line.into());
+        self.broadcast(&RelayMessage::Relayed { player, message });
+    }
+}
+
+// forwards lines from the player until it disconnects
+fn play(player: usize, reader: BufReader<TcpStream>, clients: &Mutex<Clients>) -> RelayResult<()> {
+    for line in reader.lines() {
+        clients.lock().unwrap().forward(player, &line?);
+    }
+    Ok(())
+}
+
+fn client(stream: TcpStream, clients: &Mutex<Clients>) -> RelayResult<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    stream.set_read_timeout(Some(JOIN_TIMEOUT))?;
+    reader.read_line(&mut line)?;
+    // players can take as long as they like between moves
+    stream.set_read_timeout(None)?;
+    let ClientMessage::Join { role } = serde_json::from_str(&line)?;
+    let outbox = Outbox::new(&stream)?;
+    if role == Role::Spectator {
+        // spectators only listen
+        outbox.send(&RelayMessage::Watching);
+        clients.lock().unwrap().spectators.push(outbox);
+        return Ok(());
+    }
+
+    let player = {
+        let mut clients = clients.lock().unwrap();
+        let player = match clients.players.iter().position(Option::is_none) {
+            Some(player) => player,
+            None => {
+                outbox.send(&RelayMessage::Full);
+                return Ok(());
+            }
+        };
+        outbox.send(&RelayMessage::Joined { player });
+        clients.players[player] = Some(outbox);
+        if clients.players.iter().all(Option::is_some) {
+            for outbox in clients.players.iter().flatten() {
+                outbox.send(&RelayMessage::Paired);
+            }
+        }
+        player
+    };
+    // nothing can fail between taking the seat and freeing it here
+    let result = play(player, reader, clients);
+
+    let mut clients = clients.lock().unwrap();
+    clients.players[player] = None;
+    if let Some(other) = &clients.players[1 - player] {
+        other.send(&RelayMessage::Left { player });
+    }
+    clients.broadcast(&RelayMessage::Left { player });
+    result
+}
+
+// serves clients, one thread per client
+pub fn relay(listener: TcpListener) -> io::Result<()> {
+    let clients = Arc::new(Mutex::new(Clients::default()));
+    for stream in listener.incoming() {
+        // e.g. a client that gave up while being accepted or too many open files, the
+        // next one may well work
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Warning: failed to accept a client: {}", e);
+                thread::sleep(Duration::from_millis(100)); // instead of spinning
+                continue;
+            }
+        };
+        let clients = Arc::clone(&clients);
+        thread::spawn(move || {
+            if let Err(e) = client(stream, &clients) {
+                eprintln!("Warning: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn test_relay_forwards_to_the_other_player_and_spectators() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || relay(listener));
+
+    let connect = |role| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let join = ClientMessage::Join { role };
+        writeln!(stream, "{}", serde_json::to_string(&join).unwrap()).unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        (stream, reader.lines().map(|line| line.unwrap()))
+    };
+    let received = |line: String| serde_json::from_str::<RelayMessage>(&line).unwrap();
+
+    let (_, mut first) = connect(Role::Player);
+    assert_eq!(
+        received(first.next().unwrap()),
+        RelayMessage::Joined { player: 0 }
+    );
+    let (_, mut spectator) = connect(Role::Spectator);
+    assert_eq!(received(spectator.next().unwrap()), RelayMessage::Watching);
+    let (mut second, mut second_lines) = connect(Role::Player);
+    assert_eq!(
+        received(second_lines.next().unwrap()),
+        RelayMessage::Joined { player: 1 }
+    );
+    assert_eq!(received(first.next().unwrap()), RelayMessage::Paired);
+    let (_, mut third) = connect(Role::Player);
+    assert_eq!(received(third.next().unwrap()), RelayMessage::Full);
+
+    writeln!(second, "{{\"type\":\"start\"}}").unwrap();
+    assert_eq!(first.next().unwrap(), "{\"type\":\"start\"}");
+    let message = serde_json::json!({"type": "start"});
+    let relayed = RelayMessage::Relayed { player: 1, message };
+    assert_eq!(received(spectator.next().unwrap()), relayed);
+
+    // the spectator stops reading, which doesn't hold up the players
+    let line = format!("\"{}\"", "x".repeat(1000));
+    let expected = line.clone();
+    let writer = thread::spawn(move || {
+        for _ in 0..10_000 {
+            writeln!(second, "{}", line).unwrap();
+        }
+    });
+    for _ in 0..10_000 {
+        assert_eq!(first.next().unwrap(), expected);
+    }
+    writer.join().unwrap();
+}
@@ -0,0 +1,88 @@
+use crate::game::{
+    load_masks,
+    theme::{load_theme, set_theme},
+    Game, RotationSystem, MASKS_FILE,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+// for designing piece sets and themes, masks.txt and the theme file are read again
+// whenever they change while the game is running, a file that doesn't load is reported
+// and the previous version is kept
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+struct Watched {
+    path: PathBuf,
+    modified: Option<SystemTime>, // as of the last check
+}
+
+impl Watched {
+    fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+        Self { path, modified }
+    }
+
+    // whether the file was written (or created) since the last call, a removed file isn't
+    // a change to reload
+    fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed && modified.is_some()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub struct Reloader {
+    masks: Watched,
+    theme: Watched,
+    next_check: Instant,
+}
+
+impl Reloader {
+    pub fn new(theme: PathBuf) -> Self {
+        Self {
+            masks: Watched::new(MASKS_FILE.into()),
+            theme: Watched::new(theme),
+            next_check: Instant::now() + CHECK_EVERY,
+        }
+    }
+
+    // returns whether anything was reloaded, so that whatever was drawn with the old
+    // masks or colors can be thrown away, only looks at the files once in a while
+    pub fn poll(&mut self, game: &mut Game) -> bool {
+        if Instant::now() < self.next_check {
+            return false;
+        }
+        self.next_check = Instant::now() + CHECK_EVERY;
+        let mut reloaded = false;
+        // the other rotation systems don't use masks.txt
+        if self.masks.changed() && game.rotation_system() == RotationSystem::Classic {
+            match load_masks(&self.masks.path) {
+                Ok(set) => {
+                    game.replace_pieces(set);
+                    println!("Reloaded {}", self.masks.path.display());
+                    reloaded = true;
+                }
+                Err(e) => eprintln!("Warning: {}, keeping the previous masks", e),
+            }
+        }
+        if self.theme.changed() {
+            match load_theme(&self.theme.path) {
+                Ok(theme) => {
+                    set_theme(theme);
+                    println!("Reloaded {}", self.theme.path.display());
+                    reloaded = true;
+                }
+                Err(e) => eprintln!("Warning: {}, keeping the previous colors", e),
+            }
+        }
+        reloaded
+    }
+}
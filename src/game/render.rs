@@ -0,0 +1,300 @@
+use crate::game::{Action, Game, GAME_HEIGHT, GAME_WIDTH};
+
+// everything the game draws goes through a Renderer, so the drawing code only has to
+// be written once for every frontend (see ggez_frontend for the window)
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const WHITE: Self = Self::rgb(255, 255, 255);
+    pub const BLACK: Self = Self::rgb(0, 0, 0);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    // darker for factor < 1
+    pub fn scaled(self, factor: f32) -> Self {
+        let scale = |c| (c as f32 * factor).clamp(0., 255.) as u8;
+        Self::rgb(scale(self.r), scale(self.g), scale(self.b))
+    }
+
+    // grey of the same brightness, a bit darker, for things that can't be used right now
+    pub fn greyed(self) -> Self {
+        let grey = ((self.r as u16 + self.g as u16 + self.b as u16) / 3) as u8;
+        Self::rgb(grey, grey, grey).scaled(0.6)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+pub trait Renderer {
+    fn rect(&mut self, rect: Rect, color: Color);
+    // a line through all the points
+    fn line(&mut self, points: &[(f32, f32)], width: f32, color: Color);
+    // pos is the top-left corner of the text
+    fn text(&mut self, pos: (f32, f32), text: &str, color: Color);
+}
+
+// what an input means, independent of the device it came from
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Command {
+    Play(Action),
+    Pause,
+    SwitchLayout,
+    Freeze, // frame-step debugging, see VisGame::with_frame_step
+    Step,
+    TakeOver, // from a network being watched, see NNVisGame
+}
+
+// draws everything flipped left to right between span.0 and span.1 (such as the edges of a
+// board), text can't be flipped so it only moves, by roughly its width
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub(super) struct Mirrored<'a> {
+    pub(super) inner: &'a mut dyn Renderer,
+    pub(super) span: (f32, f32),
+}
+
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+const CHAR_WIDTH: f32 = 9.; // about, in the default font
+
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+impl Mirrored<'_> {
+    fn flip(&self, x: f32) -> f32 {
+        self.span.0 + self.span.1 - x
+    }
+}
+
+impl Renderer for Mirrored<'_> {
+    fn rect(&mut self, rect: Rect, color: Color) {
+        let x = self.flip(rect.x + rect.w);
+        self.inner.rect(Rect { x, ..rect }, color);
+    }
+
+    fn line(&mut self, points: &[(f32, f32)], width: f32, color: Color) {
+        let points = points
+            .iter()
+            .map(|&(x, y)| (self.flip(x), y))
+            .collect::<Vec<_>>();
+        self.inner.line(&points, width, color);
+    }
+
+    fn text(&mut self, (x, y): (f32, f32), text: &str, color: Color) {
+        let x = self.flip(x) - CHAR_WIDTH * text.chars().count() as f32;
+        self.inner.text((x, y), text, color);
+    }
+}
+
+// small board without grid, hold or queue, for showing many games at once
+// return (bottom, right)
+pub(super) fn add_thumbnail(
+    game: &Game,
+    (left, top): (f32, f32),
+    cell_side: f32,
+    r: &mut dyn Renderer,
+) -> (f32, f32) {
+    let (width, height) = (
+        GAME_WIDTH as f32 * cell_side,
+        GAME_HEIGHT as f32 * cell_side,
+    );
+    let bg_rect = Rect {
+        x: left,
+        y: top,
+        w: width,
+        h: height,
+    };
+    r.rect(bg_rect, Color::rgb(28, 28, 28));
+    let mut cell = |x: isize, y: isize, color| {
+        let rect = Rect {
+            x: left + x as f32 * cell_side,
+            y: top + y as f32 * cell_side,
+            w: cell_side,
+            h: cell_side,
+        };
+        r.rect(rect, color);
+    };
+    for (y, row) in game.board.iter().enumerate() {
+        for (x, px) in row.iter().enumerate() {
+            if let Some(color) = px.color() {
+                cell(x as isize, y as isize, color);
+            }
+        }
+    }
+    if let Some(falling) = game.falling.as_ref() {
+        for (x, y) in falling.mask.cells_at(falling.pos) {
+            cell(x, y, falling.id.color());
+        }
+    }
+    (top + height, left + width)
+}
+
+// any number of boards in a grid inside area, row by row, every cell as large as fits with
+// room for a label below the board, a board can be drawn smaller than its cell by a scale
+// (1 fills it) and is centered at the top of it
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub(super) struct MultiBoard {
+    area: Rect,
+    columns: usize,
+    cell: (f32, f32), // width and height without the label
+    label: f32,
+}
+
+const BOARD_GAP: f32 = 10.;
+
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+impl MultiBoard {
+    // as square a grid as there is for boards
+    pub(super) fn new(boards: usize, area: Rect, label: f32) -> Self {
+        let columns = (boards as f64).sqrt().ceil().max(1.) as usize;
+        Self::with_columns(boards, columns, area, label)
+    }
+
+    pub(super) fn with_columns(boards: usize, columns: usize, area: Rect, label: f32) -> Self {
+        let columns = columns.max(1);
+        let rows = boards.div_ceil(columns).max(1);
+        let gaps = |n: usize| BOARD_GAP * n.saturating_sub(1) as f32;
+        let cell = (
+            (area.w - gaps(columns)) / columns as f32,
+            (area.h - gaps(rows)) / rows as f32 - label,
+        );
+        Self {
+            area,
+            columns,
+            cell,
+            label,
+        }
+    }
+
+    // of a board at scale 1
+    pub(super) fn cell_side(&self) -> f32 {
+        f32::min(
+            self.cell.0 / GAME_WIDTH as f32,
+            self.cell.1 / GAME_HEIGHT as f32,
+        )
+    }
+
+    // where board i goes at scale
+    pub(super) fn board_rect(&self, i: usize, scale: f32) -> Rect {
+        let cell_side = self.cell_side() * scale;
+        let (w, h) = (
+            GAME_WIDTH as f32 * cell_side,
+            GAME_HEIGHT as f32 * cell_side,
+        );
+        let (column, row) = (i % self.columns, i / self.columns);
+        let x = self.area.x + column as f32 * (self.cell.0 + BOARD_GAP);
+        let y = self.area.y + row as f32 * (self.cell.1 + self.label + BOARD_GAP);
+        Rect {
+            x: x + (self.cell.0 - w) / 2.,
+            y,
+            w,
+            h,
+        }
+    }
+
+    // draws game as board i, returns where it went, the label goes right below
+    pub(super) fn add(&self, i: usize, game: &Game, scale: f32, r: &mut dyn Renderer) -> Rect {
+        let rect = self.board_rect(i, scale);
+        add_thumbnail(game, (rect.x, rect.y), self.cell_side() * scale, r);
+        rect
+    }
+}
+
+// line graph of every series over the same range, the first value of each series is at
+// the left edge and the last one at the right edge
+// return (min, max) of the values
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub(super) fn add_plot(series: &[(&[f64], Color)], rect: Rect, r: &mut dyn Renderer) -> (f64, f64) {
+    r.rect(rect, Color::rgb(28, 28, 28));
+    let values = || series.iter().flat_map(|(values, _)| values.iter().copied());
+    let min = values().fold(0., f64::min);
+    let max = values().fold(min + 1., f64::max);
+    for (values, color) in series {
+        if values.len() < 2 {
+            continue;
+        }
+        let points = values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                (
+                    rect.x + rect.w * i as f32 / (values.len() - 1) as f32,
+                    rect.y + rect.h * (1. - ((v - min) / (max - min)) as f32),
+                )
+            })
+            .collect::<Vec<_>>();
+        r.line(&points, 2., *color);
+    }
+    (min, max)
+}
+
+// draws into an SVG document instead of a window, for pictures saved to files
+#[derive(Default)]
+pub(super) struct SvgRenderer {
+    body: String,
+}
+
+fn svg_color(color: Color) -> String {
+    format!("rgb({},{},{})", color.r, color.g, color.b)
+}
+
+impl SvgRenderer {
+    // the document with everything drawn so far, the picture is width x height
+    pub(super) fn finish(self, width: f32, height: f32) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n{}</svg>\n",
+            width, height, self.body
+        )
+    }
+}
+
+impl Renderer for SvgRenderer {
+    fn rect(&mut self, rect: Rect, color: Color) {
+        self.body += &format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            svg_color(color)
+        );
+    }
+
+    fn line(&mut self, points: &[(f32, f32)], width: f32, color: Color) {
+        let points = points
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<_>>();
+        self.body += &format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+            points.join(" "),
+            svg_color(color),
+            width
+        );
+    }
+
+    fn text(&mut self, (x, y): (f32, f32), text: &str, color: Color) {
+        let text = text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        self.body += &format!(
+            "<text x=\"{}\" y=\"{}\" fill=\"{}\" font-family=\"sans-serif\" \
+             dominant-baseline=\"hanging\">{}</text>\n",
+            x,
+            y,
+            svg_color(color),
+            text
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use crate::game::{
+    rise::StackRise, Action, Board, Game, HoldMode, PieceId, Pixel, RotationSystem, GAME_HEIGHT,
+    GAME_WIDTH,
+};
+use itertools::Itertools;
+use std::{fmt, fs, io, num::ParseIntError, path::Path};
+
+// a piece as it was locked by the player together with the board it was locked
+// onto, a replay is the list of these for a whole game
+#[derive(Clone)]
+pub struct LockRecord {
+    pub(super) board: Board, // before the lock
+    pub(super) id: PieceId,
+    pub(super) next: Vec<PieceId>, // visible queue at the time of the lock
+    pub(super) mask_idx: usize,
+    pub(super) pos: (isize, isize),
+}
+
+#[derive(From, Debug)]
+pub enum ReplayReadError {
+    IoError(io::Error),
+    ParseIntError(ParseIntError),
+    Other(String),
+}
+
+pub type ReplayReadResult<T> = Result<T, ReplayReadError>;
+
+impl fmt::Display for ReplayReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read replay: {}", e),
+            Self::ParseIntError(e) => write!(f, "invalid replay: {}", e),
+            Self::Other(e) => write!(f, "invalid replay: {}", e),
+        }
+    }
+}
+
+// one record per line: "<id> <next> <mask_idx> <x> <y> <board>" where next is a
+// string of piece letters and board is the rows concatenated, '.' empty and '#' full
+impl LockRecord {
+    fn to_line(&self) -> String {
+        let next = self.next.iter().map(|id| id.to_char()).collect::<String>();
+        let board = self
+            .board
+            .iter()
+            .flat_map(|row| row.iter().map(|px| if px.is_empty() { '.' } else { '#' }))
+            .collect::<String>();
+        format!(
+            "{} {} {} {} {} {}",
+            self.id.to_char(),
+            next,
+            self.mask_idx,
+            self.pos.0,
+            self.pos.1,
+            board
+        )
+    }
+
+    fn from_line(line: &str) -> ReplayReadResult<Self> {
+        let invalid = || ReplayReadError::Other(format!("invalid record: \"{}\"", line));
+        let piece = |c| PieceId::from_char(c).ok_or_else(invalid);
+        let mut split = line.split(' ');
+        let mut field = || split.next().ok_or_else(invalid);
+
+        let id = piece(field()?.chars().next().ok_or_else(invalid)?)?;
+        let next = field()?.chars().map(piece).collect::<Result<_, _>>()?;
+        let mask_idx = field()?.parse()?;
+        let pos = (field()?.parse()?, field()?.parse()?);
+        let cells = field()?.chars().collect::<Vec<_>>();
+        if cells.len() != GAME_WIDTH * GAME_HEIGHT {
+            return Err(invalid());
+        }
+        // the exact pieces of a locked board don't matter, only occupancy
+        let mut board = [[Pixel::Empty; GAME_WIDTH]; GAME_HEIGHT];
+        for (i, &c) in cells.iter().enumerate() {
+            if c == '#' {
+                board[i / GAME_WIDTH][i % GAME_WIDTH] = Pixel::Full(id);
+            }
+        }
+        Ok(Self {
+            board,
+            id,
+            next,
+            mask_idx,
+            pos,
+        })
+    }
+}
+
+// overwrites!
+pub fn save_replay<P: AsRef<Path>>(path: P, records: &[LockRecord]) -> io::Result<()> {
+    fs::write(path, records.iter().map(LockRecord::to_line).join("\n"))
+}
+
+pub fn load_replay<P: AsRef<Path>>(path: P) -> ReplayReadResult<Vec<LockRecord>> {
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(LockRecord::from_line)
+        .collect()
+}
+
+// every input of a seeded game with the tick it was applied on, applying them to a game
+// with the same seed and gravity reproduces it exactly (as long as the rules don't change),
+// see Simulator::replay
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputReplay {
+    pub seed: u64,
+    pub gravity: f32,
+    pub hold: HoldMode,
+    pub delays: (usize, usize), // entry, line clear
+    pub lock_delay: usize,
+    pub rotation: RotationSystem,
+    pub rise: Option<StackRise>,
+    pub frames: usize, // how long the game ran
+    pub inputs: Vec<(usize, Action)>,
+}
+
+// "seed <n>", "gravity <g>", "hold <mode>" (left out for standard hold), "delays <entry>
+// <line clear>" (left out without delays), "lock <frames>" (left out for the default lock
+// delay), "rotation <system>" (left out for the classic one), "rise <interval> <min>
+// <speedup>" (left out without a rising stack) and "frames <n>" lines followed by one
+// "<tick> <action>" line per input, actions by their variant name
+impl InputReplay {
+    pub fn to_text(&self) -> String {
+        let hold = match self.hold {
+            HoldMode::Standard => String::new(),
+            mode => format!("hold {}\n", mode.name()),
+        };
+        let delays = match self.delays {
+            (0, 0) => String::new(),
+            (entry, line_clear) => format!("delays {} {}\n", entry, line_clear),
+        };
+        let lock = match self.lock_delay {
+            Game::LOCK_DELAY => String::new(),
+            frames => format!("lock {}\n", frames),
+        };
+        let rotation = match self.rotation {
+            RotationSystem::Classic => String::new(),
+            system => format!("rotation {}\n", system.name()),
+        };
+        let rise = match self.rise {
+            None => String::new(),
+            Some(rise) => format!("rise {}\n", rise.to_text()),
+        };
+        let header = format!(
+            "seed {}\ngravity {}\n{}{}{}{}{}frames {}\n",
+            self.seed, self.gravity, hold, delays, lock, rotation, rise, self.frames
+        );
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|(tick, action)| format!("{} {:?}\n", tick, action));
+        Some(header).into_iter().chain(inputs).collect()
+    }
+
+    pub fn from_text<'a>(text: &'a str) -> ReplayReadResult<Self> {
+        let invalid = |line: &str| ReplayReadError::Other(format!("invalid line: \"{}\"", line));
+        let mut lines = text.lines().filter(|l| !l.is_empty()).peekable();
+        let header = |line: Option<&'a str>, name: &str| {
+            let line = line.unwrap_or_default();
+            match line.split_once(' ') {
+                Some((key, value)) if key == name => Ok(value),
+                _ => Err(invalid(line)),
+            }
+        };
+        let seed = header(lines.next(), "seed")?.parse()?;
+        let gravity = header(lines.next(), "gravity")?
+            .parse()
+            .map_err(|_| ReplayReadError::Other("invalid gravity".to_string()))?;
+        let hold = match lines.peek().and_then(|l| l.strip_prefix("hold ")) {
+            Some(name) => {
+                let mode = HoldMode::from_name(name).ok_or_else(|| invalid(name))?;
+                lines.next();
+                mode
+            }
+            None => HoldMode::Standard,
+        };
+        let delays = match lines.peek().and_then(|l| l.strip_prefix("delays ")) {
+            Some(value) => {
+                let (entry, line_clear) = value.split_once(' ').ok_or_else(|| invalid(value))?;
+                lines.next();
+                (entry.parse()?, line_clear.parse()?)
+            }
+            None => (0, 0),
+        };
+        let lock_delay = match lines.peek().and_then(|l| l.strip_prefix("lock ")) {
+            Some(frames) => {
+                let frames = frames.parse()?;
+                lines.next();
+                frames
+            }
+            None => Game::LOCK_DELAY,
+        };
+        let rotation = match lines.peek().and_then(|l| l.strip_prefix("rotation ")) {
+            Some(name) => {
+                let system = RotationSystem::from_name(name).ok_or_else(|| invalid(name))?;
+                lines.next();
+                system
+            }
+            None => RotationSystem::Classic,
+        };
+        let rise = match lines.peek().and_then(|l| l.strip_prefix("rise ")) {
+            Some(value) => {
+                let rise = StackRise::from_text(value).ok_or_else(|| invalid(value))?;
+                lines.next();
+                Some(rise)
+            }
+            None => None,
+        };
+        let frames = header(lines.next(), "frames")?.parse()?;
+        let inputs = lines
+            .map(|line| {
+                let (tick, action) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+                let action = Action::from_name(action).ok_or_else(|| invalid(line))?;
+                Ok((tick.parse()?, action))
+            })
+            .collect::<ReplayReadResult<_>>()?;
+        Ok(Self {
+            seed,
+            gravity,
+            hold,
+            delays,
+            lock_delay,
+            rotation,
+            rise,
+            frames,
+            inputs,
+        })
+    }
+}
+
+// overwrites!
+pub fn save_input_replay<P: AsRef<Path>>(path: P, replay: &InputReplay) -> io::Result<()> {
+    fs::write(path, replay.to_text())
+}
+
+pub fn load_input_replay<P: AsRef<Path>>(path: P) -> ReplayReadResult<InputReplay> {
+    InputReplay::from_text(&fs::read_to_string(path)?)
+}
@@ -0,0 +1,308 @@
+use crate::game::{features::BoardMetrics, Board, Game, GameEvent};
+use serde::Deserialize;
+use std::{convert::TryFrom, fmt};
+
+// a reward written as an arithmetic expression in the trainer config instead of in code, e.g.
+//
+//     reward = "1.0*lines + 0.01*ticks - 0.5*new_holes - 0.1*max_height"
+//
+// with numbers, + - * /, parentheses and these variables, counted over a whole game for the
+// genetic trainer and over a single placement for rl:
+//
+//     points      the score
+//     lines       cleared
+//     pieces      locked
+//     new_holes   created by locking pieces
+//     tspins      including those that cleared nothing
+//     level_ups
+//     game_over   1 if the game was lost, 0 otherwise
+//     ticks       frames played, 0 in rl
+//     max_height  of the stack at the end, in rows
+//     holes       at the end
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RewardExpr {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Var {
+    Points,
+    Lines,
+    Pieces,
+    NewHoles,
+    TSpins,
+    LevelUps,
+    GameOver,
+    Ticks,
+    MaxHeight,
+    Holes,
+}
+
+impl Var {
+    const ALL: [(&'static str, Var); 10] = [
+        ("points", Var::Points),
+        ("lines", Var::Lines),
+        ("pieces", Var::Pieces),
+        ("new_holes", Var::NewHoles),
+        ("tspins", Var::TSpins),
+        ("level_ups", Var::LevelUps),
+        ("game_over", Var::GameOver),
+        ("ticks", Var::Ticks),
+        ("max_height", Var::MaxHeight),
+        ("holes", Var::Holes),
+    ];
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(f64),
+    Var(Var),
+    Neg(Box<Expr>),
+    Op(Box<Expr>, char, Box<Expr>),
+}
+
+// the values of the variables
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RewardVars {
+    pub points: f64,
+    pub lines: f64,
+    pub pieces: f64,
+    pub new_holes: f64,
+    pub tspins: f64,
+    pub level_ups: f64,
+    pub game_over: f64,
+    pub ticks: f64,
+    pub max_height: f64,
+    pub holes: f64,
+}
+
+impl RewardVars {
+    // a whole game, events are all of those it emitted
+    pub fn of_game(game: &Game, events: &[GameEvent]) -> Self {
+        let mut vars = Self {
+            points: game.points as f64,
+            ticks: game.tick as f64,
+            ..Self::default()
+        };
+        vars.set_board(game.metrics());
+        for event in events {
+            match *event {
+                GameEvent::PieceLocked(_) => vars.pieces += 1.,
+                GameEvent::LinesCleared(n) => vars.lines += n as f64,
+                GameEvent::HolesCreated(n) => vars.new_holes += n as f64,
+                GameEvent::LevelUp(_) => vars.level_ups += 1.,
+                GameEvent::TSpin(_) => vars.tspins += 1.,
+                GameEvent::GameOver => vars.game_over = 1.,
+            }
+        }
+        vars
+    }
+
+    // a single placement of the current piece of game that leaves board behind
+    pub(super) fn of_placement(game: &Game, board: &Board, cleared: usize) -> Self {
+        let mut vars = Self {
+            lines: cleared as f64,
+            pieces: 1.,
+            ..Self::default()
+        };
+        vars.set_board(&BoardMetrics::of(board));
+        vars.new_holes = (vars.holes - game.metrics().holes() as f64).max(0.);
+        vars
+    }
+
+    fn set_board(&mut self, metrics: &BoardMetrics) {
+        self.max_height = metrics.max_height() as f64;
+        self.holes = metrics.holes() as f64;
+    }
+
+    fn get(&self, var: Var) -> f64 {
+        match var {
+            Var::Points => self.points,
+            Var::Lines => self.lines,
+            Var::Pieces => self.pieces,
+            Var::NewHoles => self.new_holes,
+            Var::TSpins => self.tspins,
+            Var::LevelUps => self.level_ups,
+            Var::GameOver => self.game_over,
+            Var::Ticks => self.ticks,
+            Var::MaxHeight => self.max_height,
+            Var::Holes => self.holes,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RewardParseError(String);
+
+impl fmt::Display for RewardParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid reward: {}", self.0)
+    }
+}
+
+// recursive descent over the characters, the usual precedence with left associativity
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    source: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                return Some(c);
+            }
+            self.chars.next();
+        }
+        None
+    }
+
+    fn error(&mut self, what: &str) -> RewardParseError {
+        let at = self.chars.peek().map_or(self.source.len(), |&(i, _)| i);
+        RewardParseError(format!("{} at {} in \"{}\"", what, at + 1, self.source))
+    }
+
+    fn sum(&mut self) -> Result<Expr, RewardParseError> {
+        let mut expr = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            expr = Expr::Op(Box::new(expr), op, Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, RewardParseError> {
+        let mut expr = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.chars.next();
+            expr = Expr::Op(Box::new(expr), op, Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, RewardParseError> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Expr::Neg(Box::new(self.factor()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let expr = self.sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(expr)
+                    }
+                    _ => Err(self.error("expected )")),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.take(|c| c.is_ascii_digit() || c == '.');
+                number
+                    .parse()
+                    .map(Expr::Number)
+                    .map_err(|_| self.error(&format!("invalid number \"{}\"", number)))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self.take(|c| c.is_ascii_alphanumeric() || c == '_');
+                Var::ALL
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|&(_, var)| Expr::Var(var))
+                    .ok_or_else(|| self.error(&format!("unknown variable \"{}\"", name)))
+            }
+            _ => Err(self.error("expected a number, a variable or (")),
+        }
+    }
+
+    fn take(&mut self, matches: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !matches(c) {
+                break;
+            }
+            taken.push(c);
+            self.chars.next();
+        }
+        taken
+    }
+}
+
+impl Expr {
+    fn eval(&self, vars: &RewardVars) -> f64 {
+        match self {
+            Expr::Number(x) => *x,
+            Expr::Var(var) => vars.get(*var),
+            Expr::Neg(e) => -e.eval(vars),
+            Expr::Op(a, op, b) => {
+                let (a, b) = (a.eval(vars), b.eval(vars));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    // a reward that is NaN or infinite would break sorting by fitness
+                    _ if b == 0. => 0.,
+                    _ => a / b,
+                }
+            }
+        }
+    }
+}
+
+impl RewardExpr {
+    pub fn parse(source: &str) -> Result<Self, RewardParseError> {
+        let mut parser = Parser {
+            chars: source.char_indices().peekable(),
+            source,
+        };
+        let expr = parser.sum()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    pub fn eval(&self, vars: &RewardVars) -> f64 {
+        self.expr.eval(vars)
+    }
+}
+
+impl TryFrom<String> for RewardExpr {
+    type Error = RewardParseError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::parse(&source)
+    }
+}
+
+impl fmt::Display for RewardExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[test]
+fn test_reward_expressions() {
+    let vars = RewardVars {
+        lines: 4.,
+        ticks: 100.,
+        new_holes: 2.,
+        max_height: 10.,
+        ..RewardVars::default()
+    };
+    let reward = |source| RewardExpr::parse(source).unwrap().eval(&vars);
+    let shaped = reward("1.0*lines + 0.01*ticks - 0.5*new_holes - 0.1*max_height");
+    assert!((shaped - 3.).abs() < 1e-9);
+    assert_eq!(reward("2 * (lines - 1) / 3"), 2.);
+    assert_eq!(reward("-lines - -1"), -3.);
+    assert_eq!(reward("lines / holes"), 0.);
+    for bad in &["", "lines +", "2 * (lines", "line", "1.2.3", "lines lines"] {
+        assert!(RewardExpr::parse(bad).is_err(), "{}", bad);
+    }
+}
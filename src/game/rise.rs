@@ -0,0 +1,87 @@
+use crate::game::GAME_WIDTH;
+use rand::prelude::*;
+
+// survival: a row of garbage rises from the bottom every so often and a little sooner
+// every time, until it comes every min_interval frames, the game runs it (see
+// Game::set_stack_rise) so that replays of it come out the same
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StackRise {
+    pub interval: usize,     // frames until the first row
+    pub min_interval: usize, // it never comes sooner than this
+    pub speedup: usize,      // frames less after every row
+}
+
+// what the game runs at
+const FPS: usize = 60;
+
+impl StackRise {
+    // the first row after seconds, down to one a second after twenty rows
+    pub fn every_seconds(seconds: usize) -> Self {
+        let interval = (seconds * FPS).max(1);
+        Self {
+            interval,
+            min_interval: FPS.min(interval),
+            speedup: interval.saturating_sub(FPS) / 20,
+        }
+    }
+
+    // frames between the row with index risen and the one before it (or the start)
+    pub fn interval_after(&self, risen: usize) -> usize {
+        let faster = self.interval.saturating_sub(risen * self.speedup);
+        faster.max(self.min_interval).max(1)
+    }
+
+    // where the hole of the row with index risen is, the same for the same seed
+    pub fn hole(seed: u64, risen: usize) -> usize {
+        let mut rng = StdRng::seed_from_u64(seed ^ (risen as u64).wrapping_mul(0x9e37_79b9));
+        rng.gen_range(0, GAME_WIDTH)
+    }
+
+    // "<interval> <min interval> <speedup>", as in input replays
+    pub fn to_text(self) -> String {
+        format!("{} {} {}", self.interval, self.min_interval, self.speedup)
+    }
+
+    pub fn from_text(text: &str) -> Option<Self> {
+        let mut numbers = text.split(' ').map(|n| n.parse().ok());
+        let rise = Self {
+            interval: numbers.next()??,
+            min_interval: numbers.next()??,
+            speedup: numbers.next()??,
+        };
+        match numbers.next() {
+            None => Some(rise),
+            Some(_) => None,
+        }
+    }
+}
+
+#[test]
+fn test_stack_rise_speeds_up() {
+    let rise = StackRise::every_seconds(10);
+    assert_eq!(rise.interval_after(0), 600);
+    assert_eq!(rise.interval_after(1), 573);
+    assert_eq!(rise.interval_after(100), 60);
+    assert_eq!(StackRise::from_text(&rise.to_text()), Some(rise));
+    assert_eq!(StackRise::from_text("600 60"), None);
+    assert_eq!(StackRise::hole(3, 7), StackRise::hole(3, 7));
+
+    let mut game = crate::game::Game::with_seed(3);
+    game.set_gravity(0.);
+    game.set_stack_rise(Some(StackRise {
+        interval: 5,
+        min_interval: 2,
+        speedup: 2,
+    }));
+    let rows = |game: &crate::game::Game| {
+        let ascii = game.board_to_ascii();
+        ascii.lines().filter(|line| line.contains('#')).count()
+    };
+    // after 5, 3 and 2 frames
+    for (frames, expected) in [(4, 0), (1, 1), (3, 2), (2, 3), (2, 4)] {
+        for _ in 0..frames {
+            game.iterate();
+        }
+        assert_eq!(rows(&game), expected);
+    }
+}
@@ -0,0 +1,585 @@
+use crate::{
+    game::{
+        features::InputEncoder,
+        placement::{placements, Placement},
+        reward::{RewardExpr, RewardVars},
+        zobrist::hash_state,
+        Game, GAME_WIDTH,
+    },
+    neural_network::{ActivationType, NNCreationError, NN},
+};
+use itertools::Itertools;
+use rand::prelude::*;
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    num::{ParseFloatError, ParseIntError},
+    path::{Path, PathBuf},
+};
+
+// the network estimates the value of the board left behind by a placement
+// (afterstate), the best placement is the one with the highest reward + value
+pub struct RLConfig {
+    pub episodes: usize,
+    pub max_pieces: usize, // per episode
+    pub replay_capacity: usize,
+    pub batch_size: usize, // transitions replayed after every piece
+    pub gamma: f64,        // discount
+    pub learning_rate: f64,
+    // epsilon decays linearly from start to end over the given number of episodes
+    pub epsilon_start: f64,
+    pub epsilon_end: f64,
+    pub epsilon_decay_episodes: usize,
+    pub target_update_interval: usize, // in pieces
+    pub prioritized: Option<Prioritized>,
+    pub reward: Option<RewardExpr>, // of a placement, instead of default_reward
+}
+
+// replays transitions in proportion to how wrong the network was about them (their TD error
+// to the power of alpha) instead of uniformly, the bias this introduces is corrected by
+// scaling each step by (1 / (N * P(transition)))^beta
+#[derive(Copy, Clone, Debug)]
+pub struct Prioritized {
+    pub alpha: f64,
+    pub beta: f64,
+    pub epsilon: f64, // added to every error so that no transition is never replayed
+}
+
+impl Default for Prioritized {
+    fn default() -> Self {
+        Self {
+            alpha: 0.6,
+            beta: 0.4,
+            epsilon: 0.01,
+        }
+    }
+}
+
+impl Default for RLConfig {
+    fn default() -> Self {
+        Self {
+            episodes: 2000,
+            max_pieces: 1000,
+            replay_capacity: 20_000,
+            batch_size: 32,
+            gamma: 0.95,
+            learning_rate: 0.001,
+            epsilon_start: 1.,
+            epsilon_end: 0.01,
+            epsilon_decay_episodes: 1500,
+            target_update_interval: 500,
+            prioritized: None,
+            reward: None,
+        }
+    }
+}
+
+// hashes of state and next state (see zobrist::hash_state) and done
+type TransitionKey = (u64, u64, bool);
+
+struct Transition {
+    state: Box<[f64]>,
+    reward: f64,
+    next_state: Box<[f64]>,
+    done: bool,
+    key: TransitionKey,
+}
+
+// priorities of the slots of the buffer in the leaves of a binary tree whose inner nodes
+// are the sums of their children, so that sampling and updating are both O(log n)
+struct SumTree {
+    nodes: Vec<f64>, // the root is 1, the children of i are 2i and 2i + 1
+    leaves: usize,
+}
+
+impl SumTree {
+    fn new(capacity: usize) -> Self {
+        let leaves = capacity.next_power_of_two();
+        Self {
+            nodes: vec![0.; 2 * leaves],
+            leaves,
+        }
+    }
+
+    fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    fn get(&self, slot: usize) -> f64 {
+        self.nodes[self.leaves + slot]
+    }
+
+    fn set(&mut self, slot: usize, priority: f64) {
+        let mut i = self.leaves + slot;
+        self.nodes[i] = priority;
+        while i > 1 {
+            i /= 2;
+            self.nodes[i] = self.nodes[2 * i] + self.nodes[2 * i + 1];
+        }
+    }
+
+    // the slot in which the running sum of the priorities passes mass
+    fn find(&self, mut mass: f64) -> usize {
+        let mut i = 1;
+        while i < self.leaves {
+            if mass < self.nodes[2 * i] || self.nodes[2 * i + 1] == 0. {
+                i *= 2;
+            } else {
+                mass -= self.nodes[2 * i];
+                i = 2 * i + 1;
+            }
+        }
+        i - self.leaves
+    }
+}
+
+// bounded, oldest transitions are dropped first, a transition that is already in the
+// buffer isn't added again so that common early-game states don't crowd out the rest
+pub struct ReplayBuffer {
+    capacity: usize,
+    transitions: Vec<Transition>, // a ring once full, next is the oldest
+    next: usize,
+    keys: HashSet<TransitionKey>,
+    priorities: Option<(Prioritized, SumTree)>,
+    max_priority: f64, // new transitions get it so that they are replayed at least once
+}
+
+impl ReplayBuffer {
+    fn new(capacity: usize, prioritized: Option<Prioritized>) -> Self {
+        Self {
+            capacity,
+            transitions: Vec::with_capacity(capacity),
+            next: 0,
+            keys: HashSet::with_capacity(capacity),
+            priorities: prioritized.map(|p| (p, SumTree::new(capacity))),
+            max_priority: 1.,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    fn push(&mut self, transition: Transition) {
+        self.push_with_priority(transition, self.max_priority);
+    }
+
+    fn push_with_priority(&mut self, transition: Transition, priority: f64) {
+        if !self.keys.insert(transition.key) {
+            return;
+        }
+        let slot = if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+            self.transitions.len() - 1
+        } else {
+            let slot = self.next;
+            let oldest = std::mem::replace(&mut self.transitions[slot], transition);
+            self.keys.remove(&oldest.key);
+            self.next = (slot + 1) % self.capacity;
+            slot
+        };
+        if let Some((_, tree)) = &mut self.priorities {
+            tree.set(slot, priority);
+        }
+    }
+
+    // slots and the weights of their steps, 1 without priorities
+    fn sample(&self, n: usize, rng: &mut ThreadRng) -> Vec<(usize, f64)> {
+        let len = self.transitions.len();
+        let (p, tree) = match &self.priorities {
+            Some(priorities) => priorities,
+            None => return (0..n).map(|_| (rng.gen_range(0, len), 1.)).collect(),
+        };
+        // one from each of n equal stretches of the total
+        let stretch = tree.total() / n as f64;
+        let slots = (0..n)
+            .map(|i| {
+                tree.find(stretch * (i as f64 + rng.gen::<f64>()))
+                    .min(len - 1)
+            })
+            .collect::<Vec<_>>();
+        let weight = |slot| (len as f64 * tree.get(slot) / tree.total()).powf(-p.beta);
+        // scaled so that the largest is 1 and steps only ever get smaller
+        let max = slots.iter().map(|&slot| weight(slot)).fold(0., f64::max);
+        slots
+            .into_iter()
+            .map(|slot| (slot, weight(slot) / max))
+            .collect()
+    }
+
+    // after replaying the transition in slot, error is the network's error on it
+    fn update(&mut self, slot: usize, error: f64) {
+        if let Some((p, tree)) = &mut self.priorities {
+            let priority = (error.abs() + p.epsilon).powf(p.alpha);
+            tree.set(slot, priority);
+            self.max_priority = self.max_priority.max(priority);
+        }
+    }
+
+    // oldest first, one transition a line:
+    // "<state hash> <next state hash> <done 0/1> <reward> <priority> <state> <next state>"
+    // with the states as comma separated values, priorities are 1 without prioritizing
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (newest, oldest) = self.transitions.split_at(self.next);
+        let lines = oldest.iter().chain(newest).enumerate().map(|(i, t)| {
+            let slot = (self.next + i) % self.transitions.len();
+            let priority = self
+                .priorities
+                .as_ref()
+                .map_or(1., |(_, tree)| tree.get(slot));
+            format!(
+                "{} {} {} {} {} {} {}\n",
+                t.key.0,
+                t.key.1,
+                t.done as u8,
+                t.reward,
+                priority,
+                t.state.iter().join(","),
+                t.next_state.iter().join(",")
+            )
+        });
+        fs::write(path, lines.collect::<String>())
+    }
+
+    // a buffer saved before, no file yet is an empty buffer, the states have to be of the
+    // given size (the encoder's)
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        capacity: usize,
+        prioritized: Option<Prioritized>,
+        state_size: usize,
+    ) -> ReplayBufferReadResult<Self> {
+        let mut buffer = Self::new(capacity, prioritized);
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(buffer),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid =
+            |i| ReplayBufferReadError::Other(format!("invalid transition on line {}", i + 1));
+        for (i, line) in text.lines().enumerate() {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            if fields.len() != 7 {
+                return Err(invalid(i));
+            }
+            let state = |field: &str| -> ReplayBufferReadResult<Box<[f64]>> {
+                let state = field
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<Box<[f64]>, _>>()?;
+                match state.len() == state_size {
+                    true => Ok(state),
+                    false => Err(ReplayBufferReadError::Other(
+                        "the buffer was recorded with another encoder".to_string(),
+                    )),
+                }
+            };
+            let done = match fields[2] {
+                "0" => false,
+                "1" => true,
+                _ => return Err(invalid(i)),
+            };
+            let transition = Transition {
+                key: (fields[0].parse()?, fields[1].parse()?, done),
+                done,
+                reward: fields[3].parse()?,
+                state: state(fields[5])?,
+                next_state: state(fields[6])?,
+            };
+            let priority = fields[4].parse()?;
+            buffer.max_priority = buffer.max_priority.max(priority);
+            buffer.push_with_priority(transition, priority);
+        }
+        Ok(buffer)
+    }
+}
+
+#[derive(From, Debug)]
+pub enum ReplayBufferReadError {
+    IoError(io::Error),
+    ParseIntError(ParseIntError),
+    ParseFloatError(ParseFloatError),
+    #[from(ignore)]
+    Other(String),
+}
+
+pub type ReplayBufferReadResult<T> = Result<T, ReplayBufferReadError>;
+
+impl fmt::Display for ReplayBufferReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read replay buffer: {}", e),
+            Self::ParseIntError(e) => write!(f, "invalid replay buffer: {}", e),
+            Self::ParseFloatError(e) => write!(f, "invalid replay buffer: {}", e),
+            Self::Other(e) => write!(f, "invalid replay buffer: {}", e),
+        }
+    }
+}
+
+#[derive(From, Debug)]
+pub enum RLTrainerError {
+    NNCreationError(NNCreationError),
+    #[from(ignore)]
+    BadConfig(String),
+}
+
+impl fmt::Display for RLTrainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NNCreationError(e) => write!(f, "{}", e),
+            Self::BadConfig(e) => write!(f, "invalid config: {}", e),
+        }
+    }
+}
+
+// an encoded afterstate and its hash
+type State = (Box<[f64]>, u64);
+
+fn default_reward(cleared: usize, lost: bool) -> f64 {
+    // surviving is worth a little, multiple lines at once a lot
+    let reward = 1. + (cleared * cleared * GAME_WIDTH) as f64;
+    if lost {
+        reward - 2.
+    } else {
+        reward
+    }
+}
+
+pub struct RLTrainer {
+    config: RLConfig,
+    encoder: InputEncoder,
+    nn: NN,
+    target: NN, // lagging copy of nn used for bootstrapping
+    buffer: ReplayBuffer,
+    buffer_path: Option<PathBuf>, // where the buffer is kept between runs
+    rng: ThreadRng,
+    pieces: usize, // placed over all episodes
+}
+
+impl RLTrainer {
+    #[allow(dead_code)]
+    pub fn new(encoder: InputEncoder, config: RLConfig) -> Result<Self, RLTrainerError> {
+        if config.target_update_interval == 0 {
+            return Err(RLTrainerError::BadConfig(
+                "target_update_interval has to be at least 1".to_string(),
+            ));
+        }
+        let nn = NN::make(encoder.input_size())
+            .init_range(-0.1, 0.1)
+            .add_layer(32, ActivationType::Relu)
+            .add_layer(32, ActivationType::Relu)
+            .add_layer(1, ActivationType::Linear)
+            .build()?;
+        Ok(Self {
+            buffer: ReplayBuffer::new(config.replay_capacity, config.prioritized),
+            buffer_path: None,
+            config,
+            encoder,
+            target: nn.clone(),
+            nn,
+            rng: thread_rng(),
+            pieces: 0,
+        })
+    }
+
+    // continues with the replay buffer saved in path (if there is one) and saves it there
+    // at the end, call before run
+    #[allow(dead_code)]
+    pub fn with_replay_buffer(mut self, path: PathBuf) -> ReplayBufferReadResult<Self> {
+        let (capacity, prioritized) = (self.config.replay_capacity, self.config.prioritized);
+        self.buffer = ReplayBuffer::load(&path, capacity, prioritized, self.encoder.input_size())?;
+        println!("{} transitions in the replay buffer", self.buffer.len());
+        self.buffer_path = Some(path);
+        Ok(self)
+    }
+
+    // of a placement, game_over is set once it's known whether the game was lost
+    fn reward(&self, vars: &RewardVars) -> f64 {
+        match &self.config.reward {
+            Some(reward) => reward.eval(vars),
+            None => default_reward(vars.lines as usize, vars.game_over > 0.),
+        }
+    }
+
+    // the encoded afterstate, its hash and what the placement did
+    fn encode(&self, game: &Game, placement: &Placement) -> (Box<[f64]>, u64, RewardVars) {
+        let (board, cleared) = placement.afterstate(game);
+        let mut queue = game.piece_queue.iter();
+        // after the placement, the next piece becomes the current one
+        let (current, next) = (queue.next(), queue.next());
+        let hash = hash_state(placement.afterstate_hash(game), current, next);
+        (
+            self.encoder.encode_board(&board, current, next),
+            hash,
+            RewardVars::of_placement(game, &board, cleared),
+        )
+    }
+
+    // epsilon-greedy over all reachable placements
+    fn choose(&mut self, game: &Game, epsilon: f64) -> Option<(Placement, State, RewardVars)> {
+        let mut options = placements(game)
+            .into_iter()
+            .map(|p| {
+                let (state, hash, vars) = self.encode(game, &p);
+                (p, (state, hash), vars)
+            })
+            .collect::<Vec<_>>();
+        if options.is_empty() {
+            None
+        } else if self.rng.gen::<f64>() < epsilon {
+            let idx = self.rng.gen_range(0, options.len());
+            Some(options.swap_remove(idx))
+        } else {
+            let value = |(_, (state, _), vars): &(Placement, State, RewardVars)| {
+                self.reward(vars) + self.config.gamma * self.nn.apply(state)[0]
+            };
+            let (best, _) = options
+                .iter()
+                .enumerate()
+                .map(|(i, o)| (i, value(o)))
+                .fold((0, f64::MIN), |b, c| if c.1 > b.1 { c } else { b });
+            Some(options.swap_remove(best))
+        }
+    }
+
+    fn learn(&mut self) {
+        if self.buffer.len() < self.config.batch_size {
+            return;
+        }
+        let (gamma, learning_rate) = (self.config.gamma, self.config.learning_rate);
+        for (slot, weight) in self.buffer.sample(self.config.batch_size, &mut self.rng) {
+            let t = &self.buffer.transitions[slot];
+            let mut target = t.reward;
+            if !t.done {
+                target += gamma * self.target.apply(&t.next_state)[0];
+            }
+            // the loss is half the squared error
+            let loss = self.nn.train(&t.state, &[target], learning_rate * weight);
+            self.buffer.update(slot, (2. * loss).sqrt());
+        }
+    }
+
+    // play a single game, returns the points scored
+    fn run_episode(&mut self, epsilon: f64) -> usize {
+        let mut game = Game::new();
+        let mut last_state: Option<State> = None;
+        for _ in 0..self.config.max_pieces {
+            let (placement, state, mut vars) = match self.choose(&game, epsilon) {
+                Some(choice) => choice,
+                None => break,
+            };
+            for action in placement.inputs {
+                game.apply(action);
+            }
+            let done = game.is_over();
+            vars.game_over = if done { 1. } else { 0. };
+            let reward = self.reward(&vars);
+            if let Some((last_state, last_hash)) = last_state.take() {
+                self.buffer.push(Transition {
+                    state: last_state,
+                    reward,
+                    next_state: state.0.clone(),
+                    done,
+                    key: (last_hash, state.1, done),
+                });
+            }
+            last_state = Some(state);
+
+            self.learn();
+            self.pieces += 1;
+            if self
+                .pieces
+                .is_multiple_of(self.config.target_update_interval)
+            {
+                self.target = self.nn.clone();
+            }
+            if done {
+                break;
+            }
+        }
+        game.points
+    }
+
+    // trains for the configured number of episodes and saves the network to path
+    #[allow(dead_code)]
+    pub fn run(&mut self, path: &Path) -> io::Result<()> {
+        let config = &self.config;
+        let (start, end, decay) = (
+            config.epsilon_start,
+            config.epsilon_end,
+            config.epsilon_decay_episodes,
+        );
+        for episode in 0..self.config.episodes {
+            let progress = (episode as f64 / decay as f64).min(1.);
+            let epsilon = start + (end - start) * progress;
+            let points = self.run_episode(epsilon);
+            println!(
+                "episode {}: {} points (epsilon {:.3})",
+                episode, points, epsilon
+            );
+        }
+        if let Some(buffer_path) = &self.buffer_path {
+            self.buffer.save(buffer_path)?;
+        }
+        self.nn.to_file(path)
+    }
+}
+
+#[test]
+fn test_replay_buffer() {
+    let transition = |i: u64, reward: f64| Transition {
+        state: vec![i as f64, 0.5].into_boxed_slice(),
+        reward,
+        next_state: vec![0., 1.].into_boxed_slice(),
+        done: i > 3,
+        key: (i, i + 1, i > 3),
+    };
+    let mut buffer = ReplayBuffer::new(4, Some(Prioritized::default()));
+    for i in 0..6 {
+        buffer.push(transition(i, i as f64));
+    }
+    // the two oldest were dropped, duplicates aren't added
+    buffer.push(transition(5, 5.));
+    assert_eq!(buffer.len(), 4);
+    // a transition the network was very wrong about is replayed the most, with the
+    // smallest steps
+    let surprising = buffer.sample(1, &mut thread_rng())[0].0;
+    for slot in 0..4 {
+        buffer.update(slot, if slot == surprising { 100. } else { 0. });
+    }
+    let batch = buffer.sample(100, &mut thread_rng());
+    let replayed = batch
+        .iter()
+        .filter(|&&(slot, _)| slot == surprising)
+        .count();
+    assert!(replayed > 90, "replayed {} times", replayed);
+    let (_, weight) = batch.iter().find(|&&(slot, _)| slot == surprising).unwrap();
+    assert!(batch.iter().all(|(_, w)| w >= weight));
+
+    let dir = crate::game::temp_dir::TempDir::new("replay");
+    let path = dir.join("replay.txt");
+    buffer.save(&path).unwrap();
+    let loaded = ReplayBuffer::load(&path, 4, Some(Prioritized::default()), 2).unwrap();
+    assert!(ReplayBuffer::load(&path, 4, None, 3).is_err());
+    let rewards = |buffer: &ReplayBuffer| {
+        let (newest, oldest) = buffer.transitions.split_at(buffer.next);
+        oldest
+            .iter()
+            .chain(newest)
+            .map(|t| t.reward)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(rewards(&loaded), vec![2., 3., 4., 5.]);
+    let (_, tree) = loaded.priorities.as_ref().unwrap();
+    assert!((tree.total() - buffer.priorities.as_ref().unwrap().1.total()).abs() < 1e-9);
+}
+
+#[test]
+fn test_rl_config_needs_target_updates() {
+    let config = RLConfig {
+        target_update_interval: 0,
+        ..RLConfig::default()
+    };
+    assert!(RLTrainer::new(InputEncoder::Features, config).is_err());
+    assert!(RLTrainer::new(InputEncoder::Features, RLConfig::default()).is_ok());
+}
@@ -0,0 +1,143 @@
+use crate::game::{hash::fnv1a, Action, Game, Pixel};
+use itertools::Itertools;
+
+// the other side of a networked game, whose inputs arrive some frames late. The game is
+// shown as if the other player did nothing since their last known frame, and whenever
+// inputs for a frame arrive it is simulated again from the last frame that was known for
+// sure (a rollback), which only works because the same inputs always give the same game.
+// Anything that depends on the other game, like garbage sent from it, should only look
+// at the confirmed game so that it never has to be taken back:
+//
+//     let mut remote = RemoteGame::new(Game::with_seed(seed));
+//     remote.advance(); // every local frame
+//     remote.confirm(&[Action::HardDrop]); // whenever the inputs of a frame arrive, in order
+//     draw(remote.game());
+//
+// nothing uses it yet, it's the groundwork for network versus games, which would run a
+// handshake::handshake over the connection (or a relay) first and then send each other
+// their inputs frame by frame and a checksum every now and then
+
+pub struct RemoteGame {
+    confirmed: Game, // every input up to here is known
+    predicted: Game, // confirmed followed by frames without inputs, what is shown
+}
+
+impl RemoteGame {
+    pub fn new(game: Game) -> Self {
+        Self {
+            confirmed: game.clone(),
+            predicted: game,
+        }
+    }
+
+    // a local frame passed, the other player is assumed to press nothing
+    pub fn advance(&mut self) {
+        self.predicted.iterate();
+    }
+
+    // the inputs of the next frame that wasn't confirmed yet, every frame has to be
+    // confirmed once and in order (most with no inputs at all), returns whether the shown
+    // game changed
+    pub fn confirm(&mut self, inputs: &[Action]) -> bool {
+        for &action in inputs {
+            self.confirmed.apply(action);
+        }
+        self.confirmed.iterate();
+        if self.confirmed.tick > self.predicted.tick {
+            // the other side is ahead
+            self.predicted = self.confirmed.clone();
+            return true;
+        }
+        if inputs.is_empty() {
+            // predicted correctly
+            return false;
+        }
+        let frames = self.predicted.tick - self.confirmed.tick;
+        self.predicted = self.confirmed.clone();
+        for _ in 0..frames {
+            self.predicted.iterate();
+        }
+        true
+    }
+
+    // frames shown that aren't confirmed yet
+    pub fn latency(&self) -> usize {
+        self.predicted.tick - self.confirmed.tick
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.predicted
+    }
+
+    pub fn confirmed(&self) -> &Game {
+        &self.confirmed
+    }
+}
+
+// for both sides to compare every now and then, equal games always have equal checksums
+// (on any platform) so a difference means the games went out of sync
+pub fn checksum(game: &Game) -> u64 {
+    let mut values = vec![
+        game.tick as u64,
+        game.points as u64,
+        game.cleared as u64,
+        game.pieces as u64,
+        game.fall,
+        game.board_hash,
+        game.hold.map_or(0, |id| id as u64 + 1),
+    ];
+    if let Some(falling) = game.falling.as_ref() {
+        let (x, y) = falling.pos;
+        let (id, mask_idx, lock_delay) = (falling.id, falling.mask_idx, falling.lock_delay);
+        values.extend(vec![
+            id as u64,
+            x as u64,
+            y as u64,
+            mask_idx as u64,
+            lock_delay as u64,
+        ]);
+    }
+    values.extend(game.piece_queue.iter().map(|id| id as u64));
+    // garbage and the colors of locked pieces aren't in the board hash
+    for row in game.board.iter() {
+        values.extend(row.iter().map(|&px| match px {
+            Pixel::Empty => 0,
+            Pixel::Full(id) => id as u64 + 1,
+            Pixel::Garbage => 8,
+        }));
+    }
+    let bytes = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect_vec();
+    fnv1a(&bytes)
+}
+
+#[test]
+fn test_late_inputs_give_the_same_game() {
+    let inputs = |frame: usize| match frame % 40 {
+        5 => vec![Action::MoveLeft, Action::RotateCW],
+        20 => vec![Action::HardDrop],
+        _ => vec![],
+    };
+    let mut local = Game::with_seed(3);
+    let mut remote = RemoteGame::new(Game::with_seed(3));
+    // the inputs of every frame arrive 6 frames later
+    for frame in 0..200 {
+        for &action in &inputs(frame) {
+            local.apply(action);
+        }
+        local.iterate();
+        remote.advance();
+        if frame >= 6 {
+            remote.confirm(&inputs(frame - 6));
+        }
+    }
+    assert_eq!(remote.latency(), 6);
+    for frame in 194..200 {
+        remote.confirm(&inputs(frame));
+    }
+    assert_eq!(remote.latency(), 0);
+    assert_eq!(checksum(remote.game()), checksum(&local));
+    assert!(local.pieces >= 4 && !local.is_over());
+}
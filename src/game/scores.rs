@@ -0,0 +1,271 @@
+use crate::game::{
+    hash::{hmac_sha256, to_hex},
+    Game, HoldMode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::Path,
+};
+
+// personal bests by mode, kept in a file between games (best.toml for play):
+//
+//     "classic" = 51230
+//     "srs, unlimited hold" = 80410
+//
+// a mode is everything about the rules that makes points easier or harder to get, games
+// with different rules don't compete with each other
+//
+// to compare with friends, everyone exports their bests to a JSON file signed with a key
+// the group shares and imports the others' into a leaderboard (leaderboard.json for the
+// scores command), which keeps the best of every player in every mode
+
+#[derive(Clone, Debug, Default)]
+pub struct BestScores {
+    points: HashMap<String, usize>, // by mode
+}
+
+// the rotation system followed by whatever else isn't the default, "classic" for the
+// default rules
+pub fn mode(game: &Game) -> String {
+    let mut parts = vec![game.rotation_system().name().to_string()];
+    if game.hold_mode() != HoldMode::Standard {
+        parts.push(format!("{} hold", game.hold_mode().name()));
+    }
+    if game.gravity != 1. {
+        parts.push(format!("gravity {}", game.gravity));
+    }
+    if game.delays() != (0, 0) {
+        let (entry, line_clear) = game.delays();
+        parts.push(format!("delays {} {}", entry, line_clear));
+    }
+    if game.lock_delay() != Game::LOCK_DELAY {
+        parts.push(format!("lock {}", game.lock_delay()));
+    }
+    if let Some(rise) = game.stack_rise() {
+        parts.push(format!("rise {}", rise.to_text()));
+    }
+    parts.join(", ")
+}
+
+impl BestScores {
+    pub fn best(&self, mode: &str) -> Option<usize> {
+        self.points.get(mode).copied()
+    }
+
+    // returns whether it is a new best
+    pub fn record(&mut self, mode: &str, points: usize) -> bool {
+        let new = self.best(mode).is_none_or(|best| points > best);
+        if new {
+            self.points.insert(mode.to_string(), points);
+        }
+        new
+    }
+}
+
+#[derive(From, Debug)]
+pub enum ScoresError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+    TomlWriteError(toml::ser::Error),
+    JsonError(serde_json::Error),
+    #[from(ignore)]
+    BadSignature(String), // player
+}
+
+pub type ScoresResult<T> = Result<T, ScoresError>;
+
+impl fmt::Display for ScoresError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to access best scores: {}", e),
+            Self::TomlError(e) => write!(f, "invalid best scores: {}", e),
+            Self::TomlWriteError(e) => write!(f, "failed to write best scores: {}", e),
+            Self::JsonError(e) => write!(f, "invalid exported scores: {}", e),
+            Self::BadSignature(player) => {
+                write!(f, "the scores of {} aren't signed with this key", player)
+            }
+        }
+    }
+}
+
+// no file yet is no scores yet
+pub fn load_scores<P: AsRef<Path>>(path: P) -> ScoresResult<BestScores> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(BestScores {
+            points: toml::from_str(&text)?,
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BestScores::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// overwrites!
+pub fn save_scores<P: AsRef<Path>>(path: P, scores: &BestScores) -> ScoresResult<()> {
+    fs::write(path, toml::to_string(&scores.points)?)?;
+    Ok(())
+}
+
+// someone's best scores as they are passed around, signed with the key of the group
+// comparing them, without it nobody can change the scores or make up new ones (anyone
+// who has it can though, so only share it with players you trust)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportedScores {
+    player: String,
+    scores: BTreeMap<String, usize>, // by mode, sorted so that the signature is stable
+    signature: String,
+}
+
+// hmac-sha256 of the player and the scores as JSON, in hex
+fn signature(key: &str, player: &str, scores: &BTreeMap<String, usize>) -> String {
+    let json = serde_json::to_string(&(player, scores)).expect("scores are valid JSON");
+    to_hex(&hmac_sha256(key.as_bytes(), json.as_bytes()))
+}
+
+impl ExportedScores {
+    pub fn new(key: &str, player: &str, scores: &BestScores) -> Self {
+        let scores = scores.points.clone().into_iter().collect();
+        Self {
+            player: player.to_string(),
+            signature: signature(key, player, &scores),
+            scores,
+        }
+    }
+
+    fn verify(&self, key: &str) -> ScoresResult<()> {
+        let expected = signature(key, &self.player, &self.scores);
+        // compared in full so the time taken doesn't tell how much of it was right
+        let differences = expected
+            .bytes()
+            .zip(self.signature.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if expected.len() == self.signature.len() && differences == 0 {
+            Ok(())
+        } else {
+            Err(ScoresError::BadSignature(self.player.clone()))
+        }
+    }
+}
+
+// overwrites!
+pub fn export_scores<P: AsRef<Path>>(path: P, scores: &ExportedScores) -> ScoresResult<()> {
+    fs::write(path, serde_json::to_string_pretty(scores)?)?;
+    Ok(())
+}
+
+// fails unless the scores are signed with the key
+pub fn import_scores<P: AsRef<Path>>(path: P, key: &str) -> ScoresResult<ExportedScores> {
+    let scores: ExportedScores = serde_json::from_str(&fs::read_to_string(path)?)?;
+    scores.verify(key)?;
+    Ok(scores)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Leaderboard {
+    players: BTreeMap<String, BTreeMap<String, usize>>, // mode to points by player
+}
+
+impl Leaderboard {
+    // keeps the better of the old and the new score of the player in every mode
+    pub fn merge(&mut self, exported: &ExportedScores) {
+        let scores = self.players.entry(exported.player.clone()).or_default();
+        for (mode, &points) in &exported.scores {
+            let best = scores.entry(mode.clone()).or_insert(points);
+            *best = (*best).max(points);
+        }
+    }
+
+    // every mode anyone played
+    pub fn modes(&self) -> Vec<&str> {
+        let mut modes = self
+            .players
+            .values()
+            .flat_map(|scores| scores.keys().map(|mode| mode.as_str()))
+            .collect::<Vec<_>>();
+        modes.sort_unstable();
+        modes.dedup();
+        modes
+    }
+
+    // best first
+    pub fn ranking(&self, mode: &str) -> Vec<(&str, usize)> {
+        let mut ranking = self
+            .players
+            .iter()
+            .filter_map(|(player, scores)| Some((player.as_str(), *scores.get(mode)?)))
+            .collect::<Vec<_>>();
+        ranking.sort_by_key(|&(player, points)| (std::cmp::Reverse(points), player));
+        ranking
+    }
+}
+
+// no file yet is an empty leaderboard
+pub fn load_leaderboard<P: AsRef<Path>>(path: P) -> ScoresResult<Leaderboard> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(serde_json::from_str(&text)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Leaderboard::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// overwrites!
+pub fn save_leaderboard<P: AsRef<Path>>(path: P, leaderboard: &Leaderboard) -> ScoresResult<()> {
+    fs::write(path, serde_json::to_string_pretty(leaderboard)?)?;
+    Ok(())
+}
+
+#[test]
+fn test_modes_keep_separate_bests() {
+    let mut game = Game::with_seed(0);
+    assert_eq!(mode(&game), "classic");
+    game.set_hold_mode(HoldMode::Disabled);
+    game.set_rotation_system(crate::game::RotationSystem::Srs);
+    assert_eq!(mode(&game), "srs, disabled hold");
+
+    let mut scores = BestScores::default();
+    assert!(scores.record("classic", 10));
+    assert!(!scores.record("classic", 10));
+    assert!(scores.record("srs", 5));
+    assert_eq!(scores.best("classic"), Some(10));
+    let text = toml::to_string(&scores.points).unwrap();
+    assert_eq!(
+        toml::from_str::<HashMap<_, _>>(&text).unwrap(),
+        scores.points
+    );
+}
+
+#[test]
+fn test_leaderboard_merges_signed_exports() {
+    let mut scores = BestScores::default();
+    scores.record("classic", 300);
+    scores.record("srs", 100);
+    let mut ours = ExportedScores::new("club", "ann", &scores);
+    assert!(ours.verify("club").is_ok());
+    assert!(matches!(
+        ours.verify("other"),
+        Err(ScoresError::BadSignature(_))
+    ));
+    let mut leaderboard = Leaderboard::default();
+    leaderboard.merge(&ours);
+    scores.record("classic", 500);
+    leaderboard.merge(&ExportedScores::new("club", "bo", &scores));
+    leaderboard.merge(&ExportedScores::new("club", "ann", &BestScores::default()));
+    assert_eq!(
+        leaderboard.ranking("classic"),
+        vec![("bo", 500), ("ann", 300)]
+    );
+    assert_eq!(leaderboard.modes(), vec!["classic", "srs"]);
+
+    // an edited score can't be signed again without the key
+    ours.scores.insert("classic".to_string(), 900);
+    assert!(matches!(
+        ours.verify("club"),
+        Err(ScoresError::BadSignature(_))
+    ));
+    ours.signature = signature("", "ann", &ours.scores);
+    assert!(matches!(
+        ours.verify("club"),
+        Err(ScoresError::BadSignature(_))
+    ));
+}
@@ -0,0 +1,200 @@
+use crate::{
+    game::{
+        evaluation::Noise,
+        features::InputEncoder,
+        nn_trainer::{choose_placement, TrainerConfig},
+        placement::Placement,
+        rating::Ratings,
+        versus::{play_match, Outcome},
+        Game,
+    },
+    neural_network::NN,
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+// who plays whom in a generation
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum Pairing {
+    // every genome against every other one once
+    RoundRobin,
+    // each round pairs genomes with the same score so far that haven't met yet, far fewer
+    // matches than a round robin for a large generation
+    Swiss { rounds: usize },
+}
+
+// genomes are evaluated by playing versus matches against each other instead of solo
+// games, fitness is the mean over a genome's matches of win for a win (half for a draw)
+// plus attack for every garbage row sent, so that both surviving and attacking pay
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct VersusConfig {
+    pub pairing: Pairing,
+    pub max_pieces: usize, // per side, a match that lasts longer is a draw
+    pub win: f64,
+    pub attack: f64,
+}
+
+impl Default for VersusConfig {
+    fn default() -> Self {
+        Self {
+            pairing: Pairing::RoundRobin,
+            max_pieces: 300,
+            win: 100.,
+            attack: 5.,
+        }
+    }
+}
+
+// (left, right) genome indices of every match in a round robin, the left side moves first
+// so who plays it alternates, every genome is on the left in half its matches (give or
+// take one)
+fn round_robin(n: usize) -> Vec<(usize, usize)> {
+    (0..n)
+        .flat_map(|a| {
+            (a + 1..n).map(move |b| match (a + b) % 2 {
+                1 => (a, b),
+                _ => (b, a),
+            })
+        })
+        .collect()
+}
+
+// pairs neighbours in the order of score, skipping pairs that have met unless everyone left
+// has, whoever is left over sits the round out
+fn swiss_round(scores: &[f64], met: &HashSet<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut order = (0..scores.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    let mut pairs = vec![];
+    while order.len() >= 2 {
+        let a = order.remove(0);
+        let i = order
+            .iter()
+            .position(|&b| !met.contains(&(a.min(b), a.max(b))))
+            .unwrap_or(0);
+        pairs.push((a, order.remove(i)));
+    }
+    pairs
+}
+
+// a genome playing like NNBot
+fn nn_bot(nn: &NN, encoder: InputEncoder) -> impl FnMut(&Game) -> Option<Placement> + '_ {
+    move |game: &Game| choose_placement(nn, encoder, &Noise::default(), game)
+}
+
+// fitness of every genome of the generation from versus matches against the others, seeds
+// are derived from first_seed so that every generation plays different pieces, every match
+// is also rated with the genomes known by their lineage ids
+pub fn versus_fitness(
+    generation: &[NN],
+    ids: &[usize],
+    ratings: &mut Ratings,
+    config: &TrainerConfig,
+    versus: VersusConfig,
+    first_seed: u64,
+    label: &str,
+) -> Vec<f64> {
+    let n = generation.len();
+    let mut score = vec![0.; n];
+    let mut matches = vec![0; n];
+    let mut play = |round: u64, pairs: Vec<(usize, usize)>, score: &mut Vec<f64>| {
+        for (i, &(a, b)) in pairs.iter().enumerate() {
+            let seed = first_seed
+                .wrapping_mul(1_000_003)
+                .wrapping_add(round * n as u64 + i as u64);
+            let result = play_match(
+                seed,
+                &mut nn_bot(&generation[a], config.encoder),
+                &mut nn_bot(&generation[b], config.encoder),
+                versus.max_pieces,
+            );
+            let (win_a, win_b) = match result.outcome {
+                Outcome::Left => (1., 0.),
+                Outcome::Right => (0., 1.),
+                Outcome::Draw => (0.5, 0.5),
+            };
+            score[a] += versus.win * win_a + versus.attack * result.sent.0 as f64;
+            score[b] += versus.win * win_b + versus.attack * result.sent.1 as f64;
+            matches[a] += 1;
+            matches[b] += 1;
+            let name = |i: usize| format!("genome {}", ids[i]);
+            ratings.record(&name(a), &name(b), result.outcome);
+            println!(
+                "{}genome {} vs genome {}: {:?}, sent {} and {}",
+                label, a, b, result.outcome, result.sent.0, result.sent.1
+            );
+        }
+    };
+    match versus.pairing {
+        Pairing::RoundRobin => play(0, round_robin(n), &mut score),
+        Pairing::Swiss { rounds } => {
+            let mut met = HashSet::new();
+            for round in 0..rounds {
+                let mut pairs = swiss_round(&score, &met);
+                // the better genome of each pair is on the left every other round
+                if round % 2 == 1 {
+                    pairs = pairs.into_iter().map(|(a, b)| (b, a)).collect();
+                }
+                met.extend(pairs.iter().map(|&(a, b)| (a.min(b), a.max(b))));
+                play(round as u64, pairs, &mut score);
+            }
+        }
+    }
+    score
+        .into_iter()
+        .zip(matches)
+        .map(|(score, matches)| score / matches.max(1) as f64)
+        .collect()
+}
+
+#[test]
+fn test_pairings() {
+    assert_eq!(round_robin(4).len(), 6);
+    let pairs = round_robin(5);
+    for genome in 0..5 {
+        assert_eq!(pairs.iter().filter(|&&(left, _)| left == genome).count(), 2);
+    }
+    // the two leaders meet, then the next ones
+    let pairs = swiss_round(&[1., 3., 0., 2.], &HashSet::new());
+    assert_eq!(pairs, vec![(1, 3), (0, 2)]);
+    // unless they've met already, the odd one out sits out
+    let met = [(1, 3)].iter().copied().collect();
+    assert_eq!(
+        swiss_round(&[1., 3., 0., 2., 5.], &met),
+        vec![(4, 1), (3, 0)]
+    );
+}
+
+#[test]
+fn test_versus_fitness_rewards_winning() {
+    use crate::neural_network::ActivationType;
+    let config = TrainerConfig::default();
+    let generation = (0..3)
+        .map(|_| {
+            NN::make(config.encoder.input_size())
+                .add_layer(1, ActivationType::Sigmoid)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let versus = VersusConfig {
+        max_pieces: 40,
+        attack: 0.,
+        ..VersusConfig::default()
+    };
+    let mut ratings = Ratings::default();
+    let fitness = versus_fitness(
+        &generation,
+        &[7, 8, 9],
+        &mut ratings,
+        &config,
+        versus,
+        0,
+        "",
+    );
+    // every match hands out exactly one win between its two sides
+    let total = fitness.iter().sum::<f64>() * 2.;
+    assert!((total - 3. * versus.win).abs() < 1e-9, "{:?}", fitness);
+    assert_eq!(ratings.rating("genome 7").games, 2);
+}
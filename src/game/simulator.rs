@@ -0,0 +1,272 @@
+use crate::game::{
+    replay::{InputReplay, LockRecord},
+    Action, Controller, Game, GameEvent, HoldMode, PieceId, Pixel, RotationSystem, GAME_HEIGHT,
+    GAME_WIDTH,
+};
+
+// headless access to the engine for scripts, fuzzers and other projects, nothing in here
+// needs a window or the rest of the crate
+//
+//     let mut sim = Simulator::new(GameConfig::default(), 42);
+//     sim.input(Action::HardDrop);
+//     sim.step();
+//     assert_eq!(sim.score().pieces, 1);
+//
+// the game advances one frame per step, queued inputs are applied one per frame (like a
+// bot's) and apply bypasses the queue, the same seed always deals the same pieces
+
+#[derive(Copy, Clone, Debug)]
+pub struct GameConfig {
+    // multiplier for the fall speed of the current level, 0 stops pieces from falling on
+    // their own so that only inputs move them
+    pub gravity: f32,
+    pub hold: HoldMode,
+    // frames between a lock and the next spawn, and extra ones when lines were cleared,
+    // shifts and rotations in between are applied to the next piece
+    pub entry_delay: usize,
+    pub line_clear_delay: usize,
+    // frames a piece can rest on something before it locks, Game::LOCK_DELAY by default
+    pub lock_delay: usize,
+    // the masks and wall kicks, masks.txt by default
+    pub rotation: RotationSystem,
+    // collect GameEvents, see Simulator::take_events
+    pub events: bool,
+    // remember every locked piece and every input, see Simulator::recording and
+    // Simulator::input_replay
+    pub record: bool,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            gravity: 1.,
+            hold: HoldMode::Standard,
+            entry_delay: 0,
+            line_clear_delay: 0,
+            lock_delay: Game::LOCK_DELAY,
+            rotation: RotationSystem::Classic,
+            events: true,
+            record: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Cell {
+    Empty,
+    Piece(PieceId), // locked
+    Garbage,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Score {
+    pub points: usize,
+    pub level: usize,
+    pub cleared: usize, // rows
+    pub pieces: usize,  // locked so far
+    pub frames: usize,
+}
+
+pub struct Simulator {
+    game: Game,
+}
+
+impl Simulator {
+    pub fn new(config: GameConfig, seed: u64) -> Self {
+        let mut game = Game::with_seed(seed);
+        game.set_gravity(config.gravity);
+        game.set_hold_mode(config.hold);
+        game.set_delays(config.entry_delay, config.line_clear_delay);
+        game.set_lock_delay(config.lock_delay);
+        game.set_rotation_system(config.rotation);
+        if config.events {
+            game.subscribe_events();
+        }
+        if config.record {
+            game.start_recording();
+            game.start_input_log();
+        }
+        Self { game }
+    }
+
+    // runs the recorded game to the end of the recording, with events and recording
+    pub fn replay(replay: &InputReplay) -> Self {
+        let config = GameConfig {
+            gravity: replay.gravity,
+            hold: replay.hold,
+            entry_delay: replay.delays.0,
+            line_clear_delay: replay.delays.1,
+            lock_delay: replay.lock_delay,
+            rotation: replay.rotation,
+            events: true,
+            record: true,
+        };
+        let mut sim = Self::new(config, replay.seed);
+        sim.game.set_stack_rise(replay.rise);
+        let mut inputs = replay.inputs.iter().peekable();
+        loop {
+            // inputs applied by the queue were applied during the frame, right after
+            // clearing lines, which is no different from right before it
+            while let Some((_, action)) = inputs.next_if(|&&(tick, _)| tick == sim.game.tick) {
+                sim.game.apply(*action);
+            }
+            if sim.game.tick >= replay.frames || sim.game.is_over() {
+                break;
+            }
+            sim.game.iterate();
+        }
+        sim
+    }
+
+    // applied on one of the next frames, in order
+    pub fn input(&mut self, action: Action) {
+        self.game.queue_action(action)
+    }
+
+    // applied right away, before any queued input
+    pub fn apply(&mut self, action: Action) {
+        self.game.apply(action)
+    }
+
+    pub fn has_queued_inputs(&self) -> bool {
+        self.game.has_queued_actions()
+    }
+
+    // a single frame, does nothing once the game is over
+    pub fn step(&mut self) {
+        self.game.iterate()
+    }
+
+    // stops early if the game ends
+    pub fn step_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            if self.game.is_over() {
+                break;
+            }
+            self.game.iterate();
+        }
+    }
+
+    // steps until every queued input has been applied (or the game ends)
+    pub fn step_until_idle(&mut self) {
+        while self.game.has_queued_actions() && !self.game.is_over() {
+            self.game.iterate();
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.game.is_over()
+    }
+
+    pub fn score(&self) -> Score {
+        Score {
+            points: self.game.points,
+            level: self.game.level,
+            cleared: self.game.cleared,
+            pieces: self.game.pieces,
+            frames: self.game.tick,
+        }
+    }
+
+    // events since the last call, empty unless GameConfig::events is set
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        self.game.take_events()
+    }
+
+    // x from the left, y from the top, without the falling piece
+    pub fn cell(&self, x: usize, y: usize) -> Cell {
+        match self.game.board[y][x] {
+            Pixel::Empty => Cell::Empty,
+            Pixel::Full(id) => Cell::Piece(id),
+            Pixel::Garbage => Cell::Garbage,
+        }
+    }
+
+    // GAME_HEIGHT rows of GAME_WIDTH cells, top row first
+    pub fn board(&self) -> Vec<Vec<Cell>> {
+        (0..GAME_HEIGHT)
+            .map(|y| (0..GAME_WIDTH).map(|x| self.cell(x, y)).collect())
+            .collect()
+    }
+
+    // the falling piece and the (x, y) of its four cells
+    pub fn falling(&self) -> Option<(PieceId, Vec<(usize, usize)>)> {
+        let falling = self.game.falling.as_ref()?;
+        let cells = falling.mask.cells_at(falling.pos);
+        Some((
+            falling.id,
+            cells.map(|(x, y)| (x as usize, y as usize)).collect(),
+        ))
+    }
+
+    pub fn hold(&self) -> Option<PieceId> {
+        self.game.hold
+    }
+
+    // the next pieces, in order
+    pub fn queue(&self) -> Vec<PieceId> {
+        self.game.piece_queue.iter().collect()
+    }
+
+    // the board as text, one line per row, '.' for empty cells, '#' for garbage and the
+    // piece letter otherwise (lowercase for the falling piece)
+    pub fn board_string(&self) -> String {
+        let mut rows = self
+            .game
+            .board_to_ascii()
+            .lines()
+            .map(|line| line.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if let Some((id, cells)) = self.falling() {
+            for (x, y) in cells {
+                rows[y][x] = id.to_char().to_ascii_lowercase();
+            }
+        }
+        rows.into_iter()
+            .map(|row| row.into_iter().chain(Some('\n')).collect::<String>())
+            .collect()
+    }
+
+    // every locked piece so far if GameConfig::record is set, for replay::save_replay
+    pub fn recording(&self) -> Option<&[LockRecord]> {
+        self.game.recording()
+    }
+
+    // every input so far if GameConfig::record is set
+    pub fn input_replay(&self) -> Option<InputReplay> {
+        self.game.input_replay()
+    }
+
+    // everything the simulator doesn't cover, e.g. for bots or the replay functions
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn game_mut(&mut self) -> &mut Game {
+        &mut self.game
+    }
+}
+
+#[test]
+fn test_simulator_is_deterministic() {
+    let play = || {
+        let mut sim = Simulator::new(GameConfig::default(), 7);
+        for _ in 0..5 {
+            sim.input(Action::MoveLeft);
+            sim.input(Action::HardDrop);
+            sim.step_until_idle();
+        }
+        sim.step_frames(10);
+        (sim.board_string(), sim.score(), sim.take_events())
+    };
+    let (board, score, events) = play();
+    assert_eq!(score.pieces, 5);
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| matches!(e, GameEvent::PieceLocked(_)))
+            .count(),
+        5
+    );
+    assert_eq!((board, score, events), play());
+}
@@ -0,0 +1,547 @@
+use crate::game::{
+    bot::{BoardView, BotMove, TetrisBot},
+    clear_lines,
+    placement::{occupied, placements, Placement},
+    Action, Board, Game, PieceId, Pixel, GAME_HEIGHT, GAME_WIDTH,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+// the Tetris Bot Protocol (https://github.com/tetris-bot-protocol/tbp-spec), one JSON
+// message per line, the frontend writes to the bot's stdin and reads from its stdout.
+// Pieces and cells are single letters ('G' for garbage), the board has 40 rows with
+// row 0 at the bottom and pieces are located by their SRS rotation center
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules {},
+    Start {
+        hold: Option<char>,
+        queue: Vec<char>, // starts with the current piece
+        combo: u32,
+        back_to_back: bool,
+        board: Vec<Vec<Option<char>>>,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    NewPiece {
+        piece: char,
+    },
+    Stop,
+    Quit,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+struct Location {
+    #[serde(rename = "type")]
+    piece: char,
+    orientation: Orientation,
+    x: isize,
+    y: isize,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+struct Move {
+    location: Location,
+    spin: Spin,
+}
+
+// only reported, placements are matched by their cells
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+const BOARD_ROWS: usize = 40;
+const ORIENTATIONS: [Orientation; 4] = [
+    Orientation::North,
+    Orientation::East,
+    Orientation::South,
+    Orientation::West,
+];
+
+#[derive(From, Debug)]
+pub enum TbpError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+    Protocol(String),
+}
+
+pub type TbpResult<T> = Result<T, TbpError>;
+
+impl fmt::Display for TbpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "connection to the bot failed: {}", e),
+            Self::JsonError(e) => write!(f, "invalid message: {}", e),
+            Self::Protocol(e) => write!(f, "unexpected message: {}", e),
+        }
+    }
+}
+
+// SRS cells of the piece facing north relative to its rotation center, y up
+fn north_offsets(id: PieceId) -> [(isize, isize); 4] {
+    use PieceId::*;
+    match id {
+        IBlock => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        OBlock => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        TBlock => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        LBlock => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        JBlock => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        SBlock => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        ZBlock => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    }
+}
+
+// board coordinates (y down from the top of the visible board) of a located piece
+fn location_cells(
+    id: PieceId,
+    orientation: Orientation,
+    (x, y): (isize, isize),
+) -> Vec<(isize, isize)> {
+    let mut cells = north_offsets(id)
+        .iter()
+        .map(|&(dx, dy)| match orientation {
+            Orientation::North => (dx, dy),
+            Orientation::East => (dy, -dx),
+            Orientation::South => (-dx, -dy),
+            Orientation::West => (-dy, dx),
+        })
+        .map(|(dx, dy)| (x + dx, GAME_HEIGHT as isize - 1 - (y + dy)))
+        .collect::<Vec<_>>();
+    cells.sort_unstable();
+    cells
+}
+
+// the location of a piece occupying the given board cells
+fn to_location(id: PieceId, cells: &[(isize, isize)]) -> Option<Location> {
+    let mut cells = cells.to_vec();
+    cells.sort_unstable();
+    let (x, y) = cells[0];
+    let y = GAME_HEIGHT as isize - 1 - y;
+    ORIENTATIONS.iter().find_map(|&orientation| {
+        // the center is at the same offset from every cell, try them all against the first
+        (-2..=2)
+            .flat_map(|dx| (-2..=2).map(move |dy| (x + dx, y + dy)))
+            .find(|&center| location_cells(id, orientation, center) == cells)
+            .map(|(x, y)| Location {
+                piece: id.to_char(),
+                orientation,
+                x,
+                y,
+            })
+    })
+}
+
+fn piece(c: char) -> TbpResult<PieceId> {
+    PieceId::from_char(c).ok_or_else(|| TbpError::Protocol(format!("unknown piece '{}'", c)))
+}
+
+fn board_to_rows(board: &Board) -> Vec<Vec<Option<char>>> {
+    (0..BOARD_ROWS)
+        .map(|row| {
+            if row >= GAME_HEIGHT {
+                return vec![None; GAME_WIDTH];
+            }
+            board[GAME_HEIGHT - 1 - row]
+                .iter()
+                .map(|px| match px {
+                    Pixel::Empty => None,
+                    Pixel::Full(id) => Some(id.to_char()),
+                    Pixel::Garbage => Some('G'),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// anything above the visible board is dropped
+fn board_from_rows(rows: &[Vec<Option<char>>]) -> TbpResult<Board> {
+    let mut board = [[Pixel::Empty; GAME_WIDTH]; GAME_HEIGHT];
+    for (row, cells) in rows.iter().take(GAME_HEIGHT).enumerate() {
+        for (x, cell) in cells.iter().take(GAME_WIDTH).enumerate() {
+            board[GAME_HEIGHT - 1 - row][x] = match cell {
+                None => Pixel::Empty,
+                Some('G') => Pixel::Garbage,
+                Some(c) => Pixel::Full(piece(*c)?),
+            };
+        }
+    }
+    Ok(board)
+}
+
+// a game in the given state, None if the current piece doesn't fit
+fn game_from_state(board: Board, queue: &VecDeque<PieceId>, hold: Option<PieceId>) -> Option<Game> {
+    let mut game = Game::new();
+    game.set_board(board);
+    game.hold = hold;
+    game.piece_queue.queue = queue.iter().skip(1).copied().collect();
+    game.spawn_with_id(*queue.front()?);
+    if game.is_over() {
+        None
+    } else {
+        Some(game)
+    }
+}
+
+// the placement (through hold if necessary) that puts the piece where the move says
+fn to_placement(game: &Game, mv: &Move) -> Option<Placement> {
+    let id = PieceId::from_char(mv.location.piece)?;
+    let location = (mv.location.x, mv.location.y);
+    let cells = location_cells(id, mv.location.orientation, location);
+    let matching = |game: &Game| {
+        placements(game).into_iter().find(|p| {
+            let mut occupied = occupied(&game.mask_map[&id][p.mask_idx], p.pos);
+            occupied.sort_unstable();
+            occupied == cells
+        })
+    };
+    let current = game.falling.as_ref()?.id;
+    if current == id {
+        return matching(game);
+    }
+    if !game.can_hold() {
+        return None;
+    }
+    // the piece is the held one or, with an empty hold, the next one
+    let queue = Some(current)
+        .into_iter()
+        .chain(game.piece_queue.iter())
+        .collect();
+    let mut held = game_from_state(game.board, &queue, game.hold)?;
+    held.switch_hold();
+    if held.falling.as_ref()?.id != id {
+        return None;
+    }
+    matching(&held).map(|mut p| {
+        p.inputs.insert(0, Action::Hold);
+        p
+    })
+}
+
+fn to_move(game: &Game, placement: &Placement) -> Option<Move> {
+    let id = game.falling.as_ref()?.id;
+    let cells = occupied(&game.mask_map[&id][placement.mask_idx], placement.pos);
+    Some(Move {
+        location: to_location(id, &cells)?,
+        spin: Spin::None,
+    })
+}
+
+// how long a bot gets to quit on its own before it's killed
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// a command line as words, quotes ('...' or "...") keep spaces in a word and a backslash
+// (outside single quotes) takes the next character as it is, like a shell does it
+pub fn split_command(command: &str) -> TbpResult<Vec<String>> {
+    let unterminated = || TbpError::Protocol(format!("unterminated quote in {}", command));
+    let mut words = vec![];
+    let mut word = None::<String>; // None between words
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => word.push(chars.next().ok_or_else(unterminated)?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().unwrap_or('\\');
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+// an external bot playing in this client, started with the given command line
+pub struct TbpBot {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    pub name: String,
+}
+
+impl TbpBot {
+    // the program followed by its arguments, see split_command for a command line
+    pub fn spawn(command: &[String]) -> TbpResult<Self> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| TbpError::Protocol("empty bot command".to_string()))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut bot = Self {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: BufReader::new(child.stdout.take().expect("stdout is piped")),
+            child,
+            name: String::new(),
+        };
+        match bot.receive()? {
+            BotMessage::Info { name, version, .. } => bot.name = format!("{} {}", name, version),
+            m => return Err(TbpError::Protocol(format!("expected info, got {:?}", m))),
+        }
+        bot.send(&FrontendMessage::Rules {})?;
+        match bot.receive()? {
+            BotMessage::Ready => Ok(bot),
+            m => Err(TbpError::Protocol(format!("expected ready, got {:?}", m))),
+        }
+    }
+
+    fn send(&mut self, message: &FrontendMessage) -> TbpResult<()> {
+        writeln!(self.stdin, "{}", serde_json::to_string(message)?)?;
+        Ok(self.stdin.flush()?)
+    }
+
+    fn receive(&mut self) -> TbpResult<BotMessage> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(TbpError::Protocol("bot closed its output".to_string()));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    // the bot starts from scratch for every piece, so nothing has to be kept in sync,
+    // the first suggested move that can be reached here is taken
+    pub fn suggest(&mut self, game: &Game) -> TbpResult<Option<Placement>> {
+        let current = match &game.falling {
+            Some(falling) => falling.id,
+            None => return Ok(None),
+        };
+        self.send(&FrontendMessage::Start {
+            hold: game.hold.map(PieceId::to_char),
+            queue: Some(current)
+                .into_iter()
+                .chain(game.piece_queue.iter())
+                .map(PieceId::to_char)
+                .collect(),
+            combo: 0,
+            back_to_back: false,
+            board: board_to_rows(&game.board),
+        })?;
+        self.send(&FrontendMessage::Suggest)?;
+        let moves = match self.receive()? {
+            BotMessage::Suggestion { moves } => moves,
+            m => {
+                return Err(TbpError::Protocol(format!(
+                    "expected suggestion, got {:?}",
+                    m
+                )))
+            }
+        };
+        self.send(&FrontendMessage::Stop)?;
+        Ok(moves.iter().find_map(|mv| to_placement(game, mv)))
+    }
+}
+
+// stops playing on the first error
+impl TetrisBot for TbpBot {
+    fn choose(&mut self, view: &BoardView) -> BotMove {
+        self.suggest(view.game)
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                None
+            })
+            .into()
+    }
+}
+
+// a bot that doesn't quit when asked to is killed, so that it can't keep this one running
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        let _ = self.send(&FrontendMessage::Quit);
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while let Ok(None) = self.child.try_wait() {
+            if Instant::now() > deadline {
+                let _ = self.child.kill();
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.wait();
+    }
+}
+
+// play as a TBP bot on stdin and stdout, the bot should only ever place the current piece
+pub fn serve(name: &str, bot: &mut dyn TetrisBot) -> TbpResult<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut send = |message: &BotMessage| -> TbpResult<()> {
+        writeln!(stdout, "{}", serde_json::to_string(message)?)?;
+        Ok(stdout.flush()?)
+    };
+    send(&BotMessage::Info {
+        name: name.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        author: env!("CARGO_PKG_AUTHORS").to_string(),
+        features: vec![],
+    })?;
+
+    // the state of the current game, updated with every move played
+    let mut board = [[Pixel::Empty; GAME_WIDTH]; GAME_HEIGHT];
+    let mut queue = VecDeque::new();
+    let mut hold = None;
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line)? {
+            FrontendMessage::Rules {} => send(&BotMessage::Ready)?,
+            FrontendMessage::Start {
+                hold: h,
+                queue: q,
+                board: b,
+                ..
+            } => {
+                board = board_from_rows(&b)?;
+                queue = q.into_iter().map(piece).collect::<TbpResult<_>>()?;
+                hold = h.map(piece).transpose()?;
+            }
+            FrontendMessage::Suggest => {
+                let moves = game_from_state(board, &queue, hold)
+                    .and_then(|game| {
+                        let placement = bot.choose(&BoardView::new(&game)).placement()?;
+                        to_move(&game, &placement)
+                    })
+                    .into_iter()
+                    .collect();
+                send(&BotMessage::Suggestion { moves })?;
+            }
+            FrontendMessage::Play { mv } => {
+                let id = piece(mv.location.piece)?;
+                let location = (mv.location.x, mv.location.y);
+                for (x, y) in location_cells(id, mv.location.orientation, location) {
+                    if (0..GAME_WIDTH as isize).contains(&x)
+                        && (0..GAME_HEIGHT as isize).contains(&y)
+                    {
+                        board[y as usize][x as usize] = Pixel::Full(id);
+                    }
+                }
+                clear_lines(&mut board);
+                // a piece other than the current one was played through hold
+                let current = queue.pop_front();
+                if current != Some(id) {
+                    if hold.is_none() {
+                        queue.pop_front();
+                    }
+                    hold = current;
+                }
+            }
+            FrontendMessage::NewPiece { piece: p } => queue.push_back(piece(p)?),
+            FrontendMessage::Stop => queue.clear(),
+            FrontendMessage::Quit => break,
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_locations_roundtrip() {
+    use crate::game::heuristic::HeuristicBot;
+    let bot = HeuristicBot::new();
+    let mut game = Game::new();
+    for _ in 0..30 {
+        let placement = bot.choose(&game).unwrap();
+        let mv = to_move(&game, &placement).unwrap();
+        let json = serde_json::to_string(&mv).unwrap();
+        let back = to_placement(&game, &serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(
+            (back.mask_idx, back.pos),
+            (placement.mask_idx, placement.pos)
+        );
+        for action in placement.inputs {
+            game.apply(action);
+        }
+    }
+}
+
+#[test]
+fn test_bot_commands() {
+    let words = |command| split_command(command).unwrap();
+    assert_eq!(words("  cold-clear  --fast "), vec!["cold-clear", "--fast"]);
+    assert_eq!(
+        words(r#""/opt/my bots/bot" --name 'a b' x\ y "say \"hi\"""#),
+        vec!["/opt/my bots/bot", "--name", "a b", "x y", "say \"hi\""]
+    );
+    assert_eq!(words("''"), vec![""]);
+    assert!(words("").is_empty());
+    assert!(split_command("'open").is_err());
+
+    // a bot that never answers is killed when it's dropped
+    let mut child = Command::new("sleep")
+        .arg("60")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let bot = TbpBot {
+        stdin: child.stdin.take().unwrap(),
+        stdout: BufReader::new(child.stdout.take().unwrap()),
+        child,
+        name: String::new(),
+    };
+    let start = Instant::now();
+    drop(bot);
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,40 @@
+use std::{
+    env, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+};
+
+// a directory of its own for a test, deleted with everything in it when the test ends,
+// whether it passed or not
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    // <temp>/tetris_<name>_<pid>, created empty
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("tetris_{}_{}", name, process::id()));
+        fs::remove_dir_all(&path).ok(); // left over from a run that was killed
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
@@ -0,0 +1,206 @@
+use crate::game::{render::Color, PieceId};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+// piece colors, everything that draws a piece asks PieceId::color which asks the current
+// theme, a file (see theme.toml) starts from a preset and can recolor single pieces:
+//
+//     preset = "guideline"
+//     [colors]
+//     T = [160, 0, 240]
+//
+// and can put something behind the board, see Background:
+//
+//     [background]
+//     image = "space.png"
+//     starfield = true
+//     dim = 0.6
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Classic, // this game's own colors
+    Guideline, // the standard colors, exactly
+}
+
+impl Preset {
+    fn color(self, id: PieceId) -> Color {
+        use PieceId::*;
+        match self {
+            Self::Classic => match id {
+                IBlock => Color::rgb(88, 176, 188),
+                JBlock => Color::rgb(22, 101, 167),
+                LBlock => Color::rgb(217, 133, 1),
+                OBlock => Color::rgb(235, 214, 1),
+                SBlock => Color::rgb(55, 154, 48),
+                TBlock => Color::rgb(137, 64, 135),
+                ZBlock => Color::rgb(205, 12, 17),
+            },
+            Self::Guideline => match id {
+                IBlock => Color::rgb(0, 255, 255),
+                JBlock => Color::rgb(0, 0, 255),
+                LBlock => Color::rgb(255, 127, 0),
+                OBlock => Color::rgb(255, 255, 0),
+                SBlock => Color::rgb(0, 255, 0),
+                TBlock => Color::rgb(128, 0, 128),
+                ZBlock => Color::rgb(255, 0, 0),
+            },
+        }
+    }
+}
+
+// an image stretched over the window and/or drifting stars on top of it, both darkened by
+// dim (0 leaves them as they are, 1 makes them black) so that the pieces stand out
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Background {
+    #[serde(default)]
+    pub image: Option<PathBuf>, // relative to where the game is started
+    #[serde(default)]
+    pub starfield: bool,
+    #[serde(default = "default_dim")]
+    pub dim: f32,
+}
+
+fn default_dim() -> f32 {
+    0.5
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Theme {
+    preset: Preset,
+    colors: HashMap<PieceId, Color>, // overrides
+    background: Option<Background>,
+}
+
+impl Theme {
+    pub fn color(&self, id: PieceId) -> Color {
+        self.colors
+            .get(&id)
+            .copied()
+            .unwrap_or_else(|| self.preset.color(id))
+    }
+
+    // None for plain black
+    pub fn background(&self) -> Option<&Background> {
+        self.background.as_ref()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    #[serde(default)]
+    preset: Preset,
+    #[serde(default)]
+    colors: HashMap<String, [u8; 3]>, // by piece letter
+    background: Option<Background>,
+}
+
+#[derive(From, Debug)]
+pub enum ThemeLoadError {
+    IoError(io::Error),
+    TomlError(toml::de::Error),
+    #[from(ignore)]
+    UnknownPiece(String),
+    #[from(ignore)]
+    BadDim(f32),
+}
+
+pub type ThemeLoadResult<T> = Result<T, ThemeLoadError>;
+
+impl fmt::Display for ThemeLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read theme: {}", e),
+            Self::TomlError(e) => write!(f, "invalid theme: {}", e),
+            Self::UnknownPiece(name) => write!(f, "no piece is called \"{}\"", name),
+            Self::BadDim(dim) => write!(f, "dim {} is not between 0 and 1", dim),
+        }
+    }
+}
+
+fn parse_theme(text: &str) -> ThemeLoadResult<Theme> {
+    let file: ThemeFile = toml::from_str(text)?;
+    let colors = file
+        .colors
+        .into_iter()
+        .map(|(name, [r, g, b])| {
+            let mut chars = name.chars();
+            match (chars.next().and_then(PieceId::from_char), chars.next()) {
+                (Some(id), None) => Ok((id, Color::rgb(r, g, b))),
+                _ => Err(ThemeLoadError::UnknownPiece(name)),
+            }
+        })
+        .collect::<ThemeLoadResult<_>>()?;
+    if let Some(background) = &file.background {
+        if !(0. ..=1.).contains(&background.dim) {
+            return Err(ThemeLoadError::BadDim(background.dim));
+        }
+    }
+    Ok(Theme {
+        preset: file.preset,
+        colors,
+        background: file.background,
+    })
+}
+
+pub fn load_theme<P: AsRef<Path>>(path: P) -> ThemeLoadResult<Theme> {
+    parse_theme(&fs::read_to_string(path)?)
+}
+
+// None until the first piece is drawn or a theme is set
+static THEME: RwLock<Option<Arc<Theme>>> = RwLock::new(None);
+
+// replaces theme.toml or the previous theme, anything drawn afterwards uses it
+pub fn set_theme(theme: Theme) {
+    *THEME.write().unwrap() = Some(Arc::new(theme));
+}
+
+// theme.toml if it can be loaded, the classic colors otherwise, only loaded (and warned
+// about) once
+pub(super) fn current() -> Arc<Theme> {
+    if let Some(theme) = THEME.read().unwrap().as_ref() {
+        return theme.clone();
+    }
+    let loaded = load_theme("theme.toml").unwrap_or_else(|e| {
+        eprintln!("Warning: {}, using the classic colors", e);
+        Theme::default()
+    });
+    THEME
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(loaded))
+        .clone()
+}
+
+#[test]
+fn test_theme_overrides_preset() {
+    let theme = parse_theme("preset = \"guideline\"\n[colors]\nT = [1, 2, 3]").unwrap();
+    assert_eq!(theme.color(PieceId::TBlock), Color::rgb(1, 2, 3));
+    assert_eq!(theme.color(PieceId::ZBlock), Color::rgb(255, 0, 0));
+    assert!(matches!(
+        parse_theme("[colors]\nTBlock = [1, 2, 3]"),
+        Err(ThemeLoadError::UnknownPiece(_))
+    ));
+    assert!(parse_theme("preset = \"neon\"").is_err());
+}
+
+#[test]
+fn test_theme_background() {
+    assert_eq!(parse_theme("").unwrap().background(), None);
+    let theme = parse_theme("[background]\nstarfield = true").unwrap();
+    let background = theme.background().unwrap();
+    assert!(background.starfield && background.image.is_none());
+    assert_eq!(background.dim, 0.5);
+    assert!(matches!(
+        parse_theme("[background]\ndim = 1.5"),
+        Err(ThemeLoadError::BadDim(_))
+    ));
+}
@@ -0,0 +1,49 @@
+use crate::game::{
+    bot::TetrisBot,
+    versus::{play_match, Outcome},
+};
+
+#[derive(Default, Debug)]
+pub struct TournamentResult {
+    pub left_wins: usize,
+    pub right_wins: usize,
+    pub draws: usize,
+    pub outcomes: Vec<Outcome>, // of every match in order, from the left's side
+}
+
+// plays n matches with seeds 0..n and prints the win rates, sides are swapped every
+// other match so neither contestant profits from moving first
+#[allow(dead_code)]
+pub fn tournament(
+    left: &mut dyn TetrisBot,
+    right: &mut dyn TetrisBot,
+    n: usize,
+    max_pieces: usize,
+) -> TournamentResult {
+    let mut result = TournamentResult::default();
+    for seed in 0..n as u64 {
+        let outcome = if seed % 2 == 0 {
+            play_match(seed, left, right, max_pieces).outcome
+        } else {
+            match play_match(seed, right, left, max_pieces).outcome {
+                Outcome::Left => Outcome::Right,
+                Outcome::Right => Outcome::Left,
+                Outcome::Draw => Outcome::Draw,
+            }
+        };
+        result.outcomes.push(outcome);
+        match outcome {
+            Outcome::Left => result.left_wins += 1,
+            Outcome::Right => result.right_wins += 1,
+            Outcome::Draw => result.draws += 1,
+        }
+    }
+    let rate = |wins| 100. * wins as f64 / n as f64;
+    println!(
+        "left {:.1}%, right {:.1}%, draws {:.1}%",
+        rate(result.left_wins),
+        rate(result.right_wins),
+        rate(result.draws)
+    );
+    result
+}
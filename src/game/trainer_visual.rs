@@ -0,0 +1,243 @@
+use crate::{
+    game::{
+        ggez_frontend::GgezRenderer,
+        nn_trainer::{print_baseline, TrainerConfig},
+        population::{NNReadOrCreateResult, Population},
+        render::{add_plot, Color, Command, MultiBoard, Rect, Renderer},
+        visual::VisGame,
+        Game,
+    },
+    run_game, HORIZONTAL_WINDOW_DIMS,
+};
+use ggez::{
+    event::{EventHandler, KeyMods},
+    graphics::{clear, present, BLACK},
+    input::keyboard::KeyCode,
+    Context, GameResult,
+};
+use std::path::{Path, PathBuf};
+use tap::TapOps;
+
+// trains a population in a window, showing the genome being evaluated (or every genome
+// at once in parallel mode)
+pub struct NNTrainer {
+    vis: VisGame,
+
+    dir: PathBuf,
+    population: Population,
+    fitness: Vec<f64>, // of the genomes evaluated so far in this generation
+    training: usize,   // index
+    games: Vec<Game>,  // one per genome in parallel mode, empty otherwise
+    show_plot: bool,   // fitness over the generations instead of the games
+}
+
+impl NNTrainer {
+    #[allow(dead_code)]
+    pub fn new(dir: &Path, config: TrainerConfig) -> NNReadOrCreateResult<Self> {
+        let dir = PathBuf::from(".").tap(|pb| pb.push(dir));
+        let parallel = config.parallel;
+        print_baseline(config.mode);
+        let population = Population::load_or_create(&dir, config)?;
+        let games = if parallel {
+            (0..population.len())
+                .map(|_| population.new_game())
+                .collect()
+        } else {
+            vec![]
+        };
+        let mut vis = VisGame::new();
+        vis.game = population.new_game();
+        Ok(Self {
+            vis,
+
+            dir,
+            population,
+            fitness: vec![],
+            training: 0,
+            games,
+            show_plot: false,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn run(&mut self) -> GameResult<()> {
+        run_game(self)
+    }
+}
+
+impl NNTrainer {
+    // record the fitness of the current genome and move on to the next one
+    fn finish_game(&mut self) {
+        println!(
+            "generation {}, genome {}: {}",
+            self.population.generation_number,
+            self.training,
+            self.vis.game.summary()
+        );
+        self.fitness
+            .push(self.population.fitness(&mut self.vis.game));
+        self.vis.game = self.population.new_game();
+        self.training += 1;
+        if self.training == self.population.len() {
+            self.population
+                .next_generation(self.fitness.drain(..).collect());
+            self.training = 0;
+        }
+    }
+
+    // all genomes play at once on every core, the generation is done when every game has
+    // ended
+    fn update_parallel(&mut self) {
+        if self.vis.paused {
+            return;
+        }
+        let running = self.population.step_all(&mut self.games);
+        let population = &self.population;
+        if !running {
+            for (i, game) in self.games.iter().enumerate() {
+                println!(
+                    "generation {}, genome {}: {}",
+                    population.generation_number,
+                    i,
+                    game.summary()
+                );
+            }
+            let fitness = self
+                .games
+                .iter_mut()
+                .map(|game| population.fitness(game))
+                .collect();
+            self.population.next_generation(fitness);
+            let population = &self.population;
+            self.games = (0..population.len())
+                .map(|_| population.new_game())
+                .collect();
+        }
+    }
+
+    // thumbnails laid out in a grid scaled to fit the window
+    fn draw_parallel(&mut self, ctx: &mut Context) -> GameResult<()> {
+        const MARGIN: f32 = 10.;
+        const TEXT_HEIGHT: f32 = 20.;
+        clear(ctx, BLACK);
+        let (window_width, window_height) = HORIZONTAL_WINDOW_DIMS;
+        let area = Rect {
+            x: MARGIN,
+            y: MARGIN,
+            w: window_width - 2. * MARGIN,
+            h: window_height - 2. * MARGIN,
+        };
+        let grid = MultiBoard::new(self.games.len(), area, TEXT_HEIGHT);
+
+        let mut renderer = GgezRenderer::new();
+        for (i, game) in self.games.iter().enumerate() {
+            let board = grid.add(i, game, 1., &mut renderer);
+            let finished = self.population.is_finished(game);
+            let color = if finished {
+                Color::rgb(110, 110, 110)
+            } else {
+                Color::WHITE
+            };
+            let text = format!("{}: {}", i, game.points);
+            renderer.text((board.x, board.y + board.h + 2.), &text, color);
+        }
+        renderer.finish(ctx)?;
+        present(ctx)
+    }
+}
+
+impl NNTrainer {
+    // best (green) and mean (blue) fitness of every generation so far
+    fn draw_plot(&mut self, ctx: &mut Context) -> GameResult<()> {
+        const MARGIN: f32 = 40.;
+        clear(ctx, BLACK);
+        let (window_width, window_height) = HORIZONTAL_WINDOW_DIMS;
+        let rect = Rect {
+            x: MARGIN,
+            y: MARGIN,
+            w: window_width - 2. * MARGIN,
+            h: window_height - 2. * MARGIN,
+        };
+        let history = &self.population.history;
+        let best = history.iter().map(|&(best, _)| best).collect::<Vec<_>>();
+        let mean = history.iter().map(|&(_, mean)| mean).collect::<Vec<_>>();
+        let mut renderer = GgezRenderer::new();
+        let (min, max) = add_plot(
+            &[
+                (&best, Color::rgb(55, 154, 48)),
+                (&mean, Color::rgb(22, 101, 167)),
+            ],
+            rect,
+            &mut renderer,
+        );
+        let mut label = |text: String, x, y| renderer.text((x, y), &text, Color::WHITE);
+        label(format!("{:.0}", max), MARGIN, MARGIN - 20.);
+        label(format!("{:.0}", min), MARGIN, MARGIN + rect.h + 5.);
+        label(
+            format!("{} generations", history.len()),
+            MARGIN + rect.w - 120.,
+            MARGIN + rect.h + 5.,
+        );
+        renderer.finish(ctx)?;
+        present(ctx)
+    }
+}
+
+impl EventHandler for NNTrainer {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if self.population.config.parallel {
+            self.update_parallel();
+            return Ok(());
+        }
+        if self.population.is_finished(&self.vis.game) {
+            self.finish_game();
+        }
+        let pieces = self.vis.game.pieces;
+        if !self.vis.paused {
+            self.population.decide(self.training, &mut self.vis.game);
+        }
+        self.vis.update(ctx)?;
+        if self.vis.game.pieces > pieces {
+            self.population
+                .config
+                .noise
+                .inject_garbage(&mut self.vis.game);
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if self.show_plot {
+            return self.draw_plot(ctx);
+        }
+        if self.population.config.parallel {
+            return self.draw_parallel(ctx);
+        }
+        self.vis.draw(ctx)
+    }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        keycode: KeyCode,
+        keymods: KeyMods,
+        repeat: bool,
+    ) {
+        if self.vis.command(keycode) == Some(Command::Pause) {
+            self.vis.key_down_event(ctx, keycode, keymods, repeat);
+        } else if keycode == KeyCode::P {
+            self.show_plot = !self.show_plot;
+        } else if self.vis.paused && keycode == KeyCode::LControl {
+            self.population
+                .save(&self.dir)
+                .expect("failed to save generation");
+            println!("saved nn in \"{}\"", self.dir.display());
+        }
+    }
+
+    fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) {
+        if self.vis.command(keycode) == Some(Command::Pause) {
+            self.vis.key_up_event(ctx, keycode, keymods)
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use crate::game::{
+    bot::{BoardView, TetrisBot},
+    placement::Placement,
+    Action, Game, GAME_WIDTH,
+};
+use rand::prelude::*;
+use std::collections::VecDeque;
+
+// garbage rows sent for clearing the given number of lines at once
+pub fn attack(cleared: usize) -> usize {
+    match cleared {
+        0 | 1 => 0,
+        2 => 1,
+        3 => 2,
+        _ => 4,
+    }
+}
+
+// pieces a side gets to lock after garbage was sent to it before the garbage lands
+pub const GARBAGE_DELAY: usize = 2;
+
+// makes one side's game harder so that players of different strength can have a close
+// match, no handicap by default
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Handicap {
+    pub garbage: f32,  // multiplier for the rows the side receives, rounded per batch
+    pub levels: usize, // levels the side starts above the first, for faster gravity
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Self {
+            garbage: 1.,
+            levels: 0,
+        }
+    }
+}
+
+impl Handicap {
+    // call before the first input
+    pub fn apply(&self, game: &mut Game) {
+        game.set_start_level(1 + self.levels);
+    }
+
+    // what the side receives when the opponent sends rows
+    pub fn received(&self, rows: usize) -> usize {
+        (rows as f32 * self.garbage).round() as usize
+    }
+}
+
+// keeps a bot from playing frame-perfectly in real time versus, so that it can be a fair
+// opponent for players who aren't that fast, no limits by default
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BotLimits {
+    pub pps: Option<f32>, // most pieces per second
+    pub reaction: usize,  // frames between a piece appearing and the bot starting on it
+    pub misdrop: f64,     // chance of a piece landing a column off
+}
+
+impl Default for BotLimits {
+    fn default() -> Self {
+        Self {
+            pps: None,
+            reaction: 0,
+            misdrop: 0.,
+        }
+    }
+}
+
+impl BotLimits {
+    // beginner, intermediate or expert
+    pub fn preset(name: &str) -> Option<Self> {
+        let (pps, reaction, misdrop) = match name {
+            "beginner" => (1., 30, 0.05),
+            "intermediate" => (2., 15, 0.02),
+            "expert" => (3.5, 6, 0.005),
+            _ => return None,
+        };
+        Some(Self {
+            pps: Some(pps),
+            reaction,
+            misdrop,
+        })
+    }
+
+    // what's wrong with limits given by hand, pps has to be a positive number and misdrop
+    // a chance between 0 and 1
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(pps) = self.pps.filter(|pps| !(pps.is_finite() && *pps > 0.)) {
+            return Err(format!("bot pps {} is not a positive number", pps));
+        }
+        if !(0. ..=1.).contains(&self.misdrop) {
+            let misdrop = self.misdrop;
+            return Err(format!("bot misdrop {} is not between 0 and 1", misdrop));
+        }
+        Ok(())
+    }
+
+    // the game runs at 60 frames a second
+    fn frames_per_piece(&self) -> usize {
+        self.pps.map_or(0, |pps| (60. / pps).round() as usize)
+    }
+}
+
+// a bot played one frame at a time under BotLimits
+pub struct LimitedBot {
+    bot: Box<dyn TetrisBot>,
+    limits: BotLimits,
+    rng: StdRng,
+    pieces: usize,            // locked when the current piece was first seen
+    seen_at: usize,           // game tick the current piece was first seen on
+    placed_at: Option<usize>, // game tick of the last placement
+}
+
+impl LimitedBot {
+    pub fn new(bot: Box<dyn TetrisBot>, limits: BotLimits, seed: u64) -> Self {
+        Self {
+            bot,
+            limits,
+            rng: StdRng::seed_from_u64(seed),
+            pieces: usize::MAX,
+            seen_at: 0,
+            placed_at: None,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: BotLimits) {
+        self.limits = limits;
+    }
+
+    // call every frame the bot has nothing left to do, None while the limits hold it back
+    // or when the bot passes, a misdrop is one more shift in a random direction before the
+    // hard drop (nothing if there's a wall)
+    pub fn choose(&mut self, view: &BoardView) -> Option<Placement> {
+        let game = view.game;
+        if game.pieces != self.pieces {
+            self.pieces = game.pieces;
+            self.seen_at = game.tick;
+        }
+        let next_at = self
+            .placed_at
+            .map_or(0, |at| at + self.limits.frames_per_piece());
+        if game.tick < self.seen_at + self.limits.reaction || game.tick < next_at {
+            return None;
+        }
+        let mut placement = self.bot.choose(view).placement()?;
+        self.placed_at = Some(game.tick);
+        if self.rng.gen_bool(self.limits.misdrop.clamp(0., 1.)) {
+            let shift = *[Action::MoveLeft, Action::MoveRight]
+                .choose(&mut self.rng)
+                .unwrap();
+            let drop = placement.inputs.len() - 1;
+            placement.inputs.insert(drop, shift);
+        }
+        Some(placement)
+    }
+}
+
+// garbage on its way to one side in real time versus, each batch lands when the side locks
+// its GARBAGE_DELAY-th piece without clearing lines, clearing lines cancels incoming rows
+// before anything is sent back
+#[derive(Clone, Debug, Default)]
+pub struct Incoming {
+    batches: VecDeque<(usize, usize)>, // (rows, locks left before it lands), oldest first
+}
+
+impl Incoming {
+    pub fn receive(&mut self, rows: usize) {
+        if rows > 0 {
+            self.batches.push_back((rows, GARBAGE_DELAY));
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.batches.iter().map(|&(rows, _)| rows).sum()
+    }
+
+    // (rows, locks left before it lands), oldest first
+    pub fn batches(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.batches.iter().copied()
+    }
+
+    // call after every locked piece with the lines it cleared, returns (rows that land
+    // now, oldest batch first, rows to send to the opponent)
+    pub fn lock(&mut self, cleared: usize) -> (Vec<usize>, usize) {
+        let mut sent = attack(cleared);
+        if cleared > 0 {
+            while sent > 0 {
+                match self.batches.front_mut() {
+                    Some((rows, _)) if *rows > sent => {
+                        *rows -= sent;
+                        sent = 0;
+                    }
+                    Some(&mut (rows, _)) => {
+                        sent -= rows;
+                        self.batches.pop_front();
+                    }
+                    None => break,
+                }
+            }
+            return (vec![], sent);
+        }
+        for (_, left) in self.batches.iter_mut() {
+            *left -= 1;
+        }
+        let landed = self
+            .batches
+            .iter()
+            .take_while(|&&(_, left)| left == 0)
+            .count();
+        let landed = self.batches.drain(..landed).map(|(rows, _)| rows).collect();
+        (landed, sent)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Outcome {
+    Left,
+    Right,
+    Draw, // both sides survived max_pieces
+}
+
+// one side of a match, garbage lands and is cancelled like in real time versus
+struct Side {
+    game: Game,
+    incoming: Incoming,
+}
+
+impl Side {
+    // place one piece, returns the garbage sent to the opponent or None if the game was lost
+    fn take_turn(&mut self, bot: &mut dyn TetrisBot, rng: &mut StdRng) -> Option<usize> {
+        let view = BoardView::new(&self.game).with_incoming(&self.incoming);
+        let placement = bot.choose(&view).placement()?;
+        let cleared_before = self.game.cleared;
+        for action in placement.inputs {
+            self.game.apply(action);
+        }
+        let (landed, sent) = self.incoming.lock(self.game.cleared - cleared_before);
+        for rows in landed {
+            self.game.add_garbage(rows, rng.gen_range(0, GAME_WIDTH));
+        }
+        if self.game.is_over() {
+            None
+        } else {
+            Some(sent)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MatchResult {
+    pub outcome: Outcome,
+    pub sent: (usize, usize), // garbage rows sent by the left and by the right side
+}
+
+// headless match with pieces placed alternately, both sides get the same piece sequence,
+// the first side to top out loses
+pub fn play_match(
+    seed: u64,
+    left: &mut dyn TetrisBot,
+    right: &mut dyn TetrisBot,
+    max_pieces: usize,
+) -> MatchResult {
+    let new_side = || Side {
+        game: Game::with_seed(seed),
+        incoming: Incoming::default(),
+    };
+    let (mut l, mut r) = (new_side(), new_side());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut sent = (0, 0);
+    let result = |outcome, sent| MatchResult { outcome, sent };
+    for _ in 0..max_pieces {
+        match l.take_turn(left, &mut rng) {
+            Some(rows) => {
+                r.incoming.receive(rows);
+                sent.0 += rows;
+            }
+            None => return result(Outcome::Right, sent),
+        }
+        match r.take_turn(right, &mut rng) {
+            Some(rows) => {
+                l.incoming.receive(rows);
+                sent.1 += rows;
+            }
+            None => return result(Outcome::Left, sent),
+        }
+    }
+    result(Outcome::Draw, sent)
+}
+
+#[test]
+fn test_garbage_decides_match() {
+    use crate::game::heuristic::HeuristicBot;
+    let mut stacker = |game: &Game| crate::game::placement::placements(game).pop();
+    // placing pieces arbitrarily tops out long before the heuristic bot does
+    let result = play_match(0, &mut HeuristicBot::new(), &mut stacker, 500);
+    assert_eq!(result.outcome, Outcome::Left);
+}
+
+#[test]
+fn test_incoming_garbage_lands_or_is_cancelled() {
+    let mut incoming = Incoming::default();
+    incoming.receive(2);
+    incoming.receive(4);
+    // a double cancels one row, a tetris the rest and sends one
+    assert_eq!(incoming.lock(2), (vec![], 0));
+    assert_eq!(incoming.rows(), 5);
+    assert_eq!(incoming.lock(4), (vec![], 0));
+    assert_eq!(
+        incoming.batches().collect::<Vec<_>>(),
+        vec![(1, GARBAGE_DELAY)]
+    );
+    incoming.receive(3);
+    for _ in 1..GARBAGE_DELAY {
+        assert_eq!(incoming.lock(0), (vec![], 0));
+    }
+    assert_eq!(incoming.lock(0), (vec![1, 3], 0));
+    assert_eq!(incoming.lock(3), (vec![], 2));
+}
+
+#[test]
+fn test_handicap_scales_garbage_and_starts_higher() {
+    let handicap = Handicap {
+        garbage: 1.5,
+        levels: 2,
+    };
+    assert_eq!(
+        (1..=4)
+            .map(|rows| handicap.received(rows))
+            .collect::<Vec<_>>(),
+        vec![2, 3, 5, 6]
+    );
+    let mut game = Game::with_seed(0);
+    handicap.apply(&mut game);
+    assert_eq!(game.level, 3);
+}
+
+#[test]
+fn test_limited_bot_waits_and_misdrops() {
+    use crate::game::heuristic::HeuristicBot;
+    let limits = BotLimits {
+        pps: Some(2.),
+        reaction: 10,
+        misdrop: 1.,
+    };
+    let mut bot = LimitedBot::new(Box::new(HeuristicBot::new()), limits, 0);
+    let mut game = Game::with_seed(0);
+    let mut ticks = vec![];
+    while ticks.len() < 2 {
+        if let Some(p) = bot.choose(&BoardView::new(&game)) {
+            ticks.push(game.tick);
+            // the shift goes right before the hard drop
+            let shifts = [Action::MoveLeft, Action::MoveRight];
+            assert!(shifts.contains(&p.inputs[p.inputs.len() - 2]));
+            for action in p.inputs {
+                game.apply(action);
+            }
+        }
+        game.iterate();
+    }
+    // two pieces a second is one every 30 frames
+    assert_eq!(ticks, vec![10, 40]);
+}
+
+#[test]
+fn test_bot_limits_reject_impossible_values() {
+    let with = |pps, misdrop| BotLimits {
+        pps,
+        reaction: 0,
+        misdrop,
+    };
+    assert!(with(None, 0.).check().is_ok());
+    assert!(BotLimits::preset("expert").unwrap().check().is_ok());
+    for pps in [0., -1., f32::NAN, f32::INFINITY] {
+        assert!(with(Some(pps), 0.).check().is_err());
+    }
+    for misdrop in [-0.1, 1.5, f64::NAN] {
+        assert!(with(Some(2.), misdrop).check().is_err());
+    }
+}
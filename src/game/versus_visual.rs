@@ -0,0 +1,211 @@
+use crate::{
+    game::{
+        bot::{BoardView, TetrisBot},
+        ggez_frontend::GgezRenderer,
+        render::{Color, MultiBoard, Rect, Renderer},
+        versus::{BotLimits, Handicap, Incoming, LimitedBot},
+        visual::{VisGame, CELL_SIDE},
+        Controller, Game, GAME_HEIGHT, GAME_WIDTH,
+    },
+    run_game_in,
+};
+use ggez::{
+    event::{Button, EventHandler, GamepadId, KeyMods},
+    input::keyboard::KeyCode,
+    Context, GameResult,
+};
+use rand::prelude::*;
+
+const METER_WIDTH: f32 = 12.;
+
+// the player against a bot in real time, both get the same pieces and send each other
+// garbage (see versus::Incoming), the bot's board is shown small below hold
+pub struct VersusVisGame {
+    vis: VisGame,
+    opponent: Game,
+    bot: LimitedBot,
+    // garbage for the player and for the bot
+    incoming: (Incoming, Incoming),
+    handicaps: (Handicap, Handicap), // of the player and of the bot
+    // (pieces, cleared) of the player and of the bot after the last update
+    seen: ((usize, usize), (usize, usize)),
+    rng: StdRng,
+}
+
+impl VersusVisGame {
+    pub fn new(vis: VisGame, bot: Box<dyn TetrisBot>) -> Self {
+        let seed = vis.game.piece_queue.seed;
+        let mut opponent = Game::with_seed(seed);
+        opponent.set_hold_mode(vis.game.hold_mode());
+        opponent.set_rotation_system(vis.game.rotation_system());
+        Self {
+            vis,
+            opponent,
+            bot: LimitedBot::new(bot, BotLimits::default(), seed),
+            incoming: (Incoming::default(), Incoming::default()),
+            handicaps: (Handicap::default(), Handicap::default()),
+            seen: ((0, 0), (0, 0)),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // call before run
+    #[allow(dead_code)]
+    pub fn with_handicaps(mut self, player: Handicap, bot: Handicap) -> Self {
+        player.apply(&mut self.vis.game);
+        bot.apply(&mut self.opponent);
+        self.handicaps = (player, bot);
+        self
+    }
+
+    // call before run
+    #[allow(dead_code)]
+    pub fn with_limits(mut self, limits: BotLimits) -> Self {
+        self.bot.set_limits(limits);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn run(&mut self) -> GameResult<()> {
+        let window_mode = self.vis.window_mode();
+        run_game_in(self, window_mode)
+    }
+
+    fn step_opponent(&mut self) {
+        if !self.opponent.has_queued_actions() {
+            let view = BoardView::new(&self.opponent).with_incoming(&self.incoming.1);
+            if let Some(placement) = self.bot.choose(&view) {
+                for action in placement.inputs {
+                    self.opponent.queue_action(action);
+                }
+            }
+        }
+        self.opponent.iterate();
+    }
+
+    // garbage is exchanged whenever either side locks a piece
+    fn exchange_garbage(&mut self) {
+        let rng = &mut self.rng;
+        let to_opponent = lock(
+            &mut self.vis.game,
+            &mut self.incoming.0,
+            &mut self.seen.0,
+            rng,
+        );
+        let to_player = lock(
+            &mut self.opponent,
+            &mut self.incoming.1,
+            &mut self.seen.1,
+            rng,
+        );
+        self.incoming
+            .0
+            .receive(self.handicaps.0.received(to_player));
+        self.incoming
+            .1
+            .receive(self.handicaps.1.received(to_opponent));
+    }
+
+    // as large as fits in the side panel
+    fn add_opponent(&self, r: &mut dyn Renderer) {
+        let grid = MultiBoard::new(1, self.vis.side_panel(), 0.);
+        grid.add(0, &self.opponent, 1., r);
+    }
+
+    // a bar left of the board, the oldest batch at the bottom, red when it lands with the
+    // next piece that doesn't clear lines
+    fn add_garbage_meter(&self, r: &mut dyn Renderer) {
+        let (left, top) = self.vis.board_pos();
+        let left = left - METER_WIDTH - 5.;
+        let bottom = top + GAME_HEIGHT as f32 * CELL_SIDE;
+        let mut y = bottom;
+        for (rows, locks) in self.incoming.0.batches() {
+            let height = (rows as f32 * CELL_SIDE).min(y - top);
+            y -= height;
+            let color = match locks {
+                1 => Color::rgb(220, 40, 40),
+                _ => Color::rgb(235, 190, 40),
+            };
+            let rect = Rect {
+                x: left,
+                y,
+                w: METER_WIDTH,
+                h: height - 2.,
+            };
+            r.rect(rect, color);
+        }
+    }
+}
+
+// lands garbage if the side locked a piece since the last call, returns the rows it sends
+fn lock(
+    game: &mut Game,
+    incoming: &mut Incoming,
+    (pieces, cleared): &mut (usize, usize),
+    rng: &mut StdRng,
+) -> usize {
+    if game.pieces == *pieces {
+        return 0;
+    }
+    let (landed, sent) = incoming.lock(game.cleared - *cleared);
+    for rows in landed {
+        game.add_garbage(rows, rng.gen_range(0, GAME_WIDTH));
+    }
+    *pieces = game.pieces;
+    *cleared = game.cleared;
+    sent
+}
+
+impl EventHandler for VersusVisGame {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.vis.update(ctx)?;
+        if self.vis.paused || self.vis.counting_down() || self.vis.game.is_over() {
+            return Ok(());
+        }
+        self.step_opponent();
+        self.exchange_garbage();
+        if self.opponent.is_over() {
+            println!("Won {}", self.vis.game.summary());
+            ggez::event::quit(ctx);
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if self.vis.is_minimized() {
+            return Ok(());
+        }
+        self.vis.draw_frame(ctx)?;
+        if !self.vis.paused {
+            let mut renderer = GgezRenderer::new();
+            self.add_garbage_meter(&mut renderer);
+            self.add_opponent(&mut renderer);
+            renderer.finish(ctx)?;
+        }
+        self.vis.present_frame(ctx)
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods, repeat: bool) {
+        self.vis.key_down_event(ctx, code, mods, repeat)
+    }
+
+    fn key_up_event(&mut self, ctx: &mut Context, code: KeyCode, mods: KeyMods) {
+        self.vis.key_up_event(ctx, code, mods)
+    }
+
+    fn focus_event(&mut self, ctx: &mut Context, gained: bool) {
+        self.vis.focus_event(ctx, gained)
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.vis.resize_event(ctx, width, height)
+    }
+
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, button: Button, id: GamepadId) {
+        self.vis.gamepad_button_down_event(ctx, button, id)
+    }
+
+    fn gamepad_button_up_event(&mut self, ctx: &mut Context, button: Button, id: GamepadId) {
+        self.vis.gamepad_button_up_event(ctx, button, id)
+    }
+}
@@ -1,24 +1,46 @@
 use crate::{
-    game::{intersects_with, FallingPiece, Game, PieceId, Pixel, GAME_HEIGHT, GAME_WIDTH},
-    run_game,
+    game::{
+        achievements::{save_achievements, Achievements},
+        animation::Timeline,
+        background::Starfield,
+        bot::{BoardView, TetrisBot},
+        daily::daily_seed,
+        effects::{Effects, PostProcess},
+        estimator::Estimator,
+        ggez_frontend::{
+            draw_stretched, level_up_sound, load_image, pad_command, GgezRenderer, Layer,
+        },
+        heatmap::Heatmap,
+        keymap::KeyMap,
+        missions::Missions,
+        placement::Placement,
+        reload::Reloader,
+        render::{Color, Command, Mirrored, Rect, Renderer},
+        replay::save_input_replay,
+        rise::StackRise,
+        scores::{mode, save_scores, BestScores},
+        theme, Action, Controller, Game, HoldMode, Mask, PieceId, RotationSystem, GAME_HEIGHT,
+        GAME_WIDTH,
+    },
+    run_game_in,
     support::sleep_until,
     HORIZONTAL_WINDOW_DIMS, HORIZONTAL_WINDOW_MODE, VERTICAL_WINDOW_DIMS, VERTICAL_WINDOW_MODE,
 };
-#[allow(unused_imports)]
 use ggez::{
-    event::{EventHandler, KeyMods},
+    audio::{SoundSource, Source},
+    conf::WindowMode,
+    event::{Button, EventHandler, GamepadId, KeyMods},
     graphics,
-    graphics::{
-        clear, draw, draw_queued_text, present, queue_text, Color, DrawMode, DrawParam,
-        FillOptions, FilterMode, MeshBuilder, Rect, Text, BLACK, WHITE,
-    },
+    graphics::{clear, present, Image},
     input::keyboard::KeyCode,
-    mint::Point2,
     Context, GameResult,
 };
 use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[allow(unused_imports)]
@@ -26,7 +48,7 @@ use std::cmp::min;
 #[allow(unused_imports)]
 use tuple_map::*;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
 enum Orientation {
     Horizontal,
     Vertical,
@@ -61,366 +83,1027 @@ pub struct KeyInfo {
     repeat: Repeat,
 }
 
-pub type Keys = HashMap<KeyCode, KeyInfo>;
-
-// trace_macros!(true);
+// by what the key does rather than which key it is, so that the keyboard and a gamepad
+// share the same repeat state
+pub type Keys = HashMap<Command, KeyInfo>;
 
-macro_rules! keys {
-    (@ins_key $keys:ident, $code:tt * ($initial_delay:expr, $delay:expr)) => {
-        $keys.insert(
-            KeyCode::$code,
-            KeyInfo {
-                state: PressedState::Up,
-                repeat: Repeat::Repeat { initial_delay: $initial_delay, delay: $delay }
-            },
-        )
+fn default_keys() -> Keys {
+    use Action::*;
+    let repeat = |initial_delay, delay| Repeat::Repeat {
+        initial_delay,
+        delay,
     };
-    (@ins_key $keys:ident, $code:tt) => {
-        $keys.insert(KeyCode::$code, KeyInfo { state: PressedState::Up, repeat: Repeat::NoRepeat })
-    };
-    ($( $code:tt $( * ($( $t:tt )*) )? ),* $(,)?) => {{
-        let mut keys = Keys::new();
-        $( keys!(@ins_key keys, $code $( * ($( $t )*) )?); )*
-        keys
-    }};
+    let repeating = vec![
+        (MoveLeft, repeat(2, 4)),
+        (MoveRight, repeat(2, 4)),
+        (SoftDrop, repeat(0, 3)),
+    ]
+    .into_iter()
+    .map(|(action, repeat)| (Command::Play(action), repeat));
+    let single = vec![RotateCW, RotateCCW, Rotate180, HardDrop, Hold]
+        .into_iter()
+        .map(Command::Play)
+        .chain(vec![
+            Command::Pause,
+            Command::SwitchLayout,
+            Command::Freeze,
+            Command::Step,
+        ])
+        .map(|command| (command, Repeat::NoRepeat));
+    repeating
+        .chain(single)
+        .map(|(command, repeat)| {
+            let state = PressedState::Up;
+            (command, KeyInfo { state, repeat })
+        })
+        .collect()
+}
+
+// a challenge that flips left and right, either of the controls or of the board as it is
+// drawn, the game itself stays the same (replays and bots see it as it is)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mirror {
+    Controls, // left moves right and the rotations are the other way around
+    Board,
+    Both, // looks and plays like a mirrored game
+}
+
+impl Mirror {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "controls" => Some(Self::Controls),
+            "board" => Some(Self::Board),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Controls => "controls",
+            Self::Board => "board",
+            Self::Both => "both",
+        }
+    }
+
+    fn controls(self) -> bool {
+        self != Self::Board
+    }
+
+    fn board(self) -> bool {
+        self != Self::Controls
+    }
+}
+
+// memory training, the queue is hidden except for frames after every every-th piece is
+// dealt (and the first one), never with every 0
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct QueueReveal {
+    pub every: usize,
+    pub frames: usize,
+}
+
+impl QueueReveal {
+    // whether the piece with index dealt (from 0) reveals the queue
+    fn reveals(self, dealt: usize) -> bool {
+        self.every > 0 && dealt.is_multiple_of(self.every)
+    }
+
+    fn name(self) -> String {
+        match self.every {
+            0 => "hidden queue".to_string(),
+            every => format!("queue every {} for {}", every, self.frames),
+        }
+    }
+}
+
+// a bot's choice for the current piece, outlined on the board
+struct Hint {
+    bot: Box<dyn TetrisBot>,
+    placement: Option<Placement>,
+    piece: (usize, Option<PieceId>), // pieces locked and held when it was chosen
+}
+
+// what moves for a few frames after a piece spawns
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Animation {
+    Entry,      // the new piece slides down into the board
+    QueueShift, // the queue moves up by one piece and the last one fades in
+}
+
+// the estimator's chance of surviving the next pieces, shown as a gauge
+struct Gauge {
+    estimator: Estimator,
+    estimate: f64,
+    piece: usize, // pieces locked when it was estimated
 }
 
 pub struct VisGame {
     pub game: Game,
     pub paused: bool,
+    auto_paused: bool,        // by hiding the window rather than by the player
+    minimized: bool,          // nothing is drawn and updates slow down
+    countdown: Option<usize>, // frames until the game goes on after an automatic pause
+    frame_step: bool,         // Freeze and Step work and the falling piece's state is shown
+    frozen: bool,             // nothing happens on its own, only steps advance the game
+    steps: usize,             // frames to advance while frozen
     orientation: Orientation,
     next_frame: Instant,
     pub keys: Keys,
+    keymap: KeyMap,
+    replay_path: Option<PathBuf>, // where the recording is saved at the end of the game
+    heatmap_path: Option<PathBuf>, // where the heatmap is saved at the end of the game
+    hint: Option<Hint>,
+    gauge: Option<Gauge>,
+    reloader: Option<Reloader>,
+    best: Option<(PathBuf, BestScores)>, // saved there when the game ends
+    daily: Option<String>,               // the date of the daily challenge being played
+    achievements: Option<(PathBuf, Achievements)>, // saved there when the game ends
+    missions: Option<Missions>,
+    toasts: Vec<(String, usize)>, // achievements and missions, with the tick until which they show
+    stats: bool,                  // show the piece statistics panel
+    bag: bool,                    // show the pieces left in the bag
+    side_panel: (f32, f32),       // as of the last render
+    level: usize,                 // as of the last update, to notice level ups
+    level_up_until: usize,        // tick until which the level up banner is shown
+    level_up_sound: Option<Source>, // loaded on the first level up
+    animations: Option<Timeline<Animation>>, // None without animations
+    dealt: usize,                 // pieces as of the last update, to notice spawns
+    starfield: Starfield,
+    background_image: Option<(PathBuf, Option<Image>)>, // the theme's, None if it didn't load
+    effects: Effects,
+    post: Option<PostProcess>, // created on the first frame with any effects
+    cleared: usize,            // as of the last update, to notice line clears
+    bloom_until: usize,        // tick until which the bloom fades out
+    mirror: Option<Mirror>,
+    reveal: Option<QueueReveal>, // None shows the queue all the time
+    revealed_until: usize,       // tick until which a hidden queue shows
+    fixed_layer: Option<(Orientation, Layer)>,
+    dynamic_layer: Option<(u64, Layer)>, // keyed by dynamic_key
 }
 
 impl VisGame {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        let keys = keys! {
-            Left * (2, 4),
-            Right * (2, 4),
-            Down * (0, 3),
-            Up, RShift, Space,
-            J, Escape, Tab,
-        };
+        let keys = default_keys();
         Self {
             game: Game::new(),
             paused: false,
+            auto_paused: false,
+            minimized: false,
+            countdown: None,
+            frame_step: false,
+            frozen: false,
+            steps: 0,
             orientation: Orientation::Horizontal,
             next_frame: Instant::now(),
             keys,
+            keymap: KeyMap::default(),
+            replay_path: None,
+            heatmap_path: None,
+            hint: None,
+            gauge: None,
+            reloader: None,
+            best: None,
+            daily: None,
+            achievements: None,
+            missions: None,
+            toasts: vec![],
+            stats: false,
+            bag: false,
+            side_panel: (0., 0.),
+            level: 1,
+            level_up_until: 0,
+            level_up_sound: None,
+            animations: Some(Timeline::default()),
+            dealt: 0,
+            starfield: Starfield::default(),
+            background_image: None,
+            effects: Effects::default(),
+            post: None,
+            cleared: 0,
+            bloom_until: 0,
+            mirror: None,
+            reveal: None,
+            revealed_until: 0,
+            fixed_layer: None,
+            dynamic_layer: None,
+        }
+    }
+
+    // same piece sequence every time, call before record_to
+    #[allow(dead_code)]
+    pub fn seeded(mut self, seed: u64) -> Self {
+        let (hold_mode, (entry, line_clear)) = (self.game.hold_mode(), self.game.delays());
+        let (lock_delay, rotation) = (self.game.lock_delay(), self.game.rotation_system());
+        let (traced, rise) = (self.game.kick_trace.is_some(), self.game.stack_rise());
+        self.game = Game::with_seed(seed);
+        self.game.set_hold_mode(hold_mode);
+        self.game.set_delays(entry, line_clear);
+        self.game.set_lock_delay(lock_delay);
+        self.game.set_rotation_system(rotation);
+        self.game.set_stack_rise(rise);
+        if traced {
+            self.game.trace_kicks();
+        }
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_hold_mode(mut self, mode: HoldMode) -> Self {
+        self.game.set_hold_mode(mode);
+        self
+    }
+
+    // Freeze stops the game (without hiding it like a pause does) and Step advances it one
+    // frame at a time, keys pressed while frozen still act right away, the falling piece's
+    // state is shown in the side panel, see add_frame_info
+    #[allow(dead_code)]
+    pub fn with_frame_step(mut self) -> Self {
+        self.frame_step = true;
+        self
+    }
+
+    // outline the positions every rotation tried, see add_kicks
+    #[allow(dead_code)]
+    pub fn with_kick_debug(mut self) -> Self {
+        self.game.trace_kicks();
+        self
+    }
+
+    // reload masks.txt and the theme file (theme.toml unless --theme says otherwise)
+    // whenever they are saved
+    #[allow(dead_code)]
+    pub fn with_reloading(mut self, theme: PathBuf) -> Self {
+        self.reloader = Some(Reloader::new(theme));
+        self
+    }
+
+    // survival, see StackRise
+    #[allow(dead_code)]
+    pub fn with_stack_rise(mut self, rise: StackRise) -> Self {
+        self.game.set_stack_rise(Some(rise));
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_rotation_system(mut self, system: RotationSystem) -> Self {
+        self.game.set_rotation_system(system);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_keymap(mut self, keymap: KeyMap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    // what a key does with the current bindings
+    pub(super) fn command(&self, code: KeyCode) -> Option<Command> {
+        self.keymap.command(code)
+    }
+
+    #[allow(dead_code)]
+    pub fn with_lock_delay(mut self, frames: usize) -> Self {
+        self.game.set_lock_delay(frames);
+        self
+    }
+
+    // frames without a piece after every lock, keys held in between keep repeating and
+    // move the next piece as soon as it spawns
+    #[allow(dead_code)]
+    pub fn with_delays(mut self, entry: usize, line_clear: usize) -> Self {
+        self.game.set_delays(entry, line_clear);
+        self
+    }
+
+    // start in the vertical layout instead of the horizontal one
+    #[allow(dead_code)]
+    pub fn vertical(mut self) -> Self {
+        self.orientation = Orientation::Vertical;
+        self
+    }
+
+    pub(super) fn window_mode(&self) -> WindowMode {
+        match self.orientation {
+            Orientation::Horizontal => HORIZONTAL_WINDOW_MODE,
+            Orientation::Vertical => VERTICAL_WINDOW_MODE,
+        }
+    }
+
+    fn window_dims(&self) -> (f32, f32) {
+        match self.orientation {
+            Orientation::Horizontal => HORIZONTAL_WINDOW_DIMS,
+            Orientation::Vertical => VERTICAL_WINDOW_DIMS,
+        }
+    }
+
+    // new pieces and the queue jump into place instead of sliding there
+    #[allow(dead_code)]
+    pub fn without_animations(mut self) -> Self {
+        self.animations = None;
+        self
+    }
+
+    // flip the controls, the board or both, best scores are kept separately
+    #[allow(dead_code)]
+    pub fn with_mirror(mut self, mirror: Mirror) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    // hide the queue, best scores are kept separately
+    #[allow(dead_code)]
+    pub fn with_queue_reveal(mut self, reveal: QueueReveal) -> Self {
+        self.reveal = Some(reveal);
+        self
+    }
+
+    // draw every frame through a shader, see effects
+    #[allow(dead_code)]
+    pub fn with_effects(mut self, effects: Effects) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    // count the pieces of each type in a panel
+    #[allow(dead_code)]
+    pub fn with_stats(mut self) -> Self {
+        self.stats = true;
+        self
+    }
+
+    // show the pieces left in the current bag (after the queue), for learning to count them
+    #[allow(dead_code)]
+    pub fn with_bag(mut self) -> Self {
+        self.bag = true;
+        self
+    }
+
+    // show the best score of the mode being played and save a better one at the end
+    #[allow(dead_code)]
+    pub fn with_best_scores(mut self, path: PathBuf, scores: BestScores) -> Self {
+        self.best = Some((path, scores));
+        self
+    }
+
+    // the daily challenge of the date (see daily::today), seeded by it and with bests of
+    // its own, call instead of seeded
+    #[allow(dead_code)]
+    pub fn daily(mut self, date: String) -> Self {
+        self = self.seeded(daily_seed(&date));
+        self.daily = Some(date);
+        self
+    }
+
+    // what the best score is kept under
+    fn mode(&self) -> String {
+        let mut mode = match &self.daily {
+            Some(date) => format!("daily {}, {}", date, mode(&self.game)),
+            None => mode(&self.game),
+        };
+        if let Some(mirror) = self.mirror {
+            mode += &format!(", mirrored {}", mirror.name());
+        }
+        if let Some(reveal) = self.reveal {
+            mode += &format!(", {}", reveal.name());
+        }
+        // assisted games don't compete with unassisted ones
+        if self.hint.is_some() {
+            mode += ", hinted";
+        }
+        if self.frame_step {
+            mode += ", frame step";
+        }
+        mode
+    }
+
+    // the best score to beat, None without best scores or before the first game of the mode
+    fn best(&self) -> Option<usize> {
+        let (_, scores) = self.best.as_ref()?;
+        scores.best(&self.mode())
+    }
+
+    fn save_best(&mut self) {
+        if let Some((path, mut scores)) = self.best.take() {
+            if scores.record(&self.mode(), self.game.points) {
+                save_scores(&path, &scores).expect("failed to save best scores");
+                println!("New best for {}: {}", self.mode(), self.game.points);
+            }
+        }
+    }
+
+    // announce achievements as they are unlocked and save them at the end, call after seeded
+    #[allow(dead_code)]
+    pub fn with_achievements(mut self, path: PathBuf, achievements: Achievements) -> Self {
+        self.game.subscribe_events();
+        self.achievements = Some((path, achievements));
+        self
+    }
+
+    // give the player objectives one after the other, shown under the points, call after
+    // seeded
+    #[allow(dead_code)]
+    pub fn with_missions(mut self, missions: Missions) -> Self {
+        self.game.subscribe_events();
+        self.missions = Some(missions);
+        self
+    }
+
+    // achievements and missions both go by the events of the frame
+    fn update_events(&mut self) {
+        let tick = self.game.tick;
+        self.toasts.retain(|&(_, until)| tick < until);
+        let events = self.game.take_events();
+        if let Some((_, achievements)) = self.achievements.as_mut() {
+            for achievement in achievements.update(&self.game, &events) {
+                println!("Achievement unlocked: {}", achievement.title());
+                let toast = achievement.title().to_string();
+                self.toasts.push((toast, tick + TOAST_FRAMES));
+            }
+        }
+        if let Some(missions) = self.missions.as_mut() {
+            let completed = missions.update(&events);
+            for objective in &completed {
+                println!("Mission complete: {}", objective.describe());
+                let toast = format!("Done: {}", objective.describe());
+                self.toasts.push((toast, tick + TOAST_FRAMES));
+            }
+            if !completed.is_empty() && missions.current().is_none() {
+                println!("All missions complete");
+                let toast = "All missions done".to_string();
+                self.toasts.push((toast, tick + TOAST_FRAMES));
+            }
+        }
+    }
+
+    fn save_achievements(&mut self) {
+        if let Some((path, achievements)) = self.achievements.take() {
+            save_achievements(&path, &achievements).expect("failed to save achievements");
+        }
+    }
+
+    // outline where the bot would put every piece
+    #[allow(dead_code)]
+    pub fn with_hint(mut self, bot: Box<dyn TetrisBot>) -> Self {
+        self.hint = Some(Hint {
+            bot,
+            placement: None,
+            piece: (usize::MAX, None),
+        });
+        self
+    }
+
+    // a gauge with the chance of surviving another estimator::HORIZON pieces
+    #[allow(dead_code)]
+    pub fn with_estimator(mut self, estimator: Estimator) -> Self {
+        self.gauge = Some(Gauge {
+            estimator,
+            estimate: 0.,
+            piece: usize::MAX,
+        });
+        self
+    }
+
+    // shows the banner and plays the sound when the level goes up, a game swapped in from
+    // outside (with a lower level) just starts over
+    fn update_level(&mut self, ctx: &mut Context) {
+        if self.game.level > self.level {
+            self.level_up_until = self.game.tick + LEVEL_UP_FRAMES;
+            if self.level_up_sound.is_none() {
+                // stays None without an audio device, the banner has to do then
+                self.level_up_sound = level_up_sound(ctx).ok();
+            }
+            if let Some(sound) = self.level_up_sound.as_mut() {
+                sound.play().ok();
+            }
+        } else if self.game.level < self.level {
+            self.level_up_until = 0;
+        }
+        self.level = self.game.level;
+    }
+
+    // the bloom starts over with every clear, a game swapped in from outside (with fewer
+    // lines) doesn't glow
+    fn update_bloom(&mut self) {
+        if self.game.cleared > self.cleared {
+            self.bloom_until = self.game.tick + BLOOM_FRAMES;
+        }
+        self.cleared = self.game.cleared;
+    }
+
+    fn showing_level_up(&self) -> bool {
+        self.game.tick < self.level_up_until
+    }
+
+    // starts the entry and queue animations and reveals a hidden queue when a piece was
+    // taken from the queue, a game swapped in from outside (with fewer pieces) just starts
+    // over
+    fn update_spawns(&mut self) {
+        let game = &self.game;
+        let dealt: usize = PieceId::ALL.iter().map(|&id| game.dealt(id)).sum();
+        if let Some(animations) = &mut self.animations {
+            animations.prune(game.tick);
+            if dealt > self.dealt {
+                animations.start(Animation::Entry, game.tick, ENTRY_FRAMES);
+                animations.start(Animation::QueueShift, game.tick, QUEUE_SHIFT_FRAMES);
+            }
+        }
+        match self.reveal {
+            Some(reveal) if dealt > self.dealt && reveal.reveals(dealt - 1) => {
+                self.revealed_until = game.tick + reveal.frames;
+            }
+            _ if dealt < self.dealt => self.revealed_until = 0,
+            _ => (),
+        }
+        self.dealt = dealt;
+    }
+
+    fn showing_queue(&self) -> bool {
+        self.reveal.is_none() || self.game.tick < self.revealed_until
+    }
+
+    // how far along an animation is, None when it isn't running
+    fn animation(&self, animation: Animation) -> Option<f32> {
+        let animations = self.animations.as_ref()?;
+        animations.progress(animation, self.game.tick)
+    }
+
+    fn update_hint(&mut self) {
+        let game = &self.game;
+        if let Some(hint) = &mut self.hint {
+            let piece = (game.pieces, game.hold);
+            // bots need a falling piece, the hint waits for the next one to spawn
+            if hint.piece != piece && !game.is_waiting() {
+                hint.piece = piece;
+                hint.placement = hint.bot.choose(&BoardView::new(game)).placement();
+            }
+        }
+    }
+
+    fn update_gauge(&mut self) {
+        let game = &self.game;
+        if let Some(gauge) = &mut self.gauge {
+            if gauge.piece != game.pieces && !game.is_waiting() {
+                gauge.piece = game.pieces;
+                gauge.estimate = gauge.estimator.estimate(game);
+            }
+        }
+    }
+
+    // everything drawn or chosen with the old masks or colors is thrown away
+    fn reload(&mut self) {
+        let reloaded = match &mut self.reloader {
+            Some(reloader) => reloader.poll(&mut self.game),
+            None => false,
+        };
+        if reloaded {
+            self.fixed_layer = None;
+            self.dynamic_layer = None;
+            if let Some(hint) = &mut self.hint {
+                hint.piece = (usize::MAX, None);
+            }
+            self.update_hint();
+        }
+    }
+
+    // record every input and save the replay in dir when the game ends
+    #[allow(dead_code)]
+    pub fn record_to(mut self, dir: &Path) -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.replay_path = Some(dir.join(format!("{}.replay", secs)));
+        self.game.start_input_log();
+        self
+    }
+
+    fn save_replay(&mut self) {
+        if let (Some(path), Some(replay)) = (self.replay_path.take(), self.game.input_replay()) {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).expect("failed to create replay directory");
+            }
+            save_input_replay(&path, &replay).expect("failed to save replay");
+            println!("saved replay in \"{}\"", path.display());
+        }
+    }
+
+    // save a heatmap of where the pieces were locked as an SVG when the game ends, call
+    // after seeded
+    #[allow(dead_code)]
+    pub fn with_heatmap(mut self, path: PathBuf) -> Self {
+        self.heatmap_path = Some(path);
+        self.game.start_recording();
+        self
+    }
+
+    fn save_heatmap(&mut self) {
+        if let (Some(path), Some(records)) = (self.heatmap_path.take(), self.game.recording()) {
+            Heatmap::of_records(records)
+                .save(&path)
+                .expect("failed to save heatmap");
+            println!("saved heatmap in \"{}\"", path.display());
         }
     }
 
     #[allow(dead_code)]
     pub fn run(&mut self) -> GameResult<()> {
-        run_game(self)
+        let window_mode = self.window_mode();
+        run_game_in(self, window_mode)
+    }
+}
+
+impl Default for VisGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Controller for VisGame {
+    fn queue_action(&mut self, action: Action) {
+        self.game.queue_action(action)
+    }
+
+    fn has_queued_actions(&self) -> bool {
+        self.game.has_queued_actions()
     }
 }
 
 const LEFT_MARGIN: f32 = 10.;
 const TOP_MARGIN: f32 = 10.;
 const SPACE_BETWEEN: f32 = 30.; // hspace between graphic elements such as hold and board
-const CELL_SIDE: f32 = 30.;
+pub(super) const CELL_SIDE: f32 = 30.;
 
 const PLAY_FPS: u64 = 60;
 const PLAY_WAIT: Duration = Duration::from_millis(1000 / PLAY_FPS);
 const PAUSE_FPS: u64 = 15;
 const PAUSE_WAIT: Duration = Duration::from_millis(1000 / PAUSE_FPS);
+const MINIMIZED_WAIT: Duration = Duration::from_millis(250);
+const COUNTDOWN_FRAMES: usize = 3 * PLAY_FPS as usize;
+const LEVEL_UP_FRAMES: usize = 90;
+const KICKS_FRAMES: usize = 40;
+const TOAST_FRAMES: usize = 180;
+const BLOOM_FRAMES: usize = 30;
+const ENTRY_FRAMES: usize = 6;
+const QUEUE_SHIFT_FRAMES: usize = 8;
+const ENTRY_CELLS: f32 = 1.; // how far above its place a new piece starts
+const KICK_FIT_COLOR: Color = Color::rgb(60, 220, 90);
+const KICK_MISS_COLOR: Color = Color::rgb(220, 60, 60);
+
+// of a countdown, rounded up
+fn seconds_left(frames: usize) -> usize {
+    frames.div_ceil(PLAY_FPS as usize)
+}
 
 impl VisGame {
-    fn do_key_action(&mut self, code: KeyCode, ctx: &mut Context) {
-        use KeyCode::*;
-        match code {
-            Left => self.game.move_falling_piece(-1, 0),
-            Right => self.game.move_falling_piece(1, 0),
-            Down => self.game.move_falling_piece(0, 1),
-            Up => self.game.rotate_falling_piece(1),
-            RShift => self.game.rotate_falling_piece(-1),
-            Space => self.game.hard_drop(),
-            J => self.game.switch_hold(),
-            Tab => self.switch_orientation(ctx),
-            Escape => self.paused = !self.paused,
-            c => panic!("unexpected KeyCode: {:?}", c),
+    fn do_command(&mut self, command: Command, ctx: &mut Context) {
+        match command {
+            // keys can be held during the countdown but only act once it's over
+            Command::Play(_) if self.countdown.is_some() => (),
+            Command::Play(action) => match self.mirror {
+                Some(mirror) if mirror.controls() => self.game.apply(action.mirrored()),
+                _ => self.game.apply(action),
+            },
+            Command::SwitchLayout => self.switch_orientation(ctx),
+            Command::Pause if self.auto_paused => self.resume(),
+            Command::Pause => {
+                self.paused = !self.paused;
+                self.countdown = None;
+            }
+            Command::Freeze if self.frame_step => {
+                self.frozen = !self.frozen;
+                self.steps = 0;
+            }
+            Command::Step if self.frozen => self.steps += 1,
+            Command::Freeze | Command::Step => (),
+            // only where someone else is playing
+            Command::TakeOver => (),
+        }
+    }
+
+    // the window was minimized or lost focus, keys released in the meantime wouldn't be
+    // noticed so every key counts as released
+    fn hide(&mut self) {
+        for info in self.keys.values_mut() {
+            info.state = PressedState::Up;
+        }
+        if !self.paused && !self.game.is_over() {
+            self.paused = true;
+            self.auto_paused = true;
+            self.countdown = None;
+        }
+    }
+
+    // after an automatic pause
+    fn resume(&mut self) {
+        self.paused = false;
+        self.auto_paused = false;
+        self.countdown = Some(COUNTDOWN_FRAMES);
+    }
+
+    pub(super) fn counting_down(&self) -> bool {
+        self.countdown.is_some()
+    }
+
+    pub(super) fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    // from any device, acts right away unless it was already held
+    fn press(&mut self, command: Command, ctx: &mut Context) {
+        let mut do_action = false;
+        self.keys.entry(command).and_modify(|key| {
+            if key.state == PressedState::Up {
+                key.state = match key.repeat {
+                    Repeat::Repeat { initial_delay, .. } => PressedState::Fresh(initial_delay),
+                    Repeat::NoRepeat => PressedState::Down,
+                };
+                do_action = true;
+            }
+        });
+        if do_action {
+            self.do_command(command, ctx)
         }
     }
+
+    fn release(&mut self, command: Command) {
+        self.keys.entry(command).and_modify(|v| {
+            v.state = PressedState::Up;
+        });
+    }
+}
+
+// what doesn't change from frame to frame (grid and panel backgrounds) goes to fixed and
+// is only rebuilt when the layout changes, everything else goes to dynamic
+struct Layers<'a> {
+    fixed: &'a mut dyn Renderer,
+    dynamic: &'a mut dyn Renderer,
+}
+
+// drops everything
+struct Discard;
+
+impl Renderer for Discard {
+    fn rect(&mut self, _rect: Rect, _color: Color) {}
+
+    fn line(&mut self, _points: &[(f32, f32)], _width: f32, _color: Color) {}
+
+    fn text(&mut self, _pos: (f32, f32), _text: &str, _color: Color) {}
 }
 
 const MARGIN: f32 = 0.1;
 const SIDE: f32 = CELL_SIDE - 2. * MARGIN;
+const PANEL_COLOR: Color = Color::rgb(56, 56, 56);
+const HOLD_SIDES: (f32, f32) = ((4. + 2.) * CELL_SIDE, (1. * 3. + 2.) * CELL_SIDE);
+const LEVEL_UP_COLOR: Color = Color::rgb(240, 200, 60);
 
 // drawing
 impl VisGame {
-    fn add_piece_at(&self, (vis_x, vis_y): (f32, f32), id: PieceId, builder: &mut MeshBuilder) {
+    fn add_piece_at(
+        &self,
+        (vis_x, vis_y): (f32, f32),
+        id: PieceId,
+        color: Color,
+        r: &mut dyn Renderer,
+    ) {
         let mask = self.game.mask_map[&id][0];
-        for (rel_y, row) in mask.iter().enumerate() {
-            for (rel_x, &val) in row.iter().enumerate() {
-                if val {
-                    let rect = Rect {
-                        x: vis_x + rel_x as f32 * CELL_SIDE,
-                        y: vis_y + rel_y as f32 * CELL_SIDE,
-                        w: SIDE,
-                        h: SIDE,
-                    };
-                    builder.rectangle(DrawMode::Fill(FillOptions::default()), rect, id.color());
-                }
-            }
+        for (rel_x, rel_y) in mask.cells_at((0, 0)) {
+            let rect = Rect {
+                x: vis_x + rel_x as f32 * CELL_SIDE,
+                y: vis_y + rel_y as f32 * CELL_SIDE,
+                w: SIDE,
+                h: SIDE,
+            };
+            r.rect(rect, color);
+        }
+    }
+
+    // smaller, with the top-left cell of the piece at (vis_x, vis_y)
+    fn add_icon_at(
+        &self,
+        (vis_x, vis_y): (f32, f32),
+        id: PieceId,
+        scale: f32,
+        r: &mut dyn Renderer,
+    ) {
+        let mask = self.game.mask_map[&id][0];
+        let side = scale * CELL_SIDE;
+        for (rel_x, rel_y) in mask.cells_at((-mask.min.0, -mask.min.1)) {
+            let rect = Rect {
+                x: vis_x + rel_x as f32 * side,
+                y: vis_y + rel_y as f32 * side,
+                w: side - 2. * MARGIN,
+                h: side - 2. * MARGIN,
+            };
+            r.rect(rect, id.color());
+        }
+    }
+
+    // top-left corner of the board, right of hold
+    pub(super) fn board_pos(&self) -> (f32, f32) {
+        (LEFT_MARGIN + HOLD_SIDES.0 + SPACE_BETWEEN, TOP_MARGIN)
+    }
+
+    // the free space in the left column (below hold) for whoever wants to draw there, the
+    // statistics panel is drawn at its top if enabled
+    pub(super) fn side_panel(&self) -> Rect {
+        let (x, y) = self.side_panel;
+        let (_, window_height) = self.window_dims();
+        Rect {
+            x,
+            y,
+            w: HOLD_SIDES.0,
+            h: window_height - y - TOP_MARGIN,
         }
     }
 
     // return (bottom, right)
-    fn add_hold(&mut self, builder: &mut MeshBuilder) -> (f32, f32) {
+    fn add_hold(&self, l: &mut Layers) -> (f32, f32) {
         // background
         let left = LEFT_MARGIN;
         let top = TOP_MARGIN;
-        let width = (4. + 2.) * CELL_SIDE;
-        let height = (1. * 3. + 2.) * CELL_SIDE;
+        let (width, height) = HOLD_SIDES;
         let bg_rect = Rect {
             x: left,
             y: top,
             w: width,
             h: height,
         };
-        builder.rectangle(
-            DrawMode::Fill(FillOptions::default()),
-            bg_rect,
-            Color::from_rgb(56, 56, 56),
-        );
+        l.fixed.rect(bg_rect, PANEL_COLOR);
         // piece
         if let Some(id) = self.game.hold {
             let vis_x = left + CELL_SIDE;
             let vis_y = top + CELL_SIDE;
+            // greyed out while hold can't be used again
+            let color = if self.game.can_hold() {
+                id.color()
+            } else {
+                id.color().greyed()
+            };
             // TODO: correct for non-centered pieces
-            self.add_piece_at((vis_x, vis_y), id, builder)
+            self.add_piece_at((vis_x, vis_y), id, color, l.dynamic)
         }
         (top + height, left + width)
     }
 
     // return (bottom, right)
-    fn add_grid(
-        &mut self,
-        (left, top): (f32, f32),
-        builder: &mut MeshBuilder,
-    ) -> GameResult<(f32, f32)> {
-        // not necessary because background is already black
-        // let bg = Rect {
-        //     x: left,
-        //     y: top,
-        //     w: GAME_WIDTH as f32 * CELL_SIDE,
-        //     h: GAME_HEIGHT as f32 * CELL_SIDE,
-        // };
-        // builder.rectangle(DrawMode::fill(), bg, BLACK);
-        let grid_color = Color::from_rgb(50, 50, 50);
+    fn add_grid(&self, (left, top): (f32, f32), r: &mut dyn Renderer) -> (f32, f32) {
+        // no background necessary because the background is already black
+        let grid_color = Color::rgb(50, 50, 50);
+        let (bottom, right) = (
+            top + GAME_HEIGHT as f32 * CELL_SIDE,
+            left + GAME_WIDTH as f32 * CELL_SIDE,
+        );
         for rel_x in 0..=GAME_WIDTH {
             let abs_x = left + rel_x as f32 * CELL_SIDE;
-            builder.line(
-                &[
-                    Point2 { x: abs_x, y: top },
-                    Point2 {
-                        x: abs_x,
-                        y: top + GAME_HEIGHT as f32 * CELL_SIDE,
-                    },
-                ],
-                1.,
-                grid_color,
-            )?;
+            r.line(&[(abs_x, top), (abs_x, bottom)], 1., grid_color);
         }
         for rel_y in 0..=GAME_HEIGHT {
             let abs_y = top + rel_y as f32 * CELL_SIDE;
-            builder.line(
-                &[
-                    Point2 { x: left, y: abs_y },
-                    Point2 {
-                        x: left + GAME_WIDTH as f32 * CELL_SIDE,
-                        y: abs_y,
-                    },
-                ],
-                1.,
-                grid_color,
-            )?;
-        }
-        Ok((
-            top + GAME_HEIGHT as f32 * CELL_SIDE,
-            left + GAME_WIDTH as f32 * CELL_SIDE,
-        ))
+            r.line(&[(left, abs_y), (right, abs_y)], 1., grid_color);
+        }
+        (bottom, right)
     }
 
-    fn add_pixels(&mut self, (left, top): (f32, f32), builder: &mut MeshBuilder) {
-        for (r, row) in self.game.board.iter().enumerate() {
+    fn add_pixels(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        for (row_idx, row) in self.game.board.iter().enumerate() {
             for (c, px) in row.iter().enumerate() {
-                if let Pixel::Full(id) = px {
+                if let Some(color) = px.color() {
                     let left = left + c as f32 * CELL_SIDE + MARGIN;
-                    let top = top + r as f32 * CELL_SIDE + MARGIN;
+                    let top = top + row_idx as f32 * CELL_SIDE + MARGIN;
                     let rect = Rect {
                         x: left,
                         y: top,
                         w: SIDE,
                         h: SIDE,
                     };
-                    builder.rectangle(DrawMode::Fill(FillOptions::default()), rect, id.color());
+                    r.rect(rect, color);
                 }
             }
         }
     }
 
+    // outline of the whole piece
     fn add_shadow(
         (left, top): (f32, f32),
-        falling: &FallingPiece,
-        lowest_y: isize,
-        builder: &mut MeshBuilder,
-    ) -> GameResult<()> {
-        for rel_y in 0..4 {
-            for rel_x in 0..4 {
-                if falling.mask[rel_y][rel_x] {
-                    let abs_y = (rel_y as isize + lowest_y) as usize;
-                    let abs_x = (rel_x as isize + falling.pos.0) as usize;
-                    let vis_y = top + abs_y as f32 * CELL_SIDE;
-                    let vis_x = left + abs_x as f32 * CELL_SIDE;
-
-                    // each pixel outline
-                    // let rect = Rect {
-                    //     x: vis_x,
-                    //     y: vis_y,
-                    //     w: SIDE,
-                    //     h: SIDE,
-                    // };
-                    // builder.rectangle(DrawMode::stroke(3.), rect, falling.id.color());
-
-                    // fainter color (looks bad)
-                    // let rgb = falling.id.color().to_rgb();
-                    // let increase_possible = rgb.map(|x| 255. / x as f32);
-                    // let min_increase_possible = increase_possible.tmin();
-                    // let (r, g, b) = rgb.map(|x| {
-                    //     let dx = (min_increase_possible * x as f32) as u8;
-                    //     x + min(dx, 255 - x)
-                    // });
-                    // builder.rectangle(DrawMode::fill(), rect, Color::from_rgb(r, g, b));
-
-                    // full block outline
-                    let color = falling.id.color();
-                    if rel_y == 0 || !falling.mask[rel_y - 1][rel_x] {
-                        // top line
-                        builder.line(
-                            &[
-                                Point2 { x: vis_x, y: vis_y },
-                                Point2 {
-                                    x: vis_x + SIDE,
-                                    y: vis_y,
-                                },
-                            ],
-                            3.,
-                            color,
-                        )?;
-                    }
-                    if rel_y == 3 || !falling.mask[rel_y + 1][rel_x] {
-                        // bottom line
-                        builder.line(
-                            &[
-                                Point2 {
-                                    x: vis_x,
-                                    y: vis_y + SIDE,
-                                },
-                                Point2 {
-                                    x: vis_x + SIDE,
-                                    y: vis_y + SIDE,
-                                },
-                            ],
-                            3.,
-                            color,
-                        )?;
-                    }
-                    if rel_x == 0 || !falling.mask[rel_y][rel_x - 1] {
-                        // left line
-                        builder.line(
-                            &[
-                                Point2 { x: vis_x, y: vis_y },
-                                Point2 {
-                                    x: vis_x,
-                                    y: vis_y + SIDE,
-                                },
-                            ],
-                            3.,
-                            color,
-                        )?;
-                    }
-                    if rel_x == 3 || !falling.mask[rel_y][rel_x + 1] {
-                        // right line
-                        builder.line(
-                            &[
-                                Point2 {
-                                    x: vis_x + SIDE,
-                                    y: vis_y,
-                                },
-                                Point2 {
-                                    x: vis_x + SIDE,
-                                    y: vis_y + SIDE,
-                                },
-                            ],
-                            3.,
-                            color,
-                        )?;
-                    }
-                }
+        mask: &Mask,
+        (x, y): (isize, isize),
+        color: Color,
+        r: &mut dyn Renderer,
+    ) {
+        for (abs_x, abs_y) in mask.cells_at((x, y)) {
+            let vis_y = top + abs_y as f32 * CELL_SIDE;
+            let vis_x = left + abs_x as f32 * CELL_SIDE;
+            let (right, bottom) = (vis_x + SIDE, vis_y + SIDE);
+            // an edge is drawn where the neighboring cell isn't part of the piece
+            let open = |dx, dy| !mask.contains((abs_x - x + dx, abs_y - y + dy));
+
+            if open(0, -1) {
+                r.line(&[(vis_x, vis_y), (right, vis_y)], 3., color);
+            }
+            if open(0, 1) {
+                r.line(&[(vis_x, bottom), (right, bottom)], 3., color);
+            }
+            if open(-1, 0) {
+                r.line(&[(vis_x, vis_y), (vis_x, bottom)], 3., color);
+            }
+            if open(1, 0) {
+                r.line(&[(right, vis_y), (right, bottom)], 3., color);
             }
         }
-        Ok(())
     }
 
-    fn add_falling(
-        &mut self,
-        (left, top): (f32, f32),
-        builder: &mut MeshBuilder,
-    ) -> GameResult<()> {
+    fn add_falling(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
         if let Some(falling) = self.game.falling.as_ref() {
             let mask = falling.mask;
-            let color;
-
-            if falling.is_touching_ground(&self.game.board) {
-                let lock_delay_ratio = self
-                    .game
-                    .falling
-                    .as_ref()
-                    .expect("can't draw a falling piece if there isn't one")
-                    .lock_delay as f32
-                    / FallingPiece::LOCK_DELAY as f32;
-                let mut rgb = falling.id.color().to_rgb();
-                rgb = rgb.map(|x| (x as f32 * lock_delay_ratio) as u8);
-                color = Color::from(rgb);
+            let mut color = if falling.is_touching_ground(&self.game.board) {
+                // darker as the lock delay runs out
+                let full = self.game.lock_delay().max(1);
+                let lock_delay_ratio = falling.lock_delay as f32 / full as f32;
+                falling.id.color().scaled(lock_delay_ratio)
             } else {
-                color = falling.id.color();
                 // shadow
-                let lowest_y = (falling.pos.1 + 1..GAME_HEIGHT as isize)
-                    .take_while(|&i| !intersects_with(&mask, (falling.pos.0, i), &self.game.board))
-                    .last()
-                    .expect("this should be Some, piece should not be touching ground");
-                Self::add_shadow((left, top), falling, lowest_y, builder)?;
+                let pos = self.game.ghost_pos().expect("there is a falling piece");
+                Self::add_shadow((left, top), &mask, pos, falling.id.color(), r);
+                falling.id.color()
+            };
+            // a new piece slides down into place and brightens, the shadow is already there
+            let mut slide = 0.;
+            if let Some(progress) = self.animation(Animation::Entry) {
+                slide = (1. - progress) * ENTRY_CELLS * CELL_SIDE;
+                color = color.scaled(0.4 + 0.6 * progress);
             }
 
             // piece
-            for (rel_y, row) in mask.iter().enumerate() {
-                for (rel_x, &val) in row.iter().enumerate() {
-                    if val {
-                        let abs_y = (rel_y as isize + falling.pos.1) as usize;
-                        let abs_x = (rel_x as isize + falling.pos.0) as usize;
-                        let vis_y = top + abs_y as f32 * CELL_SIDE;
-                        let vis_x = left + abs_x as f32 * CELL_SIDE;
-                        let rect = Rect {
-                            x: vis_x,
-                            y: vis_y,
-                            w: SIDE,
-                            h: SIDE,
-                        };
-                        builder.rectangle(DrawMode::Fill(FillOptions::default()), rect, color);
-                    }
-                }
+            for (abs_x, abs_y) in mask.cells_at(falling.pos) {
+                let rect = Rect {
+                    x: left + abs_x as f32 * CELL_SIDE,
+                    y: top + abs_y as f32 * CELL_SIDE - slide,
+                    w: SIDE,
+                    h: SIDE,
+                };
+                r.rect(rect, color);
             }
         }
+    }
 
-        Ok(())
+    fn add_hint(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        let placement = self.hint.as_ref().and_then(|hint| hint.placement.as_ref());
+        if let (Some(placement), Some(falling)) = (placement, self.game.falling.as_ref()) {
+            // placements through hold are for a different piece
+            if placement.inputs.first() != Some(&Action::Hold) {
+                let mask = &self.game.mask_map[&falling.id][placement.mask_idx];
+                Self::add_shadow((left, top), mask, placement.pos, Color::WHITE, r);
+            }
+        }
+    }
+
+    // the falling piece as the game sees it, for frame-step debugging
+    fn add_frame_info(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        let game = &self.game;
+        let state = if self.frozen { "frozen" } else { "running" };
+        let mut lines = vec![format!("Frame {} {}", game.tick, state)];
+        match game.falling.as_ref() {
+            Some(falling) => lines.extend(vec![
+                format!("pos {} {}", falling.pos.0, falling.pos.1),
+                format!("mask_idx {}", falling.mask_idx),
+                format!("lock_delay {}", falling.lock_delay),
+                format!("resets {}", falling.lock_delay_resets),
+                format!("fall {}", game.fall),
+            ]),
+            None => lines.push("no piece".to_string()),
+        }
+        for (i, line) in lines.iter().enumerate() {
+            r.text((left, top + i as f32 * CELL_SIDE), line, Color::WHITE);
+        }
+    }
+
+    // the positions the last rotation tried, numbered, for a moment after it, the one the
+    // piece went to in green
+    fn add_kicks(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        let trace = match self.game.last_rotation() {
+            Some(trace) if self.game.tick < trace.tick + KICKS_FRAMES => trace,
+            _ => return,
+        };
+        let mask = &self.game.mask_map[&trace.id][trace.mask_idx];
+        for (i, &pos) in trace.tried.iter().enumerate() {
+            let color = if i + 1 == trace.tried.len() && trace.succeeded {
+                KICK_FIT_COLOR
+            } else {
+                KICK_MISS_COLOR
+            };
+            Self::add_shadow((left, top), mask, pos, color, r);
+            let (x, y) = mask.cells_at(pos).next().expect("masks have 4 cells");
+            let text_pos = (
+                left + (x as f32 + 0.3) * CELL_SIDE,
+                top + (y as f32 + 0.2) * CELL_SIDE,
+            );
+            r.text(text_pos, &(i + 1).to_string(), color);
+        }
     }
 
     // return (bottom, right)
-    fn add_queue(&mut self, (left, top): (f32, f32), builder: &mut MeshBuilder) -> (f32, f32) {
+    fn add_queue(&self, (left, top): (f32, f32), l: &mut Layers) -> (f32, f32) {
         // background
         let (width, height) = match self.orientation {
             // tall and thin / short and wide
@@ -433,22 +1116,42 @@ impl VisGame {
             w: width,
             h: height,
         };
-        builder.rectangle(DrawMode::fill(), bg_rect, Color::from_rgb(56, 56, 56));
-        // pieces
+        l.fixed.rect(bg_rect, PANEL_COLOR);
+        if !self.showing_queue() {
+            self.add_hidden_queue((left, top), l.dynamic);
+            return (top + height, left + width);
+        }
+        // pieces, right after a spawn they are still moving up (left) from where they were
+        // by a fraction of a slot and the last one, which has nowhere to come from, fades in
+        let shift = self.animation(Animation::QueueShift);
+        let behind = shift.map_or(0., |progress| 1. - progress);
+        let last = self.game.piece_queue.iter().count().saturating_sub(1);
+        let color = |i: usize, id: PieceId| match shift {
+            Some(progress) if i == last => id.color().scaled(progress),
+            _ => id.color(),
+        };
+        let slot = |i: usize| {
+            let i = if i == last {
+                i as f32
+            } else {
+                i as f32 + behind
+            };
+            (i * 5. + i + 1.) * CELL_SIDE
+        };
         match self.orientation {
             Orientation::Horizontal => {
                 let x = left + CELL_SIDE;
                 for (i, id) in self.game.piece_queue.iter().enumerate() {
-                    let y = top + (i as f32 * 5. + (i + 1) as f32) * CELL_SIDE;
-                    self.add_piece_at((x, y), id, builder);
+                    let y = top + slot(i);
+                    self.add_piece_at((x, y), id, color(i, id), l.dynamic);
                 }
             }
             Orientation::Vertical => {
                 let scale = 0.8;
                 let y = top + scale * CELL_SIDE;
                 for (i, id) in self.game.piece_queue.iter().enumerate() {
-                    let x = left + scale * (i as f32 * 5. + (i + 1) as f32) * CELL_SIDE;
-                    self.add_piece_at((x, y), id, builder);
+                    let x = left + scale * slot(i);
+                    self.add_piece_at((x, y), id, color(i, id), l.dynamic);
                 }
             }
         }
@@ -456,16 +1159,34 @@ impl VisGame {
         (top + height, left + width)
     }
 
-    fn add_text_info(
-        &self,
-        (left, top): (f32, f32),
-        builder: &mut MeshBuilder,
-        ctx: &mut Context,
-    ) -> f32 {
+    // a question mark in the middle of every slot of the queue
+    fn add_hidden_queue(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        for i in 0..self.game.piece_queue.iter().count() {
+            let slot = (i as f32 * 6. + 1.) * CELL_SIDE;
+            let pos = match self.orientation {
+                Orientation::Horizontal => (left + 2.8 * CELL_SIDE, top + slot + 0.8 * CELL_SIDE),
+                Orientation::Vertical => {
+                    (left + 0.8 * slot + 1.4 * CELL_SIDE, top + 1.4 * CELL_SIDE)
+                }
+            };
+            r.text(pos, "?", Color::WHITE);
+        }
+    }
+
+    // return bottom
+    fn add_text_info(&self, (left, top): (f32, f32), fps: f64, l: &mut Layers) -> f32 {
+        // the most lines there can be, the panel is on the fixed layer and stays as tall
+        let optional = [
+            self.best.is_some(),
+            self.daily.is_some(),
+            self.missions.is_some(),
+            self.missions.is_some(),
+        ];
+        let most = 4 + optional.iter().filter(|&&shown| shown).count();
         let (width, height) = match self.orientation {
             // tall-ish / wide-ish
             Orientation::Horizontal => (6. * CELL_SIDE, 10. * CELL_SIDE),
-            Orientation::Vertical => (6. * CELL_SIDE, 6.5 * CELL_SIDE),
+            Orientation::Vertical => (6. * CELL_SIDE, (most as f32 + 0.5).max(6.5) * CELL_SIDE),
         };
         let bg_rect = Rect {
             x: left,
@@ -473,39 +1194,139 @@ impl VisGame {
             w: width,
             h: height,
         };
-        builder.rectangle(DrawMode::fill(), bg_rect, Color::from_rgb(56, 56, 56));
-        let text_positions = match self.orientation {
-            Orientation::Horizontal => (1..=4)
-                .map(|i| Point2 {
-                    x: left + CELL_SIDE,
-                    y: top + i as f32 * CELL_SIDE,
-                })
-                .collect::<Vec<_>>(),
-            Orientation::Vertical => (0..=3)
-                .map(|i| Point2 {
-                    x: left + CELL_SIDE,
-                    y: top + (i as f32 + 0.5) * CELL_SIDE,
-                })
-                .collect::<Vec<_>>(),
+        l.fixed.rect(bg_rect, PANEL_COLOR);
+        let text_position = |i: usize| match self.orientation {
+            Orientation::Horizontal => (left + CELL_SIDE, top + (i + 1) as f32 * CELL_SIDE),
+            Orientation::Vertical => (left + CELL_SIDE, top + (i as f32 + 0.5) * CELL_SIDE),
         };
 
-        macro_rules! queue_text {
-            ($pos:expr, $( $fmt:expr ),*) => {
-                queue_text(
-                    ctx, &Text::new(format!($( $fmt ),*)), text_positions[$pos], Some(WHITE)
-                );
+        // the points and the best turn gold once the best is beaten, it is only updated at
+        // the end of the game
+        let best = self.best.as_ref().map(|_| self.best());
+        let points_color = match best {
+            Some(Some(best)) if self.game.points > best => LEVEL_UP_COLOR,
+            _ => Color::WHITE,
+        };
+        let mut lines = vec![(format!("{}", self.game.points), points_color)];
+        if let Some(best) = best {
+            let best = best.map_or("-".to_string(), |best| best.to_string());
+            lines.push((format!("Best {}", best), points_color));
+        }
+        if let Some(date) = &self.daily {
+            lines.push((format!("Daily {}", date), Color::WHITE));
+        }
+        if let Some(missions) = &self.missions {
+            let (done, all) = missions.progress();
+            lines.push((format!("Mission {}/{}", done, all), Color::WHITE));
+            if let Some(objective) = missions.current() {
+                lines.push((objective.describe(), LEVEL_UP_COLOR));
             }
         }
-        queue_text!(0, "{}", self.game.points);
-        queue_text!(1, "Level {}", self.game.level);
-        queue_text!(2, "Cleared {}", self.game.cleared);
-        queue_text!(3, "fps {}", ggez::timer::fps(ctx) as u32);
+        lines.extend(vec![
+            (format!("Level {}", self.game.level), Color::WHITE),
+            (format!("Cleared {}", self.game.cleared), Color::WHITE),
+            (format!("fps {}", fps as u32), Color::WHITE),
+        ]);
+        for (i, (line, color)) in lines.iter().enumerate() {
+            l.dynamic.text(text_position(i), line, *color);
+        }
+
+        top + height
+    }
+
+    // how many of each piece were dealt and the height and holes of the stack, return bottom
+    fn add_stats(&self, (left, top): (f32, f32), l: &mut Layers) -> f32 {
+        // (icon scale, row height)
+        let (scale, row) = match self.orientation {
+            Orientation::Horizontal => (0.5, 1.5 * CELL_SIDE),
+            Orientation::Vertical => (0.3, 0.9 * CELL_SIDE),
+        };
+        let width = 6. * CELL_SIDE;
+        let height = (PieceId::ALL.len() + 1) as f32 * row + 0.5 * CELL_SIDE;
+        let bg_rect = Rect {
+            x: left,
+            y: top,
+            w: width,
+            h: height,
+        };
+        l.fixed.rect(bg_rect, PANEL_COLOR);
+        for (i, &id) in PieceId::ALL.iter().enumerate() {
+            let y = top + 0.25 * CELL_SIDE + i as f32 * row;
+            self.add_icon_at((left + CELL_SIDE, y + 0.1 * row), id, scale, l.dynamic);
+            let count = self.game.dealt(id).to_string();
+            l.dynamic.text(
+                (left + 3.5 * CELL_SIDE, y + 0.25 * row),
+                &count,
+                Color::WHITE,
+            );
+        }
+        let metrics = self.game.metrics();
+        let stack = format!("h {} holes {}", metrics.max_height(), metrics.holes());
+        let y = top + 0.25 * CELL_SIDE + PieceId::ALL.len() as f32 * row;
+        l.dynamic.text(
+            (left + 0.3 * CELL_SIDE, y + 0.25 * row),
+            &stack,
+            Color::WHITE,
+        );
 
         top + height
     }
 
+    // a bar that fills up and turns from red to green with the estimate, return bottom
+    fn add_gauge(&self, (left, top): (f32, f32), estimate: f64, l: &mut Layers) -> f32 {
+        let width = 6. * CELL_SIDE;
+        let height = 2.5 * CELL_SIDE;
+        let bg_rect = Rect {
+            x: left,
+            y: top,
+            w: width,
+            h: height,
+        };
+        l.fixed.rect(bg_rect, PANEL_COLOR);
+        let text = format!("Survival {}%", (estimate * 100.) as u32);
+        l.dynamic.text(
+            (left + 0.3 * CELL_SIDE, top + 0.25 * CELL_SIDE),
+            &text,
+            Color::WHITE,
+        );
+        let bar = Rect {
+            x: left + 0.3 * CELL_SIDE,
+            y: top + 1.4 * CELL_SIDE,
+            w: (width - 0.6 * CELL_SIDE) * estimate as f32,
+            h: 0.7 * CELL_SIDE,
+        };
+        let color = Color::rgb((255. * (1. - estimate)) as u8, (255. * estimate) as u8, 60);
+        l.dynamic.rect(bar, color);
+
+        top + height
+    }
+
+    // up to four icons per row, return bottom
+    fn add_bag(&self, (left, top): (f32, f32), l: &mut Layers) -> f32 {
+        let height = 3.5 * CELL_SIDE;
+        let bg_rect = Rect {
+            x: left,
+            y: top,
+            w: 6. * CELL_SIDE,
+            h: height,
+        };
+        l.fixed.rect(bg_rect, PANEL_COLOR);
+        l.dynamic.text(
+            (left + 0.3 * CELL_SIDE, top + 0.25 * CELL_SIDE),
+            "Bag",
+            Color::WHITE,
+        );
+        let remaining = self.game.piece_queue.remaining_bag();
+        for (i, &id) in remaining.iter().enumerate() {
+            let x = left + 0.3 * CELL_SIDE + (i % 4) as f32 * 1.4 * CELL_SIDE;
+            let y = top + 1.25 * CELL_SIDE + (i / 4) as f32 * CELL_SIDE;
+            self.add_icon_at((x, y), id, 0.3, l.dynamic);
+        }
+        top + height
+    }
+
     // return bottom
-    fn add_keys(&self, (left, top): (f32, f32), builder: &mut MeshBuilder) -> f32 {
+    fn add_keys(&self, (left, top): (f32, f32), l: &mut Layers) -> f32 {
         let scale = match self.orientation {
             Orientation::Horizontal => 1.,
             Orientation::Vertical => 0.6, // 10 wide in a space of 6
@@ -518,8 +1339,8 @@ impl VisGame {
             w: width,
             h: height,
         };
-        builder.rectangle(DrawMode::fill(), bg_rect, Color::from_rgb(56, 56, 56));
-        let mut key_bg = |code, x, y, rel_width| {
+        l.fixed.rect(bg_rect, PANEL_COLOR);
+        let mut key_bg = |action, x, y, rel_width| {
             let cells = rel_width * 3 - 1;
             let rect = Rect {
                 x: left + scale * x as f32 * CELL_SIDE,
@@ -527,32 +1348,328 @@ impl VisGame {
                 w: scale * cells as f32 * CELL_SIDE,
                 h: scale * 2. * CELL_SIDE,
             };
-            builder.rectangle(
-                DrawMode::fill(),
-                rect,
-                if self.keys[&code].state.is_pressed() {
-                    Color::from_rgb(181, 45, 45)
-                } else {
-                    Color::from_rgb(102, 25, 25)
-                },
-            );
+            let color = if self.keys[&Command::Play(action)].state.is_pressed() {
+                Color::rgb(181, 45, 45)
+            } else {
+                Color::rgb(102, 25, 25)
+            };
+            l.dynamic.rect(rect, color);
         };
 
-        use KeyCode::*;
-        key_bg(Up, 4, 1, 1);
-        key_bg(Down, 4, 4, 1);
-        key_bg(Left, 1, 4, 1);
-        key_bg(Right, 7, 4, 1);
-        key_bg(J, 1, 7, 1);
-        key_bg(RShift, 4, 7, 2);
-        key_bg(Space, 1, 10, 3);
+        // laid out like the default key bindings
+        use Action::*;
+        key_bg(Rotate180, 1, 1, 1);
+        key_bg(RotateCW, 4, 1, 1);
+        key_bg(SoftDrop, 4, 4, 1);
+        key_bg(MoveLeft, 1, 4, 1);
+        key_bg(MoveRight, 7, 4, 1);
+        key_bg(Hold, 1, 7, 1);
+        key_bg(RotateCCW, 4, 7, 2);
+        key_bg(HardDrop, 1, 10, 3);
 
         top + height
     }
+
+    // across the middle of the board
+    fn add_level_up(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        let rect = Rect {
+            x: left,
+            y: top + (GAME_HEIGHT as f32 / 2. - 1.5) * CELL_SIDE,
+            w: GAME_WIDTH as f32 * CELL_SIDE,
+            h: 3. * CELL_SIDE,
+        };
+        r.rect(rect, LEVEL_UP_COLOR);
+        let text = format!("LEVEL {}", self.game.level);
+        let pos = (rect.x + 3.5 * CELL_SIDE, rect.y + 1.2 * CELL_SIDE);
+        r.text(pos, &text, Color::BLACK);
+    }
+
+    // one line per achievement unlocked or mission completed in the last few seconds, at
+    // the top of the board
+    fn add_toasts(&self, (left, top): (f32, f32), r: &mut dyn Renderer) {
+        for (i, (text, _)) in self.toasts.iter().enumerate() {
+            let rect = Rect {
+                x: left,
+                y: top + i as f32 * 1.5 * CELL_SIDE,
+                w: GAME_WIDTH as f32 * CELL_SIDE,
+                h: 1.3 * CELL_SIDE,
+            };
+            r.rect(rect, LEVEL_UP_COLOR);
+            let pos = (rect.x + 0.3 * CELL_SIDE, rect.y + 0.3 * CELL_SIDE);
+            r.text(pos, text, Color::BLACK);
+        }
+    }
+
+    // the seconds left before the game goes on, like the level up banner
+    fn add_countdown(&self, (left, top): (f32, f32), frames: usize, r: &mut dyn Renderer) {
+        let rect = Rect {
+            x: left,
+            y: top + (GAME_HEIGHT as f32 / 2. - 1.5) * CELL_SIDE,
+            w: GAME_WIDTH as f32 * CELL_SIDE,
+            h: 3. * CELL_SIDE,
+        };
+        r.rect(rect, PANEL_COLOR);
+        let pos = (rect.x + 4.8 * CELL_SIDE, rect.y + 1.2 * CELL_SIDE);
+        r.text(pos, &seconds_left(frames).to_string(), Color::WHITE);
+    }
+
+    // changes whenever something on the dynamic layer does
+    fn dynamic_key(&self, fps: f64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let game = &self.game;
+        (
+            game.board_hash(),
+            game.pieces,
+            game.points,
+            game.level,
+            game.cleared,
+        )
+            .hash(&mut hasher);
+        if let Some(falling) = game.falling.as_ref() {
+            (
+                falling.id,
+                falling.pos,
+                falling.mask_idx,
+                falling.lock_delay,
+            )
+                .hash(&mut hasher);
+        }
+        (game.hold, game.can_hold()).hash(&mut hasher);
+        if self.stats {
+            PieceId::ALL
+                .iter()
+                .for_each(|&id| game.dealt(id).hash(&mut hasher));
+        }
+        game.piece_queue.iter().for_each(|id| id.hash(&mut hasher));
+        self.showing_queue().hash(&mut hasher);
+        if self.bag {
+            game.piece_queue.remaining_bag().hash(&mut hasher);
+        }
+        let percent = |gauge: &Gauge| (gauge.estimate * 100.) as u32;
+        self.gauge.as_ref().map(percent).hash(&mut hasher);
+        let mut pressed = self
+            .keys
+            .iter()
+            .map(|(&command, info)| (command, info.state.is_pressed()))
+            .collect::<Vec<_>>();
+        pressed.sort_unstable();
+        pressed.hash(&mut hasher);
+        let placement = self.hint.as_ref().and_then(|hint| hint.placement.as_ref());
+        placement
+            .map(|p| (p.mask_idx, p.pos, p.inputs.first() == Some(&Action::Hold)))
+            .hash(&mut hasher);
+        let kicks = self.game.last_rotation();
+        kicks
+            .filter(|trace| game.tick < trace.tick + KICKS_FRAMES)
+            .map(|trace| (trace.tick, &trace.tried))
+            .hash(&mut hasher);
+        (self.paused, self.showing_level_up()).hash(&mut hasher);
+        if self.frame_step {
+            (self.frozen, game.tick, game.fall).hash(&mut hasher);
+            let resets = game.falling.as_ref().map(|f| f.lock_delay_resets);
+            resets.hash(&mut hasher);
+        }
+        self.toasts.hash(&mut hasher);
+        let frame = |animation| self.animation(animation).map(|p| (p * 100.) as u32);
+        (frame(Animation::Entry), frame(Animation::QueueShift)).hash(&mut hasher);
+        self.countdown.map(seconds_left).hash(&mut hasher);
+        (self.orientation, fps as u32).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // the whole window, fps is only displayed
+    // return the top-left corner of the free space in the left column, see side_panel
+    fn render(&self, fps: f64, l: &mut Layers) -> (f32, f32) {
+        // left quadrant
+        let (hold_bottom, _) = self.add_hold(l);
+        // main quadrant
+        let pos = self.board_pos();
+        let (bottom, right) = self.add_grid(pos, l.fixed);
+        let mut mirrored;
+        let board: &mut dyn Renderer = match self.mirror {
+            Some(mirror) if mirror.board() => {
+                let span = (pos.0, right);
+                mirrored = Mirrored {
+                    inner: &mut *l.dynamic,
+                    span,
+                };
+                &mut mirrored
+            }
+            _ => &mut *l.dynamic,
+        };
+        self.add_pixels(pos, board);
+        self.add_falling(pos, board);
+        self.add_hint(pos, board);
+        self.add_kicks(pos, board);
+        if let Some(frames) = self.countdown {
+            self.add_countdown(pos, frames, l.dynamic);
+        } else if self.showing_level_up() {
+            self.add_level_up(pos, l.dynamic);
+        }
+        self.add_toasts(pos, l.dynamic);
+        // right or bottom quadrant
+        let side_panel = match self.orientation {
+            Orientation::Horizontal => {
+                let (_, right) = self.add_queue((right + SPACE_BETWEEN, TOP_MARGIN), l);
+                let bottom = self.add_text_info((right + SPACE_BETWEEN, TOP_MARGIN), fps, l);
+                self.add_keys((right + SPACE_BETWEEN, bottom + SPACE_BETWEEN), l);
+                (LEFT_MARGIN, hold_bottom + SPACE_BETWEEN)
+            }
+            Orientation::Vertical => {
+                self.add_queue((LEFT_MARGIN, bottom + SPACE_BETWEEN), l);
+                let bottom = self.add_keys((LEFT_MARGIN, hold_bottom + SPACE_BETWEEN / 2.), l);
+                let bottom = self.add_text_info((LEFT_MARGIN, bottom + SPACE_BETWEEN / 2.), fps, l);
+                (LEFT_MARGIN, bottom + SPACE_BETWEEN / 2.)
+            }
+        };
+        let mut free = side_panel;
+        if self.stats {
+            free.1 = self.add_stats(free, l) + SPACE_BETWEEN;
+        }
+        if self.bag {
+            free.1 = self.add_bag(free, l) + SPACE_BETWEEN;
+        }
+        if let Some(gauge) = &self.gauge {
+            free.1 = self.add_gauge(free, gauge.estimate, l) + SPACE_BETWEEN;
+        }
+        if self.frame_step {
+            self.add_frame_info(free, l.dynamic);
+        }
+        side_panel
+    }
 }
 
 // other
 impl VisGame {
+    // everything but presenting it, so that others can draw on top, present_frame then
+    // presents it all
+    pub(super) fn draw_frame(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let fps = ggez::timer::fps(ctx);
+        if self.effects.any() && self.post.is_none() {
+            match PostProcess::new(ctx, self.effects) {
+                Ok(post) => self.post = Some(post),
+                Err(e) => {
+                    eprintln!(
+                        "Warning: failed to set up the effects, going without: {}",
+                        e
+                    );
+                    self.effects = Effects::default();
+                }
+            }
+        }
+        if let Some(post) = self.post.as_mut() {
+            post.begin(ctx)?;
+        }
+        if self.paused {
+            clear(ctx, graphics::Color::from_rgb(64, 64, 64));
+        } else if self.showing_level_up() {
+            // tinted while the banner is up
+            let tint = LEVEL_UP_COLOR.scaled(0.15);
+            clear(ctx, graphics::Color::from_rgb(tint.r, tint.g, tint.b));
+        } else {
+            clear(ctx, graphics::BLACK);
+        }
+        if !self.paused {
+            self.draw_background(ctx)?;
+        }
+        // nothing is fixed while paused
+        let fixed_stale = !self.paused
+            && self
+                .fixed_layer
+                .as_ref()
+                .is_none_or(|(orientation, _)| *orientation != self.orientation);
+        let key = self.dynamic_key(fps);
+        let dynamic_stale = self.dynamic_layer.as_ref().is_none_or(|(k, _)| *k != key);
+        if fixed_stale || dynamic_stale {
+            let (mut fixed, mut dynamic) = (GgezRenderer::new(), GgezRenderer::new());
+            let mut layers = Layers {
+                fixed: if fixed_stale {
+                    &mut fixed
+                } else {
+                    &mut Discard
+                },
+                dynamic: &mut dynamic,
+            };
+            if self.paused {
+                let (window_width, window_height) = self.window_dims();
+                // only the text, the panel behind it is left out
+                let center = (window_width / 2., window_height / 2.);
+                self.add_text_info(center, fps, &mut layers);
+            } else {
+                self.side_panel = self.render(fps, &mut layers);
+            }
+            if fixed_stale {
+                let previous = self.fixed_layer.take().map(|(_, layer)| layer);
+                self.fixed_layer = Some((self.orientation, fixed.build(ctx, previous)?));
+            }
+            let previous = self.dynamic_layer.take().map(|(_, layer)| layer);
+            self.dynamic_layer = Some((key, dynamic.build(ctx, previous)?));
+        }
+        if let Some((_, layer)) = self.fixed_layer.as_ref().filter(|_| !self.paused) {
+            layer.draw(ctx)?;
+        }
+        if let Some((_, layer)) = self.dynamic_layer.as_ref() {
+            layer.draw(ctx)?;
+        }
+        Ok(())
+    }
+
+    // through the effects if there are any
+    pub(super) fn present_frame(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let bloom = self.bloom_until.saturating_sub(self.game.tick) as f32 / BLOOM_FRAMES as f32;
+        let grayscale = match self.countdown {
+            _ if self.paused => 1.,
+            Some(frames) => frames as f32 / COUNTDOWN_FRAMES as f32,
+            None => 0.,
+        };
+        let dims = self.window_dims();
+        if let Some(post) = self.post.as_mut() {
+            post.finish(ctx, dims, bloom, grayscale)?;
+        }
+        present(ctx)
+    }
+
+    // the theme's image and stars under everything else, the image is loaded the first
+    // time it's drawn (or the theme names another one)
+    fn draw_background(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let theme = theme::current();
+        let background = match theme.background() {
+            Some(background) => background,
+            None => return Ok(()),
+        };
+        let dims = self.window_dims();
+        if let Some(path) = &background.image {
+            if self.background_image.as_ref().map(|(loaded, _)| loaded) != Some(path) {
+                let image = load_image(ctx, path)
+                    .map_err(|e| {
+                        eprintln!(
+                            "Warning: failed to load background \"{}\": {}",
+                            path.display(),
+                            e
+                        )
+                    })
+                    .ok();
+                self.background_image = Some((path.clone(), image));
+            }
+            if let Some((_, Some(image))) = &self.background_image {
+                draw_stretched(ctx, image, dims, 1. - background.dim)?;
+            }
+        }
+        if background.starfield {
+            let area = Rect {
+                x: 0.,
+                y: 0.,
+                w: dims.0,
+                h: dims.1,
+            };
+            let mut renderer = GgezRenderer::new();
+            let dim = background.dim;
+            self.starfield.add(area, self.game.tick, dim, &mut renderer);
+            renderer.finish(ctx)?;
+        }
+        Ok(())
+    }
+
     fn switch_orientation(&mut self, ctx: &mut Context) {
         let dims = match self.orientation {
             Orientation::Horizontal => {
@@ -566,16 +1683,7 @@ impl VisGame {
                 HORIZONTAL_WINDOW_DIMS
             }
         };
-        graphics::set_screen_coordinates(
-            ctx,
-            Rect {
-                x: 0.,
-                y: 0.,
-                w: dims.0,
-                h: dims.1,
-            },
-        )
-        .unwrap()
+        graphics::set_screen_coordinates(ctx, graphics::Rect::new(0., 0., dims.0, dims.1)).unwrap()
     }
 }
 
@@ -584,16 +1692,35 @@ impl EventHandler for VisGame {
         sleep_until(self.next_frame);
         let start = Instant::now();
 
-        if self.paused {
-            self.next_frame = start + PAUSE_WAIT;
+        if self.game.is_over() {
+            println!("Lost {}", self.game.summary());
+            self.save_replay();
+            self.save_heatmap();
+            self.save_best();
+            self.save_achievements();
+            ggez::event::quit(ctx);
+        } else if self.paused {
+            let wait = if self.minimized {
+                MINIMIZED_WAIT
+            } else {
+                PAUSE_WAIT
+            };
+            self.next_frame = start + wait;
+        } else if let Some(frames) = self.countdown {
+            self.countdown = Some(frames - 1).filter(|&frames| frames > 0);
+            self.next_frame = start + PLAY_WAIT;
+        } else if self.frozen && self.steps == 0 {
+            // held keys don't repeat either, the tick they go by stands still
+            self.next_frame = start + PLAY_WAIT;
         } else {
+            self.steps = self.steps.saturating_sub(1);
             let mut actions = Vec::with_capacity(self.keys.len());
-            for (&code, info) in self.keys.iter_mut() {
+            for (&command, info) in self.keys.iter_mut() {
                 if let Repeat::Repeat { delay, .. } = info.repeat {
                     if self.game.tick % delay as usize == 0 {
                         match info.state {
                             ref mut s @ PressedState::Fresh(0) | ref mut s @ PressedState::Down => {
-                                actions.push(code);
+                                actions.push(command);
                                 *s = PressedState::Down;
                             }
                             PressedState::Fresh(ref mut x) => *x -= 1,
@@ -602,93 +1729,88 @@ impl EventHandler for VisGame {
                     }
                 }
             }
-            for code in actions {
-                self.do_key_action(code, ctx)
+            // the map iterates in no particular order, the same keys must always act in
+            // the same order for replays to come out the same
+            actions.sort_by_key(|&command| {
+                let priority = match command {
+                    Command::Play(action) => action.priority(),
+                    _ => u8::MAX,
+                };
+                (priority, command)
+            });
+            for command in actions {
+                self.do_command(command, ctx)
             }
 
             self.game.iterate();
+            self.update_level(ctx);
+            self.update_spawns();
+            self.update_bloom();
+            self.update_hint();
+            self.update_gauge();
+            self.update_events();
 
             self.next_frame = start + PLAY_WAIT;
         }
+        // paused too, to look at the changes without the piece falling
+        self.reload();
 
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        if self.paused {
-            let (window_width, window_height) = match self.orientation {
-                Orientation::Horizontal => HORIZONTAL_WINDOW_DIMS,
-                Orientation::Vertical => VERTICAL_WINDOW_DIMS,
-            };
-            clear(ctx, Color::from_rgb(64, 64, 64));
+        if self.is_minimized() {
+            return Ok(());
+        }
+        self.draw_frame(ctx)?;
+        self.present_frame(ctx)
+    }
 
-            let mut builder = MeshBuilder::new();
-            let _ = self.add_text_info((window_width / 2., window_height / 2.), &mut builder, ctx);
-            draw_queued_text(ctx, DrawParam::default(), None, FilterMode::Linear)?;
-        } else {
-            clear(ctx, BLACK);
-
-            let mut builder = MeshBuilder::new();
-            // left quadrant
-            let (hold_bottom, right) = self.add_hold(&mut builder);
-            // main quadrant
-            let pos = (right + SPACE_BETWEEN, TOP_MARGIN);
-            let (bottom, right) = self.add_grid(pos, &mut builder)?;
-            self.add_pixels(pos, &mut builder);
-            self.add_falling(pos, &mut builder)?;
-            // right or bottom quadrant
-            match self.orientation {
-                Orientation::Horizontal => {
-                    let (_, right) =
-                        self.add_queue((right + SPACE_BETWEEN, TOP_MARGIN), &mut builder);
-                    let bottom =
-                        self.add_text_info((right + SPACE_BETWEEN, TOP_MARGIN), &mut builder, ctx);
-                    self.add_keys(
-                        (right + SPACE_BETWEEN, bottom + SPACE_BETWEEN),
-                        &mut builder,
-                    );
-                }
-                Orientation::Vertical => {
-                    self.add_queue((LEFT_MARGIN, bottom + SPACE_BETWEEN), &mut builder);
-                    let bottom = self.add_keys(
-                        (LEFT_MARGIN, hold_bottom + SPACE_BETWEEN / 2.),
-                        &mut builder,
-                    );
-                    self.add_text_info(
-                        (LEFT_MARGIN, bottom + SPACE_BETWEEN / 2.),
-                        &mut builder,
-                        ctx,
-                    );
-                }
-            }
-            // build and draw
-            let mesh = builder.build(ctx)?;
-            draw(ctx, &mesh, DrawParam::default())?;
-            draw_queued_text(ctx, DrawParam::default(), None, FilterMode::Linear)?;
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) {
+        if !gained {
+            self.hide()
+        } else if self.auto_paused && !self.minimized {
+            self.resume()
         }
+    }
 
-        present(ctx)
+    // minimizing makes the window 0x0 on some platforms without taking its focus
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        let minimized = width == 0. || height == 0.;
+        if minimized != self.minimized {
+            self.minimized = minimized;
+            self.focus_event(ctx, !minimized);
+        }
     }
 
     fn key_down_event(&mut self, ctx: &mut Context, code: KeyCode, _mods: KeyMods, _: bool) {
-        let mut do_action = false;
-        self.keys.entry(code).and_modify(|key| {
-            if key.state == PressedState::Up {
-                key.state = match key.repeat {
-                    Repeat::Repeat { initial_delay, .. } => PressedState::Fresh(initial_delay),
-                    Repeat::NoRepeat => PressedState::Down,
-                };
-                do_action = true;
-            }
-        });
-        if do_action {
-            self.do_key_action(code, ctx)
+        if let Some(command) = self.command(code) {
+            self.press(command, ctx)
         }
     }
 
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        self.save_replay();
+        self.save_best();
+        self.save_achievements();
+        false
+    }
+
     fn key_up_event(&mut self, _ctx: &mut Context, code: KeyCode, _mods: KeyMods) {
-        self.keys.entry(code).and_modify(|v| {
-            v.state = PressedState::Up;
-        });
+        if let Some(command) = self.command(code) {
+            self.release(command)
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, button: Button, _id: GamepadId) {
+        if let Some(command) = pad_command(button) {
+            self.press(command, ctx)
+        }
+    }
+
+    fn gamepad_button_up_event(&mut self, _ctx: &mut Context, button: Button, _id: GamepadId) {
+        if let Some(command) = pad_command(button) {
+            self.release(command)
+        }
     }
 }
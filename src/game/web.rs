@@ -0,0 +1,205 @@
+use crate::{
+    game::{
+        bot::{BoardView, NNBot, TetrisBot},
+        features::InputEncoder,
+        heuristic::HeuristicBot,
+        render::{add_thumbnail, Color, Command, Rect, Renderer},
+        Action, Controller, Game,
+    },
+    neural_network::NN,
+};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+// the browser frontend, the page calls frame from requestAnimationFrame and forwards
+// keydown events, there is no event loop of our own (and no thread to sleep on)
+
+const STEP_MS: f64 = 1000. / 60.; // the game advances at 60 ticks per second like the window
+const MAX_STEPS_PER_FRAME: usize = 10; // don't try to catch up after the tab was hidden
+const MARGIN: f32 = 10.;
+const CELL_SIDE: f32 = 30.;
+
+struct CanvasRenderer<'a> {
+    context: &'a CanvasRenderingContext2d,
+}
+
+fn css(color: Color) -> String {
+    format!("rgb({}, {}, {})", color.r, color.g, color.b)
+}
+
+impl Renderer for CanvasRenderer<'_> {
+    fn rect(&mut self, rect: Rect, color: Color) {
+        self.context.set_fill_style_str(&css(color));
+        self.context
+            .fill_rect(rect.x as f64, rect.y as f64, rect.w as f64, rect.h as f64);
+    }
+
+    fn line(&mut self, points: &[(f32, f32)], width: f32, color: Color) {
+        self.context.set_stroke_style_str(&css(color));
+        self.context.set_line_width(width as f64);
+        self.context.begin_path();
+        for (i, &(x, y)) in points.iter().enumerate() {
+            if i == 0 {
+                self.context.move_to(x as f64, y as f64);
+            } else {
+                self.context.line_to(x as f64, y as f64);
+            }
+        }
+        self.context.stroke();
+    }
+
+    fn text(&mut self, (x, y): (f32, f32), text: &str, color: Color) {
+        self.context.set_fill_style_str(&css(color));
+        // the error only means the text wasn't drawn
+        let _ = self.context.fill_text(text, x as f64, y as f64);
+    }
+}
+
+// the default key bindings, by KeyboardEvent.key
+fn key_command(key: &str) -> Option<Command> {
+    Some(match key {
+        "ArrowLeft" => Command::Play(Action::MoveLeft),
+        "ArrowRight" => Command::Play(Action::MoveRight),
+        "ArrowDown" => Command::Play(Action::SoftDrop),
+        "ArrowUp" => Command::Play(Action::RotateCW),
+        "Shift" => Command::Play(Action::RotateCCW),
+        "a" | "A" => Command::Play(Action::Rotate180),
+        " " => Command::Play(Action::HardDrop),
+        "j" | "J" => Command::Play(Action::Hold),
+        "Escape" => Command::Pause,
+        _ => return None,
+    })
+}
+
+#[wasm_bindgen]
+pub struct WebGame {
+    game: Game,
+    context: CanvasRenderingContext2d,
+    bot: Option<Box<dyn TetrisBot>>, // plays instead of the keyboard
+    paused: bool,
+    last_frame: Option<f64>, // timestamp
+    lag: f64,                // ms not yet simulated
+}
+
+#[wasm_bindgen]
+impl WebGame {
+    // draws on the canvas with the given id
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas_id: &str) -> Result<WebGame, JsValue> {
+        let canvas = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(canvas_id))
+            .ok_or_else(|| JsValue::from_str("no such canvas"))?
+            .dyn_into::<HtmlCanvasElement>()?;
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("no 2d context"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+        context.set_font("16px sans-serif");
+        context.set_text_baseline("top");
+        Ok(Self {
+            game: Game::new(),
+            context,
+            bot: None,
+            paused: false,
+            last_frame: None,
+            lag: 0.,
+        })
+    }
+
+    #[wasm_bindgen(js_name = watchHeuristic)]
+    pub fn watch_heuristic(&mut self) {
+        self.bot = Some(Box::new(HeuristicBot::new()));
+    }
+
+    // a placement network in the text format of NN::from_file, fetched by the page
+    #[wasm_bindgen(js_name = watchNN)]
+    pub fn watch_nn(&mut self, nn: &str) -> Result<(), JsValue> {
+        let nn = NN::from_string(nn).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.bot = Some(Box::new(NNBot::new(nn, InputEncoder::Features)));
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = keyDown)]
+    pub fn key_down(&mut self, key: &str) {
+        match key_command(key) {
+            Some(Command::Pause) => self.paused = !self.paused,
+            Some(Command::Play(action)) if !self.paused && self.bot.is_none() => {
+                self.game.apply(action)
+            }
+            _ => (),
+        }
+    }
+
+    #[wasm_bindgen(js_name = isOver)]
+    pub fn is_over(&self) -> bool {
+        self.game.is_over()
+    }
+
+    // timestamp as passed to the requestAnimationFrame callback
+    pub fn frame(&mut self, timestamp: f64) {
+        let elapsed = self.last_frame.map_or(0., |last| timestamp - last);
+        self.last_frame = Some(timestamp);
+        if !self.paused && !self.game.is_over() {
+            self.lag = (self.lag + elapsed).min(STEP_MS * MAX_STEPS_PER_FRAME as f64);
+            while self.lag >= STEP_MS {
+                self.step();
+                self.lag -= STEP_MS;
+            }
+        }
+        self.draw();
+    }
+}
+
+impl WebGame {
+    fn step(&mut self) {
+        if let Some(bot) = self.bot.as_mut() {
+            if !self.game.has_queued_actions() {
+                let view = BoardView::new(&self.game);
+                if let Some(placement) = bot.choose(&view).placement() {
+                    for action in placement.inputs {
+                        self.game.queue_action(action);
+                    }
+                }
+            }
+        }
+        self.game.iterate();
+    }
+
+    fn draw(&self) {
+        let canvas = self.context.canvas();
+        let (width, height) = canvas.map_or((0., 0.), |c| (c.width() as f64, c.height() as f64));
+        self.context.set_fill_style_str(&css(Color::BLACK));
+        self.context.fill_rect(0., 0., width, height);
+
+        let mut r = CanvasRenderer {
+            context: &self.context,
+        };
+        let (_, right) = add_thumbnail(&self.game, (MARGIN, MARGIN), CELL_SIDE, &mut r);
+        let left = right + 2. * MARGIN;
+        let hold = match self.game.hold {
+            Some(id) if self.game.can_hold() => id.to_char().to_string(),
+            Some(id) => format!("{} (used)", id.to_char()),
+            None => "-".to_string(),
+        };
+        let queue = self
+            .game
+            .piece_queue
+            .iter()
+            .map(|id| id.to_char())
+            .collect::<String>();
+        let mut lines = vec![
+            format!("points: {}", self.game.points),
+            format!("hold: {}", hold),
+            format!("next: {}", queue),
+        ];
+        if self.paused {
+            lines.push("paused".to_string());
+        } else if self.game.is_over() {
+            lines.push("game over".to_string());
+        }
+        for (i, line) in lines.iter().enumerate() {
+            r.text((left, MARGIN + 25. * i as f32), line, Color::WHITE);
+        }
+    }
+}
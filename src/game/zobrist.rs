@@ -0,0 +1,79 @@
+use crate::game::{Board, PieceId, GAME_HEIGHT, GAME_WIDTH};
+
+// zobrist hashing: every cell and every piece in the first queue slots gets a random key,
+// the hash of a board is the xor of the keys of its full cells so placing (or removing)
+// a piece updates it with four xors. Only occupancy is hashed, like the bots only see
+// occupancy, so boards that differ in the colors of their cells hash the same
+
+const CELLS: usize = GAME_WIDTH * GAME_HEIGHT;
+const PIECES: usize = 7;
+const SLOTS: usize = 2; // current and next piece, see hash_state
+const KEYS: [u64; CELLS + SLOTS * PIECES] = keys();
+
+// splitmix64 with a fixed seed so the hashes are the same on every run
+const fn keys() -> [u64; CELLS + SLOTS * PIECES] {
+    let mut keys = [0; CELLS + SLOTS * PIECES];
+    let mut state: u64 = 0x5eed;
+    let mut i = 0;
+    while i < keys.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+fn cell_key((x, y): (isize, isize)) -> u64 {
+    KEYS[y as usize * GAME_WIDTH + x as usize]
+}
+
+fn piece_key(slot: usize, id: Option<PieceId>) -> u64 {
+    id.map_or(0, |id| KEYS[CELLS + slot * PIECES + id as usize])
+}
+
+// from scratch, see Game::board_hash for the incrementally updated one
+pub(super) fn hash_board(board: &Board) -> u64 {
+    let full = (0..GAME_HEIGHT)
+        .flat_map(|y| (0..GAME_WIDTH).map(move |x| (x, y)))
+        .filter(|&(x, y)| !board[y][x].is_empty());
+    hash_cells(full.map(|(x, y)| (x as isize, y as isize)))
+}
+
+// xor this into the hash of a board to add or remove the cells
+pub(super) fn hash_cells(cells: impl IntoIterator<Item = (isize, isize)>) -> u64 {
+    cells
+        .into_iter()
+        .map(cell_key)
+        .fold(0, |hash, key| hash ^ key)
+}
+
+// a board together with the pieces a network sees with it (see InputEncoder::encode_board)
+pub fn hash_state(board_hash: u64, current: Option<PieceId>, next: Option<PieceId>) -> u64 {
+    board_hash ^ piece_key(0, current) ^ piece_key(1, next)
+}
+
+#[test]
+fn test_hash_depends_on_occupancy_only() {
+    use crate::game::Pixel;
+    let mut board = [[Pixel::Empty; GAME_WIDTH]; GAME_HEIGHT];
+    assert_eq!(hash_board(&board), 0);
+    board[GAME_HEIGHT - 1][0] = Pixel::Full(PieceId::IBlock);
+    board[GAME_HEIGHT - 1][1] = Pixel::Garbage;
+    let hash = hash_board(&board);
+    assert_eq!(
+        hash,
+        hash_cells(vec![
+            (0, GAME_HEIGHT as isize - 1),
+            (1, GAME_HEIGHT as isize - 1)
+        ])
+    );
+    board[GAME_HEIGHT - 1][0] = Pixel::Full(PieceId::TBlock);
+    assert_eq!(hash_board(&board), hash);
+    assert_ne!(
+        hash_state(hash, Some(PieceId::IBlock), Some(PieceId::TBlock)),
+        hash_state(hash, Some(PieceId::TBlock), Some(PieceId::IBlock))
+    );
+}
@@ -0,0 +1,70 @@
+#![feature(stmt_expr_attributes)] // for fine-grained rustfmt control
+
+#[macro_use]
+extern crate derive_more;
+#[cfg(feature = "window")]
+#[macro_use]
+extern crate gfx;
+
+// the game, the bots and the training code, main.rs is only a frontend for these
+// everything that opens a window needs the "window" feature (on by default), the "web"
+// feature adds a canvas frontend for the browser instead (see game::web)
+
+#[cfg(feature = "window")]
+use ggez::{
+    conf::{FullscreenType, WindowMode},
+    event::EventHandler,
+    ContextBuilder, GameResult,
+};
+
+pub mod game;
+pub mod neural_network;
+#[cfg(feature = "window")]
+mod support;
+
+pub const HORIZONTAL_WINDOW_DIMS: (f32, f32) = (1150., 750.);
+pub const VERTICAL_WINDOW_DIMS: (f32, f32) = (550., 850.);
+
+#[cfg(feature = "window")]
+pub const HORIZONTAL_WINDOW_MODE: WindowMode = WindowMode {
+    width: HORIZONTAL_WINDOW_DIMS.0,
+    height: HORIZONTAL_WINDOW_DIMS.1,
+    maximized: false,
+    fullscreen_type: FullscreenType::Windowed,
+    borderless: false,
+    min_width: 0.0,
+    max_width: 0.0,
+    min_height: 0.0,
+    max_height: 0.0,
+    resizable: false,
+};
+
+#[cfg(feature = "window")]
+pub const VERTICAL_WINDOW_MODE: WindowMode = WindowMode {
+    width: VERTICAL_WINDOW_DIMS.0,
+    height: VERTICAL_WINDOW_DIMS.1,
+    maximized: false,
+    fullscreen_type: FullscreenType::Windowed,
+    borderless: false,
+    min_width: 0.0,
+    max_width: 0.0,
+    min_height: 0.0,
+    max_height: 0.0,
+    resizable: false,
+};
+
+#[cfg(feature = "window")]
+// todo try to factor out this function
+pub fn run_game(eh: &mut impl EventHandler) -> GameResult<()> {
+    run_game_in(eh, HORIZONTAL_WINDOW_MODE)
+}
+
+#[cfg(feature = "window")]
+pub fn run_game_in(eh: &mut impl EventHandler, window_mode: WindowMode) -> GameResult<()> {
+    let (ref mut ctx, ref mut event_loop) = ContextBuilder::new("my_game", "me")
+        .window_mode(window_mode)
+        .build()
+        .expect("failed to create context");
+
+    ggez::event::run(ctx, event_loop, eh)
+}
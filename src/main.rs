@@ -1,76 +1,44 @@
-#![feature(stmt_expr_attributes)] // for fine-grained rustfmt control
-
 #[macro_use]
 extern crate derive_more;
 
-#[allow(unused_imports)]
-use crate::game::nn_trainer::NNTrainer;
-#[allow(unused_imports)]
-use crate::game::nn_visual::NNVisGame;
-#[allow(unused_imports)]
-use crate::game::visual::VisGame;
-
-#[allow(unused_imports)]
-use crate::game::{GAME_HEIGHT, GAME_WIDTH};
-#[allow(unused_imports)]
-use crate::neural_network::{ActivationType, NNReadResult, NN};
-use ggez::{
-    conf::{FullscreenType, WindowMode},
-    event::EventHandler,
-    ContextBuilder, GameResult,
-};
-
-pub(crate) mod game;
-pub(crate) mod neural_network;
-mod support;
-
-const HORIZONTAL_WINDOW_DIMS: (f32, f32) = (1150., 750.);
-const VERTICAL_WINDOW_DIMS: (f32, f32) = (550., 850.);
-
-const HORIZONTAL_WINDOW_MODE: WindowMode = WindowMode {
-    width: HORIZONTAL_WINDOW_DIMS.0,
-    height: HORIZONTAL_WINDOW_DIMS.1,
-    maximized: false,
-    fullscreen_type: FullscreenType::Windowed,
-    borderless: false,
-    min_width: 0.0,
-    max_width: 0.0,
-    min_height: 0.0,
-    max_height: 0.0,
-    resizable: false,
-};
-
-const VERTICAL_WINDOW_MODE: WindowMode = WindowMode {
-    width: VERTICAL_WINDOW_DIMS.0,
-    height: VERTICAL_WINDOW_DIMS.1,
-    maximized: false,
-    fullscreen_type: FullscreenType::Windowed,
-    borderless: false,
-    min_width: 0.0,
-    max_width: 0.0,
-    min_height: 0.0,
-    max_height: 0.0,
-    resizable: false,
-};
+use std::{env, process};
+use tetris::game::visual::VisGame;
 
-// todo try to factor out this function
-pub fn run_game(eh: &mut impl EventHandler) -> GameResult<()> {
-    let (ref mut ctx, ref mut event_loop) = ContextBuilder::new("my_game", "me")
-        .window_mode(HORIZONTAL_WINDOW_MODE)
-        .build()
-        .expect("failed to create context");
-
-    ggez::event::run(ctx, event_loop, eh)
-}
+mod cli;
 
 fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Some(command) = args.first() {
+        let result = match command.as_str() {
+            "play" => cli::play(&args[1..]),
+            "watch" => cli::watch(&args[1..]),
+            "train" => cli::train(&args[1..]),
+            "experiments" => cli::experiments(&args[1..]),
+            "eval" => cli::eval(&args[1..]),
+            "bench" => cli::bench(&args[1..]),
+            "lineage" => cli::lineage(&args[1..]),
+            "browse" => cli::browse(&args[1..]),
+            "rl" => cli::rl(&args[1..]),
+            "estimator" => cli::estimator(&args[1..]),
+            "heatmap" => cli::heatmap(&args[1..]),
+            "dataset" => cli::dataset(&args[1..]),
+            "imitate" => cli::imitate_replays(&args[1..]),
+            "teach" => cli::teach(&args[1..]),
+            "tournament" => cli::play_tournament(&args[1..]),
+            "compare" => cli::compare(&args[1..]),
+            "tbp" => cli::tbp(&args[1..]),
+            "versus" => cli::versus(&args[1..]),
+            "scores" => cli::scores(&args[1..]),
+            "bracket" => cli::bracket(&args[1..]),
+            c => Err(cli::CliError::Usage(format!("unknown command \"{}\"", c))),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     // playable game
     VisGame::new().run().unwrap();
-
-    // NNVisGame::new().run().unwrap();
-
-    // NNTrainer::new("data/saved_gen.txt".as_ref())
-    //     .expect("failed to create nn_trainer")
-    //     .run()
-    //     .unwrap()
 }
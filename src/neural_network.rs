@@ -1,10 +1,11 @@
-use ggez::nalgebra::DMatrix;
 use itertools::Itertools;
+use nalgebra::DMatrix;
 use rand::prelude::*;
 use std::{
-    fs, io,
+    fmt, fs, io,
     num::{ParseFloatError, ParseIntError},
     path::Path,
+    sync::atomic::{AtomicU8, Ordering},
 };
 
 fn relu_activation(x: f64) -> f64 {
@@ -19,10 +20,28 @@ fn sigmoid_activation(x: f64) -> f64 {
     1. / (1. + (-x).exp())
 }
 
+fn linear_activation(x: f64) -> f64 {
+    x
+}
+
+fn relu_activation_f32(x: f32) -> f32 {
+    x.max(0.)
+}
+
+fn sigmoid_activation_f32(x: f32) -> f32 {
+    1. / (1. + (-x).exp())
+}
+
+fn linear_activation_f32(x: f32) -> f32 {
+    x
+}
+
+#[allow(dead_code)]
 #[derive(Copy, Clone, PartialEq)]
 pub enum ActivationType {
     Relu,
     Sigmoid,
+    Linear, // for unbounded outputs such as value estimates
 }
 
 impl ActivationType {
@@ -30,10 +49,35 @@ impl ActivationType {
         match self {
             ActivationType::Relu => relu_activation,
             ActivationType::Sigmoid => sigmoid_activation,
+            ActivationType::Linear => linear_activation,
+        }
+    }
+
+    fn fn_ptr_f32(self) -> fn(f32) -> f32 {
+        match self {
+            ActivationType::Relu => relu_activation_f32,
+            ActivationType::Sigmoid => sigmoid_activation_f32,
+            ActivationType::Linear => linear_activation_f32,
+        }
+    }
+
+    // derivative expressed in terms of the input (z) and output (a) of the activation
+    fn derivative(self, z: f64, a: f64) -> f64 {
+        match self {
+            ActivationType::Relu => {
+                if z < 0. {
+                    0.
+                } else {
+                    1.
+                }
+            }
+            ActivationType::Sigmoid => a * (1. - a),
+            ActivationType::Linear => 1.,
         }
     }
 }
 
+#[derive(Clone)]
 struct Activation {
     typ: ActivationType,
     fnp: fn(f64) -> f64,
@@ -45,13 +89,151 @@ impl PartialEq for Activation {
     }
 }
 
-#[derive(PartialEq)]
+// how apply_batch runs a batch of inputs through a network, chosen at runtime with
+// set_backend, Cpu (the default) is apply itself in f64, which gives the same outputs on
+// every machine so a seed trains the same way everywhere, Simd runs the inputs one at a time
+// through f32 copies of the layers (see Packed), faster but its rounding depends on the CPU,
+// and Blas (only with the blas feature) multiplies every layer with the whole batch at once
+// using matrixmultiply's gemm, which pays off for large layers and batches (every placement
+// of a piece is a batch)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Simd,
+    Cpu,
+    Blas,
+}
+
+static BACKEND: AtomicU8 = AtomicU8::new(0); // index in Backend::ALL
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Cpu, Backend::Simd, Backend::Blas];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "simd" => Some(Backend::Simd),
+            "cpu" => Some(Backend::Cpu),
+            "blas" => Some(Backend::Blas),
+            _ => None,
+        }
+    }
+
+    // whether this build has it
+    pub fn is_available(self) -> bool {
+        self != Backend::Blas || cfg!(feature = "blas")
+    }
+}
+
+// for every network from now on, falls back to Cpu if this build doesn't have the backend
+pub fn set_backend(backend: Backend) {
+    let backend = if backend.is_available() {
+        backend
+    } else {
+        Backend::Cpu
+    };
+    let index = Backend::ALL.iter().position(|&b| b == backend).unwrap();
+    BACKEND.store(index as u8, Ordering::Relaxed);
+}
+
+pub fn backend() -> Backend {
+    Backend::ALL[BACKEND.load(Ordering::Relaxed) as usize]
+}
+
+const LANES: usize = 8; // f32s in an AVX register
+
+// f32 copy of a layer's weights for the Simd backend, row-major with each row (one output)
+// padded with zeros to a whole number of LANES so that the dot products need no remainder
+#[derive(Clone, PartialEq)]
+struct Packed {
+    bias: Vec<f32>,
+    rows: Vec<f32>,
+    stride: usize, // padded number of inputs
+}
+
+impl Packed {
+    fn of(weights: &DMatrix<f64>) -> Self {
+        let (rows, cols) = weights.shape();
+        let stride = (cols - 1).div_ceil(LANES) * LANES;
+        let mut packed = vec![0.; rows * stride];
+        for r in 0..rows {
+            for c in 1..cols {
+                packed[r * stride + c - 1] = weights[(r, c)] as f32;
+            }
+        }
+        Self {
+            bias: (0..rows).map(|r| weights[(r, 0)] as f32).collect(),
+            rows: packed,
+            stride,
+        }
+    }
+
+    // activation of bias + weights * input for every output into out, input is stride long
+    // and out is left padded for the next layer
+    fn forward(&self, activation: fn(f32) -> f32, input: &[f32], out: &mut Vec<f32>) {
+        out.clear();
+        out.extend(
+            self.rows
+                .chunks_exact(self.stride)
+                .zip(&self.bias)
+                .map(|(row, bias)| activation(bias + dot(row, input))),
+        );
+        out.resize(self.bias.len().div_ceil(LANES) * LANES, 0.);
+    }
+}
+
+// both are a whole number of LANES long
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    debug_assert!(a.len() == b.len() && a.len().is_multiple_of(LANES));
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+            return unsafe { dot_avx(a, b) };
+        }
+    }
+    // separate sums per lane, which the compiler vectorizes on its own
+    let mut sums = [0.; LANES];
+    for (a, b) in a.chunks_exact(LANES).zip(b.chunks_exact(LANES)) {
+        for i in 0..LANES {
+            sums[i] += a[i] * b[i];
+        }
+    }
+    sums.iter().sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn dot_avx(a: &[f32], b: &[f32]) -> f32 {
+    use std::arch::x86_64::*;
+    let mut sums = _mm256_setzero_ps();
+    for i in (0..a.len()).step_by(LANES) {
+        let (a, b) = (a.as_ptr().add(i), b.as_ptr().add(i));
+        sums = _mm256_fmadd_ps(_mm256_loadu_ps(a), _mm256_loadu_ps(b), sums);
+    }
+    let mut lanes = [0.; LANES];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), sums);
+    lanes.iter().sum()
+}
+
+#[derive(Clone, PartialEq)]
 struct Layer {
     weights: DMatrix<f64>,
     activation: Activation,
+    packed: Packed, // kept in sync with weights
+}
+
+impl Layer {
+    fn new(weights: DMatrix<f64>, typ: ActivationType) -> Self {
+        Self {
+            packed: Packed::of(&weights),
+            weights,
+            activation: Activation {
+                typ,
+                fnp: typ.fn_ptr(),
+            },
+        }
+    }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct NN {
     layers: Box<[Layer]>,
 }
@@ -60,26 +242,46 @@ pub struct NNBuilder {
     last_size: usize,
     layers: Vec<Layer>,
     rng: ThreadRng,
+    init_range: (f64, f64),
 }
 
 // with random noise (ncols: size of from layer, nrows: size of to layer)
-fn gen_weights(ncols: usize, nrows: usize, rng: &mut ThreadRng) -> DMatrix<f64> {
+fn gen_weights(
+    ncols: usize,
+    nrows: usize,
+    (low, high): (f64, f64),
+    rng: &mut ThreadRng,
+) -> DMatrix<f64> {
     // warn: not sure about order of nrows and ncols here, it changed...
-    DMatrix::from_fn(nrows, ncols, |_, _| rng.gen_range(0.0, 0.00001))
+    DMatrix::from_fn(nrows, ncols, |_, _| rng.gen_range(low, high))
 }
 
 #[derive(Debug)]
 pub struct NNCreationError(String);
 
+impl fmt::Display for NNCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid network: {}", self.0)
+    }
+}
+
 impl NNBuilder {
+    // range of the initial weights of the layers added after this call, the default
+    // is nearly zero which is fine for evolution but too symmetric for gradient descent
+    #[allow(dead_code)]
+    pub fn init_range(mut self, low: f64, high: f64) -> Self {
+        self.init_range = (low, high);
+        self
+    }
+
     pub fn add_layer(mut self, size: usize, activation_type: ActivationType) -> Self {
-        self.layers.push(Layer {
-            weights: gen_weights(self.last_size + 1 /* bias */, size, &mut self.rng),
-            activation: Activation {
-                typ: activation_type,
-                fnp: activation_type.fn_ptr(),
-            },
-        });
+        let weights = gen_weights(
+            self.last_size + 1, /* bias */
+            size,
+            self.init_range,
+            &mut self.rng,
+        );
+        self.layers.push(Layer::new(weights, activation_type));
         self.last_size = size;
         self
     }
@@ -104,15 +306,33 @@ impl NN {
             last_size: first_layer_size,
             layers: vec![],
             rng: thread_rng(),
+            init_range: (0.0, 0.00001),
         }
     }
 
+    // size of the input layer (without bias)
+    pub fn input_size(&self) -> usize {
+        self.layers[0].weights.ncols() - 1
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.layers[self.layers.len() - 1].weights.nrows()
+    }
+
+    // units per layer, input first
+    pub fn shape(&self) -> Vec<usize> {
+        std::iter::once(self.input_size())
+            .chain(self.layers.iter().map(|layer| layer.weights.nrows()))
+            .collect()
+    }
+
     pub fn apply(&self, input: &[f64]) -> DMatrix<f64> {
         assert_eq!(input.len() + 1, self.layers[0].weights.ncols());
         let mut data = DMatrix::from_iterator(input.len(), 1, input.iter().copied());
         for Layer {
             weights,
             activation,
+            ..
         } in self.layers.iter()
         {
             // insert bias as first element
@@ -123,6 +343,231 @@ impl NN {
         }
         data
     }
+
+    // the output of every layer for the input, the last one is the output of the network,
+    // for looking inside rather than for speed
+    pub fn activations(&self, input: &[f64]) -> Vec<Vec<f64>> {
+        assert_eq!(input.len() + 1, self.layers[0].weights.ncols());
+        let mut data = DMatrix::from_iterator(input.len(), 1, input.iter().copied());
+        let mut activations = Vec::with_capacity(self.layers.len());
+        for Layer {
+            weights,
+            activation,
+            ..
+        } in self.layers.iter()
+        {
+            data = weights * data.insert_row(0, 1.);
+            data.apply(activation.fnp);
+            activations.push(data.iter().copied().collect());
+        }
+        activations
+    }
+
+    // the outputs for every input, with the backend chosen by set_backend
+    pub fn apply_batch(&self, inputs: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        self.apply_batch_with(backend(), inputs)
+    }
+
+    pub fn apply_batch_with(&self, backend: Backend, inputs: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        match backend {
+            #[cfg(feature = "blas")]
+            Backend::Blas => self.apply_gemm(inputs),
+            Backend::Simd => {
+                let (mut data, mut out) = (vec![], vec![]);
+                inputs
+                    .iter()
+                    .map(|input| self.apply_simd(input, &mut data, &mut out))
+                    .collect()
+            }
+            _ => inputs
+                .iter()
+                .map(|input| self.apply(input).iter().copied().collect())
+                .collect(),
+        }
+    }
+
+    // the layers' packed f32 weights with the bias and activation applied in the same pass,
+    // data and out are reused between inputs
+    fn apply_simd(&self, input: &[f64], data: &mut Vec<f32>, out: &mut Vec<f32>) -> Vec<f64> {
+        assert_eq!(input.len(), self.input_size());
+        data.clear();
+        data.extend(input.iter().map(|&x| x as f32));
+        data.resize(self.layers[0].packed.stride, 0.);
+        for layer in self.layers.iter() {
+            layer
+                .packed
+                .forward(layer.activation.typ.fn_ptr_f32(), data, out);
+            std::mem::swap(data, out);
+        }
+        data[..self.output_size()]
+            .iter()
+            .map(|&x| x as f64)
+            .collect()
+    }
+
+    // the batch as the columns of one matrix with a row of ones on top for the bias, every
+    // layer is a single gemm (all matrices are column major like nalgebra's)
+    #[cfg(feature = "blas")]
+    fn apply_gemm(&self, inputs: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        let n = inputs.len();
+        let mut size = self.input_size();
+        let mut data = Vec::with_capacity((size + 1) * n);
+        for input in inputs {
+            assert_eq!(input.len(), size);
+            data.push(1.);
+            data.extend_from_slice(input);
+        }
+        for (i, layer) in self.layers.iter().enumerate() {
+            let (rows, cols) = layer.weights.shape();
+            let last = i + 1 == self.layers.len();
+            // the next layer's bias row is left in place, the output layer has none
+            let stride = if last { rows } else { rows + 1 };
+            let offset = stride - rows;
+            let mut out = vec![1.; stride * n];
+            // weights is rows x cols, data cols x n and out (past the offset) rows x n
+            unsafe {
+                matrixmultiply::dgemm(
+                    rows,
+                    cols,
+                    n,
+                    1.,
+                    layer.weights.as_slice().as_ptr(),
+                    1,
+                    rows as isize,
+                    data.as_ptr(),
+                    1,
+                    cols as isize,
+                    0.,
+                    out.as_mut_ptr().add(offset),
+                    1,
+                    stride as isize,
+                );
+            }
+            for column in out.chunks_mut(stride) {
+                for x in &mut column[offset..] {
+                    *x = (layer.activation.fnp)(*x);
+                }
+            }
+            data = out;
+            size = rows;
+        }
+        data.chunks(size).map(<[f64]>::to_vec).collect()
+    }
+
+    // copy where every weight has a chance of rate to be nudged by up to strength
+    pub fn mutated(&self, rate: f64, strength: f64) -> Self {
+        let mut rng = thread_rng();
+        let mut nn = self.clone();
+        for layer in nn.layers.iter_mut() {
+            for w in layer.weights.iter_mut() {
+                if rng.gen::<f64>() < rate {
+                    *w += rng.gen_range(-strength, strength);
+                }
+            }
+            layer.packed = Packed::of(&layer.weights);
+        }
+        nn
+    }
+
+    // euclidean distance between the weights of two networks of the same shape
+    pub fn distance(&self, other: &Self) -> Option<f64> {
+        let same_shape = self.layers.len() == other.layers.len()
+            && self
+                .layers
+                .iter()
+                .zip(other.layers.iter())
+                .all(|(a, b)| a.weights.shape() == b.weights.shape());
+        if !same_shape {
+            return None;
+        }
+        let squared = self
+            .layers
+            .iter()
+            .zip(other.layers.iter())
+            .map(|(a, b)| (&a.weights - &b.weights).norm_squared())
+            .sum::<f64>();
+        Some(squared.sqrt())
+    }
+
+    // one step of gradient descent on the squared error between the output for
+    // input and target, returns the error before the step
+    #[allow(dead_code)]
+    pub fn train(&mut self, input: &[f64], target: &[f64], learning_rate: f64) -> f64 {
+        assert_eq!(input.len() + 1, self.layers[0].weights.ncols());
+        assert_eq!(target.len(), self.output_size());
+
+        // forward, remembering the (biased) input, pre-activation and output of every layer
+        let mut steps = Vec::with_capacity(self.layers.len());
+        let mut data = DMatrix::from_iterator(input.len(), 1, input.iter().copied());
+        for Layer {
+            weights,
+            activation,
+            ..
+        } in self.layers.iter()
+        {
+            let layer_input = data.insert_row(0, 1.);
+            let z = weights * &layer_input;
+            data = z.map(activation.fnp);
+            steps.push((layer_input, z, data.clone()));
+        }
+
+        let target = DMatrix::from_iterator(target.len(), 1, target.iter().copied());
+        let mut error = &data - target;
+        let loss = error.iter().map(|e| e * e).sum::<f64>() / 2.;
+
+        // backward
+        for (layer, (layer_input, z, a)) in self.layers.iter_mut().zip(steps).rev() {
+            let typ = layer.activation.typ;
+            let delta = error.zip_map(&z.zip_map(&a, |z, a| typ.derivative(z, a)), |e, d| e * d);
+            // error for the previous layer, without the bias row
+            error = (layer.weights.transpose() * &delta).remove_row(0);
+            layer.weights -= learning_rate * delta * layer_input.transpose();
+            layer.packed = Packed::of(&layer.weights);
+        }
+
+        loss
+    }
+}
+
+#[test]
+fn test_backends_agree() {
+    // sizes that aren't a multiple of LANES
+    let nn = NN::make(13)
+        .init_range(-1., 1.)
+        .add_layer(7, ActivationType::Relu)
+        .add_layer(3, ActivationType::Sigmoid)
+        .build()
+        .unwrap()
+        .mutated(0.5, 0.5); // weights changed after packing
+    let inputs = (0..9)
+        .map(|i| (0..13).map(|j| ((i * 13 + j) as f64).sin()).collect())
+        .collect::<Vec<Vec<f64>>>();
+    let expected = inputs
+        .iter()
+        .map(|input| nn.apply(input).iter().copied().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    for &backend in Backend::ALL.iter().filter(|b| b.is_available()) {
+        let outputs = nn.apply_batch_with(backend, &inputs);
+        assert_eq!(outputs.len(), inputs.len());
+        // simd is in f32
+        let tolerance = if backend == Backend::Simd {
+            1e-5
+        } else {
+            1e-12
+        };
+        for (output, expected) in outputs.iter().zip(&expected) {
+            assert_eq!(output.len(), 3);
+            for (a, b) in output.iter().zip(expected) {
+                assert!((a - b).abs() < tolerance, "{:?}: {} != {}", backend, a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_default_backend_is_exact() {
+    // no test calls set_backend, the f64 one has to be the default for reproducible training
+    assert_eq!(backend(), Backend::Cpu);
 }
 
 #[derive(From, Debug)]
@@ -135,6 +580,17 @@ pub enum NNReadError {
 
 pub type NNReadResult<T> = Result<T, NNReadError>;
 
+impl fmt::Display for NNReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IoError(e) => write!(f, "failed to read network: {}", e),
+            Self::ParseIntError(e) => write!(f, "invalid network: {}", e),
+            Self::ParseFloatError(e) => write!(f, "invalid network: {}", e),
+            Self::Other(e) => write!(f, "invalid network: {}", e),
+        }
+    }
+}
+
 #[test]
 fn test_nn_serialization() {
     use crate::{
@@ -148,12 +604,41 @@ fn test_nn_serialization() {
         .build()
         .unwrap();
     let file_path = "temporary_test_nn.txt";
-    nn.write_out(file_path).unwrap();
-    let read = NN::read_in(file_path).unwrap();
+    nn.to_file(file_path).unwrap();
+    let read = NN::from_file(file_path).unwrap();
     assert!(nn == read);
     fs::remove_file(file_path).unwrap();
 }
 
+#[test]
+fn test_nn_training() {
+    let mut nn = NN::make(2)
+        .init_range(-0.5, 0.5)
+        .add_layer(8, ActivationType::Relu)
+        .add_layer(1, ActivationType::Linear)
+        .build()
+        .unwrap();
+    // learn to add two numbers
+    let data = [
+        ([0., 0.], 0.),
+        ([0., 1.], 1.),
+        ([1., 0.], 1.),
+        ([1., 1.], 2.),
+    ];
+    let error = |nn: &NN| {
+        data.iter()
+            .map(|(input, target)| (nn.apply(input)[0] - target).abs())
+            .sum::<f64>()
+    };
+    let before = error(&nn);
+    for _ in 0..2000 {
+        for (input, target) in data.iter() {
+            nn.train(input, &[*target], 0.05);
+        }
+    }
+    assert!(error(&nn) < before / 10.);
+}
+
 impl NN {
     // overwrites!
     #[allow(dead_code)]
@@ -169,6 +654,7 @@ impl NN {
         for Layer {
             weights,
             activation,
+            ..
         } in self.layers.iter()
         {
             // nrows x ncols (height x width)
@@ -176,6 +662,7 @@ impl NN {
             let activation = match activation.typ {
                 ActivationType::Relu => "R",
                 ActivationType::Sigmoid => "S",
+                ActivationType::Linear => "L",
             };
             let ws = weights.iter().map(|w| format!("{}", w)).join(",");
             string.push_str(&size);
@@ -229,16 +716,11 @@ impl NN {
                         .parse::<usize>()?,
                 )
             };
-            let activation = {
-                let typ = match split.next().ok_or_else(|| layer_read_error(i))? {
-                    "R" => ActivationType::Relu,
-                    "S" => ActivationType::Sigmoid,
-                    s => return Err(NNReadError::Other(format!("invalid activation: {}", s))),
-                };
-                Activation {
-                    typ,
-                    fnp: typ.fn_ptr(),
-                }
+            let activation = match split.next().ok_or_else(|| layer_read_error(i))? {
+                "R" => ActivationType::Relu,
+                "S" => ActivationType::Sigmoid,
+                "L" => ActivationType::Linear,
+                s => return Err(NNReadError::Other(format!("invalid activation: {}", s))),
             };
             let weights = {
                 let ws = split
@@ -259,10 +741,7 @@ impl NN {
                 }
                 DMatrix::from_iterator(size.0, size.1, ws.iter().copied())
             };
-            layers.push(Layer {
-                weights,
-                activation,
-            })
+            layers.push(Layer::new(weights, activation))
         }
         if num_layers != layers.len() {
             return Err(NNReadError::Other(format!(
@@ -277,3 +756,21 @@ impl NN {
         })
     }
 }
+
+#[test]
+fn test_activations_end_with_the_output() {
+    let nn = NN::make(5)
+        .init_range(-1., 1.)
+        .add_layer(4, ActivationType::Relu)
+        .add_layer(2, ActivationType::Sigmoid)
+        .build()
+        .unwrap();
+    let input = [0.5, -1., 0., 2., 0.25];
+    let activations = nn.activations(&input);
+    assert_eq!(
+        activations.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![4, 2]
+    );
+    let output = nn.apply(&input);
+    assert_eq!(activations[1], output.iter().copied().collect::<Vec<_>>());
+}